@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The in-memory mock server on its own, for consumers not written in Rust.
+//!
+//!     id-registry-mock [--port N] [--latency-ms N] [--jitter-ms N] [--failure-rate F]
+//!                      [--failure STATUS|lost] [--retry-after N]
+use id_registry_client::mock::{Failure, MockConfig, MockServer};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: id-registry-mock [--port N] [--latency-ms N] [--jitter-ms N] [--failure-rate F] [--failure STATUS|lost] [--retry-after N]
+
+Serves POST /ids, POST /ids/batch, POST /ids/lookup, GET and PUT /ids/<id>,
+POST /ids/<id>/confirm, DELETE /ids/<id>, GET /owners/<owner>/ids, GET /changes and
+GET /health from memory on 127.0.0.1 (port 8000 by default). --failure-rate answers that share of
+requests with --failure: a status such as 503 (the default) or 429, or 'lost' to act and
+then close the connection without an answer.";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("id-registry-mock: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut config = MockConfig::default();
+    let mut port = 8000;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        let value = args.next().ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE))?;
+        let number = |value: &str| value.parse::<u64>().map_err(|_| format!("{} needs a number, got '{}'", arg, value));
+        match arg.as_str() {
+            "--port" => port = number(&value)? as u16,
+            "--latency-ms" => config.latency = Duration::from_millis(number(&value)?),
+            "--jitter-ms" => config.jitter = Duration::from_millis(number(&value)?),
+            "--retry-after" => config.retry_after_secs = number(&value)?,
+            "--failure-rate" => {
+                config.failure_rate = value.parse().map_err(|_| format!("--failure-rate needs a share like 0.1, got '{}'", value))?;
+            }
+            "--failure" => {
+                config.failure = match value.as_str() {
+                    "lost" => Failure::LostAnswer,
+                    status => Failure::Status(number(status)? as u16),
+                };
+            }
+            other => return Err(format!("unknown option '{}'\n\n{}", other, USAGE)),
+        }
+    }
+
+    let server = MockServer::bind(("127.0.0.1", port), config).map_err(|e| format!("cannot listen on port {}: {}", port, e))?;
+    println!("Mock ID registry listening on {}", server.url());
+    loop {
+        std::thread::park();
+    }
+}
@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Blocking client for the registry's HTTP API, with one [`RetryPolicy`] for every
+//! consumer instead of a hand-rolled retry loop each.
+//!
+//! ```no_run
+//! use id_registry_client::{Client, GenerateRequest};
+//!
+//! let client = Client::new("http://127.0.0.1:8000");
+//! let issued = client.generate(&GenerateRequest::new("person_app").external_ref("order-4711"))?;
+//! client.confirm(&issued.id, Some("billing"), Some("person_app"))?;
+//! # Ok::<(), id_registry_client::Error>(())
+//! ```
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod retry;
+pub mod sync;
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+pub use id_registry_core::errors::{ApiError, ErrorCode, FieldError};
+pub use retry::RetryPolicy;
+
+/// Why a request failed, after any retries.
+#[derive(Debug)]
+pub enum Error {
+    /// The server answered with an error status
+    Api {
+        status: u16,
+        /// The `error` code, e.g. `ErrorCode::IdNotFound`
+        code: ErrorCode,
+        message: String,
+        details: Option<String>,
+        /// Each rejected field, for `ErrorCode::ValidationFailed`
+        errors: Vec<FieldError>,
+        retry_after: Option<Duration>,
+    },
+    /// No answer: the connection failed or timed out
+    Transport(String),
+    /// An answer that isn't what the API sends
+    Decode(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Api { status, code, message, details: Some(details), .. } => write!(f, "{} {}: {} ({})", status, code_name(*code), message, details),
+            Error::Api { status, code, message, .. } => write!(f, "{} {}: {}", status, code_name(*code), message),
+            Error::Transport(message) => write!(f, "Request failed: {}", message),
+            Error::Decode(message) => write!(f, "Unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// The code as the server sends it, e.g. id_not_found
+fn code_name(code: ErrorCode) -> String {
+    serde_json::to_value(code).ok().and_then(|name| name.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// Body of `POST /ids`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenerateRequest {
+    pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    /// The caller's key for the record; asking again with it returns the same ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ref: Option<String>,
+}
+
+impl GenerateRequest {
+    pub fn new(owner: impl Into<String>) -> Self {
+        GenerateRequest { owner: owner.into(), ..GenerateRequest::default() }
+    }
+
+    pub fn table(mut self, table: impl Into<String>) -> Self {
+        self.table = Some(table.into());
+        self
+    }
+
+    pub fn suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = Some(suffix.into());
+        self
+    }
+
+    pub fn external_ref(mut self, external_ref: impl Into<String>) -> Self {
+        self.external_ref = Some(external_ref.into());
+        self
+    }
+}
+
+/// An issued ID as `POST /ids` and `GET /ids/<id>` return it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdDetails {
+    pub id: String,
+    pub owner: String,
+    #[serde(default)]
+    pub table: Option<String>,
+    pub confirmed: i32,
+    pub created_at: String,
+    pub version: i64,
+    #[serde(default)]
+    pub confirmed_at: Option<String>,
+    #[serde(default)]
+    pub confirmed_by: Option<String>,
+    #[serde(default)]
+    pub external_ref: Option<String>,
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub display_id: Option<String>,
+}
+
+/// Answer of `POST /ids/<id>/confirm`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Confirmation {
+    pub success: bool,
+    pub message: String,
+    /// `confirmed`, or `already_confirmed` when an earlier try got through
+    pub outcome: String,
+}
+
+/// One entry of `GET /changes`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Change {
+    pub seq: i64,
+    pub id: String,
+    /// `created`, `confirmed`, `updated`, `deleted` or `removed`
+    pub op: String,
+    pub version: i64,
+    pub at: String,
+    /// The ID as it is now, which may be newer than this change; None once removed
+    #[serde(default)]
+    pub details: Option<IdDetails>,
+}
+
+/// A page of `GET /changes`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangePage {
+    pub changes: Vec<Change>,
+    /// Pass as `since` to continue
+    pub next: i64,
+    /// The page was full, so more changes may be waiting
+    pub more: bool,
+}
+
+/// A page of `GET /owners/<owner>/ids`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdPage {
+    pub ids: Vec<IdDetails>,
+    /// Pass as `after` for the next page; None on the last one
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+pub struct Client {
+    base_url: String,
+    agent: ureq::Agent,
+    retry: RetryPolicy,
+    owner_key: Option<String>,
+}
+
+impl Client {
+    /// A client for the server at `base_url`, e.g. `http://127.0.0.1:8000`, with the
+    /// default [`RetryPolicy`] and a 10 s timeout per try.
+    pub fn new(base_url: &str) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+        Client { base_url: base_url.trim_end_matches('/').to_string(), agent, retry: RetryPolicy::default(), owner_key: None }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends `key` as `X-Owner-Key` with every confirmation, for an owner that has one
+    /// (see `POST /admin/owners/<owner>/key`).
+    pub fn with_owner_key(mut self, key: impl Into<String>) -> Self {
+        self.owner_key = Some(key.into());
+        self
+    }
+
+    /// Sends the requests through `agent`, e.g. for other timeouts or a proxy.
+    pub fn with_agent(mut self, agent: ureq::Agent) -> Self {
+        self.agent = agent;
+        self
+    }
+
+    /// `POST /ids`. Lost answers are only retried with an `external_ref`, or with
+    /// [`RetryPolicy::idempotency_keys`], which adds one.
+    pub fn generate(&self, request: &GenerateRequest) -> Result<IdDetails, Error> {
+        let mut request = request.clone();
+        if request.external_ref.is_none() && self.retry.idempotency_keys {
+            request.external_ref = Some(retry::idempotency_key());
+        }
+        let body = serde_json::to_string(&request).map_err(|e| Error::Decode(e.to_string()))?;
+        let idempotent = request.external_ref.is_some();
+        self.send(idempotent, Some(&body), || self.agent.post(&self.url("/ids")).set("Content-Type", "application/json"))
+    }
+
+    /// `GET /ids/<id>`.
+    pub fn get_id(&self, id: &str) -> Result<IdDetails, Error> {
+        self.send(true, None, || self.agent.get(&self.url(&format!("/ids/{}", encode(id)))))
+    }
+
+    /// `POST /ids/<id>/confirm?by=&owner=`. With `owner`, an ID of another owner is
+    /// refused with `ErrorCode::WrongOwner`. Confirming twice is harmless, so every
+    /// failure the policy allows is retried.
+    pub fn confirm(&self, id: &str, by: Option<&str>, owner: Option<&str>) -> Result<Confirmation, Error> {
+        self.send(true, None, || {
+            let mut request = self.agent.post(&self.url(&format!("/ids/{}/confirm", encode(id))));
+            if let Some(by) = by {
+                request = request.query("by", by);
+            }
+            if let Some(owner) = owner {
+                request = request.query("owner", owner);
+            }
+            match &self.owner_key {
+                Some(key) => request.set("X-Owner-Key", key),
+                None => request,
+            }
+        })
+    }
+
+    /// `GET /changes?since=&limit=`: up to `limit` changes after `since`, oldest first. A
+    /// `since` older than the server keeps fails with `ErrorCode::CursorExpired`.
+    pub fn changes(&self, since: i64, limit: usize) -> Result<ChangePage, Error> {
+        self.send(true, None, || {
+            self.agent.get(&self.url("/changes")).query("since", &since.to_string()).query("limit", &limit.to_string())
+        })
+    }
+
+    /// `GET /owners/<owner>/ids?after=&limit=1000`: a page of the owner's live IDs.
+    pub fn owner_ids(&self, owner: &str, after: Option<&str>) -> Result<IdPage, Error> {
+        self.send(true, None, || {
+            let request = self.agent.get(&self.url(&format!("/owners/{}/ids", encode(owner)))).query("limit", "1000");
+            match after {
+                Some(after) => request.query("after", after),
+                None => request,
+            }
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // Sends the request `build` makes, with `body` if given, until it succeeds or the
+    // policy gives up; `idempotent` ones may also be retried after transport errors
+    fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        idempotent: bool,
+        body: Option<&str>,
+        build: impl Fn() -> ureq::Request,
+    ) -> Result<T, Error> {
+        let mut attempt = 1;
+        loop {
+            let result = match body {
+                Some(body) => build().send_string(body),
+                None => build().call(),
+            };
+            let error = match result {
+                Ok(response) => return read_json(response),
+                Err(ureq::Error::Status(status, response)) => api_error(status, response),
+                Err(ureq::Error::Transport(transport)) => Error::Transport(transport.to_string()),
+            };
+            let retryable = match &error {
+                Error::Api { status, .. } => RetryPolicy::retryable_status(*status),
+                Error::Transport(_) => idempotent && self.retry.retry_transport_errors,
+                Error::Decode(_) => false,
+            };
+            let retry_after = match &error {
+                Error::Api { retry_after, .. } => *retry_after,
+                _ => None,
+            };
+            match self.retry.delay(attempt, retry_after).filter(|_| retryable) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(error),
+            }
+            attempt += 1;
+        }
+    }
+}
+
+fn api_error(status: u16, response: ureq::Response) -> Error {
+    let retry_after = response.header("Retry-After").and_then(|value| retry_after(value, SystemTime::now()));
+    match read_json::<ApiError>(response) {
+        Ok(body) => Error::Api { status, code: body.error, message: body.message, details: body.details, errors: body.errors, retry_after },
+        Err(_) => Error::Api {
+            status,
+            code: ErrorCode::Unknown,
+            message: format!("HTTP {}", status),
+            details: None,
+            errors: Vec::new(),
+            retry_after,
+        },
+    }
+}
+
+// Retry-After as seconds or as an HTTP date, which counts from `now`; a date already
+// past means no wait
+fn retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(response: ureq::Response) -> Result<T, Error> {
+    let body = response.into_string().map_err(|e| Error::Transport(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| Error::Decode(e.to_string()))
+}
+
+// Percent-encodes an ID for a path segment
+fn encode(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_takes_seconds_and_dates() {
+        let now = httpdate::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(retry_after(" 120 ", now), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after("Sun, 06 Nov 1994 08:50:07 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after("Sun, 06 Nov 1994 08:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,828 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `id-registry-mock`: an in-memory stand-in for the server, so consumers can run
+//! integration tests without a SQLite-backed instance. It answers the routes most
+//! consumers use with the server's JSON and error bodies: `POST /ids` (JSON, with
+//! `external_ref` returning the same ID again), `POST /ids/batch`, `POST /ids/lookup`,
+//! `GET /ids/<id>`, `PUT /ids/<id>` (with `If-Match`), `POST /ids/<id>/confirm?by=&owner=`,
+//! `DELETE /ids/<id>`, `GET /owners/<owner>/ids`, `GET /changes` and `GET /health`.
+//! Everything else is a 404 `not_found`. Latency and failures can be injected, per
+//! request at random or for the next few requests.
+use crate::{ApiError, ErrorCode, FieldError};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// An injected failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Refused with this status before doing anything, as the server refuses
+    /// (`Retry-After` included for 429 and 503)
+    Status(u16),
+    /// Done, then the connection closed without an answer, like a timeout after the
+    /// server issued the ID
+    LostAnswer,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    /// Added to every answer
+    pub latency: Duration,
+    /// Up to this much more, at random
+    pub jitter: Duration,
+    /// Share of requests (0.0-1.0) that get `failure`
+    pub failure_rate: f64,
+    pub failure: Failure,
+    /// `Retry-After` sent with injected 429s and 503s
+    pub retry_after_secs: u64,
+    pub id_length: usize,
+    pub charset: String,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            failure_rate: 0.0,
+            failure: Failure::Status(503),
+            retry_after_secs: 1,
+            id_length: 12,
+            charset: "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".to_string(),
+        }
+    }
+}
+
+// One issued ID, as IdDetails shows it
+#[derive(Debug, Clone, Serialize)]
+struct Record {
+    id: String,
+    owner: String,
+    table: Option<String>,
+    confirmed: i32,
+    created_at: String,
+    version: i64,
+    confirmed_at: Option<String>,
+    confirmed_by: Option<String>,
+    external_ref: Option<String>,
+    #[serde(skip)]
+    deleted: bool,
+}
+
+// The IDs and their change feed, under one lock so that every change is logged with it
+#[derive(Default)]
+struct Store {
+    ids: BTreeMap<String, Record>,
+    // Entries of GET /changes without the details, which are read when served
+    changes: Vec<Value>,
+}
+
+impl Store {
+    // Logs `op` on `id` as GET /changes reports it
+    fn log(&mut self, id: &str, op: &str) {
+        let version = self.ids.get(id).map_or(0, |record| record.version);
+        let seq = self.changes.len() + 1;
+        self.changes.push(json!({ "seq": seq, "id": id, "op": op, "version": version, "at": now() }));
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    store: Mutex<Store>,
+    queued: Mutex<VecDeque<Failure>>,
+    requests: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+/// A running mock; it stops when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    config: Arc<RwLock<MockConfig>>,
+    shared: Arc<Shared>,
+}
+
+impl MockServer {
+    /// Starts on a free loopback port; see [`Self::url`].
+    pub fn start(config: MockConfig) -> io::Result<Self> {
+        Self::bind("127.0.0.1:0", config)
+    }
+
+    pub fn bind(addr: impl ToSocketAddrs, config: MockConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let server = MockServer {
+            addr: listener.local_addr()?,
+            config: Arc::new(RwLock::new(config)),
+            shared: Arc::new(Shared::default()),
+        };
+
+        let (config, shared) = (server.config.clone(), server.shared.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if shared.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let (config, shared) = (config.clone(), shared.clone());
+                std::thread::spawn(move || {
+                    let _ = serve(stream, &config, &shared);
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    /// Base URL for a client, e.g. `http://127.0.0.1:49152`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Changes latency and failure injection for the requests that follow.
+    pub fn configure(&self, update: impl FnOnce(&mut MockConfig)) {
+        update(&mut self.config.write().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// The next `count` requests get `failure`, before any random failures.
+    pub fn fail_next(&self, count: usize, failure: Failure) {
+        self.shared.queued.lock().unwrap_or_else(|e| e.into_inner()).extend(std::iter::repeat_n(failure, count));
+    }
+
+    /// Requests received so far, failed ones included.
+    pub fn requests(&self) -> usize {
+        self.shared.requests.load(Ordering::SeqCst)
+    }
+
+    /// IDs issued so far, deleted ones included.
+    pub fn issued(&self) -> usize {
+        self.shared.store.lock().unwrap_or_else(|e| e.into_inner()).ids.len()
+    }
+
+    /// Forgets every ID and change.
+    pub fn reset(&self) {
+        *self.shared.store.lock().unwrap_or_else(|e| e.into_inner()) = Store::default();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        // Wakes the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    // Names in lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+struct Answer {
+    status: u16,
+    body: Value,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Answer {
+    fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+}
+
+fn answer(status: u16, body: Value) -> Answer {
+    Answer { status, body, headers: Vec::new() }
+}
+
+// A record with the ETag PUT /ids/<id> expects back in If-Match
+fn tagged(record: &Record) -> Answer {
+    answer(200, json!(record)).header("ETag", format!("\"{}\"", record.version))
+}
+
+fn error(status: u16, code: ErrorCode, message: &str, details: Option<&str>) -> Answer {
+    let body = ApiError { error: code, message: message.to_string(), details: details.map(str::to_string), errors: Vec::new() };
+    answer(status, json!(body))
+}
+
+fn serve(stream: TcpStream, config: &RwLock<MockConfig>, shared: &Shared) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let Some(request) = read_request(&mut reader)? else { return Ok(()) };
+    if shared.stopped.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    shared.requests.fetch_add(1, Ordering::SeqCst);
+
+    let config = config.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut rng = rand::thread_rng();
+    let delay = config.latency + config.jitter.mul_f64(rng.gen_range(0.0..=1.0));
+    if !delay.is_zero() {
+        std::thread::sleep(delay);
+    }
+
+    let queued = shared.queued.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+    let failure = queued.or_else(|| (config.failure_rate > 0.0 && rng.gen_bool(config.failure_rate.min(1.0))).then_some(config.failure));
+    let answer = match failure {
+        Some(Failure::Status(status)) => injected(status, config.retry_after_secs),
+        Some(Failure::LostAnswer) => {
+            route(&request, &config, shared);
+            return Ok(());
+        }
+        None => route(&request, &config, shared),
+    };
+    write_answer(stream, &answer)
+}
+
+fn injected(status: u16, retry_after_secs: u64) -> Answer {
+    let (code, message) = match status {
+        429 => (ErrorCode::RateLimited, "Too many requests"),
+        503 => (ErrorCode::Suspended, "Server is temporarily suspended for maintenance"),
+        _ => (ErrorCode::InternalError, "Injected failure"),
+    };
+    let answer = error(status, code, message, None);
+    if matches!(status, 429 | 503) {
+        return answer.header("Retry-After", retry_after_secs);
+    }
+    answer
+}
+
+fn route(request: &Request, config: &MockConfig, shared: &Shared) -> Answer {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let param = |name: &str| request.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+    let number = |name: &str| param(name).and_then(|value| value.parse::<usize>().ok());
+    let mut store = shared.store.lock().unwrap_or_else(|e| e.into_inner());
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["health"]) => answer(200, json!({ "status": "ok" })),
+        ("POST", ["ids"]) => generate(&request.body, config, &mut store),
+        ("POST", ["ids", "batch"]) => generate_batch(&request.body, config, &mut store),
+        ("POST", ["ids", "lookup"]) => lookup(&request.body, &store.ids),
+        ("GET", ["changes"]) => changes(&store, number("since").unwrap_or(0), number("limit")),
+        ("GET", ["owners", owner, "ids"]) => owner_ids(&store.ids, owner, param("after"), number("limit")),
+        ("GET", ["ids", id]) => match store.ids.get(*id) {
+            Some(record) if !record.deleted => tagged(record),
+            _ => error(404, ErrorCode::IdNotFound, "ID not found", Some(id)),
+        },
+        ("PUT", ["ids", id]) => update(id, request, &mut store),
+        ("POST", ["ids", id, "confirm"]) => {
+            let Some(record) = store.ids.get_mut(*id) else {
+                return error(404, ErrorCode::IdNotFound, "ID not found", Some(id));
+            };
+            if param("owner").is_some_and(|owner| owner != record.owner) {
+                return error(403, ErrorCode::WrongOwner, "ID belongs to another owner", Some(id));
+            }
+            if record.deleted {
+                return error(410, ErrorCode::IdDeleted, "ID has been deleted", Some(id));
+            }
+            if record.confirmed != 0 {
+                let message = format!("ID {} was already confirmed", id);
+                return answer(200, json!({ "success": true, "message": message, "outcome": "already_confirmed" }));
+            }
+            record.confirmed = 1;
+            record.confirmed_at = Some(now());
+            record.confirmed_by = param("by").map(str::to_string);
+            record.version += 1;
+            store.log(id, "confirmed");
+            answer(200, json!({ "success": true, "message": format!("ID {} confirmed", id), "outcome": "confirmed" }))
+        }
+        ("DELETE", ["ids", id]) => match store.ids.get_mut(*id) {
+            None => error(404, ErrorCode::IdNotFound, "ID not found", Some(id)),
+            Some(record) if record.deleted => error(410, ErrorCode::IdDeleted, "ID has been deleted", Some(id)),
+            Some(record) => {
+                record.deleted = true;
+                record.confirmed = 0;
+                record.version += 1;
+                store.log(id, "deleted");
+                answer(200, json!({ "success": true, "message": format!("ID {} deleted", id) }))
+            }
+        },
+        _ => error(404, ErrorCode::NotFound, "Resource not found", None),
+    }
+}
+
+// The body as a JSON object, or the server's 422 for anything else
+fn json_object(body: &[u8]) -> Result<Map<String, Value>, Answer> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(body)) => Ok(body),
+        _ => {
+            let errors = vec![FieldError { field: "body".to_string(), message: "expected a JSON object".to_string() }];
+            let body = ApiError { error: ErrorCode::ValidationFailed, message: "Request body failed validation".to_string(), details: None, errors };
+            Err(answer(422, json!(body)))
+        }
+    }
+}
+
+fn text(body: &Map<String, Value>, name: &str) -> Option<String> {
+    body.get(name).and_then(Value::as_str).map(str::to_string)
+}
+
+fn owner_of(body: &Map<String, Value>) -> Result<String, Answer> {
+    let owner = text(body, "owner").unwrap_or_default();
+    if owner.trim().is_empty() {
+        return Err(error(400, ErrorCode::InvalidOwner, "Invalid owner", Some("must not be empty")));
+    }
+    Ok(owner.trim().to_string())
+}
+
+fn generate(body: &[u8], config: &MockConfig, store: &mut Store) -> Answer {
+    let body = match json_object(body) {
+        Ok(body) => body,
+        Err(answer) => return answer,
+    };
+    let owner = match owner_of(&body) {
+        Ok(owner) => owner,
+        Err(answer) => return answer,
+    };
+    let external_ref = text(&body, "external_ref");
+
+    // Asking again with the same external_ref returns the same ID; external_refs are
+    // the owner's own, so another owner's gets an ID of its own
+    if let Some(external_ref) = &external_ref
+        && let Some(record) = store.ids.values().find(|record| record.owner == owner && record.external_ref.as_ref() == Some(external_ref))
+    {
+        if record.deleted {
+            return error(410, ErrorCode::IdDeleted, "ID has been deleted", Some(&record.id));
+        }
+        return answer(200, json!(record));
+    }
+
+    let suffix = text(&body, "suffix");
+    if let Some(suffix) = &suffix
+        && let Err(reason) = check_suffix(config, suffix)
+    {
+        return error(400, ErrorCode::BadRequest, "Invalid suffix", Some(&format!("Suffix '{}' {}", suffix, reason)));
+    }
+    match issue(config, store, &owner, text(&body, "table"), suffix.as_deref(), external_ref) {
+        Ok(record) => answer(200, json!(record)),
+        Err(answer) => answer,
+    }
+}
+
+// POST /ids/batch {"owner": ..., "table": ..., "count": N}
+fn generate_batch(body: &[u8], config: &MockConfig, store: &mut Store) -> Answer {
+    let body = match json_object(body) {
+        Ok(body) => body,
+        Err(answer) => return answer,
+    };
+    let owner = match owner_of(&body) {
+        Ok(owner) => owner,
+        Err(answer) => return answer,
+    };
+    let count = body.get("count").and_then(Value::as_u64).unwrap_or(0) as usize;
+    if count == 0 {
+        return error(400, ErrorCode::BadRequest, "Count must be at least 1", None);
+    }
+    if count > MAX_BATCH {
+        return error(400, ErrorCode::BadRequest, "Too many IDs in one request", Some(&format!("at most {} per request", MAX_BATCH)));
+    }
+
+    // All or nothing, as the server issues a batch in one transaction
+    let mut issued = BTreeMap::new();
+    for _ in 0..count {
+        let taken = |id: &str| store.ids.contains_key(id) || issued.contains_key(id);
+        let Some(id) = unused_id(config, "", taken) else {
+            return exhausted();
+        };
+        let record = new_record(&id, &owner, text(&body, "table"), None);
+        issued.insert(id, record);
+    }
+    let records: Vec<Value> = issued.values().map(|record| json!(record)).collect();
+    let ids: Vec<String> = issued.keys().cloned().collect();
+    store.ids.extend(issued);
+    ids.iter().for_each(|id| store.log(id, "created"));
+    answer(200, Value::Array(records))
+}
+
+// POST /ids/lookup {"ids": [...]}: one entry per ID, in request order
+fn lookup(body: &[u8], ids: &BTreeMap<String, Record>) -> Answer {
+    let body = match json_object(body) {
+        Ok(body) => body,
+        Err(answer) => return answer,
+    };
+    let wanted: Vec<&str> = body.get("ids").and_then(Value::as_array).map(|ids| ids.iter().filter_map(Value::as_str).collect()).unwrap_or_default();
+    if wanted.len() > MAX_BATCH {
+        return error(400, ErrorCode::BadRequest, "Too many IDs in one lookup", Some(&format!("at most {} per request", MAX_BATCH)));
+    }
+    let results: Vec<Value> = wanted
+        .into_iter()
+        .map(|id| match ids.get(id).filter(|record| !record.deleted) {
+            Some(record) => json!({ "id": id, "found": true, "details": record }),
+            None => json!({ "id": id, "found": false }),
+        })
+        .collect();
+    answer(200, Value::Array(results))
+}
+
+// PUT /ids/<id> {"owner": ..., "table": ...} with If-Match carrying the version, or *
+fn update(id: &str, request: &Request, store: &mut Store) -> Answer {
+    let Some(tag) = request.header("if-match") else {
+        return error(428, ErrorCode::PreconditionRequired, "If-Match header is required", Some("send the ETag from GET /ids/<id>"));
+    };
+    let expected = match tag.trim() {
+        "*" => None,
+        tag => match tag.trim_start_matches("W/").trim_matches('"').parse::<i64>() {
+            Ok(version) => Some(version),
+            Err(_) => return error(412, ErrorCode::VersionConflict, "If-Match does not name a version", Some(tag)),
+        },
+    };
+    let body = match json_object(&request.body) {
+        Ok(body) => body,
+        Err(answer) => return answer,
+    };
+    let owner = match body.get("owner") {
+        Some(_) => match owner_of(&body) {
+            Ok(owner) => Some(owner),
+            Err(answer) => return answer,
+        },
+        None => None,
+    };
+
+    let current = match store.ids.get(id) {
+        None => return error(404, ErrorCode::IdNotFound, "ID not found", Some(id)),
+        Some(record) if record.deleted => return error(410, ErrorCode::IdDeleted, "ID has been deleted", Some(id)),
+        Some(record) => record,
+    };
+    if expected.is_some_and(|expected| current.version != expected) {
+        let details = format!("current version is {}; fetch it again and retry", current.version);
+        return error(412, ErrorCode::VersionConflict, "ID was changed by someone else", Some(&details)).header("ETag", format!("\"{}\"", current.version));
+    }
+    // external_refs are unique per owner, so the new owner must not use this one yet
+    if let Some(owner) = &owner
+        && let Some(external_ref) = &current.external_ref
+        && store.ids.values().any(|other| other.id != id && &other.owner == owner && other.external_ref.as_ref() == Some(external_ref))
+    {
+        return error(409, ErrorCode::IdConflict, "The owner already has an ID with this external_ref", None);
+    }
+
+    let Some(record) = store.ids.get_mut(id) else {
+        return error(404, ErrorCode::IdNotFound, "ID not found", Some(id));
+    };
+    if let Some(owner) = owner {
+        record.owner = owner;
+    }
+    if let Some(table) = body.get("table") {
+        record.table = table.as_str().map(str::to_string);
+    }
+    record.version += 1;
+    let answer = tagged(record);
+    store.log(id, "updated");
+    answer
+}
+
+// GET /changes?since=&limit=: the feed after `since`, each entry with the ID as it is now
+fn changes(store: &Store, since: usize, limit: Option<usize>) -> Answer {
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let page: Vec<Value> = store
+        .changes
+        .iter()
+        .skip(since)
+        .take(limit)
+        .map(|change| {
+            let mut entry = change.clone();
+            entry["details"] = change["id"].as_str().and_then(|id| store.ids.get(id)).map_or(Value::Null, |record| json!(record));
+            entry
+        })
+        .collect();
+    let next = page.last().and_then(|entry| entry["seq"].as_u64()).unwrap_or(since as u64);
+    let more = store.changes.len() > since + limit;
+    answer(200, json!({ "changes": page, "next": next, "more": more }))
+}
+
+// GET /owners/<owner>/ids?after=&limit=: the owner's live IDs in ID order, a page at a time
+fn owner_ids(ids: &BTreeMap<String, Record>, owner: &str, after: Option<&str>, limit: Option<usize>) -> Answer {
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let mut page: Vec<&Record> = ids
+        .values()
+        .filter(|record| record.owner == owner && !record.deleted && after.is_none_or(|after| record.id.as_str() > after))
+        .take(limit + 1)
+        .collect();
+    let next = (page.len() > limit).then(|| {
+        page.truncate(limit);
+        page[limit - 1].id.clone()
+    });
+    let mut body = json!({ "ids": page });
+    if let Some(next) = next {
+        body["next"] = json!(next);
+    }
+    answer(200, body)
+}
+
+// Most IDs a batch generation or lookup takes, the server's default lookup_max_ids
+const MAX_BATCH: usize = 1000;
+
+// Candidates tried before giving up, so a full keyspace answers instead of spinning
+const MAX_ATTEMPTS: usize = 1000;
+
+// As check_suffix on the server: characters from the charset, at most half of id_length
+fn check_suffix(config: &MockConfig, suffix: &str) -> Result<(), String> {
+    if suffix.is_empty() {
+        return Err("is empty".to_string());
+    }
+    if let Some(c) = suffix.chars().find(|c| !config.charset.contains(*c)) {
+        return Err(format!("contains '{}', which is not in the charset", c));
+    }
+    let max = config.id_length / 2;
+    if suffix.chars().count() > max {
+        return Err(format!("is longer than {} characters (half of id_length)", max));
+    }
+    Ok(())
+}
+
+// A random ID ending in `suffix` that isn't `taken`, or None after MAX_ATTEMPTS tries
+fn unused_id(config: &MockConfig, suffix: &str, taken: impl Fn(&str) -> bool) -> Option<String> {
+    let chars: Vec<char> = config.charset.chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+    let random_length = config.id_length.saturating_sub(suffix.chars().count());
+    let mut rng = rand::thread_rng();
+    (0..MAX_ATTEMPTS).find_map(|_| {
+        let mut id: String = (0..random_length).map(|_| chars[rng.gen_range(0..chars.len())]).collect();
+        id.push_str(suffix);
+        (!taken(&id)).then_some(id)
+    })
+}
+
+fn exhausted() -> Answer {
+    error(503, ErrorCode::KeyspaceExhausted, "Could not find an unused ID", Some("raise id_length or add charset characters"))
+}
+
+fn issue(
+    config: &MockConfig,
+    store: &mut Store,
+    owner: &str,
+    table: Option<String>,
+    suffix: Option<&str>,
+    external_ref: Option<String>,
+) -> Result<Record, Answer> {
+    let id = unused_id(config, suffix.unwrap_or_default(), |id| store.ids.contains_key(id)).ok_or_else(exhausted)?;
+    let record = new_record(&id, owner, table, external_ref);
+    store.ids.insert(id.clone(), record.clone());
+    store.log(&id, "created");
+    Ok(record)
+}
+
+fn new_record(id: &str, owner: &str, table: Option<String>, external_ref: Option<String>) -> Record {
+    Record {
+        id: id.to_string(),
+        owner: owner.to_string(),
+        table,
+        confirmed: 0,
+        created_at: now(),
+        version: 1,
+        confirmed_at: None,
+        confirmed_by: None,
+        external_ref,
+        deleted: false,
+    }
+}
+
+// The current UTC time as the server stores it (SQLite's CURRENT_TIMESTAMP),
+// e.g. 2024-07-01 09:30:00
+fn now() -> String {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    timestamp(secs)
+}
+
+// Seconds since the epoch as YYYY-MM-DD HH:MM:SS, by the days-to-civil algorithm
+// (http://howardhinnant.github.io/date_algorithms.html)
+fn timestamp(secs: u64) -> String {
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else { return Ok(None) };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = headers.iter().find(|(name, _)| name == "content-length").and_then(|(_, value)| value.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(&key.replace('+', " ")), decode(&value.replace('+', " ")))
+        })
+        .collect();
+    let path = path.split('/').map(decode).collect::<Vec<_>>().join("/");
+    Ok(Some(Request { method: method.to_string(), path, query, headers, body }))
+}
+
+// Percent-decodes a path segment or query part
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn write_answer(mut stream: TcpStream, answer: &Answer) -> io::Result<()> {
+    let body = answer.body.to_string();
+    let reason = match answer.status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        410 => "Gone",
+        412 => "Precondition Failed",
+        422 => "Unprocessable Entity",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        answer.status,
+        reason,
+        body.len()
+    );
+    for (name, value) in &answer.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Client, Error, GenerateRequest, RetryPolicy};
+
+    fn start(config: MockConfig) -> (MockServer, Client) {
+        let server = MockServer::start(config).unwrap();
+        let client = Client::new(&server.url()).with_retry(RetryPolicy::none());
+        (server, client)
+    }
+
+    fn code(result: Result<impl std::fmt::Debug, Error>) -> (u16, ErrorCode) {
+        match result {
+            Err(Error::Api { status, code, .. }) => (status, code),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+    }
+
+    fn put(server: &MockServer, id: &str, if_match: Option<&str>, body: Value) -> Result<ureq::Response, u16> {
+        let request = ureq::put(&format!("{}/ids/{}", server.url(), id));
+        let request = match if_match {
+            Some(tag) => request.set("If-Match", tag),
+            None => request,
+        };
+        request.send_string(&body.to_string()).map_err(|e| match e {
+            ureq::Error::Status(status, _) => status,
+            ureq::Error::Transport(e) => panic!("{}", e),
+        })
+    }
+
+    fn post(server: &MockServer, path: &str, body: Value) -> Value {
+        let response = ureq::post(&format!("{}{}", server.url(), path)).send_string(&body.to_string()).unwrap();
+        serde_json::from_str(&response.into_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn timestamps_match_the_server_format() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(timestamp(951_782_400 + 3_723), "2000-02-29 01:02:03");
+        assert_eq!(timestamp(1_719_826_200), "2024-07-01 09:30:00");
+    }
+
+    #[test]
+    fn issues_and_reads_back() {
+        let (_server, client) = start(MockConfig::default());
+        let issued = client.generate(&GenerateRequest::new("billing").table("invoices")).unwrap();
+        assert_eq!(issued.id.chars().count(), 12);
+        assert_eq!(issued.created_at.len(), "2024-07-01 09:30:00".len());
+
+        let read = client.get_id(&issued.id).unwrap();
+        assert_eq!((read.owner.as_str(), read.table.as_deref(), read.version), ("billing", Some("invoices"), 1));
+        assert_eq!(code(client.get_id("missing")), (404, ErrorCode::IdNotFound));
+    }
+
+    #[test]
+    fn external_refs_are_per_owner() {
+        let (server, client) = start(MockConfig::default());
+        let first = client.generate(&GenerateRequest::new("billing").external_ref("order-1")).unwrap();
+        let again = client.generate(&GenerateRequest::new("billing").external_ref("order-1")).unwrap();
+        let other = client.generate(&GenerateRequest::new("shipping").external_ref("order-1")).unwrap();
+        assert_eq!(first.id, again.id);
+        assert_ne!(first.id, other.id);
+        assert_eq!(server.issued(), 2);
+    }
+
+    #[test]
+    fn refuses_bad_suffixes_and_a_full_keyspace() {
+        let (_server, client) = start(MockConfig { id_length: 2, charset: "ab".to_string(), ..MockConfig::default() });
+        assert_eq!(code(client.generate(&GenerateRequest::new("billing").suffix("ab"))), (400, ErrorCode::BadRequest));
+        assert_eq!(code(client.generate(&GenerateRequest::new("billing").suffix("x"))), (400, ErrorCode::BadRequest));
+
+        for _ in 0..4 {
+            client.generate(&GenerateRequest::new("billing")).unwrap();
+        }
+        assert_eq!(code(client.generate(&GenerateRequest::new("billing"))), (503, ErrorCode::KeyspaceExhausted));
+    }
+
+    #[test]
+    fn updates_need_the_current_version() {
+        let (server, client) = start(MockConfig::default());
+        let first = client.generate(&GenerateRequest::new("billing").external_ref("order-1")).unwrap();
+        client.generate(&GenerateRequest::new("shipping").external_ref("order-1")).unwrap();
+
+        assert_eq!(put(&server, &first.id, None, json!({ "table": "t" })).unwrap_err(), 428);
+        assert_eq!(put(&server, &first.id, Some("\"2\""), json!({ "table": "t" })).unwrap_err(), 412);
+        assert_eq!(put(&server, &first.id, Some("\"1\""), json!({ "owner": "shipping" })).unwrap_err(), 409);
+
+        let updated = put(&server, &first.id, Some("\"1\""), json!({ "table": "t" })).unwrap();
+        assert_eq!(updated.header("ETag"), Some("\"2\""));
+        assert_eq!(client.get_id(&first.id).unwrap().table.as_deref(), Some("t"));
+
+        let updated = put(&server, &first.id, Some("*"), json!({ "table": "u" })).unwrap();
+        assert_eq!(updated.header("ETag"), Some("\"3\""));
+    }
+
+    #[test]
+    fn batches_and_lookups() {
+        let (server, client) = start(MockConfig::default());
+        let issued = post(&server, "/ids/batch", json!({ "owner": "billing", "count": 3 }));
+        let issued = issued.as_array().unwrap();
+        assert_eq!(issued.len(), 3);
+
+        let id = issued[0]["id"].as_str().unwrap();
+        let found = post(&server, "/ids/lookup", json!({ "ids": [id, "missing"] }));
+        assert_eq!(found[0]["found"], json!(true));
+        assert_eq!(found[1], json!({ "id": "missing", "found": false }));
+        assert_eq!(code(client.confirm(id, None, Some("shipping"))), (403, ErrorCode::WrongOwner));
+        client.confirm(id, Some("billing"), Some("billing")).unwrap();
+        assert_eq!(client.get_id(id).unwrap().confirmed, 1);
+    }
+
+    #[test]
+    fn injected_failures_are_retried() {
+        let server = MockServer::start(MockConfig { retry_after_secs: 0, ..MockConfig::default() }).unwrap();
+        let client = Client::new(&server.url());
+        let issued = client.generate(&GenerateRequest::new("billing")).unwrap();
+
+        server.fail_next(2, Failure::Status(503));
+        let confirmation = client.confirm(&issued.id, None, None).unwrap();
+        assert_eq!(confirmation.outcome, "confirmed");
+        assert_eq!(server.requests(), 4);
+    }
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! When and how long [`Client`](crate::Client) waits before sending a request again.
+//! 429 and 503 mean the server refused the request without acting on it, so they are
+//! always safe to retry, after `Retry-After` when the server sends one. A failed
+//! connection or a timeout is not: the server may have issued an ID before the answer
+//! got lost. Those are only retried for requests that can't issue twice, which for
+//! `POST /ids` means one carrying an `external_ref`.
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Tries per request, the first included; 1 never retries
+    pub max_attempts: u32,
+    /// Backoff before the first retry without `Retry-After`; doubles with each retry
+    pub base_delay: Duration,
+    /// Longest backoff between two tries
+    pub max_delay: Duration,
+    /// A `Retry-After` longer than this is returned as the error instead of waited out
+    pub max_retry_after: Duration,
+    /// Retry failed connections and timeouts of requests that can't issue twice
+    pub retry_transport_errors: bool,
+    /// Give `POST /ids` requests without an `external_ref` a random one, so that their
+    /// timeouts can be retried too. The key is stored as the ID's `external_ref`.
+    pub idempotency_keys: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            max_retry_after: Duration::from_secs(30),
+            retry_transport_errors: true,
+            idempotency_keys: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Every request is tried once.
+    pub fn none() -> Self {
+        RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }
+    }
+
+    /// Whether the server refused a request with this status without acting on it.
+    pub fn retryable_status(status: u16) -> bool {
+        matches!(status, 429 | 503)
+    }
+
+    /// How long to wait before try `attempt + 1` after try `attempt` (from 1) failed, or
+    /// None to give up. `retry_after` is the server's `Retry-After`, if it sent one.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_retry_after).then_some(retry_after);
+        }
+        // Full jitter, so clients refused together don't all come back together
+        let ceiling = self.base_delay.saturating_mul(1 << (attempt - 1).min(16)).min(self.max_delay);
+        Some(rand::thread_rng().gen_range(Duration::ZERO..=ceiling))
+    }
+}
+
+/// A random `external_ref` for [`RetryPolicy::idempotency_keys`].
+pub(crate) fn idempotency_key() -> String {
+    let key: String = rand::thread_rng().sample_iter(rand::distributions::Alphanumeric).take(24).map(char::from).collect();
+    format!("retry-{}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_within_the_ceiling() {
+        let policy = RetryPolicy { max_attempts: 10, ..RetryPolicy::default() };
+        for _ in 0..100 {
+            assert!(policy.delay(1, None).unwrap() <= Duration::from_millis(200));
+            assert!(policy.delay(3, None).unwrap() <= Duration::from_millis(800));
+            assert!(policy.delay(9, None).unwrap() <= Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn delay_follows_retry_after_up_to_the_limit() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(30))), Some(Duration::from_secs(30)));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(31))), None);
+    }
+
+    #[test]
+    fn delay_gives_up_after_max_attempts() {
+        let policy = RetryPolicy::default();
+        assert!(policy.delay(3, None).is_some());
+        assert_eq!(policy.delay(4, None), None);
+        assert_eq!(policy.delay(4, Some(Duration::from_secs(1))), None);
+        assert_eq!(RetryPolicy::none().delay(1, None), None);
+    }
+}
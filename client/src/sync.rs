@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! An owner's IDs on a station whose link to the registry comes and goes, such as a
+//! shop-floor terminal. [`OfflineCache`] answers lookups from a local copy, keeps it
+//! current by following `GET /changes`, and queues confirmations made while the
+//! registry can't be reached until the next [`OfflineCache::sync`]. Saved to a file,
+//! the copy and the queue outlive a restart.
+//!
+//! ```no_run
+//! use id_registry_client::{sync::OfflineCache, Client};
+//!
+//! let client = Client::new("http://registry:8000");
+//! let mut cache = OfflineCache::load(client, "station.json")
+//!     .unwrap_or_else(|_| OfflineCache::new(Client::new("http://registry:8000"), "assembly"));
+//! let _ = cache.sync();   // fine to fail while offline
+//! if cache.get("AB3K9XQ2").is_some() {
+//!     cache.confirm("AB3K9XQ2", Some("station-4"))?;
+//! }
+//! cache.save("station.json")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::{Client, Confirmation, Error, ErrorCode, IdDetails, RetryPolicy};
+
+/// A confirmation waiting for the registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedConfirmation {
+    pub id: String,
+    pub by: Option<String>,
+}
+
+/// What [`OfflineCache::confirm`] did.
+#[derive(Debug)]
+pub enum Confirmed {
+    /// The registry confirmed it
+    Now(Confirmation),
+    /// The registry was out of reach; it is sent with the next sync
+    Queued,
+}
+
+/// What one [`OfflineCache::sync`] did.
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    /// Queued confirmations the registry accepted
+    pub sent: usize,
+    /// Queued confirmations the registry refused, e.g. for an ID deleted meanwhile;
+    /// they are dropped from the queue
+    pub rejected: Vec<(QueuedConfirmation, Error)>,
+    /// Feed entries applied to the copy
+    pub changes: usize,
+    /// The copy was loaded afresh: on the first sync, or when the feed had moved past
+    /// the last change seen
+    pub resynced: bool,
+}
+
+// What save writes and load reads
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    owner: String,
+    ids: BTreeMap<String, IdDetails>,
+    // Last feed entry applied; None before the first sync
+    cursor: Option<i64>,
+    queued: Vec<QueuedConfirmation>,
+}
+
+pub struct OfflineCache {
+    client: Client,
+    state: State,
+}
+
+impl OfflineCache {
+    /// An empty copy of `owner`'s IDs; the first [`Self::sync`] fills it.
+    pub fn new(client: Client, owner: impl Into<String>) -> Self {
+        OfflineCache { client, state: State { owner: owner.into(), ..State::default() } }
+    }
+
+    /// The copy and queue [`Self::save`] wrote to `path`.
+    pub fn load(client: Client, path: impl AsRef<Path>) -> io::Result<Self> {
+        let state = serde_json::from_slice(&std::fs::read(path)?).map_err(io::Error::other)?;
+        Ok(OfflineCache { client, state })
+    }
+
+    /// Writes the copy and the queue to `path`, through a temporary file so that a
+    /// crash never leaves half of it behind.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, serde_json::to_vec(&self.state).map_err(io::Error::other)?)?;
+        std::fs::rename(&temporary, path)
+    }
+
+    pub fn owner(&self) -> &str {
+        &self.state.owner
+    }
+
+    /// The owner's live ID as of the last sync, with confirmations queued since.
+    pub fn get(&self, id: &str) -> Option<&IdDetails> {
+        self.state.ids.get(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &IdDetails> {
+        self.state.ids.values()
+    }
+
+    pub fn queued(&self) -> &[QueuedConfirmation] {
+        &self.state.queued
+    }
+
+    /// Confirms `id` as the owner, or queues it when the registry is out of reach (a
+    /// failed connection, a timeout, 429 or 503) and marks the copy confirmed. Behind
+    /// earlier queued confirmations it is always queued, so they reach the registry in
+    /// order. Other refusals come back as errors.
+    pub fn confirm(&mut self, id: &str, by: Option<&str>) -> Result<Confirmed, Error> {
+        if self.state.queued.is_empty() {
+            match self.client.confirm(id, by, Some(&self.state.owner)) {
+                Ok(confirmation) => {
+                    self.mark_confirmed(id, by);
+                    return Ok(Confirmed::Now(confirmation));
+                }
+                Err(e) if !out_of_reach(&e) => return Err(e),
+                Err(_) => {}
+            }
+        }
+        self.state.queued.push(QueuedConfirmation { id: id.to_string(), by: by.map(str::to_string) });
+        self.mark_confirmed(id, by);
+        Ok(Confirmed::Queued)
+    }
+
+    /// Sends the queued confirmations, then applies the changes since the last sync.
+    /// Fails when the registry is out of reach; what got through before stays done, so
+    /// the next sync goes on from there.
+    pub fn sync(&mut self) -> Result<SyncSummary, Error> {
+        let mut summary = SyncSummary::default();
+
+        while let Some(queued) = self.state.queued.first().cloned() {
+            match self.client.confirm(&queued.id, queued.by.as_deref(), Some(&self.state.owner)) {
+                Ok(_) => summary.sent += 1,
+                Err(e) if out_of_reach(&e) => return Err(e),
+                Err(e) => summary.rejected.push((queued, e)),
+            }
+            self.state.queued.remove(0);
+        }
+
+        let mut cursor = match self.state.cursor {
+            Some(cursor) => cursor,
+            None => self.resync(&mut summary)?,
+        };
+        loop {
+            let page = match self.client.changes(cursor, 1000) {
+                Ok(page) => page,
+                Err(Error::Api { code: ErrorCode::CursorExpired, .. }) => {
+                    cursor = self.resync(&mut summary)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            summary.changes += page.changes.len();
+            for change in page.changes {
+                match change.details {
+                    Some(details) if details.owner == self.state.owner && !matches!(change.op.as_str(), "deleted" | "removed") => {
+                        self.state.ids.insert(change.id, details);
+                    }
+                    // Deleted, removed, or moved to another owner
+                    _ => {
+                        self.state.ids.remove(&change.id);
+                    }
+                }
+            }
+            cursor = page.next;
+            self.state.cursor = Some(cursor);
+            if !page.more {
+                return Ok(summary);
+            }
+        }
+    }
+
+    // Loads the owner's IDs afresh and returns the cursor to follow the feed from: its
+    // oldest entry, as any change kept may be newer than the listing
+    fn resync(&mut self, summary: &mut SyncSummary) -> Result<i64, Error> {
+        let mut ids = BTreeMap::new();
+        let mut after = None;
+        loop {
+            let page = self.client.owner_ids(&self.state.owner, after.as_deref())?;
+            ids.extend(page.ids.into_iter().map(|details| (details.id.clone(), details)));
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        self.state.ids = ids;
+        self.state.cursor = Some(0);
+        summary.resynced = true;
+        Ok(0)
+    }
+
+    fn mark_confirmed(&mut self, id: &str, by: Option<&str>) {
+        if let Some(details) = self.state.ids.get_mut(id) {
+            details.confirmed = 1;
+            details.confirmed_by = by.map(str::to_string);
+        }
+    }
+}
+
+// Whether `e` means the registry couldn't be asked, rather than that it said no
+fn out_of_reach(e: &Error) -> bool {
+    match e {
+        Error::Transport(_) => true,
+        Error::Api { status, .. } => RetryPolicy::retryable_status(*status),
+        Error::Decode(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Failure, MockConfig, MockServer};
+    use crate::GenerateRequest;
+
+    fn client(server: &MockServer) -> Client {
+        Client::new(&server.url()).with_retry(RetryPolicy::none())
+    }
+
+    #[test]
+    fn follows_the_owners_ids() {
+        let server = MockServer::start(MockConfig::default()).unwrap();
+        let registry = client(&server);
+        let kept = registry.generate(&GenerateRequest::new("assembly")).unwrap();
+        let gone = registry.generate(&GenerateRequest::new("assembly")).unwrap();
+        registry.generate(&GenerateRequest::new("packing")).unwrap();
+
+        let mut cache = OfflineCache::new(client(&server), "assembly");
+        assert!(cache.sync().unwrap().resynced);
+        assert_eq!(cache.ids().count(), 2);
+
+        ureq::delete(&format!("{}/ids/{}", server.url(), gone.id)).call().unwrap();
+        let added = registry.generate(&GenerateRequest::new("assembly")).unwrap();
+        registry.confirm(&kept.id, Some("line-1"), None).unwrap();
+        let summary = cache.sync().unwrap();
+        assert!(!summary.resynced);
+        assert!(cache.get(&gone.id).is_none());
+        assert!(cache.get(&added.id).is_some());
+        assert_eq!(cache.get(&kept.id).unwrap().confirmed, 1);
+    }
+
+    #[test]
+    fn queues_confirmations_while_offline() {
+        let server = MockServer::start(MockConfig::default()).unwrap();
+        let id = client(&server).generate(&GenerateRequest::new("assembly")).unwrap().id;
+        let mut cache = OfflineCache::new(client(&server), "assembly");
+        cache.sync().unwrap();
+
+        server.fail_next(1, Failure::Status(503));
+        assert!(matches!(cache.confirm(&id, Some("station-4")).unwrap(), Confirmed::Queued));
+        assert_eq!(cache.get(&id).unwrap().confirmed, 1);
+        assert_eq!(client(&server).get_id(&id).unwrap().confirmed, 0);
+
+        let path = std::env::temp_dir().join(format!("idreg-sync-{}.json", std::process::id()));
+        cache.save(&path).unwrap();
+        let mut cache = OfflineCache::load(client(&server), &path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(cache.queued().len(), 1);
+
+        assert_eq!(cache.sync().unwrap().sent, 1);
+        assert!(cache.queued().is_empty());
+        let confirmed = client(&server).get_id(&id).unwrap();
+        assert_eq!((confirmed.confirmed, confirmed.confirmed_by.as_deref()), (1, Some("station-4")));
+    }
+
+    #[test]
+    fn drops_refused_confirmations() {
+        let server = MockServer::start(MockConfig::default()).unwrap();
+        let mut cache = OfflineCache::new(client(&server), "assembly");
+        server.fail_next(1, Failure::Status(503));
+        assert!(matches!(cache.confirm("unknown", None).unwrap(), Confirmed::Queued));
+
+        let summary = cache.sync().unwrap();
+        assert_eq!(summary.sent, 0);
+        assert!(matches!(summary.rejected[0].1, Error::Api { code: ErrorCode::IdNotFound, .. }));
+        assert!(cache.queued().is_empty());
+    }
+}
@@ -0,0 +1,10 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `case_insensitive_ids`: IDs that differ only by letter case count as the same ID.
+//! Like SQLite's NOCASE, only ASCII letters fold.
+use crate::Settings;
+
+/// What two IDs must differ in to be different IDs: the ID itself, or with
+/// `case_insensitive_ids`, its lowercase form.
+pub fn key(settings: &Settings, id: &str) -> String {
+    if settings.case_insensitive_ids { id.to_ascii_lowercase() } else { id.to_string() }
+}
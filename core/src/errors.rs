@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The body of every error the API answers with, for the server to send and clients to read.
+use serde::{Deserialize, Serialize};
+
+/// Machine-readable error identifiers, sent as the `error` field of every error body.
+/// Shared so clients can match on them instead of comparing strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    NotImplemented,
+    ServiceUnavailable,
+    InternalError,
+    InvalidOwner,
+    IdNotFound,
+    Suspended,
+    PassiveNode,
+    PoolExhausted,
+    KeyspaceExhausted,
+    DatabaseError,
+    ConfigError,
+    IdConflict,
+    IdDeleted,
+    RateLimited,
+    Timeout,
+    PayloadTooLarge,
+    PreconditionRequired,
+    VersionConflict,
+    UnknownTable,
+    TableExists,
+    LabelExists,
+    DatabaseUnavailable,
+    DatabaseBusy,
+    JobNotFinished,
+    NotSuspended,
+    InvalidSnapshot,
+    ForceRequired,
+    OnHold,
+    CursorExpired,
+    ValidationFailed,
+    WrongOwner,
+    /// A code this build does not know about (e.g. from a newer server)
+    #[serde(other)]
+    Unknown,
+}
+
+/// JSON body of every error response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// Each rejected field, for `validation_failed`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// One field of a request body that failed validation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Path of the field in the body, e.g. `owner` or `ids[3]`; `body` for the body as a whole
+    pub field: String,
+    pub message: String,
+}
@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The registry's rules without the server around them: [`Settings`], ID generation,
+//! the checks and normal forms of IDs, the [`lifecycle`] of an issued ID and the
+//! [`errors`] the API answers with.
+//! Persistence goes through [`IdStore`], so offline tools and other services can
+//! generate and check IDs the way the server does against their own storage.
+pub mod casefold;
+pub mod errors;
+pub mod lifecycle;
+pub mod policy;
+pub mod sequence;
+pub mod settings;
+pub mod store;
+
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use unicode_normalization::UnicodeNormalization;
+
+pub use settings::{OpKind, Settings};
+pub use store::IdStore;
+
+/// Returned (inside anyhow::Error) when no unused ID could be found within the retry budget.
+#[derive(Debug)]
+pub struct KeyspaceExhausted {
+    pub attempts: usize,
+    /// The sequence ran out, which no retry fixes; random generation may still succeed
+    /// on a later try
+    pub sequence: bool,
+}
+
+impl std::fmt::Display for KeyspaceExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.sequence {
+            return write!(f, "The sequence has no unused values left ({} skipped)", self.attempts);
+        }
+        write!(
+            f,
+            "Failed to generate unique ID after {} attempts. Database may be very full.",
+            self.attempts
+        )
+    }
+}
+
+impl std::error::Error for KeyspaceExhausted {}
+
+/// Returned (inside anyhow::Error) when every insert attempt hit an existing ID.
+#[derive(Debug)]
+pub struct IdConflict {
+    pub attempts: usize,
+}
+
+impl std::fmt::Display for IdConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Every generated ID conflicted on insert ({} attempts)", self.attempts)
+    }
+}
+
+impl std::error::Error for IdConflict {}
+
+/// Returned (inside anyhow::Error) when an ID does not exist at all.
+#[derive(Debug)]
+pub struct IdNotFound {
+    pub id: String,
+}
+
+impl std::fmt::Display for IdNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ID {} not found", self.id)
+    }
+}
+
+impl std::error::Error for IdNotFound {}
+
+/// Returned (inside anyhow::Error) when an ID exists but has been deleted.
+#[derive(Debug)]
+pub struct IdDeleted {
+    pub id: String,
+}
+
+impl std::fmt::Display for IdDeleted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ID {} has been deleted", self.id)
+    }
+}
+
+impl std::error::Error for IdDeleted {}
+
+/// Returned (inside anyhow::Error) when a client-supplied suffix can't be used.
+#[derive(Debug)]
+pub struct InvalidSuffix {
+    pub suffix: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidSuffix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Suffix '{}' {}", self.suffix, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidSuffix {}
+
+/// Returned (inside anyhow::Error) when the row changed since the caller read it.
+#[derive(Debug)]
+pub struct VersionConflict {
+    pub expected: i64,
+    pub current: i64,
+}
+
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected version {} but the ID is at version {}", self.expected, self.current)
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+/// One issued ID with everything stored about it.
+#[derive(Debug, Clone)]
+pub struct IdRecord {
+    pub id: String,
+    pub owner: String,
+    pub table: Option<String>,
+    pub confirmed: i32,
+    pub created_at: String,
+    pub version: i64,
+    pub confirmed_at: Option<String>,
+    pub confirmed_by: Option<String>,
+    /// The generation config version the ID was issued under
+    pub config_version: Option<i64>,
+    /// The caller's key for the record, unique per owner
+    pub external_ref: Option<String>,
+}
+
+/// Changes to an issued ID; `None` leaves a field as it is and
+/// `table: Some(None)` clears the table.
+#[derive(Debug, Clone, Default)]
+pub struct IdChanges {
+    pub owner: Option<String>,
+    pub table: Option<Option<String>>,
+}
+
+// Whether `id` has this length (or up to `extra_length` more, see generate) and only
+// charset characters. The content policy is checked separately, since it may have
+// changed since the ID was generated
+pub fn matches_format(id_length: u32, extra_length: u32, charset: &str, id: &str) -> bool {
+    let length = id.chars().count();
+    (id_length as usize..=(id_length + extra_length) as usize).contains(&length) && id.chars().all(|c| charset.contains(c))
+}
+
+// Builds one random candidate of the configured length
+fn random_candidate(charset_chars: &[char], length: u32, rng: &mut impl rand::Rng) -> String {
+    let mut id = String::with_capacity(length as usize);
+
+    for _ in 0..length {
+        let c = *charset_chars
+            .choose(rng)
+            .expect("Charset cannot be empty here");
+        id.push(c);
+    }
+
+    id
+}
+
+/// Checks a client-supplied suffix: charset characters only, and short enough that
+/// the random part keeps at least half of `id_length`. Fails with InvalidSuffix.
+pub fn check_suffix(settings: &Settings, suffix: &str) -> Result<()> {
+    let invalid = |reason: String| InvalidSuffix { suffix: suffix.to_string(), reason };
+
+    if suffix.is_empty() {
+        return Err(invalid("is empty".to_string()).into());
+    }
+    if let Some(c) = suffix.chars().find(|c| !settings.charset.contains(*c)) {
+        return Err(invalid(format!("contains '{}', which is not in the charset", c)).into());
+    }
+    let max = settings.id_length as usize / 2;
+    if suffix.chars().count() > max {
+        return Err(invalid(format!("is longer than {} characters (half of id_length)", max)).into());
+    }
+    Ok(())
+}
+
+/// `id` as shown to people: groups of `display_group_size` characters joined by
+/// `display_separator`. None while display formatting is off.
+pub fn display_id(settings: &Settings, id: &str) -> Option<String> {
+    let size = settings.display_group_size as usize;
+    if size == 0 {
+        return None;
+    }
+    let chars: Vec<char> = id.chars().collect();
+    let groups: Vec<String> = chars.chunks(size).map(|group| group.iter().collect()).collect();
+    Some(groups.join(&settings.display_separator))
+}
+
+/// An ID from a client in the form it is stored in: NFC like the charset, and with
+/// display formatting on, without the separator's characters (load_settings keeps them
+/// out of the charset), so the display form is accepted too.
+pub fn normalize_id(settings: &Settings, id: &str) -> String {
+    let id: String = if id.is_ascii() { id.to_string() } else { id.nfc().collect() };
+    if settings.display_group_size == 0 {
+        return id;
+    }
+    id.chars().filter(|c| !settings.display_separator.contains(*c)).collect()
+}
+
+/// Turns what a barcode scanner or a typist produced back into an ID: surrounding
+/// whitespace, and spaces and punctuation the charset doesn't use (separators such as
+/// `-` or `.`, and the display separator), are dropped. With a single-case charset,
+/// letters are folded to that case.
+pub fn normalize_scan(settings: &Settings, scanned: &str) -> String {
+    let charset = &settings.charset;
+    let has_lower = charset.chars().any(char::is_lowercase);
+    let has_upper = charset.chars().any(char::is_uppercase);
+
+    normalize_id(settings, scanned.trim())
+        .chars()
+        .filter(|c| charset.contains(*c) || !(c.is_whitespace() || c.is_ascii_punctuation()))
+        .map(|c| match (has_lower, has_upper) {
+            (false, true) => c.to_uppercase().next().unwrap_or(c),
+            (true, false) => c.to_lowercase().next().unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Why `id` can't have been issued under the current settings, if it can't: a wrong
+/// length, a character outside the charset, or a broken `id_content_policy` rule. IDs
+/// from before a settings change may legitimately fail this, so only use it to explain
+/// an unknown ID.
+pub fn format_problem(settings: &Settings, id: &str) -> Option<String> {
+    let length = id.chars().count() as u32;
+    let extra = settings.generation_max_extra_length;
+    if length < settings.id_length || length > settings.id_length + extra {
+        let expected = match extra {
+            0 => settings.id_length.to_string(),
+            _ => format!("{}-{}", settings.id_length, settings.id_length + extra),
+        };
+        return Some(format!("{} characters, expected {}", length, expected));
+    }
+    if let Some(c) = id.chars().find(|c| !settings.charset.contains(*c)) {
+        return Some(format!("contains '{}', which is not in the charset", c));
+    }
+    settings.id_content_policy.problem(id).map(str::to_string)
+}
+
+/// Generates one random ID using current settings, ending in `suffix` if given
+/// (the random part is shortened so the total stays `id_length`).
+/// Candidates are produced in batches and checked for collisions with a single
+/// [`IdStore::existing_ids`] call per batch; candidates `id_content_policy` rejects are skipped.
+/// After `generation_lengthen_after` collisions at one length, candidates get one more
+/// character, up to `generation_max_extra_length` more. Fails with [`KeyspaceExhausted`]
+/// once `generation_retry_budget` candidates have been tried. In sequence mode this
+/// previews the next ID of the sequence without taking it.
+pub fn generate_id(store: &impl IdStore, settings: &Settings, suffix: Option<&str>) -> Result<String> {
+    generate(store, settings, suffix, false).map(|(id, _)| id)
+}
+
+/// Like [`generate_id`], also returning how many candidates collided before it succeeded.
+/// In sequence mode it takes the ID, advancing the sequence.
+pub fn generate_id_counted(store: &impl IdStore, settings: &Settings, suffix: Option<&str>) -> Result<(String, u32)> {
+    generate(store, settings, suffix, true)
+}
+
+fn generate(store: &impl IdStore, settings: &Settings, suffix: Option<&str>, advance: bool) -> Result<(String, u32)> {
+    const BATCH_SIZE: usize = 10;
+
+    let charset_chars: Vec<char> = settings.charset.chars().collect();
+    if charset_chars.is_empty() {
+        anyhow::bail!("Charset is empty");
+    }
+
+    let suffix = suffix.unwrap_or("");
+    if !suffix.is_empty() {
+        check_suffix(settings, suffix)?;
+    }
+    let mut random_length = settings.id_length - suffix.chars().count() as u32;
+    if settings.generation_mode == "sequence" {
+        return sequence::generate(store, settings, &charset_chars, random_length, suffix, advance);
+    }
+
+    let budget = settings.generation_retry_budget as usize;
+    let max_length = random_length + settings.generation_max_extra_length;
+    let mut rng = rand::thread_rng();
+    let mut attempts = 0;
+    let mut retries = 0;
+    let mut retries_at_length = 0;
+
+    while attempts < budget {
+        // Collisions piling up at this length mean its keyspace is nearly used up
+        if retries_at_length >= settings.generation_lengthen_after && random_length < max_length {
+            random_length += 1;
+            retries_at_length = 0;
+        }
+
+        let batch = BATCH_SIZE.min(budget - attempts);
+        attempts += batch;
+
+        let mut candidates: Vec<String> = Vec::with_capacity(batch);
+        for _ in 0..batch {
+            let mut id = random_candidate(&charset_chars, random_length, &mut rng);
+            id.push_str(suffix);
+
+            // Skip if the policy rejects it (or a repeat within this batch)
+            if settings.id_content_policy.allows(&id) && !candidates.contains(&id) {
+                candidates.push(id);
+            }
+        }
+
+        let taken = store.existing_ids(settings, &candidates)?;
+        match candidates.iter().position(|c| !taken.contains(&casefold::key(settings, c))) {
+            Some(index) => return Ok((candidates.swap_remove(index), retries + index as u32)),
+            None => {
+                retries += candidates.len() as u32;
+                retries_at_length += candidates.len() as u32;
+            }
+        }
+    }
+
+    Err(KeyspaceExhausted { attempts: budget, sequence: false }.into())
+}
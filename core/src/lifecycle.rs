@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The states an issued ID goes through. An ID starts out unconfirmed, is confirmed
+//! once the record it names exists downstream, and is soft-deleted when that record
+//! is gone. Deleting clears the confirmation, and a deleted ID stays deleted: it is
+//! never confirmed again or issued to anyone else.
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdState {
+    Unconfirmed,
+    Confirmed,
+    Deleted,
+}
+
+/// Something asked of an ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Confirm,
+    Delete,
+}
+
+/// Why an event leaves an ID as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+    /// Confirming twice; not an error to callers, but nothing to do
+    AlreadyConfirmed,
+    /// The ID was deleted before
+    Deleted,
+}
+
+impl IdState {
+    /// The state of an ID stored with these flags; `deleted` wins over `confirmed`.
+    pub fn from_flags(confirmed: bool, deleted: bool) -> Self {
+        match (confirmed, deleted) {
+            (_, true) => IdState::Deleted,
+            (true, false) => IdState::Confirmed,
+            (false, false) => IdState::Unconfirmed,
+        }
+    }
+
+    /// The state after `event`, or why the event doesn't apply.
+    pub fn apply(self, event: Event) -> Result<IdState, Refused> {
+        match (self, event) {
+            (IdState::Deleted, _) => Err(Refused::Deleted),
+            (IdState::Confirmed, Event::Confirm) => Err(Refused::AlreadyConfirmed),
+            (IdState::Unconfirmed, Event::Confirm) => Ok(IdState::Confirmed),
+            (_, Event::Delete) => Ok(IdState::Deleted),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `id_content_policy`: rules on what an ID may look like beyond its length and
+//! charset, such as "not all digits", so that IDs can't be mistaken for numbers or
+//! mangled by spreadsheets. Generation skips candidates that break a rule, and
+//! [`format_problem`](crate::format_problem) reports the first rule an ID breaks.
+use anyhow::Result;
+use serde::{Serialize, Serializer};
+use std::collections::HashSet;
+
+/// One content rule, named in the setting by its snake_case name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// At least one character that isn't a digit 0-9
+    NoAllDigits,
+    /// At least one letter
+    RequireLetter,
+    /// At least one digit 0-9
+    RequireDigit,
+    /// Doesn't start with 0
+    NoLeadingZero,
+    /// Not one character repeated, like `AAAAAA`
+    NoRepeatedChar,
+}
+
+impl Rule {
+    const ALL: [Rule; 5] = [Rule::NoAllDigits, Rule::RequireLetter, Rule::RequireDigit, Rule::NoLeadingZero, Rule::NoRepeatedChar];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rule::NoAllDigits => "no_all_digits",
+            Rule::RequireLetter => "require_letter",
+            Rule::RequireDigit => "require_digit",
+            Rule::NoLeadingZero => "no_leading_zero",
+            Rule::NoRepeatedChar => "no_repeated_char",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Rule::ALL.into_iter().find(|rule| rule.as_str() == name)
+    }
+
+    fn allows(self, id: &str) -> bool {
+        match self {
+            Rule::NoAllDigits => !id.chars().all(|c| c.is_ascii_digit()),
+            Rule::RequireLetter => id.chars().any(char::is_alphabetic),
+            Rule::RequireDigit => id.chars().any(|c| c.is_ascii_digit()),
+            Rule::NoLeadingZero => !id.starts_with('0'),
+            Rule::NoRepeatedChar => id.chars().skip(1).any(|c| !id.starts_with(c)),
+        }
+    }
+
+    /// Why an ID breaking this rule can't have been generated.
+    fn problem(self) -> &'static str {
+        match self {
+            Rule::NoAllDigits => "all digits, which generation never produces",
+            Rule::RequireLetter => "no letter, which generation never produces",
+            Rule::RequireDigit => "no digit, which generation never produces",
+            Rule::NoLeadingZero => "starts with 0, which generation never produces",
+            Rule::NoRepeatedChar => "one character repeated, which generation never produces",
+        }
+    }
+
+    // Roughly the share of random IDs of `length` characters from `chars` that pass
+    fn allowed_fraction(self, chars: &HashSet<char>, length: f64) -> f64 {
+        let n = chars.len() as f64;
+        let count = |f: fn(&char) -> bool| chars.iter().filter(|c| f(c)).count() as f64;
+        match self {
+            Rule::NoAllDigits => 1.0 - (count(char::is_ascii_digit) / n).powf(length),
+            Rule::RequireLetter => 1.0 - (1.0 - count(|c| c.is_alphabetic()) / n).powf(length),
+            Rule::RequireDigit => 1.0 - (1.0 - count(char::is_ascii_digit) / n).powf(length),
+            Rule::NoLeadingZero => 1.0 - count(|c| *c == '0') / n,
+            Rule::NoRepeatedChar => 1.0 - n.powf(1.0 - length),
+        }
+    }
+}
+
+/// The rules of `id_content_policy`, a comma-separated list of [`Rule`] names; empty
+/// allows every ID of the right length and charset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy(Vec<Rule>);
+
+impl Policy {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for name in spec.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let rule = Rule::parse(name).ok_or_else(|| {
+                let known: Vec<&str> = Rule::ALL.iter().map(|rule| rule.as_str()).collect();
+                anyhow::anyhow!("Unknown rule '{}': expected {}", name, known.join(", "))
+            })?;
+            if !rules.contains(&rule) {
+                rules.push(rule);
+            }
+        }
+        Ok(Policy(rules))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.0
+    }
+
+    pub fn allows(&self, id: &str) -> bool {
+        self.0.iter().all(|rule| rule.allows(id))
+    }
+
+    /// Why `id` breaks the policy, for the first rule it breaks.
+    pub fn problem(&self, id: &str) -> Option<&'static str> {
+        self.0.iter().find(|rule| !rule.allows(id)).map(|rule| rule.problem())
+    }
+
+    /// Roughly the share of IDs of `length` characters from `chars` the policy allows,
+    /// treating the rules as independent.
+    pub fn allowed_fraction(&self, chars: &HashSet<char>, length: u32) -> f64 {
+        if chars.is_empty() {
+            return 0.0;
+        }
+        self.0.iter().map(|rule| rule.allowed_fraction(chars, length as f64)).product()
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(|rule| rule.as_str()).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+// Shown as the setting's value, e.g. by /health
+impl Serialize for Policy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The share of all IDs of `length` characters from `charset` that `policy` allows
+    fn counted_fraction(policy: &Policy, charset: &[char], length: u32) -> f64 {
+        let total = charset.len().pow(length);
+        let allowed = (0..total)
+            .filter(|n| {
+                let id: String = (0..length).map(|i| charset[n / charset.len().pow(i) % charset.len()]).collect();
+                policy.allows(&id)
+            })
+            .count();
+        allowed as f64 / total as f64
+    }
+
+    #[test]
+    fn allowed_fraction_of_one_rule_is_exact() {
+        let charset: Vec<char> = "012ab".chars().collect();
+        let chars: HashSet<char> = charset.iter().copied().collect();
+        for rule in Rule::ALL {
+            let policy = Policy::parse(rule.as_str()).unwrap();
+            for length in 1..=4 {
+                let estimate = policy.allowed_fraction(&chars, length);
+                let counted = counted_fraction(&policy, &charset, length);
+                assert!((estimate - counted).abs() < 1e-9, "{} at length {}: {} vs {}", rule.as_str(), length, estimate, counted);
+            }
+        }
+    }
+
+    #[test]
+    fn allowed_fraction_of_several_rules_is_close() {
+        let charset: Vec<char> = "0123456789ABCDEF".chars().collect();
+        let chars: HashSet<char> = charset.iter().copied().collect();
+        let policy = Policy::parse("no_all_digits,no_leading_zero,no_repeated_char").unwrap();
+        let estimate = policy.allowed_fraction(&chars, 4);
+        let counted = counted_fraction(&policy, &charset, 4);
+        assert!((estimate - counted).abs() < 0.01, "{} vs {}", estimate, counted);
+    }
+
+    #[test]
+    fn allowed_fraction_edge_cases() {
+        let chars: HashSet<char> = "0123456789".chars().collect();
+        assert_eq!(Policy::default().allowed_fraction(&chars, 6), 1.0);
+        assert_eq!(Policy::parse("require_letter").unwrap().allowed_fraction(&chars, 6), 0.0);
+        assert_eq!(Policy::parse("no_all_digits").unwrap().allowed_fraction(&HashSet::new(), 6), 0.0);
+    }
+}
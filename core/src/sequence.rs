@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `generation_mode = sequence`: IDs are a counter, encrypted under `sequence_key` with
+//! a Feistel network sized to the keyspace (cycle-walking past the values that don't
+//! fit), then written in the charset. Distinct counter values give distinct IDs, so
+//! generation doesn't collide, yet consecutive IDs look unrelated to anyone without
+//! the key. [`decode`] turns an ID back into its counter value.
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{IdStore, KeyspaceExhausted, Settings};
+
+const ROUNDS: u8 = 8;
+
+/// A keyed permutation of `0..domain`.
+pub struct Permutation {
+    key: Vec<u8>,
+    domain: u128,
+    half_bits: u32,
+}
+
+impl Permutation {
+    /// Fails if `domain` is 0.
+    pub fn new(key: &str, domain: u128) -> Result<Self> {
+        if domain == 0 {
+            anyhow::bail!("Empty keyspace");
+        }
+        // Both halves get the same width, so the network covers up to 4x the domain
+        let bits = (128 - (domain - 1).leading_zeros()).max(2);
+        Ok(Permutation { key: key.as_bytes().to_vec(), domain, half_bits: bits.div_ceil(2) })
+    }
+
+    fn round(&self, round: u8, half: u128) -> u128 {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(&[round]);
+        mac.update(&half.to_le_bytes());
+        let digest = mac.finalize().into_bytes();
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        u128::from_le_bytes(bytes) & self.mask()
+    }
+
+    fn mask(&self) -> u128 {
+        (1u128 << self.half_bits) - 1
+    }
+
+    fn feistel(&self, value: u128) -> u128 {
+        let (mut left, mut right) = (value >> self.half_bits, value & self.mask());
+        for round in 0..ROUNDS {
+            (left, right) = (right, left ^ self.round(round, right));
+        }
+        (left << self.half_bits) | right
+    }
+
+    fn feistel_inverse(&self, value: u128) -> u128 {
+        let (mut left, mut right) = (value >> self.half_bits, value & self.mask());
+        for round in (0..ROUNDS).rev() {
+            (left, right) = (right ^ self.round(round, left), left);
+        }
+        (left << self.half_bits) | right
+    }
+
+    pub fn encrypt(&self, value: u128) -> u128 {
+        let mut value = self.feistel(value);
+        while value >= self.domain {
+            value = self.feistel(value);
+        }
+        value
+    }
+
+    pub fn decrypt(&self, value: u128) -> u128 {
+        let mut value = self.feistel_inverse(value);
+        while value >= self.domain {
+            value = self.feistel_inverse(value);
+        }
+        value
+    }
+}
+
+// charset_len ^ length, if it fits
+fn keyspace(charset_len: usize, length: u32) -> Result<u128> {
+    (charset_len as u128)
+        .checked_pow(length)
+        .ok_or_else(|| anyhow::anyhow!("id_length is too long for sequence mode (the keyspace must fit in 128 bits)"))
+}
+
+// `value` in base charset_len, zero-padded to `length` characters
+fn encode(charset: &[char], length: u32, mut value: u128) -> String {
+    let base = charset.len() as u128;
+    let mut digits = vec![charset[0]; length as usize];
+    for digit in digits.iter_mut().rev() {
+        *digit = charset[(value % base) as usize];
+        value /= base;
+    }
+    digits.into_iter().collect()
+}
+
+/// The counter value `id` was generated from, if it is a sequence-mode ID of the
+/// current settings. `suffix_length` is how many trailing characters are a suffix.
+pub fn decode(settings: &Settings, id: &str, suffix_length: usize) -> Result<Option<u128>> {
+    let charset: Vec<char> = settings.charset.chars().collect();
+    let chars: Vec<char> = id.chars().collect();
+    if chars.len() != settings.id_length as usize || suffix_length >= chars.len() {
+        return Ok(None);
+    }
+    let random = &chars[..chars.len() - suffix_length];
+    let mut value: u128 = 0;
+    for c in random {
+        let Some(digit) = charset.iter().position(|x| x == c) else {
+            return Ok(None);
+        };
+        value = value * charset.len() as u128 + digit as u128;
+    }
+    let permutation = Permutation::new(&settings.sequence_key, keyspace(charset.len(), random.len() as u32)?)?;
+    Ok(Some(permutation.decrypt(value)))
+}
+
+/// The next ID of the sequence, ending in `suffix`. With `advance` the counter of
+/// `random_length` moves past it (roll back the store's transaction to give it back);
+/// without, this only previews it. Values whose ID `id_content_policy` rejects or that
+/// is already taken (e.g. issued before sequence mode) are skipped, and counted as
+/// retries. The counter moves past skipped values even when no ID is found, so they
+/// aren't checked again. Fails with [`KeyspaceExhausted`]: a final one once the counter
+/// reaches the end of the keyspace, a retryable one after `generation_retry_budget`
+/// skipped values.
+pub(crate) fn generate(
+    store: &impl IdStore,
+    settings: &Settings,
+    charset: &[char],
+    random_length: u32,
+    suffix: &str,
+    advance: bool,
+) -> Result<(String, u32)> {
+    let domain = keyspace(charset.len(), random_length)?;
+    let permutation = Permutation::new(&settings.sequence_key, domain)?;
+    let start = store.sequence_next(random_length)?;
+    let mut next = start;
+
+    let budget = settings.generation_retry_budget;
+    let mut found = None;
+    for skipped in 0..budget {
+        let value = next as u128;
+        if value >= domain {
+            break;
+        }
+        next += 1;
+
+        let mut id = encode(charset, random_length, permutation.encrypt(value));
+        id.push_str(suffix);
+        if settings.id_content_policy.allows(&id) && store.existing_ids(settings, std::slice::from_ref(&id))?.is_empty() {
+            found = Some((id, skipped));
+            break;
+        }
+    }
+    if advance && next != start {
+        store.set_sequence_next(random_length, next)?;
+    }
+
+    found.ok_or_else(|| {
+        let sequence = next as u128 >= domain;
+        KeyspaceExhausted { attempts: (next - start) as usize, sequence }.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn permutation_is_a_bijection_on_its_domain() {
+        for domain in [1, 2, 3, 7, 62, 100, 1000, 3844] {
+            let permutation = Permutation::new("key", domain).unwrap();
+            let encrypted: HashSet<u128> = (0..domain).map(|value| permutation.encrypt(value)).collect();
+            assert_eq!(encrypted.len() as u128, domain);
+            assert!(encrypted.iter().all(|value| *value < domain));
+            assert!((0..domain).all(|value| permutation.decrypt(permutation.encrypt(value)) == value));
+        }
+    }
+
+    #[test]
+    fn permutation_round_trips_large_domains() {
+        let domain = keyspace(62, 12).unwrap();
+        let permutation = Permutation::new("key", domain).unwrap();
+        for value in [0, 1, 2, 12345, domain / 2, domain - 1] {
+            let encrypted = permutation.encrypt(value);
+            assert!(encrypted < domain);
+            assert_eq!(permutation.decrypt(encrypted), value);
+        }
+        assert!(Permutation::new("key", 0).is_err());
+    }
+
+    #[test]
+    fn permutation_depends_on_the_key() {
+        let (a, b) = (Permutation::new("a", 1000).unwrap(), Permutation::new("b", 1000).unwrap());
+        assert!((0..1000).any(|value| a.encrypt(value) != b.encrypt(value)));
+    }
+}
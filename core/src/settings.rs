@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The registry's settings as the rules below read them. Loading and storing them is
+//! up to the embedder; the server keeps them in its `settings` table.
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::Duration;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::matches_format;
+use crate::policy::Policy;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Settings {
+    pub id_length: u32,
+    pub charset: String,
+    #[serde(serialize_with = "redacted")]
+    pub admin_secret: String,
+    pub lease_ttl_secs: u64,
+    pub cache_capacity: usize,
+    pub cache_ttl_secs: u64,
+    pub negative_cache_ttl_secs: u64,
+    pub id_pool_size: usize,
+    pub rate_limit_per_minute: u32,
+    pub suspend_retry_after_secs: u64,
+    pub read_timeout_ms: u64,
+    pub write_timeout_ms: u64,
+    pub admin_timeout_ms: u64,
+    pub json_limit_kb: u64,
+    pub form_limit_kb: u64,
+    pub bytes_limit_kb: u64,
+    pub compression_min_bytes: usize,
+    pub local_socket_path: String,
+    pub admin_port: u16,
+    pub admin_address: String,
+    pub secret_grace_secs: u64,
+    pub shadow_mode: bool,
+    pub enforce_table_catalog: bool,
+    pub lookup_max_ids: usize,
+    pub pool_wait_ms: u64,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown_secs: u64,
+    pub busy_retry_budget_ms: u64,
+    pub batch_stream_max_ids: usize,
+    pub job_retention_hours: u64,
+    pub consistency_schedule: String,
+    pub janitor_schedule: String,
+    pub checkpoint_schedule: String,
+    pub backup_schedule: String,
+    pub backup_dir: String,
+    pub backup_keep: u32,
+    pub backup_upload: String,
+    pub backup_upload_url: String,
+    pub backup_upload_prefix: String,
+    pub backup_upload_keep: u32,
+    pub backup_s3_region: String,
+    pub backup_s3_access_key: String,
+    #[serde(serialize_with = "redacted")]
+    pub backup_s3_secret_key: String,
+    #[serde(serialize_with = "redacted")]
+    pub backup_azure_sas: String,
+    pub restore_upload_max_mb: u64,
+    pub replica_path: String,
+    pub replica_interval_ms: u64,
+    pub audit_retention_days: u64,
+    pub audit_max_rows: u64,
+    pub generation_stats_retention_days: u64,
+    pub deleted_retention_days: u64,
+    pub purge_schedule: String,
+    pub changes_retention_days: u64,
+    pub anomaly_schedule: String,
+    pub anomaly_factor: u64,
+    pub anomaly_min_ids: u64,
+    pub anomaly_baseline_days: u64,
+    #[serde(serialize_with = "redacted")]
+    pub token_key: String,
+    pub generation_mode: String,
+    #[serde(serialize_with = "redacted")]
+    pub sequence_key: String,
+    pub qr_url_template: String,
+    pub display_group_size: u32,
+    pub display_separator: String,
+    pub id_max_bytes: u32,
+    pub case_insensitive_ids: bool,
+    pub min_keyspace_bits: u32,
+    pub min_keyspace_mode: String,
+    pub confirm_requires_owner: bool,
+    pub generation_retry_budget: u32,
+    pub generation_lengthen_after: u32,
+    pub generation_max_extra_length: u32,
+    pub id_content_policy: Policy,
+}
+
+// Storage credentials are shown as set or not, never in full (e.g. by /health)
+fn redacted<S: serde::Serializer>(value: &str, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(if value.is_empty() { "" } else { "<redacted>" })
+}
+
+/// Route categories with their own database timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Settings {
+    /// How long a database operation of this kind may take before the request gives up.
+    pub fn timeout_for(&self, kind: OpKind) -> Duration {
+        let ms = match kind {
+            OpKind::Read => self.read_timeout_ms,
+            OpKind::Write => self.write_timeout_ms,
+            OpKind::Admin => self.admin_timeout_ms,
+        };
+        Duration::from_millis(ms)
+    }
+
+    /// Size of the ID space in bits: distinct charset characters to the power of
+    /// id_length, less the IDs `id_content_policy` rules out (an estimate when it has
+    /// several rules). 0 when nothing is usable.
+    pub fn keyspace_bits(&self) -> f64 {
+        let chars: HashSet<char> = self.charset.chars().collect();
+        if chars.is_empty() {
+            return 0.0;
+        }
+        let n = chars.len() as f64;
+        let length = self.id_length as f64;
+
+        let bits = length * n.log2() + self.id_content_policy.allowed_fraction(&chars, self.id_length).log2();
+        if bits.is_finite() { bits.max(0.0) } else { 0.0 }
+    }
+
+    /// What is wrong when the keyspace is smaller than `min_keyspace_bits`, which makes
+    /// collisions pile up and the keyspace run out within hours at a modest rate.
+    pub fn keyspace_shortfall(&self) -> Option<String> {
+        let bits = self.keyspace_bits();
+        (bits < self.min_keyspace_bits as f64).then(|| {
+            format!("The keyspace of {:.1} bits is below 'min_keyspace_bits' ({})", bits, self.min_keyspace_bits)
+        })
+    }
+
+    /// The most bytes an ID can take as UTF-8: the longest ID generation may produce
+    /// times the widest charset character.
+    pub fn max_id_bytes(&self) -> usize {
+        max_utf8_bytes(self.id_length + self.generation_max_extra_length, &self.charset)
+    }
+
+    /// Whether `id` could have been generated with these settings.
+    pub fn id_matches_format(&self, id: &str) -> bool {
+        matches_format(self.id_length, self.generation_max_extra_length, &self.charset, id) && self.id_content_policy.allows(id)
+    }
+}
+
+/// The charset in NFC, so that an ID is the same string however its characters were typed.
+/// Each character must be a whole user-perceived character by itself: combining marks,
+/// joiners and variation selectors only make sense attached to a neighbour, and IDs of
+/// them would count more characters than they show. The same goes for characters that
+/// merge with another charset character next to them, into one grapheme (regional
+/// indicators, emoji modifiers) or under NFC into one character (Hangul jamo).
+pub fn normalize_charset(charset: &str) -> Result<String> {
+    let charset: String = charset.nfc().collect();
+    for c in charset.chars() {
+        if is_combining_mark(c) || matches!(c, '\u{200c}' | '\u{200d}') {
+            anyhow::bail!("U+{:04X} is not a character by itself (combining mark or joiner)", c as u32);
+        }
+        if c.is_control() || c.is_whitespace() {
+            anyhow::bail!("U+{:04X} is a control or whitespace character", c as u32);
+        }
+    }
+
+    // Any two may end up side by side in an ID, each before the other; ASCII pairs
+    // (no controls left) never merge
+    let mut unique: Vec<char> = charset.chars().collect();
+    unique.sort_unstable();
+    unique.dedup();
+    for &a in &unique {
+        for &b in unique.iter().filter(|b| !(a.is_ascii() && b.is_ascii())) {
+            let pair: String = [a, b].iter().collect();
+            if pair.graphemes(true).count() != 2 {
+                anyhow::bail!("U+{:04X} followed by U+{:04X} shows as one character", a as u32, b as u32);
+            }
+            if pair.nfc().ne(pair.chars()) {
+                anyhow::bail!("U+{:04X} followed by U+{:04X} composes into another character under NFC", a as u32, b as u32);
+            }
+        }
+    }
+    Ok(charset)
+}
+
+/// The most bytes an ID of `id_length` charset characters can take as UTF-8.
+pub fn max_utf8_bytes(id_length: u32, charset: &str) -> usize {
+    id_length as usize * charset.chars().map(char::len_utf8).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charset_characters_must_stand_alone() {
+        assert_eq!(normalize_charset("abc\u{e9}").unwrap(), "abc\u{e9}");
+        assert_eq!(normalize_charset("abe\u{301}").unwrap(), "ab\u{e9}");
+        // Regional indicators pair into flags
+        assert!(normalize_charset("ab\u{1f1e9}\u{1f1ea}").is_err());
+        // A skin tone modifies the emoji before it
+        assert!(normalize_charset("\u{1f44d}\u{1f3fd}").is_err());
+        // Conjoining jamo make a syllable once next to each other, under NFC too
+        assert!(normalize_charset("\u{1161}\u{1100}").is_err());
+        assert!(normalize_charset("\u{11a8}\u{ac00}").is_err());
+    }
+}
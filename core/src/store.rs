@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! What generation needs from wherever the issued IDs are kept.
+use anyhow::Result;
+use std::collections::HashSet;
+
+use crate::Settings;
+
+/// Storage behind [`generate_id`](crate::generate_id). Generation itself writes
+/// nothing but the sequence counter; inserting the ID it returns is the caller's job,
+/// and should rely on a unique constraint, since another writer may take the ID first.
+pub trait IdStore {
+    /// The subset of `candidates` already in use (issued, reserved or otherwise kept
+    /// back), as [`casefold::key`](crate::casefold::key)s.
+    fn existing_ids(&self, settings: &Settings, candidates: &[String]) -> Result<HashSet<String>>;
+
+    /// The counter value the next sequence-mode ID with a random part of
+    /// `random_length` characters is made from. Each length has its own counter, since
+    /// each has its own keyspace.
+    fn sequence_next(&self, random_length: u32) -> Result<i64>;
+
+    /// Moves the sequence counter of `random_length` to `next`.
+    fn set_sequence_next(&self, random_length: u32, next: i64) -> Result<()>;
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::sync::RwLock;
+
+use crate::Settings;
+
+// Stored form of a hashed secret: sha256$<salt>$<hex digest of salt + secret>
+const HASH_PREFIX: &str = "sha256$";
+
+/// Salted SHA-256 of `secret`, in the form kept in the settings table.
+pub fn hash_secret(secret: &str) -> String {
+    let salt = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    format!("{}{}${}", HASH_PREFIX, salt, digest(&salt, secret))
+}
+
+/// A fresh random secret for rotation.
+pub fn generate_secret() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
+
+/// Checks `candidate` against a stored secret, which is either a hash from
+/// [`hash_secret`] or plaintext (as the setup GUI writes it).
+pub fn verify_secret(stored: &str, candidate: &str) -> bool {
+    match stored.strip_prefix(HASH_PREFIX).and_then(|rest| rest.split_once('$')) {
+        Some((salt, expected)) => constant_time_eq(digest(salt, candidate).as_bytes(), expected.as_bytes()),
+        None => constant_time_eq(stored.as_bytes(), candidate.as_bytes()),
+    }
+}
+
+fn digest(salt: &str, secret: &str) -> String {
+    let hash = Sha256::new().chain_update(salt).chain_update(secret).finalize();
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Compares without stopping at the first difference, so timing doesn't leak a prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The admin secret in force, plus the one it replaced while its grace period lasts.
+pub struct AdminSecrets {
+    inner: RwLock<Secrets>,
+}
+
+struct Secrets {
+    current: String,
+    // (unix time it stops working, stored secret)
+    previous: Option<(i64, String)>,
+}
+
+/// A rotation written to the database but not yet in force; see [`AdminSecrets::apply`].
+pub struct Rotation {
+    current: String,
+    pub previous_expires_at: i64,
+    previous: String,
+}
+
+impl AdminSecrets {
+    /// Starts from the settings, picking up a previous secret still in its grace period.
+    pub fn load(conn: &Connection, settings: &Settings) -> Result<Self> {
+        let previous: Option<String> = conn
+            .query_row("SELECT value FROM settings WHERE key = 'admin_secret_previous'", [], |row| row.get(0))
+            .optional()?;
+
+        let previous = previous.and_then(|value| {
+            let (expires, stored) = value.split_once('|')?;
+            Some((expires.parse().ok()?, stored.to_string()))
+        });
+
+        Ok(AdminSecrets {
+            inner: RwLock::new(Secrets {
+                current: settings.admin_secret.clone(),
+                previous,
+            }),
+        })
+    }
+
+    /// Replaces the secrets in force with those of `conn`'s database, as [`load`](Self::load)
+    /// would read them; for a database that was restored or switched to.
+    pub fn reload(&self, conn: &Connection, settings: &Settings) -> Result<()> {
+        let fresh = Self::load(conn, settings)?.inner.into_inner().unwrap_or_else(|e| e.into_inner());
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(())
+    }
+
+    pub fn verify(&self, candidate: &str) -> bool {
+        let secrets = self.inner.read().unwrap_or_else(|e| e.into_inner());
+
+        if verify_secret(&secrets.current, candidate) {
+            return true;
+        }
+        match &secrets.previous {
+            Some((expires, stored)) => chrono::Utc::now().timestamp() < *expires && verify_secret(stored, candidate),
+            None => false,
+        }
+    }
+
+    /// Stores the hash of `new_secret` as the admin secret and keeps the current one
+    /// valid for `grace_secs` more. Nothing changes in memory until [`apply`](Self::apply),
+    /// so call that once the surrounding transaction has committed.
+    pub fn prepare_rotation(&self, conn: &Connection, new_secret: &str, grace_secs: u64) -> Result<Rotation> {
+        let previous = self.inner.read().unwrap_or_else(|e| e.into_inner()).current.clone();
+        let current = hash_secret(new_secret);
+        let previous_expires_at = chrono::Utc::now().timestamp() + grace_secs as i64;
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        )?;
+        stmt.execute(["admin_secret", current.as_str()])?;
+        stmt.execute(["admin_secret_previous", format!("{}|{}", previous_expires_at, previous).as_str()])?;
+
+        Ok(Rotation {
+            current,
+            previous_expires_at,
+            previous,
+        })
+    }
+
+    pub fn apply(&self, rotation: Rotation) {
+        let mut secrets = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        secrets.current = rotation.current;
+        secrets.previous = Some((rotation.previous_expires_at, rotation.previous));
+    }
+}
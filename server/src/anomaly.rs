@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Allocation rate anomalies: an owner issuing far more IDs in an hour than it usually
+//! does, which is most often a client stuck in a retry loop.
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::sync::RwLock;
+
+use crate::{error, DbPool, Settings};
+
+/// One owner's hour that is out of line with its baseline.
+#[derive(Debug, Clone, Serialize)]
+pub struct Anomaly {
+    pub owner: String,
+    /// The hour (UTC) the IDs were issued in; the current one may still be growing
+    pub hour: String,
+    pub ids: i64,
+    /// IDs per hour the owner averaged over the `anomaly_baseline_days` before
+    pub baseline_per_hour: f64,
+}
+
+/// Outcome of the latest run, as shown by /stats.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyReport {
+    pub checked_at: String,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Compares each owner's IDs in the current and the previous hour with its hourly average
+/// over the `anomaly_baseline_days` before them. An hour is anomalous with at least
+/// `anomaly_min_ids` IDs and more than `anomaly_factor` times the average; an owner
+/// without history only needs the minimum.
+pub fn check(conn: &Connection, settings: &Settings) -> Result<Vec<Anomaly>> {
+    let days = settings.anomaly_baseline_days.max(1);
+    let mut stmt = conn.prepare(
+        "SELECT r.owner, r.hour, r.ids,
+                COALESCE((SELECT SUM(b.ids) FROM generation_volume b
+                          WHERE b.owner = r.owner AND b.hour < ?3
+                            AND b.hour >= strftime('%Y-%m-%d %H:00', ?3, '-' || ?1 || ' days')), 0) * 1.0 / (?1 * 24)
+         FROM generation_volume r
+         WHERE r.hour >= ?3 AND r.ids >= ?2
+         ORDER BY r.hour, r.owner",
+    )?;
+    let since = chrono::Utc::now() - chrono::Duration::hours(1);
+    let anomalies = stmt
+        .query_map(rusqlite::params![days, settings.anomaly_min_ids, since.format("%Y-%m-%d %H:00").to_string()], |row| {
+            Ok(Anomaly { owner: row.get(0)?, hour: row.get(1)?, ids: row.get(2)?, baseline_per_hour: row.get(3)? })
+        })?
+        .filter(|anomaly| {
+            anomaly.as_ref().map_or(true, |a| a.ids as f64 > settings.anomaly_factor as f64 * a.baseline_per_hour)
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(anomalies)
+}
+
+/// Runs the check and publishes the report in `latest`, for the scheduler. Anomalies
+/// are logged as alerts the first time they are seen, not again on every run of their hour.
+pub fn refresh(pool: &DbPool, settings: &Settings, latest: &RwLock<Option<AnomalyReport>>) -> Result<String> {
+    let conn = pool.get()?;
+    let anomalies = check(&conn, settings)?;
+
+    let mut latest = latest.write().unwrap_or_else(|e| e.into_inner());
+    let seen = |a: &Anomaly| {
+        latest.as_ref().is_some_and(|r| r.anomalies.iter().any(|s| s.owner == a.owner && s.hour == a.hour))
+    };
+    for a in anomalies.iter().filter(|a| !seen(a)) {
+        error!(
+            "ALERT: owner '{}' was issued {} IDs in the hour from {}, against {:.1} per hour normally",
+            a.owner, a.ids, a.hour, a.baseline_per_hour
+        );
+    }
+
+    let summary = format!("{} anomalous owner hour(s)", anomalies.len());
+    *latest = Some(AnomalyReport { checked_at: chrono::Utc::now().to_rfc3339(), anomalies });
+    Ok(summary)
+}
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::Connection;
+
+/// Appends one entry to the audit log. `actor` says who did it (e.g. "admin@127.0.0.1"),
+/// `target` what it was done to, if anything in particular.
+pub fn record(conn: &Connection, actor: &str, action: &str, target: Option<&str>, details: Option<&str>) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (actor, action, target, details) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![actor, action, target, details],
+    )?;
+    Ok(())
+}
+
+/// Deletes audit entries older than `max_age_days`, then the oldest beyond `max_rows`.
+/// A limit of 0 is no limit. Returns how many entries were deleted.
+pub fn prune(conn: &Connection, max_age_days: u64, max_rows: u64) -> Result<usize> {
+    let mut deleted = 0;
+    if max_age_days > 0 {
+        deleted += conn.execute(
+            "DELETE FROM audit_log WHERE at < datetime('now', ?1)",
+            [format!("-{} days", max_age_days)],
+        )?;
+    }
+    if max_rows > 0 {
+        // ids only grow, so everything below the newest max_rows is older
+        deleted += conn.execute(
+            "DELETE FROM audit_log WHERE id <= (SELECT id FROM audit_log ORDER BY id DESC LIMIT 1 OFFSET ?1)",
+            [max_rows],
+        )?;
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{holds, id_state, issue_id, owners, Settings};
+use anyhow::Result;
+use id_registry_core::lifecycle::{Event, Refused};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+/// What a batch confirm or delete did to one ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    Deleted,
+    /// The ID was deleted before this batch
+    IdDeleted,
+    IdNotFound,
+    /// The ID or its owner is under a legal hold; it was left alone
+    OnHold,
+    /// The ID belongs to another owner than the one the caller named
+    WrongOwner,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub id: String,
+    pub outcome: BatchOutcome,
+}
+
+/// Issues `count` IDs for `owner`, returning (id, created_at, config_version) in order.
+/// Meant to run inside one transaction: [`issue_id`] uses cached statements, so the
+/// insert is prepared once for the whole batch.
+pub fn issue_many(
+    conn: &Connection,
+    settings: &Settings,
+    owner: &str,
+    table: Option<&str>,
+    count: usize,
+) -> Result<Vec<(String, String, Option<i64>)>> {
+    (0..count).map(|_| issue_id(conn, settings, owner, table, None, None)).collect()
+}
+
+/// Confirms each of `ids` (recording `by`) with one prepared statement; IDs that
+/// can't be confirmed are reported in their result rather than failing the batch.
+/// With `owner`, only that owner's IDs are confirmed and the others are `wrong_owner`,
+/// as are those of owners `key` doesn't open.
+pub fn confirm_many(conn: &Connection, ids: &[String], by: Option<&str>, owner: Option<&str>, key: owners::KeyCheck) -> Result<Vec<BatchResult>> {
+    let mut confirm = conn.prepare_cached(
+        "UPDATE ids SET confirmed = 1, confirmed_at = CURRENT_TIMESTAMP, confirmed_by = ?2,
+                        version = version + 1, updated_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND confirmed = 0 AND deleted = 0 AND (?3 IS NULL OR owner = ?3)",
+    )?;
+
+    ids.iter()
+        .map(|id| {
+            let found = id_owner(conn, id)?;
+            let allowed = match found.as_deref() {
+                Some(found) => key.allows(conn, found)?,
+                None => true,
+            };
+            let outcome = if !allowed {
+                BatchOutcome::WrongOwner
+            } else if confirm.execute(rusqlite::params![id, by, owner])? > 0 {
+                BatchOutcome::Confirmed
+            } else if owner.is_some() && found.is_some_and(|found| Some(found.as_str()) != owner) {
+                BatchOutcome::WrongOwner
+            } else {
+                refused(conn, id, Event::Confirm)?
+            };
+            Ok(BatchResult { id: id.clone(), outcome })
+        })
+        .collect()
+}
+
+/// Confirms every unconfirmed live ID of `owner` (only those of `table`, if given),
+/// recording `by`. Returns the IDs it confirmed.
+pub fn confirm_owner(conn: &Connection, owner: &str, table: Option<&str>, by: Option<&str>) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "UPDATE ids SET confirmed = 1, confirmed_at = CURRENT_TIMESTAMP, confirmed_by = ?3,
+                        version = version + 1, updated_at = CURRENT_TIMESTAMP
+         WHERE owner = ?1 AND (?2 IS NULL OR table_name = ?2) AND confirmed = 0 AND deleted = 0
+         RETURNING id",
+    )?;
+    let ids = stmt.query_map(rusqlite::params![owner, table, by], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+/// Soft-deletes each of `ids` with one prepared statement, like [`confirm_many`].
+pub fn delete_many(conn: &Connection, ids: &[String]) -> Result<Vec<BatchResult>> {
+    let mut delete = conn.prepare_cached(
+        "UPDATE ids SET deleted = 1, confirmed = 0, version = version + 1, updated_at = CURRENT_TIMESTAMP,
+                        deleted_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND deleted = 0",
+    )?;
+
+    ids.iter()
+        .map(|id| {
+            if let Err(e) = holds::check_id(conn, id) {
+                e.downcast::<holds::OnHold>()?;
+                return Ok(BatchResult { id: id.clone(), outcome: BatchOutcome::OnHold });
+            }
+            let outcome = if delete.execute([id])? > 0 {
+                BatchOutcome::Deleted
+            } else {
+                refused(conn, id, Event::Delete)?
+            };
+            Ok(BatchResult { id: id.clone(), outcome })
+        })
+        .collect()
+}
+
+/// The owner `id` was issued to, if it was issued.
+pub fn id_owner(conn: &Connection, id: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare_cached("SELECT owner FROM ids WHERE id = ?1")?;
+    Ok(stmt.query_row([id], |row| row.get(0)).optional()?)
+}
+
+// Why `event` changed nothing for `id`: it doesn't exist, or its state refuses the event
+fn refused(conn: &Connection, id: &str, event: Event) -> Result<BatchOutcome> {
+    let Some(state) = id_state(conn, id)? else {
+        return Ok(BatchOutcome::IdNotFound);
+    };
+    Ok(match state.apply(event) {
+        Err(Refused::AlreadyConfirmed) => BatchOutcome::AlreadyConfirmed,
+        // Ok can't happen: the update would have matched an ID the event applies to
+        Err(Refused::Deleted) | Ok(_) => BatchOutcome::IdDeleted,
+    })
+}
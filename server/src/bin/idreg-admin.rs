@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Command-line administration of a registry database, for when editing it with sqlite3
+//! would bypass the server's checks.
+//!
+//!     idreg-admin settings [--db PATH] [--list] [--set KEY=VALUE ...] [--dry-run] [--force]
+use anyhow::{bail, Context, Result};
+use id_registry_server::{adminauth, check_keyspace, genconfig, get_db_path, load_settings, migrations, stage_settings, Settings};
+use rusqlite::Connection;
+use std::io::{self, BufRead, Write};
+
+const USAGE: &str = "Usage: idreg-admin settings [--db PATH] [--list] [--set KEY=VALUE ...] [--dry-run] [--force]
+
+Without --list or --set, the settings are edited interactively.
+Shrinking the keyspace or changing the charset needs --force.
+The database is PATH, else IDREG_DB_PATH, else the DBPath in the registry.";
+
+// Settings whose values are never printed
+const SECRET_SETTINGS: [&str; 3] = ["admin_secret", "backup_s3_secret_key", "backup_azure_sas"];
+
+struct Options {
+    db: Option<String>,
+    list: bool,
+    set: Vec<(String, String)>,
+    dry_run: bool,
+    force: bool,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("idreg-admin: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("settings") => settings(parse_options(args)?),
+        Some("--help" | "-h") | None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => bail!("unknown command '{}'\n\n{}", other, USAGE),
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options { db: None, list: false, set: Vec::new(), dry_run: false, force: false };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => options.db = Some(args.next().context("--db needs a path")?),
+            "--list" => options.list = true,
+            "--dry-run" => options.dry_run = true,
+            "--force" => options.force = true,
+            "--set" => {
+                let change = args.next().context("--set needs KEY=VALUE")?;
+                options.set.push(parse_change(&change)?);
+            }
+            other => bail!("unknown option '{}'\n\n{}", other, USAGE),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_change(change: &str) -> Result<(String, String)> {
+    let Some((key, value)) = change.split_once('=') else {
+        bail!("expected KEY=VALUE, got '{}'", change);
+    };
+    let key = key.trim().to_string();
+    // Stored hashed, as POST /admin/rotate_secret does
+    let value = if key == "admin_secret" { adminauth::hash_secret(value) } else { value.to_string() };
+    Ok((key, value))
+}
+
+fn settings(options: Options) -> Result<()> {
+    let path = match options.db {
+        Some(path) => path,
+        None => match std::env::var("IDREG_DB_PATH") {
+            Ok(path) if !path.trim().is_empty() => path,
+            _ => get_db_path().context("No database given (use --db PATH)")?,
+        },
+    };
+    let mut conn = Connection::open_with_flags(&path, rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .with_context(|| format!("Cannot open {}", path))?;
+    // The tables written below may be newer than the last server start
+    migrations::run(&mut conn)?;
+
+    if options.list {
+        return list(&conn);
+    }
+    let changes = if options.set.is_empty() { edit(&mut conn)? } else { options.set };
+    if changes.is_empty() {
+        println!("Nothing changed");
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    // Settings that don't load can't be made worse; there is nothing to guard
+    let current = load_settings(&tx).ok();
+    let proposed = stage_settings(&tx, &changes)?;
+    check_keyspace(&proposed)?;
+    if let Some(current) = &current {
+        genconfig::guard(current, &proposed, options.force).context("Rerun with --force to save it")?;
+    }
+    if options.dry_run {
+        println!("{} setting(s) valid; nothing saved (dry run)", changes.len());
+        return Ok(());
+    }
+    let version = genconfig::record(&tx, &proposed)?;
+    tx.commit()?;
+    println!("{} setting(s) saved; restart the server to apply them", changes.len());
+    if current.is_none_or(|current| genconfig::differs(&current, &proposed)) {
+        println!("Generation config version {}", version);
+    }
+    Ok(())
+}
+
+// Prints the settings the server would run with, defaults included
+fn list(conn: &Connection) -> Result<()> {
+    for (key, value) in values(&load_settings(conn)?)? {
+        println!("{} = {}", key, value);
+    }
+    Ok(())
+}
+
+fn values(settings: &Settings) -> Result<Vec<(String, String)>> {
+    let serde_json::Value::Object(map) = serde_json::to_value(settings)? else {
+        bail!("settings did not serialize to an object");
+    };
+    Ok(map
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                _ if SECRET_SETTINGS.contains(&key.as_str()) => "<secret>".to_string(),
+                serde_json::Value::String(text) => text,
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (key, value)
+        })
+        .collect())
+}
+
+// Reads KEY=VALUE lines until an empty one, checking each against the ones before it
+// (a rolled-back transaction), and returns the accepted changes once confirmed
+fn edit(conn: &mut Connection) -> Result<Vec<(String, String)>> {
+    match load_settings(conn) {
+        Ok(settings) => {
+            for (key, value) in values(&settings)? {
+                println!("  {} = {}", key, value);
+            }
+        }
+        // The point of the editor may be to repair exactly this
+        Err(e) => println!("The current settings don't load: {:#}", e),
+    }
+    println!("Enter KEY=VALUE to change a setting, KEY to show it, an empty line to finish.");
+
+    let mut changes: Vec<(String, String)> = Vec::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else { break };
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if !line.contains('=') {
+            if let Some((key, value)) = changes.iter().find(|(key, _)| key == line) {
+                let value = if SECRET_SETTINGS.contains(&key.as_str()) { "<secret>" } else { value };
+                println!("  {} = {} (not saved yet)", key, value);
+                continue;
+            }
+            let shown = load_settings(conn).and_then(|settings| values(&settings))?;
+            match shown.iter().find(|(key, _)| key == line) {
+                Some((key, value)) => println!("  {} = {}", key, value),
+                None => println!("  Unknown setting '{}'", line),
+            }
+            continue;
+        }
+
+        let change = match parse_change(line) {
+            Ok(change) => change,
+            Err(e) => {
+                println!("  {:#}", e);
+                continue;
+            }
+        };
+        let mut proposed = changes.clone();
+        proposed.retain(|(key, _)| *key != change.0);
+        proposed.push(change);
+        let tx = conn.transaction()?;
+        match stage_settings(&tx, &proposed).and_then(|settings| check_keyspace(&settings)) {
+            Ok(_) => changes = proposed,
+            Err(e) => println!("  Rejected: {:#}", e),
+        }
+    }
+
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    println!("Changes:");
+    for (key, value) in &changes {
+        let value = if SECRET_SETTINGS.contains(&key.as_str()) { "<secret>" } else { value };
+        println!("  {} = {}", key, value);
+    }
+    print!("Save them? [y/N] ");
+    io::stdout().flush()?;
+    let answer = lines.next().transpose()?.unwrap_or_default();
+    Ok(if answer.trim().eq_ignore_ascii_case("y") { changes } else { Vec::new() })
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{error, info};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops sending work to a failing database: after `threshold` failures in a row the
+/// circuit opens and requests are refused for `cooldown`. Then a single probe request
+/// is let through; its outcome closes the circuit or opens it again. A probe that hasn't
+/// reported back within `cooldown` is taken as lost, and the next request probes instead.
+/// A threshold of 0 disables it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // The probe is in flight since `since`; everyone else waits for its outcome
+    HalfOpen { since: Instant },
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Ok if a request may use the database, Err(time until the next probe) otherwise.
+    pub fn admit(&self) -> Result<(), Duration> {
+        if self.threshold == 0 {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(until - now);
+                }
+                info!("Database circuit half-open: probing");
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+            State::HalfOpen { since } => {
+                let now = Instant::now();
+                if now.duration_since(since) < self.cooldown {
+                    return Err(Duration::from_secs(1));
+                }
+                // The request that took the probe never reported back, e.g. it was cancelled
+                info!("Database circuit half-open: the last probe was lost; probing again");
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Reports how an admitted request went.
+    pub fn record(&self, ok: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        *state = match (&*state, ok) {
+            (State::HalfOpen { .. }, true) => {
+                info!("Database circuit closed: probe succeeded");
+                State::Closed { failures: 0 }
+            }
+            (State::HalfOpen { .. }, false) => {
+                error!("ALERT: database circuit re-opened: probe failed");
+                State::Open { until: Instant::now() + self.cooldown }
+            }
+            (State::Closed { failures }, false) if failures + 1 >= self.threshold => {
+                error!("ALERT: database circuit opened after {} failures in a row", failures + 1);
+                State::Open { until: Instant::now() + self.cooldown }
+            }
+            (State::Closed { failures }, false) => State::Closed { failures: failures + 1 },
+            (State::Closed { .. }, true) => State::Closed { failures: 0 },
+            // A request admitted before the circuit opened; its outcome changes nothing
+            (State::Open { until }, _) => State::Open { until: *until },
+        };
+    }
+
+    /// "closed", "open" or "half_open", for /stats.
+    pub fn state_name(&self) -> &'static str {
+        match *self.state.lock().unwrap_or_else(|e| e.into_inner()) {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry<V> {
+    value: V,
+    stored_at: Instant,
+    tick: u64,
+}
+
+struct Inner<V> {
+    entries: HashMap<String, Entry<V>>,
+    // Recency order: oldest tick first
+    order: BTreeMap<u64, String>,
+    next_tick: u64,
+    // Bumped by every invalidate and clear; see `generation`
+    generation: u64,
+}
+
+/// Small thread-safe LRU cache whose entries also expire after a fixed TTL.
+/// A capacity of 0 disables caching entirely.
+pub struct TtlCache<V> {
+    inner: Mutex<Inner<V>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        TtlCache {
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                next_tick: 0,
+                generation: 0,
+            }),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Returns a clone of the cached value, if present and not expired.
+    pub fn get(&self, key: &str) -> Option<V> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let tick = inner.next_tick;
+
+        let (old_tick, value) = match inner.entries.get_mut(key) {
+            Some(entry) if entry.stored_at.elapsed() < self.ttl => {
+                let old_tick = entry.tick;
+                entry.tick = tick;
+                (old_tick, Some(entry.value.clone()))
+            }
+            Some(entry) => (entry.tick, None),
+            None => return None,
+        };
+
+        inner.order.remove(&old_tick);
+        if value.is_some() {
+            inner.order.insert(tick, key.to_string());
+            inner.next_tick += 1;
+        } else {
+            inner.entries.remove(key);
+        }
+
+        value
+    }
+
+    /// Taken before reading a value from the database; pass it to [`insert`](Self::insert).
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).generation
+    }
+
+    /// Stores a value read since `generation`, evicting the least recently used entry if
+    /// full. Skipped if anything was invalidated meanwhile: a write may have committed
+    /// after the read, and its invalidation must not be undone by the older value.
+    pub fn insert(&self, key: &str, value: V, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.generation != generation {
+            return;
+        }
+        let tick = inner.next_tick;
+        inner.next_tick += 1;
+
+        if let Some(old) = inner.entries.remove(key) {
+            inner.order.remove(&old.tick);
+        }
+
+        while inner.entries.len() >= self.capacity {
+            match inner.order.pop_first() {
+                Some((_, oldest)) => {
+                    inner.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        inner.entries.insert(
+            key.to_string(),
+            Entry {
+                value,
+                stored_at: Instant::now(),
+                tick,
+            },
+        );
+        inner.order.insert(tick, key.to_string());
+    }
+
+    /// Drops every entry, e.g. after the database was replaced.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.entries.clear();
+        inner.order.clear();
+        inner.generation += 1;
+    }
+
+    /// Drops a key so the next read goes to the database.
+    pub fn invalidate(&self, key: &str) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = inner.entries.remove(key) {
+            inner.order.remove(&old.tick);
+        }
+        inner.generation += 1;
+    }
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Canary IDs: IDs that are never issued and that nobody has a reason to look up, planted
+//! in exports or test data so that a lookup shows the data has leaked or is being enumerated.
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Canary {
+    pub id: String,
+    /// Where the canary was planted, for whoever gets the alert
+    pub note: Option<String>,
+    pub created_at: String,
+}
+
+/// Returned (inside anyhow::Error) when registering an ID that has been issued, which
+/// its owner may look up any time.
+#[derive(Debug)]
+pub struct IssuedId {
+    pub id: String,
+}
+
+impl std::fmt::Display for IssuedId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ID {} has been issued and cannot be a canary", self.id)
+    }
+}
+
+impl std::error::Error for IssuedId {}
+
+pub fn list(conn: &Connection) -> Result<Vec<Canary>> {
+    let mut stmt = conn.prepare("SELECT id, note, created_at FROM canaries ORDER BY created_at, id")?;
+    let canaries = stmt
+        .query_map([], |row| Ok(Canary { id: row.get(0)?, note: row.get(1)?, created_at: row.get(2)? }))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(canaries)
+}
+
+/// Every canary ID, for checking lookups against without a query each.
+pub fn all(conn: &Connection) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT id FROM canaries")?;
+    let ids = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+/// Registers `id` as a canary, taking it out of the ID pool so it is never issued either.
+/// Returns false if it already is one; fails with IssuedId if it has been issued.
+pub fn add(conn: &Connection, id: &str, note: Option<&str>) -> Result<bool> {
+    if conn.query_row("SELECT EXISTS (SELECT 1 FROM ids WHERE id = ?1)", [id], |row| row.get(0))? {
+        return Err(IssuedId { id: id.to_string() }.into());
+    }
+    let added = conn.execute("INSERT OR IGNORE INTO canaries (id, note) VALUES (?1, ?2)", rusqlite::params![id, note])?;
+    conn.execute("DELETE FROM pool WHERE id = ?1", [id])?;
+    Ok(added > 0)
+}
+
+/// Unregisters a canary; returns false if `id` was none.
+pub fn remove(conn: &Connection, id: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM canaries WHERE id = ?1", [id])? > 0)
+}
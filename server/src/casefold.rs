@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `case_insensitive_ids`: IDs that differ only by letter case count as the same ID, for
+//! downstream systems that compare them that way. A `COLLATE NOCASE` unique index keeps
+//! a second spelling out of `ids`, generation treats one as taken, and lookups find the
+//! ID in whatever case it is asked for. Like SQLite's NOCASE, only ASCII letters fold.
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::Settings;
+
+pub use id_registry_core::casefold::key;
+
+/// The spelling `id` was issued in, if it differs only by case and the setting is on;
+/// otherwise `id` as it is. Only `ids` is searched, so retired IDs resolve through their
+/// alias afterwards like any other.
+pub fn stored_id(conn: &Connection, settings: &Settings, id: &str) -> Result<String> {
+    if !settings.case_insensitive_ids {
+        return Ok(id.to_string());
+    }
+    let stored = conn
+        .prepare_cached("SELECT id FROM ids WHERE id = ?1 COLLATE NOCASE")?
+        .query_row([id], |row| row.get(0))
+        .optional()?;
+    Ok(stored.unwrap_or_else(|| id.to_string()))
+}
+
+/// How many IDs share their lowercase form with another one, deleted IDs included.
+/// These have to be repaired (see repair::find_duplicates) before the setting can go on.
+pub fn duplicates(conn: &Connection) -> Result<u64> {
+    let count = conn.query_row(
+        "SELECT COALESCE(SUM(n), 0) FROM (SELECT COUNT(*) AS n FROM ids GROUP BY id COLLATE NOCASE HAVING n > 1)",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count)
+}
+
+/// Creates the NOCASE unique index when the setting is on and drops it when off, at
+/// startup. Fails while IDs that differ only by case exist.
+pub fn sync_index(conn: &Connection, settings: &Settings) -> Result<()> {
+    if !settings.case_insensitive_ids {
+        conn.execute("DROP INDEX IF EXISTS ids_id_nocase", [])?;
+        return Ok(());
+    }
+    let clashing = duplicates(conn)?;
+    if clashing > 0 {
+        anyhow::bail!(
+            "{} IDs differ from another only by letter case; repair them (POST /admin/find_duplicates) before turning on 'case_insensitive_ids'",
+            clashing
+        );
+    }
+    conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS ids_id_nocase ON ids (id COLLATE NOCASE)", [])
+        .context("Failed to create the case-insensitive index")?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The changes feed: every insert, update and removal of an `ids` row, in commit order.
+//! Triggers on `ids` (migration 18) write it, so no write path can forget to.
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+
+/// What happened to an ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOp {
+    Created,
+    Confirmed,
+    Updated,
+    /// Soft-deleted; the row is still there
+    Deleted,
+    /// The row itself is gone (purged or erased)
+    Removed,
+}
+
+impl ChangeOp {
+    fn parse(op: &str) -> Self {
+        match op {
+            "created" => ChangeOp::Created,
+            "confirmed" => ChangeOp::Confirmed,
+            "deleted" => ChangeOp::Deleted,
+            "removed" => ChangeOp::Removed,
+            _ => ChangeOp::Updated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    /// Position in the feed; pass the last one seen as `since` to continue after it
+    pub seq: i64,
+    pub id: String,
+    pub op: ChangeOp,
+    /// The ID's version after the change (before it, for `removed`)
+    pub version: i64,
+    pub at: String,
+}
+
+/// Returned (inside anyhow::Error) when `since` points before the oldest change kept,
+/// so changes the caller never saw have been pruned.
+#[derive(Debug)]
+pub struct CursorExpired {
+    pub since: i64,
+    pub oldest: i64,
+}
+
+impl std::fmt::Display for CursorExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "changes after {} were pruned; the oldest kept is {}", self.since, self.oldest)
+    }
+}
+
+impl std::error::Error for CursorExpired {}
+
+/// Up to `limit` changes after `since`, oldest first, only those of `id` if given.
+/// `since` = 0 starts at the oldest change kept. Fails with CursorExpired if some of the
+/// changes after a later `since` are no longer kept.
+pub fn after(conn: &Connection, since: i64, limit: usize, id: Option<&str>) -> Result<Vec<Change>> {
+    // seq never goes back (AUTOINCREMENT), so a gap before the oldest row means pruning;
+    // with every row pruned, the next change gets the sequence after the last one used
+    let oldest: Option<i64> = conn.query_row(
+        "SELECT COALESCE(MIN(seq), (SELECT seq + 1 FROM sqlite_sequence WHERE name = 'id_changes')) FROM id_changes",
+        [],
+        |row| row.get(0),
+    )?;
+    if let Some(oldest) = oldest
+        && since > 0
+        && since < oldest - 1
+    {
+        return Err(CursorExpired { since, oldest }.into());
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT seq, id, op, version, at FROM id_changes WHERE seq > ?1 AND (?3 IS NULL OR id = ?3) ORDER BY seq LIMIT ?2",
+    )?;
+    let changes = stmt
+        .query_map(rusqlite::params![since, limit, id], |row| {
+            let op: String = row.get(2)?;
+            Ok(Change { seq: row.get(0)?, id: row.get(1)?, op: ChangeOp::parse(&op), version: row.get(3)?, at: row.get(4)? })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(changes)
+}
+
+/// Deletes changes older than `days`; 0 keeps them all.
+pub fn prune(conn: &Connection, days: u64) -> Result<usize> {
+    if days == 0 {
+        return Ok(0);
+    }
+    Ok(conn.execute("DELETE FROM id_changes WHERE at < datetime('now', ?1)", [format!("-{} days", days)])?)
+}
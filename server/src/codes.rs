@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Machine-readable renderings of IDs for label printing, as PNG or SVG, so clients
+//! don't each need a barcode library.
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+/// A grid of modules, dark or light, row by row.
+pub struct Matrix {
+    pub width: usize,
+    pub height: usize,
+    pub dark: Vec<bool>,
+    /// Light modules to add left and right
+    pub quiet_x: usize,
+    /// Light modules to add above and below
+    pub quiet_y: usize,
+}
+
+impl Matrix {
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        x >= self.quiet_x && y >= self.quiet_y && x - self.quiet_x < self.width && y - self.quiet_y < self.height
+            && self.dark[(y - self.quiet_y) * self.width + x - self.quiet_x]
+    }
+
+    fn full_width(&self) -> usize {
+        self.width + 2 * self.quiet_x
+    }
+
+    fn full_height(&self) -> usize {
+        self.height + 2 * self.quiet_y
+    }
+
+    /// The module size in pixels that brings the image closest to `size` pixels wide, at least 1.
+    pub fn scale_for(&self, size: u32) -> u32 {
+        (size / self.full_width() as u32).max(1)
+    }
+}
+
+/// The QR code of `data`, at the smallest version that holds it, with medium error correction.
+pub fn qr(data: &str) -> Result<Matrix> {
+    let code = qrcode::QrCode::with_error_correction_level(data.as_bytes(), qrcode::EcLevel::M)?;
+    let width = code.width();
+    let dark = code.to_colors().into_iter().map(|c| c == qrcode::Color::Dark).collect();
+    Ok(Matrix { width, height: width, dark, quiet_x: 4, quiet_y: 4 })
+}
+
+// Code 128 symbols by value: widths of bar, space, bar, space, bar, space (and a final
+// bar for stop), in modules
+const CODE128: [&[u8]; 107] = [
+    b"212222", b"222122", b"222221", b"121223", b"121322", b"131222", b"122213", b"122312", b"132212", b"221213",
+    b"221312", b"231212", b"112232", b"122132", b"122231", b"113222", b"123122", b"123221", b"223211", b"221132",
+    b"221231", b"213212", b"223112", b"312131", b"311222", b"321122", b"321221", b"312212", b"322112", b"322211",
+    b"212123", b"212321", b"232121", b"111323", b"131123", b"131321", b"112313", b"132113", b"132311", b"211313",
+    b"231113", b"231311", b"112133", b"112331", b"132131", b"113123", b"113321", b"133121", b"313121", b"211331",
+    b"231131", b"213113", b"213311", b"213131", b"311123", b"311321", b"331121", b"312113", b"312311", b"332111",
+    b"314111", b"221411", b"431111", b"111224", b"111422", b"121124", b"121421", b"141122", b"141221", b"112214",
+    b"112412", b"122114", b"122411", b"142112", b"142211", b"241211", b"221114", b"413111", b"241112", b"134111",
+    b"111242", b"121142", b"121241", b"114212", b"124112", b"124211", b"411212", b"421112", b"421211", b"212141",
+    b"214121", b"412121", b"111143", b"111341", b"131141", b"114113", b"114311", b"411113", b"411311", b"113141",
+    b"114131", b"311141", b"411131", b"211412", b"211214", b"211232", b"2331112",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: usize = 106;
+// Bar height in modules; about a quarter of the width of a 12 character ID
+const CODE128_HEIGHT: usize = 50;
+
+/// The Code 128 barcode of `data` in code set B, which covers printable ASCII. Fails on
+/// anything else.
+pub fn code128(data: &str) -> Result<Matrix> {
+    let mut values = vec![CODE128_START_B];
+    for c in data.chars() {
+        if !(' '..='~').contains(&c) {
+            anyhow::bail!("'{}' cannot be written in Code 128 (printable ASCII only)", c);
+        }
+        values.push(c as usize - 32);
+    }
+    let checksum = values.iter().enumerate().map(|(i, v)| i.max(1) * v).sum::<usize>() % 103;
+    values.push(checksum);
+    values.push(CODE128_STOP);
+
+    let mut row = Vec::new();
+    for value in values {
+        for (i, width) in CODE128[value].iter().enumerate() {
+            row.extend(std::iter::repeat_n(i % 2 == 0, usize::from(width - b'0')));
+        }
+    }
+    let width = row.len();
+    Ok(Matrix { width, height: CODE128_HEIGHT, dark: row.repeat(CODE128_HEIGHT), quiet_x: 10, quiet_y: 0 })
+}
+
+/// A 1-bit grayscale PNG of `matrix`, each module `scale` pixels square.
+pub fn png(matrix: &Matrix, scale: u32) -> Result<Vec<u8>> {
+    let scale = scale as usize;
+    let (width, height) = (matrix.full_width() * scale, matrix.full_height() * scale);
+
+    // Each scanline: filter type 0, then 8 pixels per byte, 1 for white
+    let mut raw = Vec::with_capacity(height * (1 + width.div_ceil(8)));
+    for y in 0..matrix.full_height() {
+        let mut line = vec![0u8; width.div_ceil(8)];
+        for px in 0..width {
+            if !matrix.is_dark(px / scale, y) {
+                line[px / 8] |= 0x80 >> (px % 8);
+            }
+        }
+        for _ in 0..scale {
+            raw.push(0);
+            raw.extend_from_slice(&line);
+        }
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&raw)?;
+    let data = zlib.finish()?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, body) in [(b"IHDR", header.as_slice()), (b"IDAT", data.as_slice()), (b"IEND", &[][..])] {
+        png.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(body);
+        png.extend_from_slice(kind);
+        png.extend_from_slice(body);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    Ok(png)
+}
+
+/// An SVG of `matrix`, `scale` pixels per module. Dark runs of a row are drawn as one
+/// rectangle, as tall as the rows below that repeat it (all of a barcode's).
+pub fn svg(matrix: &Matrix, scale: u32) -> String {
+    let (width, height) = (matrix.full_width(), matrix.full_height());
+    let row = |y: usize| (0..width).map(|x| matrix.is_dark(x, y)).collect::<Vec<_>>();
+    let mut path = String::new();
+    let mut y = 0;
+    while y < height {
+        let dark = row(y);
+        let mut rows = 1;
+        while y + rows < height && row(y + rows) == dark {
+            rows += 1;
+        }
+        let mut x = 0;
+        while x < width {
+            if !dark[x] {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < width && dark[x] {
+                x += 1;
+            }
+            let _ = write!(path, "M{},{}h{}v{}h-{}z", start, y, x - start, rows, x - start);
+        }
+        y += rows;
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">\
+         <rect width=\"{}\" height=\"{}\" fill=\"#fff\"/><path d=\"{}\" fill=\"#000\"/></svg>\n",
+        width as u32 * scale, height as u32 * scale, width, height, width, height, path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The symbol values of a Code 128 matrix, read back from its first row: 11 modules
+    // each, then the 13 of the stop pattern
+    fn symbols(matrix: &Matrix) -> Vec<usize> {
+        let row = &matrix.dark[..matrix.width];
+        let modules = |pattern: &[u8]| -> Vec<bool> {
+            pattern.iter().enumerate().flat_map(|(i, width)| std::iter::repeat_n(i % 2 == 0, usize::from(width - b'0'))).collect()
+        };
+        let (body, stop) = row.split_at(row.len() - 13);
+        assert_eq!(stop, modules(CODE128[CODE128_STOP]).as_slice());
+        body.chunks(11)
+            .map(|chunk| CODE128.iter().position(|pattern| modules(pattern) == chunk).expect("a Code 128 symbol"))
+            .collect()
+    }
+
+    #[test]
+    fn code128_checksum() {
+        // 104 + 48×1 + 42×2 + 42×3 + 17×4 + 18×5 + 19×6 + 35×7 = 879, and 879 mod 103 = 55
+        assert_eq!(symbols(&code128("PJJ123C").unwrap()), [104, 48, 42, 42, 17, 18, 19, 35, 55]);
+        // 104 + 33×1 = 137, and 137 mod 103 = 34
+        assert_eq!(symbols(&code128("A").unwrap()), [104, 33, 34]);
+        // Start B alone: 104 mod 103 = 1
+        assert_eq!(symbols(&code128("").unwrap()), [104, 1]);
+    }
+
+    #[test]
+    fn code128_refuses_other_than_printable_ascii() {
+        assert!(code128("AB\u{e9}").is_err());
+        assert!(code128("AB\n").is_err());
+    }
+}
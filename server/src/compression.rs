@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+use std::io::{Cursor, Write};
+use id_registry_server::error;
+
+/// Compresses sized response bodies of at least `min_bytes` with br or gzip,
+/// whichever the client prefers in Accept-Encoding. Streamed bodies are left alone.
+pub struct Compression {
+    pub min_bytes: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+// Picks the highest-weighted encoding we support; br wins ties
+fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(f32, Encoding)> = None;
+
+    for part in accept_encoding.split(',') {
+        let mut pieces = part.trim().split(';');
+        let name = pieces.next().unwrap_or("").trim().to_ascii_lowercase();
+        let weight = pieces
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let encoding = match name.as_str() {
+            "br" => Encoding::Brotli,
+            "gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+
+        if weight > 0.0 && best.is_none_or(|(w, _)| weight > w) {
+            best = Some((weight, encoding));
+        }
+    }
+
+    best.map(|(_, encoding)| encoding)
+}
+
+fn compress(encoding: Encoding, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut out = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                encoder.write_all(data)?;
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.headers().contains("Content-Encoding") {
+            return;
+        }
+        match res.body().preset_size() {
+            Some(size) if size >= self.min_bytes => {}
+            _ => return,
+        }
+
+        let Some(encoding) = req.headers().get_one("Accept-Encoding").and_then(preferred_encoding) else {
+            return;
+        };
+
+        let Ok(body) = res.body_mut().to_bytes().await else {
+            return;
+        };
+
+        match compress(encoding, &body) {
+            Ok(compressed) => {
+                res.set_header(Header::new("Content-Encoding", encoding.name()));
+                res.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            Err(e) => {
+                error!("Response compression failed: {}", e);
+                res.set_sized_body(body.len(), Cursor::new(body));
+            }
+        }
+        res.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+    }
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::sync::RwLock;
+
+use crate::{error, genconfig, DbPool, Settings};
+
+/// One broken invariant: how many rows break it and a few of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub check: &'static str,
+    pub count: u64,
+    pub examples: Vec<String>,
+}
+
+/// Outcome of the latest run, as shown by /stats.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyReport {
+    pub checked_at: String,
+    pub violations: Vec<Violation>,
+}
+
+// Each check selects the offending rows' IDs
+const CHECKS: &[(&str, &str)] = &[
+    ("confirmed_and_deleted", "SELECT id FROM ids WHERE confirmed != 0 AND deleted != 0"),
+    ("missing_created_at", "SELECT id FROM ids WHERE created_at IS NULL OR created_at = ''"),
+    ("pooled_id_already_issued", "SELECT id FROM pool WHERE id IN (SELECT id FROM ids)"),
+    ("alias_to_missing_id", "SELECT alias FROM id_aliases WHERE id NOT IN (SELECT id FROM ids WHERE deleted = 0)"),
+];
+
+/// Runs every invariant check and returns the ones that fail.
+pub fn check(conn: &Connection, settings: &Settings) -> Result<Vec<Violation>> {
+    let mut violations = Vec::new();
+
+    for (name, sql) in CHECKS {
+        let mut stmt = conn.prepare(sql)?;
+        let ids = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        push_violation(&mut violations, name, ids);
+    }
+
+    // The pool is cleared at startup, so anything left in it must fit the current settings
+    let mut stmt = conn.prepare("SELECT id FROM pool")?;
+    let mut stale = Vec::new();
+    for id in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        let id = id?;
+        if !settings.id_matches_format(&id) {
+            stale.push(id);
+        }
+    }
+    push_violation(&mut violations, "pooled_id_wrong_format", stale);
+
+    // IDs must fit the generation config they were recorded under
+    let configs = genconfig::all(conn)?;
+    let mut stmt = conn.prepare("SELECT id, config_version FROM ids WHERE config_version IS NOT NULL AND deleted = 0")?;
+    let mut mismatched = Vec::new();
+    for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))? {
+        let (id, version) = row?;
+        if !configs.get(&version).is_some_and(|config| config.matches(&id, settings.generation_max_extra_length)) {
+            mismatched.push(id);
+        }
+    }
+    push_violation(&mut violations, "id_wrong_format_for_config", mismatched);
+
+    Ok(violations)
+}
+
+fn push_violation(violations: &mut Vec<Violation>, check: &'static str, ids: Vec<String>) {
+    if ids.is_empty() {
+        return;
+    }
+    violations.push(Violation {
+        check,
+        count: ids.len() as u64,
+        examples: ids.into_iter().take(10).collect(),
+    });
+}
+
+/// Runs the checks and publishes the report in `latest`, for the scheduler.
+/// Violations are logged as alerts.
+pub fn refresh(pool: &DbPool, settings: &Settings, latest: &RwLock<Option<ConsistencyReport>>) -> Result<String> {
+    let conn = pool.get()?;
+    let violations = check(&conn, settings)?;
+    for v in &violations {
+        error!("ALERT: consistency check {} failed for {} row(s), e.g. {:?}", v.check, v.count, v.examples);
+    }
+
+    let summary = format!("{} violation(s)", violations.len());
+    let report = ConsistencyReport {
+        checked_at: chrono::Utc::now().to_rfc3339(),
+        violations,
+    };
+    *latest.write().unwrap_or_else(|e| e.into_inner()) = Some(report);
+    Ok(summary)
+}
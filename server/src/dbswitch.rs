@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::restore::{self, InvalidSnapshot, SnapshotCheck};
+use crate::{audit, idpool, jobs, load_settings, migrations, DbManager};
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+
+/// Checks that the database at `path` can replace the current one: the same checks as
+/// a restore, and its settings must load as they would at startup.
+pub fn validate(path: &str) -> Result<SnapshotCheck> {
+    if path.trim().is_empty() || !Path::new(path).is_file() {
+        return Err(InvalidSnapshot(format!("no database file at '{}'", path)).into());
+    }
+    let check = restore::verify(Path::new(path))?;
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    load_settings(&conn).map_err(|e| InvalidSnapshot(format!("settings don't load: {:#}", e)))?;
+    Ok(check)
+}
+
+/// Moves `manager`'s pool onto the (validated) database at `path`. It is migrated first
+/// and, when `active`, prepared as at startup: pooled IDs may come from other settings
+/// and nobody runs its jobs any more. The switch is audited as done by `actor`.
+/// Call it on the writer thread, so no write is half-way when the pool moves over.
+pub fn switch(manager: &DbManager, path: &str, actor: &str, active: bool) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    migrations::run(&mut conn)?;
+    if active {
+        idpool::clear(&conn)?;
+        jobs::abandon_running(&conn)?;
+        audit::record(&conn, actor, "switch_db", Some(path), Some(&manager.path()))?;
+    }
+    manager.switch(path)
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Data-deletion requests: removing everything the registry holds about one owner.
+use anyhow::Result;
+use rusqlite::Connection;
+
+use crate::{genstats, holds};
+use serde::{Deserialize, Serialize};
+
+/// The owner anonymized IDs are handed to.
+pub const ERASED_OWNER: &str = "erased";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EraseMode {
+    /// Keep the IDs (so they stay reserved) under [`ERASED_OWNER`], without user_id, confirmed_by
+    /// or external_ref
+    Anonymize,
+    /// Remove the ID rows too; the registry forgets the IDs were ever issued
+    Delete,
+}
+
+impl EraseMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EraseMode::Anonymize => "anonymize",
+            EraseMode::Delete => "delete",
+        }
+    }
+}
+
+/// What an erasure touched, or would touch.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureSummary {
+    pub mode: EraseMode,
+    pub ids: usize,
+    pub notes: usize,
+    pub refs: usize,
+    pub labels: usize,
+    pub aliases: usize,
+    pub audit_entries: usize,
+    /// Background jobs whose stored results listed the owner's IDs
+    pub jobs: usize,
+}
+
+// The owner's IDs, for the statements below
+const OWNER_IDS: &str = "SELECT id FROM ids WHERE owner = ?1";
+
+/// Erases `owner`: their notes, external refs, labels and audit entries go, as do the
+/// stored results of jobs that listed them, and their IDs are anonymized or deleted.
+/// Aliases are only removed with [`EraseMode::Delete`]. Fails with OnHold, changing
+/// nothing, if the owner or one of their IDs is held. Run it in a transaction.
+pub fn erase_owner(conn: &Connection, owner: &str, mode: EraseMode) -> Result<ErasureSummary> {
+    holds::check_owner(conn, owner)?;
+    let delete = |sql: &str| -> Result<usize> { Ok(conn.execute(&sql.replace("{ids}", OWNER_IDS), [owner])?) };
+
+    let notes = delete("DELETE FROM id_notes WHERE target IN ({ids})")?;
+    let refs = delete("DELETE FROM id_refs WHERE target IN ({ids})")?;
+    let labels = delete("DELETE FROM id_labels WHERE id IN ({ids})")?;
+    let audit_entries = delete("DELETE FROM audit_log WHERE target = ?1 OR target IN ({ids})")?;
+
+    // Job results are NDJSON of ID details, so the owner appears verbatim
+    let fragment = serde_json::to_string(&serde_json::json!({ "owner": owner }))?;
+    let fragment = fragment.trim_start_matches('{').trim_end_matches('}');
+    let job_ids: Vec<String> = conn
+        .prepare("SELECT DISTINCT job_id FROM job_chunks WHERE instr(body, ?1) > 0")?
+        .query_map([fragment], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for job_id in &job_ids {
+        conn.execute("DELETE FROM job_chunks WHERE job_id = ?1", [job_id])?;
+        conn.execute("DELETE FROM jobs WHERE job_id = ?1", [job_id])?;
+    }
+
+    let (ids, aliases) = match mode {
+        EraseMode::Anonymize => {
+            let ids = conn.execute(
+                "UPDATE ids SET owner = ?2, user_id = NULL, confirmed_by = NULL, external_ref = NULL,
+                                version = version + 1, updated_at = CURRENT_TIMESTAMP
+                 WHERE owner = ?1",
+                [owner, ERASED_OWNER],
+            )?;
+            (ids, 0)
+        }
+        EraseMode::Delete => {
+            let aliases = delete("DELETE FROM id_aliases WHERE id IN ({ids}) OR alias IN ({ids})")?;
+            (delete("DELETE FROM ids WHERE owner = ?1")?, aliases)
+        }
+    };
+
+    // The counts stay, so generation volume totals don't change, but not under the owner's name
+    genstats::move_owner(conn, owner, ERASED_OWNER)?;
+    conn.execute("DELETE FROM owner_keys WHERE owner = ?1", [owner])?;
+
+    Ok(ErasureSummary { mode, ids, notes, refs, labels, aliases, audit_entries, jobs: job_ids.len() })
+}
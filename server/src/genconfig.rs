@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{policy::Policy, Settings};
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// Returned (inside anyhow::Error) when a settings change needs `force` to be saved.
+#[derive(Debug)]
+pub struct ForceRequired(pub String);
+
+impl std::fmt::Display for ForceRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ForceRequired {}
+
+/// One recorded id_length / charset / id_content_policy / generation_mode combination.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationConfig {
+    pub version: i64,
+    pub id_length: u32,
+    pub charset: String,
+    /// `id_content_policy` as saved, e.g. `no_all_digits`
+    pub content_policy: String,
+    /// `random` or `sequence`
+    pub generation_mode: String,
+    /// Characters generation added to `id_length` for the IDs of this version, when
+    /// collisions piled up (see `generation_max_extra_length`); 0 for the configs as set
+    pub extra_length: u32,
+    pub created_at: String,
+}
+
+/// Why changing from `current` to `proposed` needs an explicit `force`, if it does: a
+/// smaller keyspace, a different set of characters or new `id_content_policy` rules.
+/// IDs issued under the old values may no longer pass validation against the new ones.
+pub fn force_reason(current: &Settings, proposed: &Settings) -> Option<String> {
+    let chars = |settings: &Settings| settings.charset.chars().collect::<BTreeSet<_>>();
+    let added: Vec<&str> = proposed
+        .id_content_policy
+        .rules()
+        .iter()
+        .filter(|rule| !current.id_content_policy.rules().contains(rule))
+        .map(|rule| rule.as_str())
+        .collect();
+    if chars(current) != chars(proposed) {
+        Some("the charset changes".to_string())
+    } else if !added.is_empty() {
+        Some(format!("id_content_policy adds {}", added.join(", ")))
+    } else if proposed.keyspace_bits() < current.keyspace_bits() {
+        Some(format!(
+            "the keyspace shrinks from {:.1} to {:.1} bits",
+            current.keyspace_bits(),
+            proposed.keyspace_bits()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Fails with [`ForceRequired`] when the change needs `force` and didn't get it.
+pub fn guard(current: &Settings, proposed: &Settings, force: bool) -> Result<()> {
+    match force_reason(current, proposed) {
+        Some(reason) if !force => Err(ForceRequired(reason).into()),
+        _ => Ok(()),
+    }
+}
+
+impl GenerationConfig {
+    /// Whether `id` could have been generated under this config, allowing for up to
+    /// `extra_length` characters added by generation when collisions piled up.
+    pub fn matches(&self, id: &str, extra_length: u32) -> bool {
+        crate::matches_format(self.id_length + self.extra_length, extra_length, &self.charset, id)
+            && Policy::parse(&self.content_policy).is_ok_and(|policy| policy.allows(id))
+    }
+}
+
+/// A recorded config with the number of IDs issued under it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigUsage {
+    #[serde(flatten)]
+    pub config: GenerationConfig,
+    pub ids: i64,
+}
+
+/// Whether `current` and `proposed` would be recorded as different versions.
+pub fn differs(current: &Settings, proposed: &Settings) -> bool {
+    current.id_length != proposed.id_length
+        || current.charset != proposed.charset
+        || current.id_content_policy != proposed.id_content_policy
+        || current.generation_mode != proposed.generation_mode
+}
+
+/// Records the id_length, charset, id_content_policy and generation_mode of `settings`
+/// as a new version unless they match the latest one. Returns the version they are recorded under.
+pub fn record(conn: &Connection, settings: &Settings) -> Result<i64> {
+    let policy = settings.id_content_policy.to_string();
+    if let Some(latest) = latest(conn)?
+        && latest.id_length == settings.id_length
+        && latest.charset == settings.charset
+        && latest.content_policy == policy
+        && latest.generation_mode == settings.generation_mode
+    {
+        return Ok(latest.version);
+    }
+    conn.execute(
+        "INSERT INTO generation_configs (id_length, charset, content_policy, generation_mode) VALUES (?1, ?2, ?3, ?4)",
+        (settings.id_length, &settings.charset, &policy, &settings.generation_mode),
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Records the config of IDs generated `extra_length` characters longer than the
+/// id_length of `settings`, unless it already is. The insert in [`issue_id`](crate::issue_id)
+/// stamps such IDs with it.
+pub fn record_lengthened(conn: &Connection, settings: &Settings, extra_length: u32) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO generation_configs (id_length, charset, content_policy, generation_mode, extra_length)
+         SELECT ?1, ?2, ?3, ?4, ?5 WHERE NOT EXISTS
+             (SELECT 1 FROM generation_configs
+              WHERE id_length = ?1 AND charset = ?2 AND content_policy = ?3 AND generation_mode = ?4 AND extra_length = ?5)",
+    )?
+    .execute((settings.id_length, &settings.charset, settings.id_content_policy.to_string(), &settings.generation_mode, extra_length))?;
+    Ok(())
+}
+
+/// The most recently recorded configuration as set, leaving out lengthened ones.
+pub fn latest(conn: &Connection) -> Result<Option<GenerationConfig>> {
+    Ok(conn
+        .query_row(
+            "SELECT version, id_length, charset, content_policy, generation_mode, extra_length, created_at FROM generation_configs
+             WHERE extra_length = 0 ORDER BY version DESC LIMIT 1",
+            [],
+            from_row,
+        )
+        .optional()?)
+}
+
+/// Every recorded config, oldest first, with its ID count.
+pub fn usage(conn: &Connection) -> Result<Vec<ConfigUsage>> {
+    let mut stmt = conn.prepare(
+        "SELECT g.version, g.id_length, g.charset, g.content_policy, g.generation_mode, g.extra_length, g.created_at,
+                (SELECT COUNT(*) FROM ids WHERE config_version = g.version)
+         FROM generation_configs g ORDER BY g.version",
+    )?;
+    let rows = stmt
+        .query_map([], |row| Ok(ConfigUsage { config: from_row(row)?, ids: row.get(7)? }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Every recorded config by version.
+pub fn all(conn: &Connection) -> Result<HashMap<i64, GenerationConfig>> {
+    let mut stmt = conn.prepare(
+        "SELECT version, id_length, charset, content_policy, generation_mode, extra_length, created_at FROM generation_configs",
+    )?;
+    let rows = stmt
+        .query_map([], from_row)?
+        .map(|config| config.map(|config| (config.version, config)))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    Ok(rows)
+}
+
+fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<GenerationConfig> {
+    Ok(GenerationConfig {
+        version: row.get(0)?,
+        id_length: row.get(1)?,
+        charset: row.get(2)?,
+        content_policy: row.get(3)?,
+        generation_mode: row.get(4)?,
+        extra_length: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
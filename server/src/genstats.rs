@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Generation statistics: collision retries per day, and the number of IDs issued per
+//! hour and owner (`generation_volume`, counted by a trigger on `ids`, migration 20).
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+
+/// Collision retries of the IDs generated on one UTC day.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationDay {
+    pub day: String,
+    pub generations: i64,
+    pub avg_retries: f64,
+    pub max_retries: i64,
+}
+
+/// Adds one successful generation that needed `retries` rejected candidates to today's totals.
+pub fn record(conn: &Connection, retries: u32) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO generation_stats (day, generations, retries, max_retries) VALUES (date('now'), 1, ?1, ?1)
+         ON CONFLICT (day) DO UPDATE SET generations = generations + 1,
+                                         retries = retries + excluded.retries,
+                                         max_retries = MAX(max_retries, excluded.max_retries)",
+    )?.execute([retries])?;
+    Ok(())
+}
+
+/// Deletes the totals, and hourly volumes, of days more than `days` ago; 0 keeps them
+/// all. Returns the number of days removed from the retry totals.
+pub fn prune(conn: &Connection, days: u64) -> Result<usize> {
+    if days == 0 {
+        return Ok(0);
+    }
+    let cutoff = format!("-{} days", days);
+    conn.execute("DELETE FROM generation_volume WHERE hour < date('now', ?1)", [&cutoff])?;
+    Ok(conn.execute("DELETE FROM generation_stats WHERE day < date('now', ?1)", [&cutoff])?)
+}
+
+/// The last `days` days that saw any generation, newest first.
+pub fn recent(conn: &Connection, days: u32) -> Result<Vec<GenerationDay>> {
+    let mut stmt = conn.prepare(
+        "SELECT day, generations, CAST(retries AS REAL) / generations, max_retries FROM generation_stats
+         WHERE day > date('now', '-' || ?1 || ' days') ORDER BY day DESC",
+    )?;
+    let recent = stmt
+        .query_map([days], |row| {
+            Ok(GenerationDay {
+                day: row.get(0)?,
+                generations: row.get(1)?,
+                avg_retries: row.get(2)?,
+                max_retries: row.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(recent)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    pub fn parse(granularity: &str) -> Option<Self> {
+        match granularity {
+            "hour" => Some(Granularity::Hour),
+            "day" => Some(Granularity::Day),
+            _ => None,
+        }
+    }
+
+    // Length of the bucket's prefix of an hour ("2024-05-01 13:00")
+    fn prefix(self) -> usize {
+        match self {
+            Granularity::Hour => 16,
+            Granularity::Day => 10,
+        }
+    }
+}
+
+/// IDs issued in one hour or day (UTC).
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumePoint {
+    pub at: String,
+    pub ids: i64,
+}
+
+/// IDs issued per hour or day over the last `days` days, oldest first, for one owner or
+/// all of them. Buckets without any are left out.
+pub fn volume(conn: &Connection, owner: Option<&str>, granularity: Granularity, days: u32) -> Result<Vec<VolumePoint>> {
+    let mut stmt = conn.prepare(
+        "SELECT substr(hour, 1, ?1) AS bucket, SUM(ids) FROM generation_volume
+         WHERE hour >= date('now', '-' || ?2 || ' days') AND (?3 IS NULL OR owner = ?3)
+         GROUP BY bucket ORDER BY bucket",
+    )?;
+    let points = stmt
+        .query_map(rusqlite::params![granularity.prefix(), days, owner], |row| {
+            Ok(VolumePoint { at: row.get(0)?, ids: row.get(1)? })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(points)
+}
+
+/// Moves the volumes counted for `from` to `to`, when an owner is merged or erased.
+pub fn move_owner(conn: &Connection, from: &str, to: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO generation_volume (hour, owner, ids)
+         SELECT hour, ?2, ids FROM generation_volume WHERE owner = ?1
+         ON CONFLICT (hour, owner) DO UPDATE SET ids = ids + excluded.ids",
+        [from, to],
+    )?;
+    conn.execute("DELETE FROM generation_volume WHERE owner = ?1", [from])?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Legal holds: IDs, or all IDs of an owner, that must not be deleted, purged or erased
+//! until the hold is released.
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldKind {
+    /// One ID
+    Id,
+    /// Every ID of an owner, including ones issued after the hold was placed
+    Owner,
+}
+
+impl HoldKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HoldKind::Id => "id",
+            HoldKind::Owner => "owner",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "id" => Some(HoldKind::Id),
+            "owner" => Some(HoldKind::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hold {
+    pub kind: HoldKind,
+    pub target: String,
+    pub reason: Option<String>,
+    pub created_at: String,
+}
+
+/// Returned (inside anyhow::Error) when deleting, purging or erasing something under a hold.
+#[derive(Debug)]
+pub struct OnHold {
+    pub kind: HoldKind,
+    pub target: String,
+}
+
+impl std::fmt::Display for OnHold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            HoldKind::Id => write!(f, "ID {} is under a legal hold", self.target),
+            HoldKind::Owner => write!(f, "Owner '{}' is under a legal hold", self.target),
+        }
+    }
+}
+
+impl std::error::Error for OnHold {}
+
+/// SQL condition true for rows of `ids` (aliased `i`) that no hold covers.
+pub const NOT_HELD: &str = "i.id NOT IN (SELECT target FROM holds WHERE kind = 'id')
+     AND i.owner NOT IN (SELECT target FROM holds WHERE kind = 'owner')";
+
+fn hold_from_row(row: &Row<'_>) -> rusqlite::Result<Hold> {
+    let kind: String = row.get(0)?;
+    Ok(Hold {
+        kind: HoldKind::parse(&kind).unwrap_or(HoldKind::Id),
+        target: row.get(1)?,
+        reason: row.get(2)?,
+        created_at: row.get(3)?,
+    })
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<Hold>> {
+    let mut stmt = conn.prepare("SELECT kind, target, reason, created_at FROM holds ORDER BY created_at, kind, target")?;
+    let holds = stmt.query_map([], hold_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(holds)
+}
+
+pub fn get(conn: &Connection, kind: HoldKind, target: &str) -> Result<Option<Hold>> {
+    let hold = conn
+        .query_row(
+            "SELECT kind, target, reason, created_at FROM holds WHERE kind = ?1 AND target = ?2",
+            [kind.as_str(), target],
+            hold_from_row,
+        )
+        .optional()?;
+    Ok(hold)
+}
+
+/// Places a hold; returns false if there already is one on `target`.
+pub fn place(conn: &Connection, kind: HoldKind, target: &str, reason: Option<&str>) -> Result<bool> {
+    let placed = conn.execute(
+        "INSERT OR IGNORE INTO holds (kind, target, reason) VALUES (?1, ?2, ?3)",
+        rusqlite::params![kind.as_str(), target, reason],
+    )?;
+    Ok(placed > 0)
+}
+
+/// Releases a hold; returns false if there was none.
+pub fn release(conn: &Connection, kind: HoldKind, target: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM holds WHERE kind = ?1 AND target = ?2", [kind.as_str(), target])? > 0)
+}
+
+/// Fails with OnHold if `id`, or its owner, is held.
+pub fn check_id(conn: &Connection, id: &str) -> Result<()> {
+    let held = conn
+        .prepare_cached(
+            "SELECT kind, target FROM holds
+             WHERE (kind = 'id' AND target = ?1)
+                OR (kind = 'owner' AND target = (SELECT owner FROM ids WHERE id = ?1))
+             LIMIT 1",
+        )?
+        .query_row([id], held_row)
+        .optional()?;
+    held.map_or(Ok(()), |held| Err(held.into()))
+}
+
+/// Fails with OnHold if `owner`, or any of their IDs, is held.
+pub fn check_owner(conn: &Connection, owner: &str) -> Result<()> {
+    let held = conn
+        .query_row(
+            "SELECT kind, target FROM holds
+             WHERE (kind = 'owner' AND target = ?1)
+                OR (kind = 'id' AND target IN (SELECT id FROM ids WHERE owner = ?1))
+             ORDER BY kind DESC
+             LIMIT 1",
+            [owner],
+            held_row,
+        )
+        .optional()?;
+    held.map_or(Ok(()), |held| Err(held.into()))
+}
+
+fn held_row(row: &Row<'_>) -> rusqlite::Result<OnHold> {
+    let kind: String = row.get(0)?;
+    Ok(OnHold { kind: HoldKind::parse(&kind).unwrap_or(HoldKind::Id), target: row.get(1)? })
+}
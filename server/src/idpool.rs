@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::{error, generate_id_counted, genstats, DbPool, Settings};
+
+/// Takes one pre-generated ID out of the pool, or None if the pool is empty.
+/// The DELETE ... RETURNING makes the claim atomic across workers.
+pub fn claim(conn: &Connection) -> Result<Option<String>> {
+    let mut stmt = conn.prepare_cached("DELETE FROM pool WHERE id = (SELECT id FROM pool LIMIT 1) RETURNING id")?;
+    let id = stmt.query_row([], |row| row.get(0)).optional()?;
+    Ok(id)
+}
+
+/// Tops the pool up to `target` entries. Returns how many IDs were added.
+pub fn refill(conn: &Connection, settings: &Settings, target: usize) -> Result<usize> {
+    let current: usize = conn.query_row("SELECT COUNT(*) FROM pool", [], |row| row.get(0))?;
+
+    let mut added = 0;
+    for _ in current..target {
+        let (id, retries) = generate_id_counted(conn, settings, None)?;
+        conn.execute("INSERT INTO pool (id) VALUES (?1)", [&id])?;
+        genstats::record(conn, retries)?;
+        added += 1;
+    }
+
+    Ok(added)
+}
+
+/// Empties the pool, e.g. after the ID format settings changed.
+pub fn clear(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM pool", [])?;
+    Ok(())
+}
+
+/// Starts a background thread that keeps the pool at `settings.id_pool_size`.
+/// `may_write` is checked before each round so passive or suspended nodes leave the DB alone.
+pub fn spawn_refiller<F>(pool: DbPool, settings: Arc<Settings>, may_write: F)
+where
+    F: Fn() -> bool + Send + 'static,
+{
+    if settings.id_pool_size == 0 {
+        return;
+    }
+
+    thread::spawn(move || loop {
+        if may_write() {
+            let result = pool
+                .get()
+                .map_err(anyhow::Error::from)
+                .and_then(|conn| refill(&conn, &settings, settings.id_pool_size));
+
+            if let Err(e) = result {
+                error!("ID pool refill failed: {}", e);
+            }
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    });
+}
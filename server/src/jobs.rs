@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+/// A background job as stored in `jobs`. `status` is `running`, `done` or `failed`;
+/// `done` counts the result lines written so far, out of `total` when known up front.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub job_id: String,
+    pub kind: String,
+    pub status: String,
+    pub total: Option<i64>,
+    pub done: i64,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+}
+
+/// Registers a new running job and returns its ID.
+pub fn create(conn: &Connection, kind: &str, total: Option<usize>) -> Result<String> {
+    let job_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    conn.execute(
+        "INSERT INTO jobs (job_id, kind, total) VALUES (?1, ?2, ?3)",
+        rusqlite::params![job_id, kind, total.map(|t| t as i64)],
+    )?;
+    Ok(job_id)
+}
+
+pub fn get(conn: &Connection, job_id: &str) -> Result<Option<Job>> {
+    let job = conn
+        .query_row(
+            "SELECT job_id, kind, status, total, done, error, created_at, finished_at FROM jobs WHERE job_id = ?1",
+            [job_id],
+            |row| {
+                Ok(Job {
+                    job_id: row.get(0)?,
+                    kind: row.get(1)?,
+                    status: row.get(2)?,
+                    total: row.get(3)?,
+                    done: row.get(4)?,
+                    error: row.get(5)?,
+                    created_at: row.get(6)?,
+                    finished_at: row.get(7)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(job)
+}
+
+/// Stores the next chunk of `lines` result lines (NDJSON in `body`) and advances progress.
+pub fn append(conn: &Connection, job_id: &str, body: &str, lines: usize) -> Result<()> {
+    conn.execute(
+        "INSERT INTO job_chunks (job_id, seq, body)
+         VALUES (?1, (SELECT COALESCE(MAX(seq), 0) + 1 FROM job_chunks WHERE job_id = ?1), ?2)",
+        rusqlite::params![job_id, body],
+    )?;
+    conn.execute("UPDATE jobs SET done = done + ?2 WHERE job_id = ?1", rusqlite::params![job_id, lines as i64])?;
+    Ok(())
+}
+
+pub fn finish(conn: &Connection, job_id: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE jobs SET status = 'done', finished_at = CURRENT_TIMESTAMP WHERE job_id = ?1",
+        [job_id],
+    )?;
+    Ok(())
+}
+
+/// Marks a job failed; the chunks written before the failure are kept.
+pub fn fail(conn: &Connection, job_id: &str, error: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE jobs SET status = 'failed', error = ?2, finished_at = CURRENT_TIMESTAMP WHERE job_id = ?1",
+        rusqlite::params![job_id, error],
+    )?;
+    Ok(())
+}
+
+/// The result chunk following `seq` (0 for the first), as (seq, body).
+pub fn chunk_after(conn: &Connection, job_id: &str, seq: i64) -> Result<Option<(i64, String)>> {
+    let chunk = conn
+        .query_row(
+            "SELECT seq, body FROM job_chunks WHERE job_id = ?1 AND seq > ?2 ORDER BY seq LIMIT 1",
+            rusqlite::params![job_id, seq],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(chunk)
+}
+
+/// Fails jobs left running by a previous process; they have no one working on them.
+/// Returns how many there were.
+pub fn abandon_running(conn: &Connection) -> Result<usize> {
+    let count = conn.execute(
+        "UPDATE jobs SET status = 'failed', error = 'interrupted by a server restart',
+                         finished_at = CURRENT_TIMESTAMP
+         WHERE status = 'running'",
+        [],
+    )?;
+    Ok(count)
+}
+
+/// Deletes finished jobs, with their results, older than `hours`. Returns how many.
+pub fn expire(conn: &Connection, hours: u64) -> Result<usize> {
+    let cutoff = format!("-{} hours", hours);
+    conn.execute(
+        "DELETE FROM job_chunks WHERE job_id IN
+             (SELECT job_id FROM jobs WHERE status != 'running' AND finished_at < datetime('now', ?1))",
+        [&cutoff],
+    )?;
+    let count = conn.execute(
+        "DELETE FROM jobs WHERE status != 'running' AND finished_at < datetime('now', ?1)",
+        [&cutoff],
+    )?;
+    Ok(count)
+}
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::IdNotFound;
+
+/// A label and how many IDs carry it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Label {
+    pub name: String,
+    pub description: Option<String>,
+    pub ids: i64,
+}
+
+/// Returned (inside anyhow::Error) when tagging with a label that was never created.
+#[derive(Debug)]
+pub struct UnknownLabel {
+    pub name: String,
+}
+
+impl std::fmt::Display for UnknownLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Label '{}' does not exist", self.name)
+    }
+}
+
+impl std::error::Error for UnknownLabel {}
+
+pub fn list(conn: &Connection) -> Result<Vec<Label>> {
+    let mut stmt = conn.prepare(
+        "SELECT l.name, l.description, COUNT(il.id) FROM labels l
+         LEFT JOIN id_labels il ON il.label = l.name
+         GROUP BY l.name ORDER BY l.name",
+    )?;
+    let labels = stmt
+        .query_map([], |row| {
+            Ok(Label {
+                name: row.get(0)?,
+                description: row.get(1)?,
+                ids: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(labels)
+}
+
+/// Creates a label; fails with a UNIQUE violation if the name is taken.
+pub fn create(conn: &Connection, name: &str, description: Option<&str>) -> Result<()> {
+    conn.execute("INSERT INTO labels (name, description) VALUES (?1, ?2)", rusqlite::params![name, description])?;
+    Ok(())
+}
+
+/// Removes a label from the catalog and from every ID carrying it. Returns false if
+/// there is no such label.
+pub fn delete(conn: &Connection, name: &str) -> Result<bool> {
+    conn.execute("DELETE FROM id_labels WHERE label = ?1", [name])?;
+    Ok(conn.execute("DELETE FROM labels WHERE name = ?1", [name])? > 0)
+}
+
+/// Puts `label` on every ID in `ids`; IDs already carrying it are left alone. Fails with
+/// UnknownLabel or IdNotFound (for the first ID never issued) without tagging anything,
+/// provided the caller rolls back. Returns how many IDs were newly tagged.
+pub fn tag(conn: &Connection, label: &str, ids: &[String]) -> Result<usize> {
+    let known: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM labels WHERE name = ?1)", [label], |row| row.get(0))?;
+    if !known {
+        return Err(UnknownLabel { name: label.to_string() }.into());
+    }
+
+    let mut exists = conn.prepare("SELECT EXISTS (SELECT 1 FROM ids WHERE id = ?1)")?;
+    let mut insert = conn.prepare("INSERT OR IGNORE INTO id_labels (id, label) VALUES (?1, ?2)")?;
+    let mut tagged = 0;
+    for id in ids {
+        if !exists.query_row([id], |row| row.get::<_, bool>(0))? {
+            return Err(IdNotFound { id: id.clone() }.into());
+        }
+        tagged += insert.execute([id.as_str(), label])?;
+    }
+    Ok(tagged)
+}
+
+/// Takes `label` off `id`. Returns false if the ID didn't carry it.
+pub fn untag(conn: &Connection, label: &str, id: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM id_labels WHERE id = ?1 AND label = ?2", [id, label])? > 0)
+}
+
+/// Labels on `id`, by name.
+pub fn labels_of(conn: &Connection, id: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT label FROM id_labels WHERE id = ?1 ORDER BY label")?;
+    let labels = stmt.query_map([id], |row| row.get(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(labels)
+}
@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use id_registry_server::info;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The request paths whose latency is tracked.
+#[derive(Clone, Copy)]
+pub enum Path {
+    Generate,
+    Confirm,
+    Get,
+}
+
+impl Path {
+    const ALL: [Path; 3] = [Path::Generate, Path::Confirm, Path::Get];
+
+    fn name(self) -> &'static str {
+        match self {
+            Path::Generate => "generate",
+            Path::Confirm => "confirm",
+            Path::Get => "get",
+        }
+    }
+
+    // Maps a Rocket route (by handler name) to the path it belongs to
+    fn of_route(name: &str) -> Option<Path> {
+        match name {
+            "generate" | "generate_proto" | "generate_form" | "generate_query" => Some(Path::Generate),
+            "confirm" => Some(Path::Confirm),
+            "get_id" => Some(Path::Get),
+            _ => None,
+        }
+    }
+}
+
+// Bucket upper bounds in microseconds, 100 µs to 10 s; anything slower lands in +Inf
+const BOUNDS_MICROS: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+    2_500_000, 5_000_000, 10_000_000,
+];
+
+// Fixed-bucket histogram, cheap enough to update on every request
+struct Histogram {
+    buckets: [AtomicU64; BOUNDS_MICROS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = BOUNDS_MICROS.iter().position(|&b| micros <= b).unwrap_or(BOUNDS_MICROS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts(&self) -> Vec<u64> {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect()
+    }
+
+    // Upper bound of the bucket holding the q-th quantile, in ms; None before any sample
+    // or when it falls in the +Inf bucket
+    fn quantile_ms(counts: &[u64], q: f64) -> Option<f64> {
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = (q * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BOUNDS_MICROS.get(i).map(|&b| b as f64 / 1000.0);
+            }
+        }
+        None
+    }
+
+    fn summary(&self) -> Summary {
+        let counts = self.counts();
+        Summary {
+            count: counts.iter().sum(),
+            p50_ms: Self::quantile_ms(&counts, 0.50),
+            p95_ms: Self::quantile_ms(&counts, 0.95),
+            p99_ms: Self::quantile_ms(&counts, 0.99),
+        }
+    }
+}
+
+/// Percentiles of one histogram, as bucket upper bounds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+/// Total request time and the part of it spent in the database, for one path.
+#[derive(Debug, Clone, Serialize)]
+pub struct PathLatency {
+    pub total: Summary,
+    pub db: Summary,
+}
+
+/// Latency histograms per path since the server started.
+pub struct Latency {
+    total: [Histogram; 3],
+    db: [Histogram; 3],
+}
+
+impl Latency {
+    pub fn new() -> Self {
+        Latency {
+            total: std::array::from_fn(|_| Histogram::new()),
+            db: std::array::from_fn(|_| Histogram::new()),
+        }
+    }
+
+    pub fn record_db(&self, path: Path, elapsed: Duration) {
+        self.db[path as usize].record(elapsed);
+    }
+
+    fn record_total(&self, path: Path, elapsed: Duration) {
+        self.total[path as usize].record(elapsed);
+    }
+
+    /// Percentiles per path, as shown by /stats.
+    pub fn report(&self) -> BTreeMap<&'static str, PathLatency> {
+        Path::ALL
+            .iter()
+            .map(|&path| {
+                let latency = PathLatency {
+                    total: self.total[path as usize].summary(),
+                    db: self.db[path as usize].summary(),
+                };
+                (path.name(), latency)
+            })
+            .collect()
+    }
+
+    /// The histograms in Prometheus text format, for /metrics.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP id_registry_request_duration_seconds Request latency by path; part=db is the database share");
+        let _ = writeln!(out, "# TYPE id_registry_request_duration_seconds histogram");
+
+        for path in Path::ALL {
+            for (part, histogram) in [("total", &self.total[path as usize]), ("db", &self.db[path as usize])] {
+                let labels = format!("path=\"{}\",part=\"{}\"", path.name(), part);
+                let mut cumulative = 0;
+                for (i, count) in histogram.counts().iter().enumerate() {
+                    cumulative += count;
+                    let le = match BOUNDS_MICROS.get(i) {
+                        Some(&b) => format!("{}", b as f64 / 1_000_000.0),
+                        None => "+Inf".to_string(),
+                    };
+                    let _ = writeln!(out, "id_registry_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, le, cumulative);
+                }
+                let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+                let _ = writeln!(out, "id_registry_request_duration_seconds_sum{{{}}} {}", labels, sum);
+                let _ = writeln!(out, "id_registry_request_duration_seconds_count{{{}}} {}", labels, cumulative);
+            }
+        }
+        out
+    }
+}
+
+/// Times every request to a tracked path from arrival to response.
+pub struct LatencyTimer(pub Arc<Latency>);
+
+// When the request arrived, kept in the request-local cache
+struct Started(Instant);
+
+#[rocket::async_trait]
+impl Fairing for LatencyTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Latency histograms",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        // With JSON logging Rocket's request log is off, so every request gets a line here
+        if id_registry_server::log::json() {
+            let started = req.local_cache(|| Started(Instant::now()));
+            info!("{} {} {} {:.1}ms", req.method(), req.uri().path(), res.status().code, started.0.elapsed().as_secs_f64() * 1000.0);
+        }
+        let Some(path) = req.route().and_then(|r| r.name.as_deref()).and_then(Path::of_route) else {
+            return;
+        };
+        let started = req.local_cache(|| Started(Instant::now()));
+        self.0.record_total(path, started.0.elapsed());
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::Connection;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{error, info, DbPool};
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Builds an identifier for this server instance, unique enough to tell
+/// two nodes sharing one database apart.
+pub fn node_id() -> String {
+    let host = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "node".to_string());
+
+    format!("{}-{}", host, std::process::id())
+}
+
+/// Takes or refreshes the leader lease.
+/// Succeeds if nobody holds it, we already hold it, or the holder let it expire.
+/// Returns true if this node is the leader afterwards.
+pub fn try_acquire(conn: &Connection, node_id: &str, ttl_secs: u64) -> Result<bool> {
+    let now = now_secs();
+
+    let changed = conn.execute(
+        "INSERT INTO leader_lease (id, holder, expires_at) VALUES (1, ?1, ?2)
+         ON CONFLICT(id) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at
+         WHERE leader_lease.holder = excluded.holder OR leader_lease.expires_at < ?3",
+        rusqlite::params![node_id, now + ttl_secs as i64, now],
+    )?;
+
+    Ok(changed > 0)
+}
+
+/// Gives up the lease (if we hold it) so a standby can take over immediately.
+pub fn release(conn: &Connection, node_id: &str) -> Result<()> {
+    conn.execute("DELETE FROM leader_lease WHERE id = 1 AND holder = ?1", [node_id])?;
+    Ok(())
+}
+
+/// Returns the current lease holder, if the lease is still valid.
+pub fn current_holder(conn: &Connection) -> Result<Option<String>> {
+    use rusqlite::OptionalExtension;
+
+    let holder = conn
+        .query_row(
+            "SELECT holder FROM leader_lease WHERE id = 1 AND expires_at >= ?1",
+            [now_secs()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(holder)
+}
+
+/// Starts a background thread that keeps refreshing (or trying to take) the lease
+/// every third of its lifetime, and publishes the outcome through `is_leader`.
+pub fn spawn_keeper(pool: DbPool, node_id: String, ttl_secs: u64, is_leader: Arc<AtomicBool>) {
+    let interval = Duration::from_secs((ttl_secs / 3).max(1));
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let leader = match pool.get() {
+            Ok(conn) => try_acquire(&conn, &node_id, ttl_secs).unwrap_or_else(|e| {
+                error!("Lease refresh failed: {}", e);
+                false
+            }),
+            Err(e) => {
+                error!("Lease refresh could not get a connection: {}", e);
+                false
+            }
+        };
+
+        let was_leader = is_leader.swap(leader, Ordering::SeqCst);
+        if leader && !was_leader {
+            info!("Leader lease acquired – this node is now active");
+        } else if !leader && was_leader {
+            info!("Leader lease lost – this node is now passive");
+        }
+    });
+}
@@ -1,21 +1,71 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
+pub mod adminauth;
+pub mod anomaly;
+pub mod audit;
+pub mod batch;
+pub mod breaker;
+pub mod changes;
+pub mod cache;
+pub mod canaries;
+pub mod casefold;
+pub mod codes;
+pub mod consistency;
+pub mod dbswitch;
+pub mod erasure;
+pub mod genconfig;
+pub mod genstats;
+pub mod holds;
+pub mod idpool;
+pub mod jobs;
+pub mod labels;
+pub mod lease;
+pub mod log;
+pub mod maintenance;
+pub mod migrations;
+pub mod notes;
+#[cfg(feature = "object-storage")]
+pub mod offsite;
+pub mod owners;
+pub mod preflight;
+pub mod proto;
+pub mod ratelimit;
+pub mod refs;
+#[cfg(windows)]
+pub mod regwatch;
+pub mod repair;
+pub mod replication;
+pub mod restore;
+pub mod scheduler;
+pub mod selftest;
+pub mod store;
+pub mod suspension;
+pub mod tables;
+pub mod tokens;
+pub mod writer;
+
 use anyhow::{Context, Result};
-use rand::seq::SliceRandom;
 use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use serde::Serialize;
+use rusqlite::{Connection, OptionalExtension};
+use std::sync::{Arc, RwLock};
+#[cfg(windows)]
 use winreg::enums::*;
+#[cfg(windows)]
 use winreg::RegKey;
 
-#[derive(Debug, Clone, Serialize)]
-pub struct Settings {
-    pub id_length: u32,
-    pub charset: String,
-    pub admin_secret: String,
-}
+// The rules themselves live in id-registry-core; re-exported so the server's modules
+// and binaries keep using them from here
+pub use id_registry_core::{
+    check_suffix, display_id, errors, format_problem, lifecycle, matches_format, policy, normalize_id, normalize_scan,
+    sequence, IdChanges, IdConflict, IdDeleted, IdNotFound, IdRecord, InvalidSuffix, KeyspaceExhausted, OpKind,
+    Settings, VersionConflict,
+};
+use id_registry_core::lifecycle::IdState;
+use id_registry_core::settings::{max_utf8_bytes, normalize_charset};
+use store::SqliteStore;
 
-pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbPool = Pool<DbManager>;
 
+#[cfg(windows)]
 pub fn get_db_path() -> Result<String> {
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
     let key = hkcu
@@ -33,7 +83,109 @@ pub fn get_db_path() -> Result<String> {
     Ok(path)
 }
 
-pub fn load_settings(conn: &r2d2::PooledConnection<SqliteConnectionManager>) -> Result<Settings> {
+/// Stores `path` as the DBPath the server opens at its next start.
+#[cfg(windows)]
+pub fn set_db_path(path: &str) -> Result<()> {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let (key, _) = hkcu
+        .create_subkey("Software\\IdRegistry\\Settings")
+        .context("Failed to open IdRegistry registry key")?;
+    key.set_value("DBPath", &path).context("Failed to write DBPath to registry")?;
+    Ok(())
+}
+
+// Elsewhere there is no registry; the container profile and --dev do without it
+#[cfg(not(windows))]
+pub fn get_db_path() -> Result<String> {
+    anyhow::bail!("No Windows registry on this platform (set IDREG_PROFILE=container or run with --dev)")
+}
+
+#[cfg(not(windows))]
+pub fn set_db_path(_path: &str) -> Result<()> {
+    anyhow::bail!("No Windows registry on this platform")
+}
+
+/// Returned (inside anyhow::Error) by [`stage_settings`] for a key that is not a setting.
+#[derive(Debug)]
+pub struct UnknownSetting(pub String);
+
+impl std::fmt::Display for UnknownSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown setting '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownSetting {}
+
+/// Writes `changes` to the settings table and loads the result, which validates it the
+/// way the server does at startup. Meant for a transaction the caller commits, or drops
+/// to only check the values. Fails with [`UnknownSetting`] for a key the server doesn't
+/// read, so a typo isn't silently stored and ignored.
+pub fn stage_settings(conn: &Connection, changes: &[(String, String)]) -> Result<Settings> {
+    for (key, value) in changes {
+        conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            [key, value],
+        )?;
+    }
+    let settings = load_settings(conn)?;
+
+    let known = serde_json::to_value(&settings)?;
+    if let Some((key, _)) = changes.iter().find(|(key, _)| known.get(key).is_none()) {
+        return Err(UnknownSetting(key.clone()).into());
+    }
+    Ok(settings)
+}
+
+/// Container profile: stores each `IDREG_<KEY>` environment variable as setting
+/// `<key>` (lowercased), so the environment stays the source of truth across restarts.
+/// Unknown keys and invalid values fail the whole set and nothing is stored, as does a
+/// change that [`genconfig::guard`] refuses unless `IDREG_FORCE=1`. The admin secret is
+/// stored hashed. Returns the names of the settings applied.
+pub fn apply_env_settings(conn: &mut Connection) -> Result<Vec<String>> {
+    let tx = conn.transaction()?;
+    let applied = stage_env_settings(&tx)?;
+    if !applied.is_empty() {
+        tx.commit()?;
+    }
+    Ok(applied)
+}
+
+/// [`apply_env_settings`] without the transaction: the caller commits it, or drops it
+/// to only check the environment (`--check`).
+pub fn stage_env_settings(tx: &Connection) -> Result<Vec<String>> {
+    let vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("IDREG_") && !PROFILE_VARS.contains(&name.as_str()))
+        .map(|(name, value)| (name["IDREG_".len()..].to_ascii_lowercase(), value))
+        .collect();
+    if vars.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let current = load_settings(tx)?;
+    let mut changes = Vec::new();
+    for (key, value) in &vars {
+        if key == "admin_secret" {
+            // Rehashing an unchanged secret would only churn the salt
+            if !adminauth::verify_secret(&current.admin_secret, value) {
+                changes.push((key.clone(), adminauth::hash_secret(value)));
+            }
+        } else {
+            changes.push((key.clone(), value.clone()));
+        }
+    }
+    let proposed = stage_settings(tx, &changes).map_err(|e| match e.downcast_ref::<UnknownSetting>() {
+        Some(unknown) => anyhow::anyhow!("IDREG_{} is not a known setting", unknown.0.to_ascii_uppercase()),
+        None => e.context("Invalid setting in the environment"),
+    })?;
+    let force = std::env::var("IDREG_FORCE").is_ok_and(|value| value == "1");
+    genconfig::guard(&current, &proposed, force).context("Set IDREG_FORCE=1 to apply the environment's settings")?;
+
+    Ok(vars.into_iter().map(|(key, _)| key).collect())
+}
+
+pub fn load_settings(conn: &Connection) -> Result<Settings> {
     let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
 
     let id_length_str: String = stmt
@@ -47,98 +199,580 @@ pub fn load_settings(conn: &r2d2::PooledConnection<SqliteConnectionManager>) ->
     let charset: String = stmt
         .query_row(["charset"], |row| row.get(0))
         .context("Missing 'charset' in settings table")?;
+    let charset = normalize_charset(&charset).context("Invalid 'charset' value")?;
 
     let admin_secret: String = stmt
         .query_row(["admin_secret"], |row| row.get(0))
         .context("Missing 'admin_secret' in settings table")?;
 
-    Ok(Settings {
+    let lease_ttl_secs = optional_setting(&mut stmt, "lease_ttl_secs", 15)?;
+    let cache_capacity = optional_setting(&mut stmt, "cache_capacity", 10_000)?;
+    let cache_ttl_secs = optional_setting(&mut stmt, "cache_ttl_secs", 30)?;
+    let negative_cache_ttl_secs = optional_setting(&mut stmt, "negative_cache_ttl_secs", 5)?;
+    let id_pool_size = optional_setting(&mut stmt, "id_pool_size", 0)?;
+    let rate_limit_per_minute = optional_setting(&mut stmt, "rate_limit_per_minute", 0)?;
+    let suspend_retry_after_secs = optional_setting(&mut stmt, "suspend_retry_after_secs", 10)?;
+    let read_timeout_ms = optional_setting(&mut stmt, "read_timeout_ms", 2_000)?;
+    let write_timeout_ms = optional_setting(&mut stmt, "write_timeout_ms", 5_000)?;
+    let admin_timeout_ms = optional_setting(&mut stmt, "admin_timeout_ms", 30_000)?;
+    let json_limit_kb = optional_setting(&mut stmt, "json_limit_kb", 1_024)?;
+    let form_limit_kb = optional_setting(&mut stmt, "form_limit_kb", 32)?;
+    let bytes_limit_kb = optional_setting(&mut stmt, "bytes_limit_kb", 64)?;
+    let compression_min_bytes = optional_setting(&mut stmt, "compression_min_bytes", 1_024)?;
+    let local_socket_path = optional_setting(&mut stmt, "local_socket_path", String::new())?;
+    let admin_port = optional_setting(&mut stmt, "admin_port", 0)?;
+    let admin_address = optional_setting(&mut stmt, "admin_address", "127.0.0.1".to_string())?;
+    let secret_grace_secs = optional_setting(&mut stmt, "secret_grace_secs", 300)?;
+    let shadow_mode = optional_setting(&mut stmt, "shadow_mode", false)?;
+    let enforce_table_catalog = optional_setting(&mut stmt, "enforce_table_catalog", false)?;
+    let lookup_max_ids = optional_setting(&mut stmt, "lookup_max_ids", 1000)?;
+    let pool_wait_ms = optional_setting(&mut stmt, "pool_wait_ms", 250)?;
+    let breaker_threshold = optional_setting(&mut stmt, "breaker_threshold", 5)?;
+    let breaker_cooldown_secs = optional_setting(&mut stmt, "breaker_cooldown_secs", 10)?;
+    let busy_retry_budget_ms = optional_setting(&mut stmt, "busy_retry_budget_ms", 1000)?;
+    let batch_stream_max_ids = optional_setting(&mut stmt, "batch_stream_max_ids", 100000)?;
+    let job_retention_hours = optional_setting(&mut stmt, "job_retention_hours", 24)?;
+    let consistency_schedule = optional_setting(&mut stmt, "consistency_schedule", "0 * * * *".to_string())?;
+    let janitor_schedule = optional_setting(&mut stmt, "janitor_schedule", "*/15 * * * *".to_string())?;
+    let checkpoint_schedule = optional_setting(&mut stmt, "checkpoint_schedule", "*/5 * * * *".to_string())?;
+    let backup_schedule = optional_setting(&mut stmt, "backup_schedule", String::new())?;
+    let backup_dir = optional_setting(&mut stmt, "backup_dir", String::new())?;
+    let backup_keep = optional_setting(&mut stmt, "backup_keep", 7)?;
+    let backup_upload = optional_setting(&mut stmt, "backup_upload", String::new())?;
+    let backup_upload_url = optional_setting(&mut stmt, "backup_upload_url", String::new())?;
+    let backup_upload_prefix = optional_setting(&mut stmt, "backup_upload_prefix", "id-registry/".to_string())?;
+    let backup_upload_keep = optional_setting(&mut stmt, "backup_upload_keep", 7)?;
+    let backup_s3_region = optional_setting(&mut stmt, "backup_s3_region", "us-east-1".to_string())?;
+    let backup_s3_access_key = optional_setting(&mut stmt, "backup_s3_access_key", String::new())?;
+    let backup_s3_secret_key = optional_setting(&mut stmt, "backup_s3_secret_key", String::new())?;
+    let backup_azure_sas = optional_setting(&mut stmt, "backup_azure_sas", String::new())?;
+    let restore_upload_max_mb = optional_setting(&mut stmt, "restore_upload_max_mb", 1024)?;
+    let replica_path = optional_setting(&mut stmt, "replica_path", String::new())?;
+    let replica_interval_ms = optional_setting(&mut stmt, "replica_interval_ms", 1000)?;
+    let audit_retention_days = optional_setting(&mut stmt, "audit_retention_days", 0)?;
+    let audit_max_rows = optional_setting(&mut stmt, "audit_max_rows", 0)?;
+    let generation_stats_retention_days = optional_setting(&mut stmt, "generation_stats_retention_days", 0)?;
+    let deleted_retention_days = optional_setting(&mut stmt, "deleted_retention_days", 0)?;
+    let purge_schedule = optional_setting(&mut stmt, "purge_schedule", "0 3 * * *".to_string())?;
+    let changes_retention_days = optional_setting(&mut stmt, "changes_retention_days", 7)?;
+    let anomaly_schedule = optional_setting(&mut stmt, "anomaly_schedule", "*/5 * * * *".to_string())?;
+    let anomaly_factor = optional_setting(&mut stmt, "anomaly_factor", 100)?;
+    let anomaly_min_ids = optional_setting(&mut stmt, "anomaly_min_ids", 1000)?;
+    let anomaly_baseline_days = optional_setting(&mut stmt, "anomaly_baseline_days", 7)?;
+    let token_key = optional_setting(&mut stmt, "token_key", String::new())?;
+    let generation_mode = optional_setting(&mut stmt, "generation_mode", "random".to_string())?;
+    let sequence_key = optional_setting(&mut stmt, "sequence_key", String::new())?;
+    let qr_url_template = optional_setting(&mut stmt, "qr_url_template", String::new())?;
+    let display_group_size = optional_setting(&mut stmt, "display_group_size", 0)?;
+    let display_separator = optional_setting(&mut stmt, "display_separator", "-".to_string())?;
+    let id_max_bytes = optional_setting(&mut stmt, "id_max_bytes", 0)?;
+    let case_insensitive_ids = optional_setting(&mut stmt, "case_insensitive_ids", false)?;
+    let min_keyspace_bits = optional_setting(&mut stmt, "min_keyspace_bits", 32)?;
+    // Databases from before the check keep starting; new ones are created with 'refuse' (see migrations::bootstrap)
+    let min_keyspace_mode = optional_setting(&mut stmt, "min_keyspace_mode", "warn".to_string())?;
+    let confirm_requires_owner = optional_setting(&mut stmt, "confirm_requires_owner", false)?;
+    let generation_retry_budget = optional_setting(&mut stmt, "generation_retry_budget", 100)?;
+    let generation_lengthen_after = optional_setting(&mut stmt, "generation_lengthen_after", 50)?;
+    let generation_max_extra_length = optional_setting(&mut stmt, "generation_max_extra_length", 0)?;
+    let id_content_policy = optional_setting(&mut stmt, "id_content_policy", "no_all_digits".to_string())?;
+    let id_content_policy = policy::Policy::parse(&id_content_policy).context("Invalid 'id_content_policy' value")?;
+
+    // Schedules are checked here so a bad one is refused when saved, not at the next start
+    for (key, expr) in [
+        ("consistency_schedule", &consistency_schedule),
+        ("janitor_schedule", &janitor_schedule),
+        ("checkpoint_schedule", &checkpoint_schedule),
+        ("backup_schedule", &backup_schedule),
+        ("purge_schedule", &purge_schedule),
+        ("anomaly_schedule", &anomaly_schedule),
+    ] {
+        if !expr.trim().is_empty() {
+            scheduler::Cron::parse(expr).with_context(|| format!("Invalid '{}' value", key))?;
+        }
+    }
+    match min_keyspace_mode.as_str() {
+        "refuse" | "warn" => {}
+        other => anyhow::bail!("Invalid 'min_keyspace_mode' value '{}': expected refuse or warn", other),
+    }
+    match generation_mode.as_str() {
+        "random" => {}
+        "sequence" if sequence_key.is_empty() => anyhow::bail!("'generation_mode' sequence needs 'sequence_key'"),
+        "sequence" => {}
+        other => anyhow::bail!("Invalid 'generation_mode' value '{}': expected random or sequence", other),
+    }
+    if generation_retry_budget == 0 {
+        anyhow::bail!("'generation_retry_budget' must be at least 1");
+    }
+    // 0 would lengthen before the first candidate, so every ID would get the extra length
+    if generation_lengthen_after == 0 {
+        anyhow::bail!("'generation_lengthen_after' must be at least 1");
+    }
+    if id_max_bytes > 0 {
+        let longest = id_length + generation_max_extra_length;
+        let bytes = max_utf8_bytes(longest, &charset);
+        if bytes > id_max_bytes as usize {
+            anyhow::bail!(
+                "IDs of {} charset characters can take {} bytes as UTF-8, over 'id_max_bytes' ({})",
+                longest, bytes, id_max_bytes
+            );
+        }
+    }
+    if display_group_size > 0 {
+        if display_separator.is_empty() {
+            anyhow::bail!("'display_group_size' needs a 'display_separator'");
+        }
+        if let Some(c) = display_separator.chars().find(|c| charset.contains(*c)) {
+            anyhow::bail!("'display_separator' contains '{}', which is in the charset", c);
+        }
+    }
+    if !backup_schedule.trim().is_empty() && backup_dir.trim().is_empty() {
+        anyhow::bail!("'backup_schedule' needs 'backup_dir'");
+    }
+    match backup_upload.as_str() {
+        "" => {}
+        _ if !cfg!(feature = "object-storage") => {
+            anyhow::bail!("'backup_upload' needs a server built with the object-storage feature")
+        }
+        "s3" if backup_s3_access_key.is_empty() || backup_s3_secret_key.is_empty() => {
+            anyhow::bail!("'backup_upload' s3 needs 'backup_s3_access_key' and 'backup_s3_secret_key'")
+        }
+        "azure" if backup_azure_sas.is_empty() => anyhow::bail!("'backup_upload' azure needs 'backup_azure_sas'"),
+        "s3" | "azure" => {}
+        other => anyhow::bail!("Invalid 'backup_upload' value '{}' (expected s3 or azure)", other),
+    }
+    if !backup_upload.is_empty() && backup_upload_url.trim().is_empty() {
+        anyhow::bail!("'backup_upload' needs 'backup_upload_url'");
+    }
+
+    let settings = Settings {
         id_length,
         charset,
         admin_secret,
-    })
+        lease_ttl_secs,
+        cache_capacity,
+        cache_ttl_secs,
+        negative_cache_ttl_secs,
+        id_pool_size,
+        rate_limit_per_minute,
+        suspend_retry_after_secs,
+        read_timeout_ms,
+        write_timeout_ms,
+        admin_timeout_ms,
+        json_limit_kb,
+        form_limit_kb,
+        bytes_limit_kb,
+        compression_min_bytes,
+        local_socket_path,
+        admin_port,
+        admin_address,
+        secret_grace_secs,
+        shadow_mode,
+        enforce_table_catalog,
+        lookup_max_ids,
+        pool_wait_ms,
+        breaker_threshold,
+        breaker_cooldown_secs,
+        busy_retry_budget_ms,
+        batch_stream_max_ids,
+        job_retention_hours,
+        consistency_schedule,
+        janitor_schedule,
+        checkpoint_schedule,
+        backup_schedule,
+        backup_dir,
+        backup_keep,
+        backup_upload,
+        backup_upload_url,
+        backup_upload_prefix,
+        backup_upload_keep,
+        backup_s3_region,
+        backup_s3_access_key,
+        backup_s3_secret_key,
+        backup_azure_sas,
+        restore_upload_max_mb,
+        replica_path,
+        replica_interval_ms,
+        audit_retention_days,
+        audit_max_rows,
+        generation_stats_retention_days,
+        deleted_retention_days,
+        purge_schedule,
+        changes_retention_days,
+        anomaly_schedule,
+        anomaly_factor,
+        anomaly_min_ids,
+        anomaly_baseline_days,
+        token_key,
+        generation_mode,
+        sequence_key,
+        qr_url_template,
+        display_group_size,
+        display_separator,
+        id_max_bytes,
+        case_insensitive_ids,
+        min_keyspace_bits,
+        min_keyspace_mode,
+        confirm_requires_owner,
+        generation_retry_budget,
+        generation_lengthen_after,
+        generation_max_extra_length,
+        id_content_policy,
+    };
+    Ok(settings)
+}
+
+/// Fails when the keyspace is below `min_keyspace_bits` and `min_keyspace_mode` is
+/// `refuse`. Checked at startup and when settings are saved rather than on every load,
+/// so a database with too small a keyspace can still be opened to fix it.
+pub fn check_keyspace(settings: &Settings) -> Result<()> {
+    match settings.keyspace_shortfall() {
+        Some(shortfall) if settings.min_keyspace_mode == "refuse" => {
+            anyhow::bail!("{}; raise id_length or add charset characters, or set 'min_keyspace_mode' to warn", shortfall)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Reads a setting that may be absent from older databases, falling back to a default
+fn optional_setting<T: std::str::FromStr>(stmt: &mut rusqlite::Statement, key: &str, default: T) -> Result<T> {
+    let value: Option<String> = stmt.query_row([key], |row| row.get(0)).optional()?;
+    match value {
+        Some(v) => v
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid '{}' value", key)),
+        None => Ok(default),
+    }
 }
 
-pub fn create_db_pool() -> Result<DbPool> {
-    let path = get_db_path()
-        .context("No database path configured in registry")?;
+/// Connection manager for [`DbPool`] whose database file can be switched while the
+/// server runs (`POST /admin/switch_db`): new connections open the current file, and
+/// connections to the previous one are dropped when they go back to the pool.
+#[derive(Debug, Clone)]
+pub struct DbManager {
+    // The path as given, and as SQLite reports it for connections to it
+    current: Arc<RwLock<(String, String)>>,
+}
+
+impl DbManager {
+    pub fn new(path: &str) -> Result<Self> {
+        let conn = Self::open(path)?;
+        let filename = conn.path().unwrap_or(path).to_string();
+        Ok(DbManager { current: Arc::new(RwLock::new((path.to_string(), filename))) })
+    }
+
+    fn open(path: &str) -> rusqlite::Result<Connection> {
+        let conn = Connection::open(path)?;
+        // WAL mode on every new connection
+        conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+        Ok(conn)
+    }
+
+    /// The database file new connections open.
+    pub fn path(&self) -> String {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).0.clone()
+    }
+
+    /// Makes new connections open `path`; existing ones become [`Self::is_stale`].
+    pub fn switch(&self, path: &str) -> Result<()> {
+        let conn = Self::open(path).with_context(|| format!("Cannot open {}", path))?;
+        let filename = conn.path().unwrap_or(path).to_string();
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = (path.to_string(), filename);
+        Ok(())
+    }
+
+    /// Whether `conn` was opened on a file the manager has since switched away from.
+    /// Threads holding a connection for long should check this and reconnect.
+    pub fn is_stale(&self, conn: &Connection) -> bool {
+        let current = self.current.read().unwrap_or_else(|e| e.into_inner());
+        conn.path().is_some_and(|filename| filename != current.1)
+    }
+}
+
+impl r2d2::ManageConnection for DbManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> rusqlite::Result<Connection> {
+        Self::open(&self.path())
+    }
+
+    // Checked when a connection is handed out, which catches idle ones to the old file
+    fn is_valid(&self, conn: &mut Connection) -> rusqlite::Result<()> {
+        if self.is_stale(conn) {
+            return Err(rusqlite::Error::InvalidPath(conn.path().unwrap_or_default().into()));
+        }
+        conn.execute_batch("")
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        self.is_stale(conn)
+    }
+}
+
+/// Where `--dev` keeps its database when the registry names none.
+pub const DEV_DB_PATH: &str = "./idregistry.sqlite";
+
+/// Environment variables the container profile reads itself rather than as settings.
+const PROFILE_VARS: [&str; 5] = ["IDREG_PROFILE", "IDREG_DB_PATH", "IDREG_PORT", "IDREG_ADDRESS", "IDREG_FORCE"];
+
+/// Where the server takes its configuration from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// DBPath from the registry, settings from the database
+    Desktop,
+    /// `--dev`: as Desktop, falling back to [`DEV_DB_PATH`]
+    Dev,
+    /// `IDREG_PROFILE=container`: no registry; the database path and settings come
+    /// from `IDREG_*` environment variables and logs are JSON lines on stdout
+    Container,
+}
+
+impl Profile {
+    /// Reads `IDREG_PROFILE` and the command line.
+    pub fn detect() -> Result<Profile> {
+        match std::env::var("IDREG_PROFILE").unwrap_or_default().trim() {
+            "container" => Ok(Profile::Container),
+            "" | "desktop" if std::env::args().skip(1).any(|arg| arg == "--dev") => Ok(Profile::Dev),
+            "" | "desktop" => Ok(Profile::Desktop),
+            other => anyhow::bail!("Unknown IDREG_PROFILE '{}' (expected 'container' or 'desktop')", other),
+        }
+    }
+}
 
-    let manager = SqliteConnectionManager::file(path)
-        .with_init(|conn| {
-            // Optional: set WAL mode on every new connection
-            conn.execute_batch("PRAGMA journal_mode=WAL;")?;
-            Ok(())
-        });
+/// The database the server opens: the registry's DBPath, or `IDREG_DB_PATH` in the
+/// container profile. With [`Profile::Dev`], a missing key or value falls back to
+/// [`DEV_DB_PATH`].
+pub fn db_path(profile: Profile) -> Result<String> {
+    let path = match (profile, get_db_path()) {
+        (Profile::Container, _) => std::env::var("IDREG_DB_PATH")
+            .ok()
+            .filter(|path| !path.trim().is_empty())
+            .context("The container profile needs IDREG_DB_PATH")?,
+        (_, Ok(path)) => path,
+        (Profile::Dev, Err(_)) => DEV_DB_PATH.to_string(),
+        (Profile::Desktop, Err(e)) => return Err(e.context("No database path configured in registry (run with --dev to use a local database)")),
+    };
+    Ok(path)
+}
+
+/// Opens the pool on [`db_path`]. Databases the server picks itself are created with
+/// the base schema if needed. The manager returned with the pool switches it to
+/// another file.
+pub fn create_db_pool(profile: Profile) -> Result<(DbPool, DbManager)> {
+    let path = db_path(profile)?;
+    if profile == Profile::Dev && path == DEV_DB_PATH {
+        info!("Developer mode: no DBPath in the registry; using {}", DEV_DB_PATH);
+    }
+
+    let manager = DbManager::new(&path).with_context(|| format!("Cannot open database {}", path))?;
 
     let pool = r2d2::Pool::builder()
         .max_size(10)           // adjust based on expected load
-        .build(manager)
+        .build(manager.clone())
         .context("Failed to create connection pool")?;
 
     // Test one connection at startup
     let conn = pool.get()?;
     let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
-    println!("Connection pool created – WAL mode: {}", mode);
+    info!("Connection pool created – WAL mode: {}", mode);
 
-    Ok(pool)
-}
+    if (profile == Profile::Container || path == DEV_DB_PATH) && let Some(secret) = migrations::bootstrap(&conn)? {
+        // A secret given in the environment replaces the generated one straight away.
+        // Container logs are collected and kept, so the secret goes to a file instead
+        if std::env::var_os("IDREG_ADMIN_SECRET").is_some() {
+            info!("Database initialised");
+        } else if profile == Profile::Container {
+            match write_secret_file(&path, &secret) {
+                Ok(file) => info!("Database initialised; admin secret written to {} (delete it once noted)", file.display()),
+                Err(e) => error!("Database initialised, but the admin secret could not be written: {:#}; set IDREG_ADMIN_SECRET and restart", e),
+            }
+        } else {
+            info!("Database initialised; admin secret: {}", secret);
+        }
+    }
 
-// Returns true if the string consists only of digits 0-9
-fn is_all_numeric(s: &str) -> bool {
-    s.chars().all(|c| c.is_ascii_digit())
+    Ok((pool, manager))
 }
 
-// Checks whether the ID already exists in the ids table
-fn id_exists(conn: &r2d2::PooledConnection<SqliteConnectionManager>, id: &str) -> Result<bool> {
-    let count: u64 = conn.query_row(
-        "SELECT COUNT(*) FROM ids WHERE id = ?1",
-        [id],
-        |row| row.get(0),
-    )?;
-    Ok(count > 0)
+// Writes a generated admin secret next to the database as `<name>.admin-secret`,
+// readable by the server's user only
+fn write_secret_file(db_path: &str, secret: &str) -> Result<std::path::PathBuf> {
+    use std::io::Write;
+
+    let file = std::path::Path::new(db_path).with_extension("admin-secret");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut out = options.open(&file).with_context(|| format!("Cannot create {}", file.display()))?;
+    // The mode only applies to a new file; one left from an earlier start is tightened too
+    #[cfg(unix)]
+    std::fs::set_permissions(&file, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    writeln!(out, "{}", secret).with_context(|| format!("Cannot write {}", file.display()))?;
+    Ok(file)
 }
 
-/// Generates one random ID using current settings.
-/// Retries on collision or all-numeric result.
-/// Returns Ok(id) or Err after max retries.
-pub fn generate_id(conn: &r2d2::PooledConnection<SqliteConnectionManager>, settings: &Settings) -> Result<String> {
-    const MAX_RETRIES: usize = 100;
+/// Generates one ID against the database; see [`id_registry_core::generate_id`].
+pub fn generate_id(conn: &Connection, settings: &Settings, suffix: Option<&str>) -> Result<String> {
+    id_registry_core::generate_id(&SqliteStore(conn), settings, suffix)
+}
 
-    let charset_chars: Vec<char> = settings.charset.chars().collect();
-    if charset_chars.is_empty() {
-        anyhow::bail!("Charset is empty");
-    }
+/// Like [`generate_id`], also returning how many candidates collided before it succeeded.
+/// In sequence mode it takes the ID, advancing the sequence.
+pub fn generate_id_counted(conn: &Connection, settings: &Settings, suffix: Option<&str>) -> Result<(String, u32)> {
+    id_registry_core::generate_id_counted(&SqliteStore(conn), settings, suffix)
+}
 
-    let mut rng = rand::thread_rng();
+/// Claims or generates an ID and inserts it for `owner`, returning (id, created_at,
+/// config_version) as stored; a lengthened ID has the version of its own config.
+/// IDs with a `suffix` are always generated live, since the pool holds plain ones.
+/// An `external_ref` already used by the owner fails on its unique index; see [`find_by_external_ref`].
+/// Live generations are counted in [`genstats`], including retries after a lost insert.
+/// The insert relies on the unique index rather than the earlier existence check,
+/// so if another writer took the ID in the meantime we simply try a fresh one. With
+/// `case_insensitive_ids` that includes another spelling of it, which the NOCASE index
+/// refuses outside the conflict target.
+pub fn issue_id(
+    conn: &Connection,
+    settings: &Settings,
+    owner: &str,
+    table: Option<&str>,
+    suffix: Option<&str>,
+    external_ref: Option<&str>,
+) -> Result<(String, String, Option<i64>)> {
+    const MAX_INSERT_ATTEMPTS: usize = 5;
 
-    for attempt in 1..=MAX_RETRIES {
-        let mut id = String::with_capacity(settings.id_length as usize);
+    let mut lost_inserts = 0;
+    for _ in 0..MAX_INSERT_ATTEMPTS {
+        // Prefer a pre-generated ID; fall back to live generation when the pool is dry
+        let pooled = match suffix {
+            Some(_) => None,
+            None => idpool::claim(conn)?,
+        };
+        let (id, retries) = match pooled {
+            Some(id) => (id, None),
+            None => {
+                let (id, retries) = generate_id_counted(conn, settings, suffix)?;
+                (id, Some(retries))
+            }
+        };
 
-        for _ in 0..settings.id_length {
-            let c = *charset_chars
-                .choose(&mut rng)
-                .expect("Charset cannot be empty here");
-            id.push(c);
+        // A lengthened ID gets a config version of its own
+        let extra_length = (id.chars().count() as u32).saturating_sub(settings.id_length);
+        if extra_length > 0 {
+            genconfig::record_lengthened(conn, settings, extra_length)?;
         }
 
-        // Skip if all numeric
-        if is_all_numeric(&id) {
-            continue;
-        }
+        // Cached, so a batch issuing many IDs in one transaction prepares it once.
+        // The config version is the one recorded for the settings the ID was made with
+        let mut insert = conn.prepare_cached(
+            "INSERT INTO ids (id, owner, table_name, confirmed, created_at, config_version, external_ref)
+             VALUES (?1, ?2, ?3, 0, CURRENT_TIMESTAMP,
+                     (SELECT MAX(version) FROM generation_configs
+                      WHERE id_length = ?4 AND charset = ?5 AND content_policy = ?8 AND generation_mode = ?9
+                        AND extra_length = ?7), ?6)
+             ON CONFLICT (id) DO NOTHING
+             RETURNING created_at, config_version",
+        )?;
+        let policy = settings.id_content_policy.to_string();
+        let params = rusqlite::params![&id, owner, table, settings.id_length, &settings.charset, external_ref, extra_length, &policy, &settings.generation_mode];
+        let inserted: Option<(String, Option<i64>)> = match insert.query_row(params, |row| Ok((row.get(0)?, row.get(1)?))).optional() {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == rusqlite::ErrorCode::ConstraintViolation && casefold::stored_id(conn, settings, &id)? != id =>
+            {
+                None
+            }
+            result => result?,
+        };
 
-        // Check collision
-        if !id_exists(conn, &id)? {
-            return Ok(id);
+        match inserted {
+            Some((created_at, config_version)) => {
+                if let Some(retries) = retries {
+                    genstats::record(conn, retries + lost_inserts)?;
+                }
+                return Ok((id, created_at, config_version));
+            }
+            None => {
+                error!("ID {} was taken before insert, regenerating", id);
+                lost_inserts += 1;
+            }
         }
+    }
 
-        // Optional: log attempts in dev mode
-        if attempt % 20 == 0 {
-            println!("Collision detected on attempt {}, retrying...", attempt);
-        }
+    Err(IdConflict { attempts: MAX_INSERT_ATTEMPTS }.into())
+}
+
+/// The ID issued to `owner` for `external_ref`, if any. A retired ID resolves to its
+/// replacement, so a retry after a repair still gets the ID now in use.
+pub fn find_by_external_ref(conn: &Connection, owner: &str, external_ref: &str) -> Result<Option<String>> {
+    let id = conn
+        .query_row(
+            "SELECT COALESCE((SELECT a.id FROM id_aliases a WHERE a.alias = i.id), i.id) FROM ids i
+             WHERE i.owner = ?1 AND i.external_ref = ?2",
+            [owner, external_ref],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(id)
+}
+
+/// Applies `changes` to `id` only if its version is still `expected_version` (None takes
+/// any version, as `If-Match: *` does), bumping the version. The check is part of the
+/// UPDATE itself, so two writers that read the same version cannot both succeed. Fails
+/// with IdNotFound, IdDeleted or VersionConflict.
+pub fn update_id_record(conn: &Connection, id: &str, expected_version: Option<i64>, changes: &IdChanges) -> Result<IdRecord> {
+    let updated = conn.query_row(
+        "UPDATE ids SET owner = COALESCE(?2, owner),
+                        table_name = CASE WHEN ?3 THEN ?4 ELSE table_name END,
+                        version = version + 1,
+                        updated_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND deleted = 0 AND (?5 IS NULL OR version = ?5)
+         RETURNING owner, table_name, confirmed, created_at, version, confirmed_at, confirmed_by, config_version, external_ref",
+        rusqlite::params![
+            id,
+            changes.owner,
+            changes.table.is_some(),
+            changes.table.clone().flatten(),
+            expected_version,
+        ],
+        |row| {
+            Ok(IdRecord {
+                id: id.to_string(),
+                owner: row.get(0)?,
+                table: row.get(1)?,
+                confirmed: row.get(2)?,
+                created_at: row.get(3)?,
+                version: row.get(4)?,
+                confirmed_at: row.get(5)?,
+                confirmed_by: row.get(6)?,
+                config_version: row.get(7)?,
+                external_ref: row.get(8)?,
+            })
+        },
+    ).optional()?;
+
+    if let Some(record) = updated {
+        return Ok(record);
     }
 
-    anyhow::bail!(
-        "Failed to generate unique ID after {} attempts. Database may be very full.",
-        MAX_RETRIES
-    );
-}
\ No newline at end of file
+    // Nothing changed: find out why
+    let current: Option<(bool, i64)> = conn.query_row(
+        "SELECT deleted != 0, version FROM ids WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+
+    match current {
+        None => Err(IdNotFound { id: id.to_string() }.into()),
+        Some((true, _)) => Err(IdDeleted { id: id.to_string() }.into()),
+        Some((false, current)) => Err(VersionConflict { expected: expected_version.unwrap_or(current), current }.into()),
+    }
+}
+
+/// Where `id` is in its [`lifecycle`], or None if it was never issued.
+pub fn id_state(conn: &Connection, id: &str) -> Result<Option<IdState>> {
+    let mut stmt = conn.prepare_cached("SELECT confirmed != 0, deleted != 0 FROM ids WHERE id = ?1")?;
+    let flags = stmt.query_row([id], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+    Ok(flags.map(|(confirmed, deleted)| IdState::from_flags(confirmed, deleted)))
+}
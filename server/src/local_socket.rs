@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Local listener (Unix domain socket, or a named pipe on Windows) for co-located clients.
+//!
+//! Rocket 0.5 can only bind TCP, so connections accepted here are relayed byte-for-byte
+//! to Rocket's own (loopback) address. Requests arriving this way therefore show up with
+//! a 127.0.0.1 client IP; [`Relayed`] tells them apart from direct loopback clients.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use id_registry_server::{error, info};
+
+/// The local addresses of the relay's open connections to Rocket, so a request's remote
+/// address shows whether it came through the socket.
+#[derive(Clone, Default)]
+pub struct Relayed(Arc<Mutex<HashSet<SocketAddr>>>);
+
+impl Relayed {
+    pub fn contains(&self, remote: SocketAddr) -> bool {
+        self.lock().contains(&remote)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<SocketAddr>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Copies data both ways between a local client and Rocket until either side closes. The
+// connection is registered before the first byte reaches Rocket
+async fn relay<S>(mut client: S, target: SocketAddr, relayed: Relayed)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match TcpStream::connect(target).await {
+        Ok(mut upstream) => {
+            let local = upstream.local_addr().ok();
+            if let Some(local) = local {
+                relayed.lock().insert(local);
+            }
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            if let Some(local) = local {
+                relayed.lock().remove(&local);
+            }
+        }
+        Err(e) => error!("Local socket: cannot reach {}: {}", target, e),
+    }
+}
+
+/// Accepts connections on the Unix socket at `path` and relays them to `target`,
+/// recording them in `relayed`.
+#[cfg(unix)]
+pub async fn serve(path: String, target: SocketAddr, relayed: Relayed) -> std::io::Result<()> {
+    use tokio::net::UnixListener;
+
+    // A socket file left behind by a previous run would make bind fail
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    info!("Listening on Unix socket {}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(relay(stream, target, relayed.clone()));
+    }
+}
+
+/// Accepts connections on the named pipe `name` (e.g. `\\.\pipe\idregistry`) and relays them to `target`,
+/// recording them in `relayed`.
+#[cfg(windows)]
+pub async fn serve(name: String, target: SocketAddr, relayed: Relayed) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(&name)?;
+    info!("Listening on named pipe {}", name);
+
+    loop {
+        server.connect().await?;
+        let connected = server;
+        // Create the next instance before serving this one, so clients never find the pipe missing
+        server = ServerOptions::new().create(&name)?;
+        tokio::spawn(relay(connected, target, relayed.clone()));
+    }
+}
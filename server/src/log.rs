@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Server log output: plain lines (errors on stderr) by default, or one JSON object
+//! per line on stdout for log collectors (the container profile).
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Switches all further log lines to JSON.
+pub fn use_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+#[doc(hidden)]
+pub fn write(level: &str, message: fmt::Arguments) {
+    if json() {
+        let line = serde_json::json!({
+            "ts": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "level": level,
+            "msg": message.to_string(),
+        });
+        println!("{}", line);
+    } else if level == "error" {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Logs an informational line, formatted like `println!`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write("info", format_args!($($arg)*)) };
+}
+
+/// Logs a failure, formatted like `eprintln!`.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write("error", format_args!($($arg)*)) };
+}
@@ -1,13 +1,35 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
+mod compression;
+mod latency;
+mod local_socket;
+mod negotiate;
+mod validation;
+
 use anyhow::Result;
-use rocket::{get, post, put, delete, routes, serde::json::Json, State, Request, catch, catchers};
-use rocket::http::{ContentType, Status};
-use rocket::response::{self, Responder};
-use rusqlite::OptionalExtension;
-use serde::Serialize;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use rocket::{get, post, put, delete, routes, uri, serde::json::Json, State, Request, catch, catchers, FromForm, FromFormField};
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::form::Form;
+use rocket::request::{self, FromRequest};
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::response::{self, Responder, stream::TextStream};
+use rocket::Either;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use r2d2::PooledConnection;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::time::{Duration, Instant};
 
-use id_registry_server::{create_db_pool, DbPool, generate_id, get_db_path, load_settings, Settings};
+use id_registry_server::{adminauth::{self, AdminSecrets}, anomaly::{self, AnomalyReport}, audit, batch::{self, BatchResult}, breaker::CircuitBreaker, changes::{self, Change, ChangeOp, CursorExpired}, cache::TtlCache, canaries::{self, Canary, IssuedId}, casefold, codes::{self, Matrix}, consistency::{self, ConsistencyReport}, dbswitch, erasure::{self, EraseMode, ErasureSummary}, genconfig::{self, ForceRequired}, repair::{self, DuplicateReport}, replication::{self, Replication, ReplicationStatus}, restore::{self, InvalidSnapshot, SnapshotCheck}, tables::{self, RenameSummary, TableEntry, UnknownTable}, tokens, writer::{Claim, Writer}, apply_env_settings, stage_env_settings, check_keyspace, create_db_pool, DbManager, genstats::{self, GenerationDay, Granularity, VolumePoint}, holds::{self, Hold, HoldKind, OnHold}, errors::{ApiError, ErrorCode, FieldError}, DbPool, generate_id, idpool, issue_id, jobs::{self, Job}, lease, load_settings, maintenance, migrations, notes::{self, Note}, owners::{self, ExternalRefClash, MergeSummary}, preflight, labels::{self, Label, UnknownLabel}, proto, refs::{self, ExternalRef, RefChanges}, scheduler::{ScheduleStatus, Scheduler, Schedules}, selftest::{self, SelftestReport}, suspension::{Scope, Suspension, SuspensionStatus}, OpKind, ratelimit::{RateInfo, RateLimiter}, IdChanges, IdConflict, IdDeleted, IdNotFound, IdRecord, find_by_external_ref, format_problem, id_state, lifecycle::{Event, Refused}, InvalidSuffix, KeyspaceExhausted, normalize_scan, display_id, normalize_id, Profile, set_db_path, Settings, UnknownSetting, update_id_record, VersionConflict};
+use compression::Compression;
+use latency::{Latency, LatencyTimer, PathLatency};
+use negotiate::{Negotiated, ProtoEncode};
+use validation::{Checks, Invalid, Valid, Validate};
+use prost::Message;
+use id_registry_server::{error, info};
+#[cfg(windows)]
+use id_registry_server::{regwatch, DEV_DB_PATH};
 
 //
 // Structs
@@ -15,31 +37,277 @@ use id_registry_server::{create_db_pool, DbPool, generate_id, get_db_path, load_
 
 #[derive(Clone)]
 struct AppState {
-    settings: Arc<Settings>,
+    // Reloaded when the database is restored or switched; see forget_database
+    settings: Arc<RwLock<Arc<Settings>>>,
     pool: DbPool,
-    suspended: Arc<AtomicBool>,
-}
-
-#[derive(Serialize)]
-struct ApiError {
-    error: String,
-    message: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    details: Option<String>,
+    suspension: Arc<Suspension>,
+    leader: Arc<AtomicBool>,
+    id_cache: Arc<TtlCache<IdDetails>>,
+    missing_cache: Arc<TtlCache<()>>,
+    // Canary IDs, checked on every lookup; reloaded whenever they change
+    canaries: Arc<RwLock<HashSet<String>>>,
+    limiter: Arc<RateLimiter>,
+    // Connections relayed from local_socket_path, which the rate limit leaves alone
+    relayed: local_socket::Relayed,
+    admin: Arc<AdminSecrets>,
+    consistency: Arc<RwLock<Option<ConsistencyReport>>>,
+    anomalies: Arc<RwLock<Option<AnomalyReport>>>,
+    latency: Arc<Latency>,
+    // Requests turned away because no connection freed up within pool_wait_ms
+    pool_rejections: Arc<AtomicU64>,
+    breaker: Arc<CircuitBreaker>,
+    // Every mutating route runs on this thread's connection, one at a time
+    writer: Arc<Writer>,
+    schedules: Schedules,
+    // Which database file the pool opens; see POST /admin/switch_db
+    db: DbManager,
+    replication: Option<Replication>,
+    // The container profile never reads or writes the registry
+    profile: Profile,
+    // A DBPath changed in the registry while switching wasn't allowed; followed at the next suspend
+    #[cfg(windows)]
+    pending_db_path: Arc<std::sync::Mutex<Option<String>>>,
 }
 
 struct JsonError {
     status: Status,
     error: ApiError,
+    headers: Vec<(&'static str, String)>,
 }
 
+// Request guard applying the per-IP rate limit; see the 429 catcher. Holds the client's
+// address, for routes that record who called them
+struct RateLimited(Option<IpAddr>);
+
 #[derive(serde::Serialize)]
 struct HealthResponse {
     status: String,
+    suspension: SuspensionStatus,
+    role: String,
     db_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replication: Option<ReplicationStatus>,
+    settings: Settings,
+}
+
+// Everything the admin dashboard shows
+#[derive(serde::Serialize)]
+struct AdminStatus {
+    status: String,
+    suspension: SuspensionStatus,
+    role: String,
+    stats: IdStats,
+    recent: Vec<IdDetails>,
     settings: Settings,
 }
 
+// The backups in backup_dir and, with backup_upload set, in object storage
+#[derive(serde::Serialize)]
+struct BackupsResponse {
+    schedule: Option<ScheduleStatus>,
+    local: Vec<maintenance::BackupFile>,
+    upload: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct RestoreResponse {
+    snapshot: String,
+    check: SnapshotCheck,
+    // Where the replaced database was copied; absent for a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+}
+
+// Body of POST /admin/switch_db
+#[derive(serde::Deserialize)]
+struct SwitchDbRequest {
+    path: String,
+}
+
+#[derive(serde::Serialize)]
+struct SwitchDbResponse {
+    previous: String,
+    path: String,
+    check: SnapshotCheck,
+    // Whether DBPath now names the new file, so a restart keeps it
+    persisted: bool,
+}
+
+#[derive(serde::Serialize)]
+struct StatsResponse {
+    ids: IdStats,
+    // Collision retries per day over the last 30 days, newest first
+    generation: Vec<GenerationDay>,
+    // None until the first consistency run has finished (or when it is disabled)
+    consistency: Option<ConsistencyReport>,
+    // Owners issuing far above their usual rate, as of the latest anomaly check
+    anomalies: Option<AnomalyReport>,
+    // p50/p95/p99 per path since start, total and database time
+    latency: BTreeMap<&'static str, PathLatency>,
+    // Requests answered 503 pool_exhausted since start
+    pool_rejections: u64,
+    // Database circuit breaker: closed, open or half_open
+    circuit: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct IdStats {
+    total: i64,
+    confirmed: i64,
+    unconfirmed: i64,
+    deleted: i64,
+}
+
+// One row of GET /stats/tables
+#[derive(serde::Serialize)]
+struct TableStats {
+    // null for IDs issued without a table
+    table: Option<String>,
+    #[serde(flatten)]
+    ids: IdStats,
+    // confirmed / (confirmed + unconfirmed) of the live IDs; null when there are none
+    confirmation_ratio: Option<f64>,
+    issued_last_24h: i64,
+    issued_last_7d: i64,
+    last_issued_at: Option<String>,
+}
+
+// Result of GET /stats/timeseries
+#[derive(serde::Serialize)]
+struct Timeseries {
+    // null for all owners together
+    owner: Option<String>,
+    granularity: Granularity,
+    days: u32,
+    points: Vec<VolumePoint>,
+}
+
+// Result of POST /admin/settings/validate
+#[derive(serde::Serialize)]
+struct SettingsReport {
+    current_keyspace_bits: f64,
+    proposed_keyspace_bits: f64,
+    keyspace_delta_bits: f64,
+    // Longest possible ID as UTF-8, for sizing columns downstream
+    current_max_id_bytes: usize,
+    proposed_max_id_bytes: usize,
+    existing_ids: u64,
+    // Live IDs whose length or characters don't fit the proposed settings
+    invalid_ids: u64,
+    invalid_examples: Vec<String>,
+    collision_probability: f64,
+    // Why PUT /admin/settings would need force=true for this change
+    force_required: Option<String>,
+    warnings: Vec<String>,
+}
+
+// Body of PUT /tables/<name>; absent fields are left alone
+#[derive(serde::Deserialize)]
+struct TableUpdate {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    active: Option<bool>,
+    // null goes back to the deleted_retention_days setting
+    #[serde(default, deserialize_with = "present")]
+    deleted_retention_days: Option<Option<u32>>,
+}
+
+// Result of POST /admin/find_duplicates
+#[derive(serde::Serialize)]
+struct DuplicatesResponse {
+    #[serde(flatten)]
+    report: DuplicateReport,
+    // What POST /admin/repair_ids can do about them
+    actions: &'static [&'static str],
+}
+
+// Body of POST /admin/erase_owner
+#[derive(serde::Deserialize)]
+struct EraseRequest {
+    owner: String,
+    mode: EraseMode,
+}
+
+// Body of POST /admin/owners/merge
+#[derive(serde::Deserialize)]
+struct MergeRequest {
+    from: String,
+    to: String,
+}
+
+// Body of POST /admin/tables/rename
+#[derive(serde::Deserialize)]
+struct RenameRequest {
+    from: String,
+    to: String,
+}
+
+// Body of POST /admin/canaries
+#[derive(serde::Deserialize)]
+struct CanaryRequest {
+    id: String,
+    #[serde(default)]
+    note: Option<String>,
+}
+
+// Body of POST /admin/holds
+#[derive(serde::Deserialize)]
+struct HoldRequest {
+    kind: HoldKind,
+    target: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// Body of POST /admin/repair_ids
+#[derive(serde::Deserialize)]
+struct RepairRequest {
+    action: RepairAction,
+    ids: Vec<String>,
+    // The ID to fold into, for "alias"
+    #[serde(default)]
+    target: Option<String>,
+    // Flag text for "flag"
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RepairAction {
+    Flag,
+    Reissue,
+    Alias,
+    UnconfirmDeleted,
+}
+
+#[derive(serde::Serialize)]
+struct RepairResult {
+    id: String,
+    // The ID it now resolves to, for reissue and alias
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_id: Option<String>,
+}
+
+// Optional body of POST /admin/rotate_secret; a random secret is generated without one
+#[derive(serde::Deserialize)]
+struct RotateRequest {
+    new_secret: String,
+}
+
+#[derive(serde::Serialize)]
+struct RotateResponse {
+    // Only echoed when the server generated it: this is the one chance to see it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    previous_valid_until: String,
+}
+
 #[derive(serde::Serialize)]
 struct PreviewResponse {
     preview_id: String,
@@ -50,329 +318,4862 @@ struct GenerateRequest {
     owner: String,
     #[serde(default)]
     table: Option<String>,
+    // Appended after the random part, e.g. a site code
+    #[serde(default)]
+    suffix: Option<String>,
+    // The caller's key for the record; asking again with it returns the same ID
+    #[serde(default)]
+    external_ref: Option<String>,
 }
 
-#[derive(serde::Serialize)]
+// Same fields as GenerateRequest, for clients that can only post forms or query strings
+#[derive(FromForm)]
+struct GenerateForm {
+    owner: String,
+    table: Option<String>,
+    suffix: Option<String>,
+    external_ref: Option<String>,
+}
+
+// Serialized by hand (below) so that only the selected `fields` are written
+#[derive(Clone)]
 struct IdDetails {
     id: String,
     owner: String,
     table: Option<String>,
     confirmed: i32,
     created_at: String,
+    version: i64,
+    confirmed_at: Option<String>,
+    // Whatever the confirming system called itself (?by=), if anything
+    confirmed_by: Option<String>,
+    config_version: Option<i64>,
+    external_ref: Option<String>,
+    // Only visible to admins asking for deleted rows (see DeletedScope)
+    deleted: bool,
+    // Signed token for the ID (see tokens), when token_key is set
+    token: Option<String>,
+    // The ID grouped for people to read, when display_group_size is set
+    display_id: Option<String>,
+    links: Option<Links>,
+    // Support notes, only loaded for GET /ids/<id>?notes=true
+    notes: Option<Vec<Note>>,
+    fields: Fields,
 }
 
-#[derive(serde::Deserialize)]
-struct ConfirmRequest {
-    id: String,
-}
-
-#[derive(serde::Serialize)]
-struct ConfirmResponse {
-    success: bool,
-    message: String,
+// Request guard for ?include_deleted=true / ?only_deleted=true, which need ?secret=
+#[derive(Clone, Copy, PartialEq)]
+enum DeletedScope {
+    Live,
+    Include,
+    Only,
 }
 
-//
-// Functions
-//
+// Request guard for ?fields=id,confirmed: which IdDetails columns to send (all by default)
+#[derive(Clone, Copy)]
+struct Fields(u16);
 
-impl<'r> Responder<'r, 'r> for JsonError {
-    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'r> {
-        let body = serde_json::to_string(&self.error).unwrap_or_else(|_| {
-            r#"{"error":"internal_error","message":"Failed to serialize error"}"#.to_string()
-        });
+// Name of a field ?fields= doesn't know, for the 400 catcher
+struct UnknownField(String);
 
-        response::Response::build()
-            .status(self.status)
-            .header(ContentType::JSON)
-            .sized_body(body.len(), std::io::Cursor::new(body))
-            .ok()
-    }
+// Related routes for an ID, only included when asked for (see WantLinks)
+#[derive(Clone, serde::Serialize)]
+struct Links {
+    #[serde(rename = "self")]
+    self_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirm: Option<String>,
+    notes: String,
+    refs: String,
+    // The ID's entries in the changes feed
+    history: String,
+    // The owner's IDs
+    owner: String,
 }
 
-#[catch(400)]
-fn bad_request(_req: &Request<'_>) -> JsonError {
-    JsonError {
-        status: Status::BadRequest,
-        error: ApiError {
-            error: "bad_request".to_string(),
-            message: "Invalid request parameters or body".to_string(),
-            details: None,
-        },
-    }
-}
+// Request guard: true for ?links=true or an Accept profile of "links"
+struct WantLinks(bool);
 
-#[catch(401)]
-fn unauthorized(_req: &Request<'_>) -> JsonError {
-    JsonError {
-        status: Status::Unauthorized,
-        error: ApiError {
-            error: "unauthorized".to_string(),
-            message: "Authentication required".to_string(),
-            details: None,
-        },
-    }
-}
+// Request guard: true when the client accepts application/x-ndjson, one JSON document per line
+struct WantNdjson(bool);
 
-#[catch(404)]
-fn not_found(_req: &Request<'_>) -> JsonError {
-    JsonError {
-        status: Status::NotFound,
-        error: ApiError {
-            error: "not_found".to_string(),
-            message: "Resource not found".to_string(),
-            details: None,
-        },
-    }
-}
+// Body of a streamed NDJSON response, fed line by line from a background task
+type NdjsonStream = TextStream<BoxStream<'static, String>>;
 
-#[catch(501)]
-fn not_implemented(_req: &Request<'_>) -> JsonError {
-    JsonError {
-        status: Status::NotImplemented,
-        error: ApiError {
-            error: "not_implemented".to_string(),
-            message: "This feature is not yet available".to_string(),
-            details: None,
-        },
-    }
+// A database copy served by GET /admin/snapshot; the temporary file is deleted once
+// the response body is dropped, whether or not the download completed
+struct SnapshotDownload {
+    file: Option<tokio::fs::File>,
+    path: std::path::PathBuf,
+    name: String,
 }
 
-#[catch(503)]
-fn service_unavailable(_req: &Request<'_>) -> JsonError {
-    JsonError {
-        status: Status::ServiceUnavailable,
-        error: ApiError {
-            error: "service_unavailable".to_string(),
-            message: "Server is temporarily suspended for maintenance".to_string(),
-            details: None,
-        },
-    }
+// Body of POST /jobs/export; without filters every live ID is exported
+#[derive(serde::Deserialize)]
+struct ExportRequest {
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    table: Option<String>,
 }
 
-#[catch(default)]
-fn default_error(status: Status, _req: &Request<'_>) -> JsonError {
-    JsonError {
-        status,
-        error: ApiError {
-            error: "internal_error".to_string(),
-            message: format!("Unexpected error ({})", status.code),
-            details: None,
-        },
-    }
+// A job as shown by GET /jobs/<id>, with where to poll and, once finished, the result
+#[derive(serde::Serialize)]
+struct JobStatus {
+    #[serde(flatten)]
+    job: Job,
+    links: JobLinks,
 }
 
-#[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
-    println!("Starting ID Registry Server...");
+#[derive(serde::Serialize)]
+struct JobLinks {
+    #[serde(rename = "self")]
+    self_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+}
 
-    let pool = create_db_pool().expect("Failed to create DB pool");
+// Body of PUT /ids/<id>; absent fields are left alone, "table": null clears the table
+#[derive(serde::Deserialize)]
+struct UpdateRequest {
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    table: Option<Option<String>>,
+}
 
-    // Load settings once at startup (using a connection from pool)
-    let conn = pool.get().expect("Failed to get connection for init");
-    let settings = load_settings(&conn).expect("Failed to load settings");
+// Request guard for the X-Dry-Run header (always on in shadow mode): validate everything, write nothing
+#[derive(Clone, Copy)]
+struct DryRun(bool);
 
-    println!("Database pool ready");
-    println!("ID length: {}", settings.id_length);
-    println!("Charset  : {}", settings.charset);
+// Tags responses to dry-run requests with X-Dry-Run: true, so a client can tell
+// the server honoured the header rather than silently writing
+struct DryRunMarked<R>(R, DryRun);
 
-    let settings_arc = Arc::new(settings);
+#[derive(serde::Serialize)]
+struct DeleteResponse {
+    success: bool,
+    message: String,
+}
 
-    let suspended = Arc::new(AtomicBool::new(false));
+// Request guard exposing the If-Match header, if any
+struct IfMatch(Option<String>);
 
-    rocket::build()
-        .manage(AppState {
-            settings: settings_arc,
-            pool,
-            suspended,
-        })
-        .mount("/", routes![health, preview, generate, confirm, update_id, delete_id, get_id, suspend, resume])
-        .register("/", catchers![
-            bad_request,
-            unauthorized,
-            not_found,
-            not_implemented,
-            service_unavailable,
-            default_error
-        ])
-        .launch()
-        .await?;
+// Request guard exposing the X-Owner-Key header, which confirming a keyed owner's IDs needs
+struct OwnerKey(Option<String>);
 
-    Ok(())
+// Adds an ETag header carrying the row version to any response
+struct Tagged<R> {
+    body: R,
+    version: i64,
 }
 
-// POST /suspend?secret=yourpassword
-#[post("/suspend?<secret>")]
-fn suspend(
-    secret: Option<String>,
-    state: &State<AppState>,
-) -> Result<String, Status> {
-    if secret.as_deref() != Some(&state.settings.admin_secret) {
-        return Err(Status::Unauthorized);
-    }
+// Query of GET /changes
+#[derive(FromForm)]
+struct ChangesQuery {
+    // Cursor: the `next` value of the previous page; 0 (the default) starts at the oldest change kept
+    since: Option<i64>,
+    limit: Option<usize>,
+    // Only the changes of this ID, as linked from its details
+    id: Option<String>,
+}
 
-    state.suspended.store(true, Ordering::SeqCst);
-    Ok("Server suspended (new requests rejected)".to_string())
+// Query of GET /owners/<owner>/ids; every filter is optional
+#[derive(FromForm)]
+struct OwnerListQuery<'r> {
+    table: Option<String>,
+    confirmed: Option<bool>,
+    limit: Option<usize>,
+    after: Option<String>,
+    sort: rocket::form::Result<'r, SortKey>,
+    order: rocket::form::Result<'r, SortOrder>,
 }
 
-// POST /resume?secret=yourpassword
-#[post("/resume?<secret>")]
-fn resume(
-    secret: Option<String>,
-    state: &State<AppState>,
-) -> Result<String, Status> {
-    if secret.as_deref() != Some(&state.settings.admin_secret) {
-        return Err(Status::Unauthorized);
-    }
+// Lets uri! link to the routes taking these without filters
+impl rocket::http::uri::fmt::Ignorable<rocket::http::uri::fmt::Query> for OwnerListQuery<'_> {}
+impl rocket::http::uri::fmt::Ignorable<rocket::http::uri::fmt::Query> for ChangesQuery {}
 
-    state.suspended.store(false, Ordering::SeqCst);
-    Ok("Server resumed".to_string())
+// Query of GET /ids; every filter is optional
+#[derive(FromForm)]
+struct ListQuery<'r> {
+    // Only IDs starting with this, e.g. the legible part of a damaged label
+    prefix: Option<String>,
+    limit: Option<usize>,
+    // Cursor: the `next` value of the previous page
+    after: Option<String>,
+    // Only IDs carrying this label
+    label: Option<String>,
+    // Kept as results so a misspelt value is a 400 rather than silently ignored
+    sort: rocket::form::Result<'r, SortKey>,
+    order: rocket::form::Result<'r, SortOrder>,
 }
 
-#[get("/health")]
-fn health(state: &State<AppState>,) -> Result<Json<HealthResponse>, Status> {
-    let db_path = get_db_path().map_err(|_| Status::InternalServerError)?;
-
-    Ok(Json(HealthResponse {
-        status: if state.suspended.load(Ordering::SeqCst) { "Suspended".to_string() } else { "ok".to_string() },
-        db_path,
-        settings: state.settings.as_ref().clone(),
-    }))
+// Listing order; ties (same owner or created_at) are broken by ID
+#[derive(FromFormField, Clone, Copy)]
+enum SortKey {
+    Id,
+    #[field(value = "created_at")]
+    CreatedAt,
+    Owner,
 }
 
-#[get("/preview")]
-fn preview(state: &State<AppState>,) -> Result<Json<PreviewResponse>, Status> {
-    let conn = &state.pool.get()
-        .map_err(|e| {
-            eprintln!("Pool error: {}", e);
-            Status::InternalServerError
-        })?;
+#[derive(FromFormField, Clone, Copy)]
+enum SortOrder {
+    Asc,
+    Desc,
+}
 
-    match generate_id(&conn, &state.settings.as_ref()) {
-        Ok(id) => Ok(Json(PreviewResponse { preview_id: id })),
-        Err(e) => {
-            eprintln!("Generation failed: {}", e);
-            Err(Status::InternalServerError)
+impl SortKey {
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::Id => "id",
+            SortKey::CreatedAt => "created_at",
+            SortKey::Owner => "owner",
         }
     }
 }
 
-#[post("/generate", format = "json", data = "<request>")]
-fn generate(
-    request: Json<GenerateRequest>,
-    state: &State<AppState>,
-) -> Result<Json<IdDetails>, Status> {
-    if state.suspended.load(Ordering::SeqCst) {
-        return Err(Status::ServiceUnavailable);
-    }
+// Query of GET /ids/count
+#[derive(FromForm)]
+struct CountQuery {
+    owner: Option<String>,
+    table: Option<String>,
+    confirmed: Option<bool>,
+    // A date (2026-10-01) or date and time (2026-10-01 12:00:00), UTC
+    created_after: Option<String>,
+    label: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct CountResponse {
+    count: i64,
+}
 
-    println!("Generate request: owner={}, table={:?}", request.owner, request.table);
+// Condition for ?label=, served by idx_id_labels_label
+const LABEL_FILTER: &str = "id IN (SELECT id FROM id_labels WHERE label = ?)";
 
-    let owner_clean = request.owner.trim().to_string();
-    if owner_clean.is_empty() || !owner_clean.chars().all(|c| c.is_alphanumeric() || c == '_') {
-        return Err(Status::BadRequest);
-    }
+// WHERE clause built up from optional filters, with numbered parameters
+struct SqlFilter {
+    conditions: Vec<String>,
+    params: Vec<rusqlite::types::Value>,
+}
 
-    let conn = &state.pool.get()
-        .map_err(|_| Status::InternalServerError)?;
+#[derive(serde::Serialize)]
+struct IdList {
+    ids: Vec<IdDetails>,
+    // Pass as ?after= to get the next page; absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
 
-    let id = generate_id(&conn, &state.settings.as_ref())
-        .map_err(|_| Status::InternalServerError)?;
+// Response of GET /changes
+#[derive(serde::Serialize)]
+struct ChangeFeed {
+    changes: Vec<ChangeEntry>,
+    // Pass as ?since= to continue; the same cursor when there was nothing new
+    next: i64,
+    // Whether the page was full, so more changes may be waiting
+    more: bool,
+}
 
-    let mut stmt = conn.prepare(
-        "INSERT INTO ids (id, owner, table_name, confirmed, created_at)
-         VALUES (?1, ?2, ?3, 0, CURRENT_TIMESTAMP)"
-    ).map_err(|_| Status::InternalServerError)?;
+#[derive(serde::Serialize)]
+struct ChangeEntry {
+    #[serde(flatten)]
+    change: Change,
+    // The ID as it is now, which may be newer than this change; null once removed
+    details: Option<IdDetails>,
+}
 
-    stmt.execute(rusqlite::params![&id, &owner_clean, &request.table])
-        .map_err(|_| Status::InternalServerError)?;
+// Body of POST /ids/lookup, also used by the other routes taking a list of IDs
+#[derive(serde::Deserialize)]
+struct LookupRequest {
+    ids: Vec<String>,
+}
 
-    let created_at: String = conn.query_row(
-        "SELECT created_at FROM ids WHERE id = ?1",
-        [&id],
-        |row| row.get(0),
-    ).unwrap_or_else(|_| "unknown".to_string());
+// Body of POST /verify_scan: the code exactly as the scanner read it
+#[derive(serde::Deserialize)]
+struct ScanRequest {
+    scan: String,
+}
 
-    Ok(Json(IdDetails {
-        id,
-        owner: owner_clean,
-        table: request.table.clone(),
-        confirmed: 0,
-        created_at,
-    }))
+// Body of POST /tokens/verify
+#[derive(serde::Deserialize)]
+struct TokenRequest {
+    token: String,
 }
 
-#[post("/confirm", format = "json", data = "<request>")]
-fn confirm(
-    request: Json<ConfirmRequest>,
-    state: &State<AppState>,
-) -> Result<Json<ConfirmResponse>, Status> {
-    if state.suspended.load(Ordering::SeqCst) {
-        return Err(Status::ServiceUnavailable);
-    }
+// Body of POST /ids/batch
+#[derive(serde::Deserialize)]
+struct BatchGenerateRequest {
+    owner: String,
+    #[serde(default)]
+    table: Option<String>,
+    count: usize,
+}
 
-    let conn = &state.pool.get()
-        .map_err(|_| Status::InternalServerError)?;
+// Body of POST /labels
+#[derive(serde::Deserialize)]
+struct LabelRequest {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+}
 
-    let rows_affected = conn.execute(
-        "UPDATE ids SET confirmed = 1 WHERE id = ?1",
-        [&request.id],
-    ).map_err(|_| Status::InternalServerError)?;
+#[derive(serde::Serialize)]
+struct TagResponse {
+    label: String,
+    tagged: usize,
+}
 
-    if rows_affected == 0 {
-        return Ok(Json(ConfirmResponse {
-            success: false,
-            message: format!("ID {} not found or already confirmed", request.id),
-        }));
-    }
+// Response of POST /owners/<owner>/confirm_all
+#[derive(serde::Serialize)]
+struct ConfirmAllResponse {
+    owner: String,
+    table: Option<String>,
+    confirmed: usize,
+}
 
-    Ok(Json(ConfirmResponse {
-        success: true,
-        message: format!("ID {} confirmed", request.id),
-    }))
+// Body of POST /ids/<id>/notes
+#[derive(serde::Deserialize)]
+struct NoteRequest {
+    author: String,
+    text: String,
 }
 
-#[get("/get_id/<id>")]
-fn get_id(id: &str, state: &State<AppState>) -> Result<Json<IdDetails>, Status> {
-    let conn = &state.pool.get()
-        .map_err(|_| Status::InternalServerError)?;
+// Body of POST /ids/<id>/refs
+#[derive(serde::Deserialize)]
+struct RefRequest {
+    kind: String,
+    #[serde(default)]
+    label: Option<String>,
+    url: String,
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT owner, table_name, confirmed, created_at FROM ids WHERE id = ?1 AND deleted = 0"
-    ).map_err(|_| Status::InternalServerError)?;
+// Body of PUT /ids/<id>/refs/<ref_id>; absent fields are kept, "label": null clears the label
+#[derive(serde::Deserialize)]
+struct RefUpdate {
+    #[serde(default)]
+    kind: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    label: Option<Option<String>>,
+    #[serde(default)]
+    url: Option<String>,
+}
 
-    let details: Option<IdDetails> = stmt.query_row([&id], |row| {
-        Ok(IdDetails {
-            id: id.to_string(),
-            owner: row.get(0)?,
-            table: row.get(1)?,
-            confirmed: row.get(2)?,
-            created_at: row.get(3)?,
-        })
-    }).optional().map_err(|_| Status::InternalServerError)?;
+// One entry per requested ID, in request order
+#[derive(serde::Serialize)]
+struct LookupResult {
+    id: String,
+    found: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<IdDetails>,
+}
 
-    match details {
-        Some(d) => Ok(Json(d)),
-        None => Err(Status::NotFound),
-    }
+// Result of POST /tokens/verify
+#[derive(serde::Serialize)]
+struct TokenCheck {
+    valid: bool,
+    // The signed ID, for valid tokens only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
 }
 
-// "/ids/" should probably be called something else
-#[put("/ids/<_id>", format = "json", data = "<_data>")]
-fn update_id(_id: &str, _data: Json<serde_json::Value>, state: &State<AppState>,) -> Result<String, Status> {
-    if state.suspended.load(Ordering::SeqCst) {
-        return Err(Status::ServiceUnavailable);
-    }
+// Result of GET /validate/<id>
+#[derive(serde::Serialize)]
+struct IdValidation {
+    id: String,
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    problem: Option<String>,
+}
 
-    Err(Status::NotImplemented)  // 501
+// Result of POST /verify_scan
+#[derive(serde::Serialize)]
+struct ScanResult {
+    scan: String,
+    // The scan normalized; what was looked up
+    id: String,
+    status: ScanStatus,
+    // Why an unknown ID can't be one issued under the current settings, e.g. a misread
+    #[serde(skip_serializing_if = "Option::is_none")]
+    problem: Option<String>,
+    // Only for live IDs; for a repaired ID these are of the ID that replaced it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<IdDetails>,
 }
 
-// "/ids/" should probably be called something else
-#[delete("/ids/<_id>")]
-fn delete_id(_id: &str, state: &State<AppState>,) -> Result<String, Status> {
-    if state.suspended.load(Ordering::SeqCst) {
-        return Err(Status::ServiceUnavailable);
-    }
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ScanStatus {
+    Confirmed,
+    Unconfirmed,
+    Deleted,
+    Unknown,
+}
 
-    Err(Status::NotImplemented)  // 501
-}
\ No newline at end of file
+#[derive(serde::Serialize)]
+struct ConfirmResponse {
+    success: bool,
+    message: String,
+    outcome: ConfirmOutcome,
+}
+
+// Both outcomes are a 200: confirming twice is not an error
+#[derive(Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ConfirmOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+}
+
+// Field rules of the JSON bodies; see validation
+impl Validate for GenerateRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("owner", validation::owner(&self.owner));
+        if let Some(table) = &self.table {
+            checks.check("table", validation::table(table));
+        }
+        if let Some(suffix) = &self.suffix {
+            let result = validation::suffix(checks.settings, suffix);
+            checks.check("suffix", result);
+        }
+        if let Some(external_ref) = &self.external_ref {
+            checks.check("external_ref", validation::external_ref(external_ref));
+        }
+    }
+}
+
+impl Validate for BatchGenerateRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("owner", validation::owner(&self.owner));
+        if let Some(table) = &self.table {
+            checks.check("table", validation::table(table));
+        }
+        if self.count == 0 {
+            checks.check("count", Err("must be at least 1".to_string()));
+        }
+    }
+}
+
+impl Validate for ExportRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        if let Some(owner) = &self.owner {
+            checks.check("owner", validation::owner(owner));
+        }
+        if let Some(table) = &self.table {
+            checks.check("table", validation::table(table));
+        }
+    }
+}
+
+impl Validate for UpdateRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        if let Some(owner) = &self.owner {
+            checks.check("owner", validation::owner(owner));
+        }
+        if let Some(Some(table)) = &self.table {
+            checks.check("table", validation::table(table));
+        }
+    }
+}
+
+impl Validate for LookupRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        let max = checks.settings.lookup_max_ids;
+        checks.ids("ids", &self.ids, max);
+    }
+}
+
+impl Validate for ScanRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("scan", validation::text(&self.scan, validation::MAX_ID_CHARS));
+    }
+}
+
+impl Validate for TokenRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("token", validation::text(&self.token, validation::MAX_TEXT_CHARS));
+    }
+}
+
+impl Validate for NoteRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("author", validation::principal(&self.author));
+        checks.check("text", validation::text(&self.text, notes::MAX_NOTE_CHARS));
+    }
+}
+
+impl Validate for RefRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("kind", validation::principal(&self.kind));
+        if let Some(label) = &self.label {
+            checks.check("label", validation::label(label));
+        }
+        checks.check("url", validation::url(&self.url));
+    }
+}
+
+impl Validate for RefUpdate {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        if let Some(kind) = &self.kind {
+            checks.check("kind", validation::principal(kind));
+        }
+        if let Some(Some(label)) = &self.label {
+            checks.check("label", validation::label(label));
+        }
+        if let Some(url) = &self.url {
+            checks.check("url", validation::url(url));
+        }
+    }
+}
+
+impl Validate for LabelRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("name", validation::principal(&self.name));
+        if let Some(description) = &self.description {
+            checks.check("description", validation::text(description, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for TableEntry {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("name", validation::table(&self.name));
+        if let Some(description) = &self.description {
+            checks.check("description", validation::text(description, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for TableUpdate {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        if let Some(description) = &self.description {
+            checks.check("description", validation::text(description, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for RenameRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("from", validation::table(&self.from));
+        checks.check("to", validation::table(&self.to));
+    }
+}
+
+impl Validate for MergeRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("from", validation::owner(&self.from));
+        checks.check("to", validation::owner(&self.to));
+    }
+}
+
+impl Validate for EraseRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("owner", validation::owner(&self.owner));
+    }
+}
+
+impl Validate for HoldRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        let target = match self.kind {
+            HoldKind::Id => validation::id(&self.target),
+            HoldKind::Owner => validation::owner(&self.target),
+        };
+        checks.check("target", target);
+        if let Some(reason) = &self.reason {
+            checks.check("reason", validation::text(reason, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for CanaryRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("id", validation::id(&self.id));
+        if let Some(note) = &self.note {
+            checks.check("note", validation::text(note, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for RepairRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        let max = checks.settings.lookup_max_ids;
+        checks.ids("ids", &self.ids, max);
+        if let Some(target) = &self.target {
+            checks.check("target", validation::id(target));
+        }
+        if let Some(reason) = &self.reason {
+            checks.check("reason", validation::text(reason, validation::MAX_TEXT_CHARS));
+        }
+    }
+}
+
+impl Validate for SwitchDbRequest {
+    fn validate(&self, checks: &mut Checks<'_>) {
+        checks.check("path", validation::text(&self.path, validation::MAX_TEXT_CHARS));
+    }
+}
+
+// Setting names and values are checked against the settings themselves
+impl Validate for HashMap<String, String> {}
+
+//
+// Functions
+//
+
+impl AppState {
+    // The settings in force, as of the last start, restore or switch
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Writes need an active (lease-holding) node that is not suspended
+    fn ensure_writable(&self) -> Result<(), JsonError> {
+        if self.suspension.writes_blocked() {
+            return Err(self.suspended("Server is temporarily suspended for maintenance"));
+        }
+        if !self.leader.load(Ordering::SeqCst) {
+            // A standby takes over at the latest once the active node's lease runs out
+            return Err(JsonError::new(
+                Status::ServiceUnavailable,
+                ErrorCode::PassiveNode,
+                "This node is passive; writes are handled by the active node",
+            )
+            .header("Retry-After", self.settings().lease_ttl_secs));
+        }
+        Ok(())
+    }
+
+    // ensure_writable for writes to `owner`'s IDs, which an owner suspension also stops
+    fn ensure_owner_writable(&self, owner: &str) -> Result<(), JsonError> {
+        self.ensure_writable()?;
+        if self.suspension.owner_blocked(owner) {
+            return Err(self.suspended("This owner is temporarily suspended for maintenance").details(owner));
+        }
+        Ok(())
+    }
+
+    // ensure_owner_writable for the owners of `ids`; only looked up while an owner is suspended
+    fn ensure_ids_writable(&self, conn: &Connection, ids: &[String]) -> Result<(), JsonError> {
+        self.ensure_writable()?;
+        if !self.suspension.any_owner() {
+            return Ok(());
+        }
+        for id in ids {
+            if let Some(owner) = batch::id_owner(conn, id).map_err(db_error)? {
+                self.ensure_owner_writable(&owner)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Suspending "all" stops reads as well; admin work goes on
+    fn ensure_readable(&self) -> Result<(), JsonError> {
+        if self.suspension.reads_blocked() {
+            return Err(self.suspended("Server is temporarily suspended for maintenance"));
+        }
+        Ok(())
+    }
+
+    fn suspended(&self, message: &str) -> JsonError {
+        JsonError::new(Status::ServiceUnavailable, ErrorCode::Suspended, message)
+            .header("Retry-After", self.settings().suspend_retry_after_secs)
+    }
+
+    // missing_cache is keyed by casefold::key, so that issuing an ID also forgets a miss
+    // cached for another spelling of it
+    fn forget_missing(&self, id: &str) {
+        self.missing_cache.invalidate(&casefold::key(&self.settings(), id));
+    }
+
+    // Runs blocking database work off the async workers, giving up with 504 after the
+    // timeout for its kind so a wedged SQLite lock can't pin Rocket's workers
+    async fn run_db<T, F>(&self, kind: OpKind, work: F) -> Result<T, JsonError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&AppState) -> Result<T, JsonError> + Send + 'static,
+    {
+        let state = self.clone();
+        let claim = Claim::default();
+        let timed_out = timed_out(kind, self.settings().timeout_for(kind));
+        self.dispatch(kind, claim.clone(), move || {
+            tokio::task::spawn_blocking(move || if claim.start() { work(&state) } else { Err(timed_out) })
+        }).await
+    }
+
+    // Shared by run_db and run_write: checks the circuit breaker, starts the task and
+    // waits for it within the timeout for `kind`, then reports the outcome to the breaker.
+    // At the timeout, work that hasn't started is cancelled through `claim` and answered
+    // with 504. Started reads are answered with 504 too, but started writes are waited
+    // for, since they can't be taken back: a 504 then always means nothing was written
+    async fn dispatch<T, E, Task>(&self, kind: OpKind, claim: Claim, start: impl FnOnce() -> Task) -> Result<T, JsonError>
+    where
+        E: std::fmt::Display,
+        Task: Future<Output = Result<Result<T, JsonError>, E>>,
+    {
+        if kind == OpKind::Read {
+            self.ensure_readable()?;
+        }
+        if let Err(wait) = self.breaker.admit() {
+            return Err(JsonError::new(
+                Status::ServiceUnavailable,
+                ErrorCode::DatabaseUnavailable,
+                "Database is failing; requests are paused while it recovers",
+            )
+            .header("Retry-After", (wait.as_millis() as u64).div_ceil(1000).max(1)));
+        }
+
+        let limit = self.settings().timeout_for(kind);
+
+        let mut task = std::pin::pin!(start());
+        let finished = match tokio::time::timeout(limit, &mut task).await {
+            Ok(finished) => Some(finished),
+            Err(_) if claim.cancel() || kind == OpKind::Read => None,
+            Err(_) => {
+                info!("{:?} operation ran past {} ms; waiting for it to finish", kind, limit.as_millis());
+                Some(task.await)
+            }
+        };
+        let result = match finished {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => {
+                error!("Database task failed: {}", e);
+                Err(JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Database task failed"))
+            }
+            None => Err(timed_out(kind, limit)),
+        };
+
+        // Only connection and query failures count; a 404 is the database working fine
+        let failed = matches!(
+            &result,
+            Err(e) if matches!(
+                e.error.error,
+                ErrorCode::DatabaseError | ErrorCode::PoolExhausted | ErrorCode::Timeout | ErrorCode::InternalError
+            )
+        );
+        self.breaker.record(!failed);
+        result
+    }
+
+    // run_db for the paths with latency histograms, recording the time spent in `work`
+    async fn run_db_metered<T, F>(&self, kind: OpKind, path: latency::Path, work: F) -> Result<T, JsonError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&AppState) -> Result<T, JsonError> + Send + 'static,
+    {
+        self.run_db(kind, move |state| {
+            let started = Instant::now();
+            let result = work(state);
+            state.latency.record_db(path, started.elapsed());
+            result
+        }).await
+    }
+
+    // Queues mutating work on the writer thread, which runs it on its own connection.
+    // Work that hits SQLITE_BUSY/LOCKED (from background jobs or another process) is
+    // re-run with jittered exponential backoff until busy_retry_budget_ms is spent
+    async fn run_write<T, F>(&self, kind: OpKind, work: F) -> Result<T, JsonError>
+    where
+        T: Send + 'static,
+        F: Fn(&AppState, &mut Connection) -> Result<T, JsonError> + Send + 'static,
+    {
+        let state = self.clone();
+        let claim = Claim::default();
+        self.dispatch(kind, claim.clone(), move || {
+            self.writer.submit_claimed(claim, move |conn| state.retry_busy(|| work(&state, conn)))
+        }).await
+    }
+
+    // run_write for the paths with latency histograms; the time queued behind other
+    // writes is not database time, so only the work itself is recorded
+    async fn run_write_metered<T, F>(&self, path: latency::Path, work: F) -> Result<T, JsonError>
+    where
+        T: Send + 'static,
+        F: Fn(&AppState, &mut Connection) -> Result<T, JsonError> + Send + 'static,
+    {
+        self.run_write(OpKind::Write, move |state, conn| {
+            let started = Instant::now();
+            let result = work(state, conn);
+            state.latency.record_db(path, started.elapsed());
+            result
+        }).await
+    }
+
+    fn retry_busy<T>(&self, mut work: impl FnMut() -> Result<T, JsonError>) -> Result<T, JsonError> {
+        let deadline = Instant::now() + Duration::from_millis(self.settings().busy_retry_budget_ms);
+        let mut backoff = Duration::from_millis(5);
+        let mut attempts = 1;
+        loop {
+            match work() {
+                Err(e) if e.error.error == ErrorCode::DatabaseBusy => {
+                    // Full jitter so contending writers don't wake up in lockstep
+                    let pause = backoff.mul_f64(rand::random::<f64>());
+                    if Instant::now() + pause >= deadline {
+                        error!("Database still busy after {} attempts; giving up", attempts);
+                        return Err(e);
+                    }
+                    std::thread::sleep(pause);
+                    backoff = (backoff * 2).min(Duration::from_millis(200));
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Waits at most pool_wait_ms for a connection, then fails fast with 503 so requests
+    // don't pile up behind a saturated pool
+    fn conn(&self) -> Result<PooledConnection<DbManager>, JsonError> {
+        let wait = Duration::from_millis(self.settings().pool_wait_ms);
+        self.pool.get_timeout(wait).map_err(|e| {
+            self.pool_rejections.fetch_add(1, Ordering::Relaxed);
+            error!("Pool error: {}", e);
+            JsonError::new(
+                Status::ServiceUnavailable,
+                ErrorCode::PoolExhausted,
+                "No database connection available",
+            )
+            .details(e)
+            .header("Retry-After", 1)
+        })
+    }
+}
+
+impl JsonError {
+    fn new(status: Status, error: ErrorCode, message: &str) -> Self {
+        JsonError {
+            status,
+            error: ApiError {
+                error,
+                message: message.to_string(),
+                details: None,
+                errors: Vec::new(),
+            },
+            headers: Vec::new(),
+        }
+    }
+
+    fn details(mut self, details: impl std::fmt::Display) -> Self {
+        self.error.details = Some(details.to_string());
+        self
+    }
+
+    fn header(mut self, name: &'static str, value: impl std::fmt::Display) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    fn errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.error.errors = errors;
+        self
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let Some(state) = req.rocket().state::<AppState>() else {
+            return request::Outcome::Success(RateLimited(req.client_ip()));
+        };
+        let Some(ip) = req.client_ip() else {
+            return request::Outcome::Success(RateLimited(None));
+        };
+        // Socket clients would otherwise all share the 127.0.0.1 bucket; the socket's
+        // file permissions already decide who may use it
+        if !state.limiter.enabled() || req.remote().is_some_and(|remote| state.relayed.contains(remote)) {
+            return request::Outcome::Success(RateLimited(Some(ip)));
+        }
+
+        match state.limiter.check(ip) {
+            Ok(_) => request::Outcome::Success(RateLimited(Some(ip))),
+            Err(info) => {
+                // Picked up by the 429 catcher to fill in the headers
+                req.local_cache(|| Some(info));
+                request::Outcome::Error((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WantLinks {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let by_query = matches!(req.query_value::<bool>("links"), Some(Ok(true)));
+        let by_profile = req.accept().is_some_and(|accept| {
+            accept.iter().any(|media| {
+                media.params().any(|(key, value)| key == "profile" && value.split_whitespace().any(|p| p == "links"))
+            })
+        });
+        request::Outcome::Success(WantLinks(by_query || by_profile))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WantNdjson {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let ndjson = req.accept().is_some_and(|accept| {
+            accept.iter().any(|media| media.top() == "application" && matches!(media.sub().as_str(), "x-ndjson" | "ndjson"))
+        });
+        request::Outcome::Success(WantNdjson(ndjson))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Fields {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let list = match req.query_value::<&str>("fields") {
+            Some(Ok(list)) => list,
+            _ => return request::Outcome::Success(Fields::ALL),
+        };
+        match Fields::parse(list) {
+            Ok(fields) => request::Outcome::Success(fields),
+            Err(unknown) => {
+                // Picked up by the 400 catcher to name the field
+                req.local_cache(|| Some(unknown));
+                request::Outcome::Error((Status::BadRequest, ()))
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DeletedScope {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let flag = |name| matches!(req.query_value::<bool>(name), Some(Ok(true)));
+        let scope = if flag("only_deleted") {
+            DeletedScope::Only
+        } else if flag("include_deleted") {
+            DeletedScope::Include
+        } else {
+            return request::Outcome::Success(DeletedScope::Live);
+        };
+
+        let secret = req.query_value::<&str>("secret").and_then(Result::ok);
+        match req.rocket().state::<AppState>() {
+            Some(state) if check_secret(secret, state).is_ok() => request::Outcome::Success(scope),
+            _ => request::Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DryRun {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let value = req.headers().get_one("X-Dry-Run").unwrap_or("");
+        let requested = matches!(value.trim().to_ascii_lowercase().as_str(), "true" | "1");
+
+        // In shadow mode every mutation is a dry run
+        let shadow = req.rocket().state::<AppState>().is_some_and(|state| state.settings().shadow_mode);
+        if shadow {
+            info!("Shadow mode: {} {} answered without writing", req.method(), req.uri());
+        }
+
+        request::Outcome::Success(DryRun(requested || shadow))
+    }
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for DryRunMarked<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(req)?;
+        if self.1.0 {
+            response.set_raw_header("X-Dry-Run", "true");
+        }
+        Ok(response)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for SnapshotDownload {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let disposition = format!("attachment; filename=\"{}\"", self.name);
+        response::Response::build()
+            .header(ContentType::new("application", "vnd.sqlite3"))
+            .raw_header("Content-Disposition", disposition)
+            .sized_body(None, self)
+            .ok()
+    }
+}
+
+impl tokio::io::AsyncRead for SnapshotDownload {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.file.as_mut() {
+            Some(file) => std::pin::Pin::new(file).poll_read(cx, buf),
+            None => std::task::Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl tokio::io::AsyncSeek for SnapshotDownload {
+    fn start_seek(mut self: std::pin::Pin<&mut Self>, position: std::io::SeekFrom) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => std::pin::Pin::new(file).start_seek(position),
+            None => Ok(()),
+        }
+    }
+
+    fn poll_complete(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<u64>> {
+        match self.file.as_mut() {
+            Some(file) => std::pin::Pin::new(file).poll_complete(cx),
+            None => std::task::Poll::Ready(Ok(0)),
+        }
+    }
+}
+
+impl Drop for SnapshotDownload {
+    fn drop(&mut self) {
+        // Close first: Windows won't delete an open file
+        drop(self.file.take());
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for OwnerKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(OwnerKey(req.headers().get_one("X-Owner-Key").map(|key| key.trim().to_string())))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(IfMatch(req.headers().get_one("If-Match").map(str::to_string)))
+    }
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Tagged<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.body.respond_to(req)?;
+        response.set_raw_header("ETag", etag(self.version));
+        Ok(response)
+    }
+}
+
+fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+// Accepts "3" as well as the weak form W/"3"
+fn parse_etag(tag: &str) -> Option<i64> {
+    tag.trim().trim_start_matches("W/").trim_matches('"').parse().ok()
+}
+
+// Lets a field sent as null be told apart from one left out (serde makes both None)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl From<IdRecord> for IdDetails {
+    fn from(record: IdRecord) -> Self {
+        IdDetails {
+            id: record.id,
+            owner: record.owner,
+            table: record.table,
+            confirmed: record.confirmed,
+            created_at: record.created_at,
+            version: record.version,
+            confirmed_at: record.confirmed_at,
+            confirmed_by: record.confirmed_by,
+            config_version: record.config_version,
+            external_ref: record.external_ref,
+            deleted: false,
+            token: None,
+            display_id: None,
+            links: None,
+            notes: None,
+            fields: Fields::ALL,
+        }
+    }
+}
+
+impl Fields {
+    const ID: u16 = 1 << 0;
+    const OWNER: u16 = 1 << 1;
+    const TABLE: u16 = 1 << 2;
+    const CONFIRMED: u16 = 1 << 3;
+    const CREATED_AT: u16 = 1 << 4;
+    const VERSION: u16 = 1 << 5;
+    const CONFIRMED_AT: u16 = 1 << 6;
+    const CONFIRMED_BY: u16 = 1 << 7;
+    const CONFIG_VERSION: u16 = 1 << 8;
+    const EXTERNAL_REF: u16 = 1 << 9;
+    // Derived from the ID (see IdDetails::with_derived), and only when selected
+    const TOKEN: u16 = 1 << 10;
+    const DISPLAY_ID: u16 = 1 << 11;
+    const DERIVED: u16 = Self::TOKEN | Self::DISPLAY_ID;
+    const ALL: Fields = Fields(0b1111_1111_1111);
+
+    const NAMES: [(&'static str, u16); 12] = [
+        ("id", Self::ID),
+        ("owner", Self::OWNER),
+        ("table", Self::TABLE),
+        ("confirmed", Self::CONFIRMED),
+        ("created_at", Self::CREATED_AT),
+        ("version", Self::VERSION),
+        ("confirmed_at", Self::CONFIRMED_AT),
+        ("confirmed_by", Self::CONFIRMED_BY),
+        ("config_version", Self::CONFIG_VERSION),
+        ("external_ref", Self::EXTERNAL_REF),
+        ("token", Self::TOKEN),
+        ("display_id", Self::DISPLAY_ID),
+    ];
+
+    // An empty list means everything
+    fn parse(list: &str) -> Result<Fields, UnknownField> {
+        let mut mask = 0;
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match Self::NAMES.iter().find(|(n, _)| *n == name) {
+                Some((_, bit)) => mask |= bit,
+                None => return Err(UnknownField(name.to_string())),
+            }
+        }
+        Ok(if mask == 0 { Fields::ALL } else { Fields(mask) })
+    }
+
+    fn has(self, bit: u16) -> bool {
+        self.0 & bit != 0
+    }
+}
+
+impl serde::Serialize for IdDetails {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let f = self.fields;
+        let token = self.token.as_ref().filter(|_| f.has(Fields::TOKEN));
+        let display_id = self.display_id.as_ref().filter(|_| f.has(Fields::DISPLAY_ID));
+        let len = (f.0 & !Fields::DERIVED).count_ones() as usize
+            + usize::from(self.deleted)
+            + usize::from(token.is_some())
+            + usize::from(display_id.is_some())
+            + usize::from(self.links.is_some())
+            + usize::from(self.notes.is_some());
+        let mut map = serializer.serialize_map(Some(len))?;
+        if f.has(Fields::ID) {
+            map.serialize_entry("id", &self.id)?;
+        }
+        if let Some(display_id) = display_id {
+            map.serialize_entry("display_id", display_id)?;
+        }
+        if f.has(Fields::OWNER) {
+            map.serialize_entry("owner", &self.owner)?;
+        }
+        if f.has(Fields::TABLE) {
+            map.serialize_entry("table", &self.table)?;
+        }
+        if f.has(Fields::CONFIRMED) {
+            map.serialize_entry("confirmed", &self.confirmed)?;
+        }
+        if f.has(Fields::CREATED_AT) {
+            map.serialize_entry("created_at", &self.created_at)?;
+        }
+        if f.has(Fields::VERSION) {
+            map.serialize_entry("version", &self.version)?;
+        }
+        if f.has(Fields::CONFIRMED_AT) {
+            map.serialize_entry("confirmed_at", &self.confirmed_at)?;
+        }
+        if f.has(Fields::CONFIRMED_BY) {
+            map.serialize_entry("confirmed_by", &self.confirmed_by)?;
+        }
+        if f.has(Fields::CONFIG_VERSION) {
+            map.serialize_entry("config_version", &self.config_version)?;
+        }
+        if f.has(Fields::EXTERNAL_REF) {
+            map.serialize_entry("external_ref", &self.external_ref)?;
+        }
+        if self.deleted {
+            map.serialize_entry("deleted", &true)?;
+        }
+        if let Some(token) = token {
+            map.serialize_entry("token", token)?;
+        }
+        if let Some(links) = &self.links {
+            map.serialize_entry("links", links)?;
+        }
+        if let Some(notes) = &self.notes {
+            map.serialize_entry("notes", notes)?;
+        }
+        map.end()
+    }
+}
+
+// Columns read by details_from_row, in order
+const DETAIL_COLUMNS: &str =
+    "id, owner, table_name, confirmed, created_at, version, confirmed_at, confirmed_by, deleted != 0, config_version, external_ref";
+
+fn details_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<IdDetails> {
+    Ok(IdDetails {
+        id: row.get(0)?,
+        owner: row.get(1)?,
+        table: row.get(2)?,
+        confirmed: row.get(3)?,
+        created_at: row.get(4)?,
+        version: row.get(5)?,
+        confirmed_at: row.get(6)?,
+        confirmed_by: row.get(7)?,
+        deleted: row.get(8)?,
+        config_version: row.get(9)?,
+        external_ref: row.get(10)?,
+        token: None,
+        display_id: None,
+        links: None,
+        notes: None,
+        fields: Fields::ALL,
+    })
+}
+
+impl DeletedScope {
+    // Base condition on the ids table
+    fn condition(self) -> &'static str {
+        match self {
+            DeletedScope::Live => "deleted = 0",
+            DeletedScope::Include => "1 = 1",
+            DeletedScope::Only => "deleted != 0",
+        }
+    }
+}
+
+impl IdDetails {
+    // A freshly issued ID, as it was just inserted
+    fn issued(id: String, owner: &str, table: Option<&str>, created_at: String, config_version: Option<i64>) -> Self {
+        IdDetails {
+            id,
+            owner: owner.to_string(),
+            table: table.map(str::to_string),
+            confirmed: 0,
+            created_at,
+            version: 1,
+            confirmed_at: None,
+            confirmed_by: None,
+            config_version,
+            external_ref: None,
+            deleted: false,
+            token: None,
+            display_id: None,
+            links: None,
+            notes: None,
+            fields: Fields::ALL,
+        }
+    }
+
+    fn with_fields(mut self, fields: Fields) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    // Fields computed from the ID under the current settings, those ?fields= selects
+    // (call with_fields first). IDs from a dry run were never issued, so they get no token
+    fn with_derived(mut self, settings: &Settings, dry_run: DryRun) -> Self {
+        if self.fields.has(Fields::TOKEN) && !settings.token_key.is_empty() && !dry_run.0 {
+            self.token = Some(tokens::sign(&settings.token_key, &self.id));
+        }
+        if self.fields.has(Fields::DISPLAY_ID) {
+            self.display_id = display_id(settings, &self.id);
+        }
+        self
+    }
+
+    fn with_links(mut self, want: &WantLinks) -> Self {
+        if want.0 {
+            self.links = Some(Links {
+                self_: uri!(get_id(id = &self.id, notes = _)).to_string(),
+                confirm: (self.confirmed == 0).then(|| uri!(confirm(id = &self.id, by = _, owner = _)).to_string()),
+                notes: uri!(list_notes(id = &self.id)).to_string(),
+                refs: uri!(list_refs(id = &self.id)).to_string(),
+                history: format!("{}?id={}", uri!(list_changes(query = _)), RawStr::new(&self.id).percent_encode()),
+                owner: uri!(list_owner_ids(owner = &self.owner, query = _)).to_string(),
+            });
+        }
+        self
+    }
+}
+
+// Database work of `kind` that didn't finish within `limit`
+fn timed_out(kind: OpKind, limit: Duration) -> JsonError {
+    JsonError::new(Status::GatewayTimeout, ErrorCode::Timeout, "Database operation timed out")
+        .details(format!("{:?} operations are limited to {} ms", kind, limit.as_millis()))
+}
+
+// Any unexpected SQLite failure; lock contention becomes a retryable 503 instead
+fn db_error(e: impl Into<anyhow::Error>) -> JsonError {
+    let e = e.into();
+    if is_busy(&e) {
+        return JsonError::new(Status::ServiceUnavailable, ErrorCode::DatabaseBusy, "Database is busy")
+            .details(e)
+            .header("Retry-After", 1);
+    }
+    error!("Database error: {}", e);
+    JsonError::new(Status::InternalServerError, ErrorCode::DatabaseError, "Database operation failed").details(e)
+}
+
+// True for SQLITE_BUSY / SQLITE_LOCKED anywhere in the error chain
+fn is_busy(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| matches!(
+        cause.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(f, _))
+            if matches!(f.code, rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    ))
+}
+
+// True for SQLite UNIQUE / PRIMARY KEY violations
+fn is_unique_violation(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(f, _) if f.code == rusqlite::ErrorCode::ConstraintViolation)
+}
+
+// Failures from update_id_record: the typed outcomes map to 404, 410 and 412
+fn update_error(e: anyhow::Error) -> JsonError {
+    if let Some(missing) = e.downcast_ref::<IdNotFound>() {
+        return id_not_found(&missing.id);
+    }
+    if let Some(deleted) = e.downcast_ref::<IdDeleted>() {
+        return id_deleted(&deleted.id);
+    }
+    if e.downcast_ref::<OnHold>().is_some() {
+        return hold_error(e);
+    }
+    if let Some(conflict) = e.downcast_ref::<VersionConflict>() {
+        return JsonError::new(Status::PreconditionFailed, ErrorCode::VersionConflict, "ID was changed by someone else")
+            .details(format!("current version is {}; fetch it again and retry", conflict.current))
+            .header("ETag", etag(conflict.current));
+    }
+    // The only unique key an update can hit: moving an ID to an owner using its external_ref
+    if e.downcast_ref::<rusqlite::Error>().is_some_and(is_unique_violation) {
+        return JsonError::new(Status::Conflict, ErrorCode::IdConflict, "The owner already has an ID with this external_ref");
+    }
+    db_error(e)
+}
+
+// Failures from tables::check
+fn table_error(e: anyhow::Error) -> JsonError {
+    match e.downcast_ref::<UnknownTable>() {
+        Some(unknown) => JsonError::new(Status::BadRequest, ErrorCode::UnknownTable, "Unknown table").details(unknown),
+        None => db_error(e),
+    }
+}
+
+// Failures from generate_id / issue_id: running out of free IDs and insert conflicts are reported separately
+fn generation_error(e: anyhow::Error) -> JsonError {
+    if let Some(invalid) = e.downcast_ref::<InvalidSuffix>() {
+        return JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid suffix").details(invalid);
+    }
+    error!("Generation failed: {}", e);
+    let conflicted = e.downcast_ref::<IdConflict>().is_some()
+        || e.downcast_ref::<rusqlite::Error>().is_some_and(is_unique_violation);
+    if conflicted {
+        return JsonError::new(Status::Conflict, ErrorCode::IdConflict, "Generated ID collided with an existing one")
+            .details(e);
+    }
+    // A spent sequence is final (507); random generation ran out of luck this time and
+    // may find a free ID on a retry (503), but the keyspace is close to full either way
+    if let Some(exhausted) = e.downcast_ref::<KeyspaceExhausted>() {
+        let guidance = "raise id_length, add charset characters, or let generation lengthen IDs with generation_max_extra_length";
+        if exhausted.sequence {
+            return JsonError::new(Status::InsufficientStorage, ErrorCode::KeyspaceExhausted, "The ID sequence is used up")
+                .details(format!("{}; {}", e, guidance));
+        }
+        return JsonError::new(Status::ServiceUnavailable, ErrorCode::KeyspaceExhausted, "Could not find an unused ID")
+            .details(format!("{}; {}", e, guidance))
+            .header("Retry-After", 1);
+    }
+    db_error(e)
+}
+
+// Fields left out by ?fields= keep their proto3 defaults, so they cost nothing on the wire
+impl ProtoEncode for IdDetails {
+    fn encode_proto(&self) -> Option<Vec<u8>> {
+        let f = self.fields;
+        Some(proto::IdDetails {
+            id: if f.has(Fields::ID) { self.id.clone() } else { String::new() },
+            owner: if f.has(Fields::OWNER) { self.owner.clone() } else { String::new() },
+            table: self.table.clone().filter(|_| f.has(Fields::TABLE)),
+            confirmed: if f.has(Fields::CONFIRMED) { self.confirmed } else { 0 },
+            created_at: if f.has(Fields::CREATED_AT) { self.created_at.clone() } else { String::new() },
+            version: if f.has(Fields::VERSION) { self.version } else { 0 },
+            confirmed_at: self.confirmed_at.clone().filter(|_| f.has(Fields::CONFIRMED_AT)),
+            confirmed_by: self.confirmed_by.clone().filter(|_| f.has(Fields::CONFIRMED_BY)),
+            config_version: self.config_version.filter(|_| f.has(Fields::CONFIG_VERSION)),
+            external_ref: self.external_ref.clone().filter(|_| f.has(Fields::EXTERNAL_REF)),
+            token: self.token.clone().filter(|_| f.has(Fields::TOKEN)),
+            display_id: self.display_id.clone().filter(|_| f.has(Fields::DISPLAY_ID)),
+        }.encode_to_vec())
+    }
+}
+
+impl ProtoEncode for DeleteResponse {}
+
+impl ProtoEncode for Vec<LookupResult> {}
+
+impl ProtoEncode for ScanResult {}
+
+impl ProtoEncode for IdList {}
+
+impl ProtoEncode for ChangeFeed {}
+
+impl ProtoEncode for CountResponse {}
+
+impl ProtoEncode for Note {}
+
+impl ProtoEncode for Vec<Note> {}
+
+impl ProtoEncode for ExternalRef {}
+
+impl ProtoEncode for Vec<Label> {}
+
+impl ProtoEncode for Vec<String> {}
+
+impl ProtoEncode for TagResponse {}
+
+impl ProtoEncode for ConfirmAllResponse {}
+
+impl ProtoEncode for Vec<ExternalRef> {}
+
+impl ProtoEncode for Vec<IdDetails> {}
+
+impl ProtoEncode for Vec<BatchResult> {}
+
+impl ProtoEncode for JobStatus {}
+
+impl From<Job> for JobStatus {
+    fn from(job: Job) -> Self {
+        let links = JobLinks {
+            self_: uri!(get_job(job_id = &job.job_id)).to_string(),
+            result: (job.status != "running").then(|| uri!(job_result(job_id = &job.job_id)).to_string()),
+        };
+        JobStatus { job, links }
+    }
+}
+
+impl SqlFilter {
+    fn new(base: &str) -> Self {
+        SqlFilter { conditions: vec![base.to_string()], params: Vec::new() }
+    }
+
+    // `condition` uses a bare ? for the value, e.g. "owner = ?"
+    fn add(&mut self, condition: &str, value: impl Into<rusqlite::types::Value>) {
+        self.params.push(value.into());
+        self.conditions.push(condition.replace('?', &format!("?{}", self.params.len())));
+    }
+
+    fn where_clause(&self) -> String {
+        self.conditions.join(" AND ")
+    }
+}
+
+impl ProtoEncode for ConfirmResponse {
+    fn encode_proto(&self) -> Option<Vec<u8>> {
+        Some(proto::ConfirmResponse {
+            success: self.success,
+            message: self.message.clone(),
+            outcome: match self.outcome {
+                ConfirmOutcome::Confirmed => "confirmed",
+                ConfirmOutcome::AlreadyConfirmed => "already_confirmed",
+            }.to_string(),
+        }.encode_to_vec())
+    }
+}
+
+impl<'r> Responder<'r, 'r> for JsonError {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'r> {
+        let body = serde_json::to_string(&self.error).unwrap_or_else(|_| {
+            r#"{"error":"internal_error","message":"Failed to serialize error"}"#.to_string()
+        });
+
+        let mut response = response::Response::build();
+        response
+            .status(self.status)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), std::io::Cursor::new(body));
+
+        for (name, value) in self.headers {
+            response.raw_header(name, value);
+        }
+
+        response.ok()
+    }
+}
+
+#[catch(400)]
+fn bad_request(req: &Request<'_>) -> JsonError {
+    if let Some(UnknownField(name)) = req.local_cache(|| None::<UnknownField>) {
+        let known: Vec<&str> = Fields::NAMES.iter().map(|(n, _)| *n).collect();
+        return JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Unknown field in ?fields=")
+            .details(format!("'{}'; available: {}", name, known.join(", ")));
+    }
+    JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid request parameters or body")
+}
+
+#[catch(401)]
+fn unauthorized(_req: &Request<'_>) -> JsonError {
+    JsonError::new(Status::Unauthorized, ErrorCode::Unauthorized, "Authentication required")
+}
+
+#[catch(404)]
+fn not_found(_req: &Request<'_>) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Resource not found")
+}
+
+#[catch(413)]
+fn payload_too_large(req: &Request<'_>) -> JsonError {
+    let error = JsonError::new(Status::PayloadTooLarge, ErrorCode::PayloadTooLarge, "Request body is too large");
+
+    // Name the limit that applied, so callers know what to stay under
+    let limit = match req.content_type() {
+        Some(ct) if ct.is_json() => req.limits().get("json"),
+        Some(ct) if ct.is_form() => req.limits().get("form"),
+        _ => req.limits().get("bytes"),
+    };
+    match limit {
+        Some(limit) => error.details(format!("limit is {}", limit)),
+        None => error,
+    }
+}
+
+// A JSON body that didn't parse or failed its field rules; see validation
+#[catch(422)]
+fn unprocessable(req: &Request<'_>) -> JsonError {
+    let errors = req.local_cache(|| None::<Invalid>).as_ref().map_or_else(Vec::new, |invalid| invalid.0.clone());
+    JsonError::new(Status::UnprocessableEntity, ErrorCode::ValidationFailed, "Request body failed validation").errors(errors)
+}
+
+#[catch(429)]
+fn too_many_requests(req: &Request<'_>) -> JsonError {
+    let mut error = JsonError::new(Status::TooManyRequests, ErrorCode::RateLimited, "Too many requests; slow down");
+
+    if let Some(info) = req.local_cache(|| None::<RateInfo>) {
+        error = error
+            .header("X-RateLimit-Limit", info.limit)
+            .header("X-RateLimit-Remaining", info.remaining)
+            .header("X-RateLimit-Reset", info.reset_secs)
+            .header("Retry-After", info.reset_secs);
+    }
+
+    error
+}
+
+#[catch(501)]
+fn not_implemented(_req: &Request<'_>) -> JsonError {
+    JsonError::new(Status::NotImplemented, ErrorCode::NotImplemented, "This feature is not yet available")
+}
+
+#[catch(503)]
+fn service_unavailable(_req: &Request<'_>) -> JsonError {
+    JsonError::new(
+        Status::ServiceUnavailable,
+        ErrorCode::ServiceUnavailable,
+        "Server is temporarily suspended for maintenance",
+    )
+}
+
+#[catch(default)]
+fn default_error(status: Status, _req: &Request<'_>) -> JsonError {
+    JsonError::new(status, ErrorCode::InternalError, &format!("Unexpected error ({})", status.code))
+}
+
+// Rocket's configuration shared by both listeners. Its log output is plain text, so in a
+// container it is switched off and the server's own JSON lines (see LatencyTimer) stand in
+fn base_figment(limits: &Limits, container: bool) -> rocket::figment::Figment {
+    let figment = rocket::Config::figment().merge(("limits", limits.clone()));
+    if container { figment.merge(("log_level", rocket::config::LogLevel::Off)) } else { figment }
+}
+
+fn catchers() -> Vec<rocket::Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        not_found,
+        payload_too_large,
+        unprocessable,
+        too_many_requests,
+        not_implemented,
+        service_unavailable,
+        default_error
+    ]
+}
+
+// Registers the maintenance tasks on their *_schedule settings and starts the scheduler.
+// Tasks that write (or that only one node should run) are skipped unless `may_write`
+#[allow(clippy::too_many_arguments)]
+fn schedule_maintenance(
+    pool: &DbPool,
+    writer: &Arc<Writer>,
+    id_cache: &Arc<TtlCache<IdDetails>>,
+    missing_cache: &Arc<TtlCache<()>>,
+    settings: &Arc<Settings>,
+    consistency: &Arc<RwLock<Option<ConsistencyReport>>>,
+    anomalies: &Arc<RwLock<Option<AnomalyReport>>>,
+    may_write: impl Fn() -> bool + Clone + Send + 'static,
+) -> Schedules {
+    const SKIPPED: &str = "skipped: this node is not writing";
+    let mut scheduler = Scheduler::new();
+
+    // Runs once at start too, so /stats has a report right away
+    let (p, s, latest) = (pool.clone(), settings.clone(), consistency.clone());
+    scheduler.add("consistency", &settings.consistency_schedule, true, move || consistency::refresh(&p, &s, &latest))
+        .expect("Invalid consistency_schedule");
+
+    let (p, s, latest) = (pool.clone(), settings.clone(), anomalies.clone());
+    scheduler.add("anomaly", &settings.anomaly_schedule, false, move || anomaly::refresh(&p, &s, &latest))
+        .expect("Invalid anomaly_schedule");
+
+    let (p, s, writing) = (pool.clone(), settings.clone(), may_write.clone());
+    scheduler.add("janitor", &settings.janitor_schedule, false, move || {
+        if !writing() {
+            return Ok(SKIPPED.to_string());
+        }
+        maintenance::janitor(&p, &s)
+    }).expect("Invalid janitor_schedule");
+
+    // On the writer thread, as it deletes rows requests may be writing to. The caches
+    // are dropped afterwards: they may still remember purged IDs or aliases to them
+    let (w, s, writing) = (writer.clone(), settings.clone(), may_write.clone());
+    let (ids, missing) = (id_cache.clone(), missing_cache.clone());
+    scheduler.add("purge", &settings.purge_schedule, false, move || {
+        if !writing() {
+            return Ok(SKIPPED.to_string());
+        }
+        let s = s.clone();
+        let summary = w.submit(move |conn| maintenance::purge(conn, &s))
+            .blocking_recv()
+            .map_err(|_| anyhow::anyhow!("The writer thread is gone"))??;
+        ids.clear();
+        missing.clear();
+        Ok(summary)
+    }).expect("Invalid purge_schedule");
+
+    let p = pool.clone();
+    scheduler.add("checkpoint", &settings.checkpoint_schedule, false, move || maintenance::checkpoint(&p))
+        .expect("Invalid checkpoint_schedule");
+
+    let (p, s) = (pool.clone(), settings.clone());
+    scheduler.add("backup", &settings.backup_schedule, false, move || {
+        if !may_write() {
+            return Ok(SKIPPED.to_string());
+        }
+        maintenance::backup(&p, &s)
+    }).expect("Invalid backup_schedule");
+
+    scheduler.spawn()
+}
+
+#[rocket::main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    // --check validates the setup, prints the report and exits without serving
+    if std::env::args().skip(1).any(|arg| arg == "--check") {
+        let report = preflight::run();
+        println!("{}", serde_json::to_string_pretty(&report).expect("Failed to serialize the check report"));
+        std::process::exit(report.exit_code());
+    }
+
+    // IDREG_PROFILE=container takes everything from the environment and logs JSON;
+    // --dev runs without the registry setup, on a local database created on first use
+    let profile = Profile::detect().expect("Failed to select a startup profile");
+    let container = profile == Profile::Container;
+    if container {
+        id_registry_server::log::use_json();
+    }
+    info!("Starting ID Registry Server...");
+
+    let (pool, db) = create_db_pool(profile).expect("Failed to create DB pool");
+
+    // Load settings once at startup (using a connection from pool)
+    let mut conn = pool.get().expect("Failed to get connection for init");
+    migrations::run(&mut conn).expect("Failed to migrate database schema");
+    if container {
+        let applied = apply_env_settings(&mut conn).expect("Failed to apply IDREG_* settings");
+        if !applied.is_empty() {
+            info!("Settings from the environment: {}", applied.join(", "));
+        }
+    }
+    let settings = load_settings(&conn).expect("Failed to load settings");
+    check_keyspace(&settings).expect("Keyspace too small");
+    let admin = Arc::new(AdminSecrets::load(&conn, &settings).expect("Failed to load admin secret"));
+    let canaries = Arc::new(RwLock::new(canaries::all(&conn).expect("Failed to load canary IDs")));
+
+    info!("Database pool ready");
+    info!("ID length: {}", settings.id_length);
+    info!("Charset  : {}", settings.charset);
+    // With min_keyspace_mode = refuse, check_keyspace has already failed
+    if let Some(shortfall) = settings.keyspace_shortfall() {
+        error!("WARNING: {}; IDs will collide and run out quickly", shortfall);
+    }
+
+    // Only the node holding the lease generates; others serve reads until it lapses
+    let node_id = lease::node_id();
+    let shadow = settings.shadow_mode;
+
+    // A shadow instance answers as if active but must never take the lease from the real one
+    let is_leader = if shadow {
+        info!("Node {} starting in shadow mode: mutations are answered but not persisted", node_id);
+        true
+    } else {
+        let is_leader = lease::try_acquire(&conn, &node_id, settings.lease_ttl_secs)
+            .expect("Failed to check leader lease");
+        info!("Node {} starting as {}", node_id, if is_leader { "active" } else { "passive" });
+        is_leader
+    };
+
+    // Pooled IDs were made under the previous settings, which may have changed since
+    if is_leader && !shadow {
+        // Catches id_length / charset / id_content_policy edited outside the server; new
+        // IDs are stamped with the config's version (see issue_id)
+        genconfig::record(&conn, &settings).expect("Failed to record the generation config");
+        casefold::sync_index(&conn, &settings).expect("Failed to apply case_insensitive_ids");
+        idpool::clear(&conn).expect("Failed to clear ID pool");
+        let abandoned = jobs::abandon_running(&conn).expect("Failed to check for abandoned jobs");
+        if abandoned > 0 {
+            info!("Marked {} job(s) left running by the previous process as failed", abandoned);
+        }
+    }
+    drop(conn);
+
+    let leader = Arc::new(AtomicBool::new(is_leader));
+    if !shadow {
+        lease::spawn_keeper(pool.clone(), node_id.clone(), settings.lease_ttl_secs, leader.clone());
+    }
+
+    let id_cache = Arc::new(TtlCache::new(
+        settings.cache_capacity,
+        Duration::from_secs(settings.cache_ttl_secs),
+    ));
+    // Short-lived memory of unknown IDs, so scanners probing guesses don't each hit the DB
+    let missing_cache = Arc::new(TtlCache::new(
+        settings.cache_capacity,
+        Duration::from_secs(settings.negative_cache_ttl_secs),
+    ));
+
+    let limiter = Arc::new(RateLimiter::new(settings.rate_limit_per_minute, Duration::from_secs(60)));
+    let breaker = Arc::new(CircuitBreaker::new(
+        settings.breaker_threshold,
+        Duration::from_secs(settings.breaker_cooldown_secs),
+    ));
+
+    let writer = Arc::new(Writer::start(&pool, db.clone()).expect("Failed to start the writer thread"));
+
+    let settings_arc = Arc::new(settings);
+
+    let suspension = Arc::new(Suspension::default());
+
+    // Background writers leave the DB alone on passive, suspended or shadow nodes
+    let may_write = {
+        let (suspension, leader) = (suspension.clone(), leader.clone());
+        move || !shadow && !suspension.writes_blocked() && leader.load(Ordering::SeqCst)
+    };
+    idpool::spawn_refiller(pool.clone(), settings_arc.clone(), may_write.clone());
+
+    let consistency = Arc::new(RwLock::new(None));
+    let anomalies = Arc::new(RwLock::new(None));
+    let schedules = schedule_maintenance(&pool, &writer, &id_cache, &missing_cache, &settings_arc, &consistency, &anomalies, may_write);
+
+    // Passive nodes read the same file, so only the active one keeps the standby copy
+    let replication = {
+        let leader = leader.clone();
+        replication::spawn(&settings_arc, db.clone(), move || !shadow && leader.load(Ordering::SeqCst))
+            .expect("Failed to start replication")
+    };
+
+    // Body size limits: json for POST /ids, form for form posts, bytes for protobuf
+    let limits = Limits::default()
+        .limit("json", settings_arc.json_limit_kb.kibibytes())
+        .limit("form", settings_arc.form_limit_kb.kibibytes())
+        .limit("bytes", settings_arc.bytes_limit_kb.kibibytes());
+
+    let compression = Compression { min_bytes: settings_arc.compression_min_bytes };
+    let local_socket_path = settings_arc.local_socket_path.clone();
+    let (admin_address, admin_port) = (settings_arc.admin_address.clone(), settings_arc.admin_port);
+
+    let state = AppState {
+        settings: Arc::new(RwLock::new(settings_arc)),
+        pool: pool.clone(),
+        suspension,
+        leader,
+        id_cache,
+        missing_cache,
+        canaries,
+        limiter,
+        relayed: local_socket::Relayed::default(),
+        admin,
+        consistency,
+        anomalies,
+        latency: Arc::new(Latency::new()),
+        pool_rejections: Arc::new(AtomicU64::new(0)),
+        breaker,
+        writer,
+        schedules,
+        replication,
+        db,
+        profile,
+        #[cfg(windows)]
+        pending_db_path: Arc::new(std::sync::Mutex::new(None)),
+    };
+    let latency_timer = LatencyTimer(state.latency.clone());
+
+    // A DBPath edited in the registry (e.g. by the config GUI) is followed without a restart
+    #[cfg(windows)]
+    if !container && state.db.path() != DEV_DB_PATH {
+        let watched = state.clone();
+        regwatch::spawn(watched.db.path(), move |path| follow_db_path(&watched, path));
+    }
+
+    let mut data_routes = routes![health, stats, table_stats, timeseries, metrics, list_tables, get_table, preview, list_changes, generate, generate_proto, generate_form, generate_query, generate_batch, confirm_batch, confirm_owner, delete_batch, start_generate_job, start_export_job, get_job, job_result, list_ids, list_owner_ids, count_ids, add_note, list_notes, list_refs, add_ref, update_ref, delete_ref, list_labels, create_label, delete_label, tag_ids, id_labels, tag_id, untag_id, lookup_ids, confirm, update_id, delete_id, get_id, verify_scan, validate_id, verify_token, id_qr_png, id_qr_svg, id_barcode_png];
+    let admin_routes = routes![suspend, resume, admin_page, admin_status, admin_schedules, admin_generation_configs, admin_backups, download_snapshot, restore_backup, erase_owner, merge_owners, set_owner_key, remove_owner_key, list_holds, place_hold, release_hold, list_canaries, add_canary, remove_canary, switch_db, update_settings, validate_settings, rotate_secret, find_duplicates, repair_ids, run_selftest, create_table, update_table, rename_table, delete_table];
+
+    // With an admin port the administrative routes live only on that listener; otherwise they share the main one
+    let admin = if admin_port > 0 {
+        let figment = base_figment(&limits, container)
+            .merge(("address", admin_address))
+            .merge(("port", admin_port));
+        let admin = rocket::custom(figment)
+            .manage(state.clone())
+            .mount("/", admin_routes)
+            .register("/", catchers())
+            .ignite()
+            .await?;
+        Some(admin)
+    } else {
+        data_routes.extend(admin_routes);
+        None
+    };
+
+    let mut figment = base_figment(&limits, container);
+    if container {
+        // Reachable from outside the container unless told otherwise; the port defaults to Rocket's
+        figment = figment.merge(("address", std::env::var("IDREG_ADDRESS").unwrap_or_else(|_| "0.0.0.0".to_string())));
+        if let Ok(port) = std::env::var("IDREG_PORT") {
+            figment = figment.merge(("port", port.parse::<u16>().expect("IDREG_PORT must be a port number")));
+        }
+    }
+    let rocket = rocket::custom(figment)
+        .attach(compression)
+        .attach(latency_timer)
+        .manage(state)
+        .mount("/", data_routes)
+        .register("/", catchers())
+        .ignite()
+        .await?;
+
+    // Local clients can use a Unix socket / named pipe; it is relayed into Rocket's TCP listener
+    if !local_socket_path.is_empty() {
+        let config = rocket.config();
+        let address = if config.address.is_unspecified() { IpAddr::from([127, 0, 0, 1]) } else { config.address };
+        let target = SocketAddr::new(address, config.port);
+        let relayed = rocket.state::<AppState>().map(|state| state.relayed.clone()).unwrap_or_default();
+
+        rocket::tokio::spawn(async move {
+            if let Err(e) = local_socket::serve(local_socket_path, target, relayed).await {
+                error!("Local socket listener stopped: {}", e);
+            }
+        });
+    }
+
+    // Rocket's own launch messages are switched off in a container
+    if container {
+        info!("Listening on {}:{}", rocket.config().address, rocket.config().port);
+    }
+
+    match admin {
+        Some(admin) => {
+            info!("Admin endpoints on {}:{}", admin.config().address, admin.config().port);
+            rocket::tokio::try_join!(rocket.launch(), admin.launch())?;
+        }
+        None => {
+            rocket.launch().await?;
+        }
+    }
+
+    // Hand over to a standby straight away instead of making it wait out the lease
+    if !shadow && let Ok(conn) = pool.get() {
+        let _ = lease::release(&conn, &node_id);
+    }
+
+    Ok(())
+}
+
+// Who an admin action is attributed to in the audit log
+fn admin_actor(client: Option<IpAddr>) -> String {
+    format!("admin@{}", client.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string()))
+}
+
+// Checks the admin secret passed as ?secret=
+fn check_secret(secret: Option<&str>, state: &AppState) -> Result<(), JsonError> {
+    if !secret.is_some_and(|secret| state.admin.verify(secret)) {
+        return Err(JsonError::new(Status::Unauthorized, ErrorCode::Unauthorized, "Missing or wrong admin secret"));
+    }
+    Ok(())
+}
+
+// POST /suspend?secret=yourpassword&scope=writes|all|owner:<name> – writes by default
+#[post("/suspend?<secret>&<scope>")]
+fn suspend(
+    secret: Option<String>,
+    scope: Option<String>,
+    state: &State<AppState>,
+) -> Result<String, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let scope = parse_scope(scope.as_deref())?.unwrap_or(Scope::Writes);
+    let message = match &scope {
+        Scope::Writes => "Server suspended (new writes rejected)".to_string(),
+        Scope::All => "Server suspended (new requests rejected)".to_string(),
+        Scope::Owner(owner) => format!("Owner {} suspended (writes to its IDs rejected)", owner),
+    };
+    state.suspension.suspend(scope);
+
+    // A DBPath change from the registry waits for this; see follow_db_path
+    #[cfg(windows)]
+    if state.suspension.writes_blocked() {
+        let pending = state.pending_db_path.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(path) = pending {
+            let state = state.inner().clone();
+            tokio::task::spawn_blocking(move || follow_db_path(&state, &path));
+        }
+    }
+    Ok(message)
+}
+
+// POST /resume?secret=yourpassword&scope=... – lifts one scope, or without one everything
+#[post("/resume?<secret>&<scope>")]
+fn resume(
+    secret: Option<String>,
+    scope: Option<String>,
+    state: &State<AppState>,
+) -> Result<String, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    state.suspension.resume(parse_scope(scope.as_deref())?);
+    Ok("Server resumed".to_string())
+}
+
+fn parse_scope(scope: Option<&str>) -> Result<Option<Scope>, JsonError> {
+    scope.map(|scope| {
+        let owner = scope.strip_prefix("owner:").map(clean_owner).transpose()?;
+        owner.map(Scope::Owner).or_else(|| Scope::parse(scope)).ok_or_else(|| {
+            JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid scope").details("expected writes, all or owner:<name>")
+        })
+    }).transpose()
+}
+
+// Counts of live, confirmed and deleted IDs
+fn id_stats(conn: &rusqlite::Connection) -> Result<IdStats, JsonError> {
+    conn.query_row(
+        "SELECT COUNT(*),
+                COALESCE(SUM(confirmed = 1 AND deleted = 0), 0),
+                COALESCE(SUM(confirmed = 0 AND deleted = 0), 0),
+                COALESCE(SUM(deleted = 1), 0)
+         FROM ids",
+        [],
+        |row| Ok(IdStats {
+            total: row.get(0)?,
+            confirmed: row.get(1)?,
+            unconfirmed: row.get(2)?,
+            deleted: row.get(3)?,
+        }),
+    ).map_err(db_error)
+}
+
+// POST /admin/rotate_secret?secret=yourpassword, optionally with {"new_secret": "..."}
+// The old secret keeps working for secret_grace_secs so clients can be switched over
+#[post("/admin/rotate_secret?<secret>", data = "<request>")]
+async fn rotate_secret(
+    secret: Option<String>,
+    request: Option<Json<RotateRequest>>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<RotateResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let supplied = request.map(|r| r.into_inner().new_secret);
+    if let Some(new_secret) = &supplied
+        && new_secret.chars().count() < 12
+    {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "New secret is too short")
+            .details("use at least 12 characters"));
+    }
+    let generated = supplied.is_none();
+    let new_secret = supplied.unwrap_or_else(adminauth::generate_secret);
+
+    let actor = admin_actor(client);
+    let grace_secs = state.settings().secret_grace_secs;
+
+    let stored_secret = new_secret.clone();
+    let previous_expires_at = state.run_write(OpKind::Admin, move |state, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let rotation = state.admin.prepare_rotation(&tx, &stored_secret, grace_secs).map_err(db_error)?;
+        let details = format!("previous secret valid for {} more seconds", grace_secs);
+        audit::record(&tx, &actor, "rotate_admin_secret", None, Some(&details)).map_err(db_error)?;
+
+        let expires_at = rotation.previous_expires_at;
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+            state.admin.apply(rotation);
+        }
+        Ok(expires_at)
+    }).await?;
+
+    let previous_valid_until = chrono::DateTime::from_timestamp(previous_expires_at, 0)
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+
+    Ok(DryRunMarked(Json(RotateResponse {
+        secret: generated.then_some(new_secret),
+        previous_valid_until,
+    }), dry_run))
+}
+
+// POST /admin/find_duplicates?secret=yourpassword – read-only scan of the live IDs
+#[post("/admin/find_duplicates?<secret>")]
+async fn find_duplicates(secret: Option<String>, state: &State<AppState>) -> Result<Json<DuplicatesResponse>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let report = state.run_db(OpKind::Admin, |state| {
+        let conn = state.conn()?;
+        repair::find_duplicates(&conn, &state.settings()).map_err(db_error)
+    }).await?;
+
+    Ok(Json(DuplicatesResponse {
+        report,
+        actions: &["flag", "reissue", "alias", "unconfirm_deleted"],
+    }))
+}
+
+// POST /admin/selftest?secret=yourpassword&ops=10000 – issues and looks up `ops` IDs for a
+// throwaway owner on a copy of the database, and reports throughput and latency
+// percentiles. Allowed on a passive node too, since the database it serves is only read:
+// that is a new host before cutover
+#[post("/admin/selftest?<secret>&<ops>")]
+async fn run_selftest(secret: Option<String>, ops: Option<usize>, state: &State<AppState>) -> Result<Json<SelftestReport>, JsonError> {
+    // Rounds per job, so each stays well within the admin timeout
+    const CHUNK: usize = 200;
+
+    check_secret(secret.as_deref(), state)?;
+    let ops = ops.unwrap_or(1_000).clamp(1, 100_000);
+
+    let db_path = state.db.path();
+    let scratch = state.run_db(OpKind::Admin, move |state| {
+        let conn = state.conn()?;
+        selftest::Scratch::create(&conn, &db_path).map_err(|e| {
+            JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Could not copy the database for the self-test")
+                .details(format!("{:#}", e))
+        })
+    }).await?;
+    let scratch = Arc::new(std::sync::Mutex::new(scratch));
+
+    let started = Instant::now();
+    let mut samples = Vec::with_capacity(ops);
+    while samples.len() < ops {
+        let rounds = CHUNK.min(ops - samples.len());
+        let scratch = scratch.clone();
+        let chunk = state.run_db(OpKind::Admin, move |state| {
+            let mut scratch = scratch.lock().unwrap_or_else(|e| e.into_inner());
+            scratch.run(&state.settings(), rounds).map_err(generation_error)
+        }).await?;
+        samples.extend(chunk);
+    }
+
+    let report = selftest::report(&samples, started.elapsed());
+    info!(
+        "Self-test: {} ops at {:.0}/s, generate p99 {:.2} ms, lookup p99 {:.2} ms",
+        report.ops, report.ops_per_sec, report.generate.p99_ms, report.lookup.p99_ms
+    );
+    Ok(Json(report))
+}
+
+// GET /admin/snapshot?secret=yourpassword – downloads a consistent copy of the database
+#[get("/admin/snapshot?<secret>")]
+async fn download_snapshot(
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    state: &State<AppState>,
+) -> Result<SnapshotDownload, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let token: String = rand::distributions::DistString::sample_string(&rand::distributions::Alphanumeric, &mut rand::thread_rng(), 12);
+    let path = std::env::temp_dir().join(format!("id-registry-snapshot-{}.db", token));
+
+    let target = path.clone();
+    let written = state.run_db(OpKind::Admin, move |state| {
+        let conn = state.conn()?;
+        conn.backup(rusqlite::DatabaseName::Main, &target, None).map_err(db_error)
+    }).await;
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&path);
+        return Err(e);
+    }
+    // From here on dropping the download deletes the file, even if the request goes away
+    let mut download = SnapshotDownload { file: None, path, name: format!("id-registry-{}.db", stamp) };
+    download.file = Some(tokio::fs::File::open(&download.path).await.map_err(|e| {
+        JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Could not read the snapshot").details(e)
+    })?);
+
+    // Whole-database downloads are worth a trace; a failure to record one doesn't stop it
+    let actor = admin_actor(client);
+    let recorded = state.run_write(OpKind::Admin, move |_, conn| {
+        audit::record(conn, &actor, "download_snapshot", None, None).map_err(db_error)
+    }).await;
+    if let Err(e) = recorded {
+        error!("Could not audit a snapshot download: {}", e.error.message);
+    }
+
+    Ok(download)
+}
+
+// POST /admin/restore?secret=yourpassword&backup=name.db – replaces the database with a backup from
+// backup_dir, or with the SQLite file in the request body when no backup is named. The server must be
+// suspended; X-Dry-Run only verifies the snapshot
+#[post("/admin/restore?<secret>&<backup>", data = "<upload>")]
+async fn restore_backup(
+    secret: Option<String>,
+    backup: Option<String>,
+    upload: Data<'_>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<RestoreResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    ensure_suspended_active(state)?;
+
+    if state.settings().backup_dir.trim().is_empty() {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::ConfigError, "Restoring needs 'backup_dir'"));
+    }
+    let dir = std::path::PathBuf::from(&state.settings().backup_dir);
+
+    let (path, uploaded) = match backup {
+        Some(name) => {
+            if name.contains(['/', '\\']) || name.starts_with('.') || !name.ends_with(".db") {
+                return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid backup name").details(name));
+            }
+            let path = dir.join(&name);
+            if !path.is_file() {
+                return Err(JsonError::new(Status::NotFound, ErrorCode::NotFound, "No such backup").details(name));
+            }
+            (path, false)
+        }
+        None => {
+            let path = dir.join(format!("upload-{}.db", chrono::Utc::now().format("%Y%m%dT%H%M%SZ")));
+            receive_snapshot(upload, &dir, &path, state.settings().restore_upload_max_mb).await?;
+            (path, true)
+        }
+    };
+
+    let result = restore_snapshot(&path, &dir, dry_run.0, state).await;
+    // An uploaded snapshot has served its purpose either way; a named backup stays
+    if uploaded {
+        let _ = std::fs::remove_file(&path);
+    }
+    let (check, previous) = result?;
+
+    Ok(DryRunMarked(Json(RestoreResponse {
+        snapshot: if uploaded { "upload".to_string() } else { path.display().to_string() },
+        check,
+        previous: previous.map(|p| p.display().to_string()),
+    }), dry_run))
+}
+
+// Writes the request body to `path`, refusing bodies over `max_mb` and empty ones
+async fn receive_snapshot(upload: Data<'_>, dir: &std::path::Path, path: &std::path::Path, max_mb: u64) -> Result<(), JsonError> {
+    let io_error = |e: std::io::Error| {
+        JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Could not store the upload").details(e)
+    };
+    std::fs::create_dir_all(dir).map_err(io_error)?;
+    let file = upload.open(max_mb.mebibytes()).into_file(path).await.map_err(io_error)?;
+    if !file.is_complete() {
+        let _ = std::fs::remove_file(path);
+        return Err(JsonError::new(Status::PayloadTooLarge, ErrorCode::PayloadTooLarge, "Snapshot too large")
+            .details(format!("restore_upload_max_mb is {}", max_mb)));
+    }
+    if file.n.written == 0 {
+        let _ = std::fs::remove_file(path);
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Name a backup or upload a snapshot"));
+    }
+    Ok(())
+}
+
+// Verifies the snapshot and, unless this is a dry run, swaps it in on the writer thread
+async fn restore_snapshot(
+    path: &std::path::Path,
+    dir: &std::path::Path,
+    dry_run: bool,
+    state: &AppState,
+) -> Result<(SnapshotCheck, Option<std::path::PathBuf>), JsonError> {
+    let snapshot = path.to_path_buf();
+    let check = tokio::task::spawn_blocking(move || restore::verify(&snapshot))
+        .await
+        .map_err(|_| JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Verification failed"))?
+        .map_err(restore_error)?;
+    if dry_run {
+        return Ok((check, None));
+    }
+
+    let (snapshot, dir) = (path.to_path_buf(), dir.to_path_buf());
+    // Forgotten in the same job, so the caches are cleared even if the request times out
+    let previous = state.run_write(OpKind::Admin, move |state, conn| {
+        let previous = restore::swap_in(conn, &snapshot, &dir).map_err(restore_error)?;
+        forget_database(state);
+        Ok(previous)
+    }).await?;
+
+    info!("Database restored from {} (previous contents kept as {})", path.display(), previous.display());
+    Ok((check, Some(previous)))
+}
+
+// Replacing the database (restore, switch) needs the server suspended, and the active node
+fn ensure_suspended_active(state: &AppState) -> Result<(), JsonError> {
+    if !state.suspension.writes_blocked() {
+        return Err(JsonError::new(Status::Conflict, ErrorCode::NotSuspended, "Suspend the server first")
+            .details("an owner suspension is not enough; suspend writes or all"));
+    }
+    if !state.leader.load(Ordering::SeqCst) {
+        return Err(JsonError::new(
+            Status::ServiceUnavailable,
+            ErrorCode::PassiveNode,
+            "This node is passive; do this on the active node",
+        ));
+    }
+    Ok(())
+}
+
+// Nothing cached about a replaced database still holds. Its settings and admin secrets
+// take over; those only read at startup (listener, pool, caches, background tasks) wait
+// for the next restart
+fn forget_database(state: &AppState) {
+    state.id_cache.clear();
+    state.missing_cache.clear();
+    *state.consistency.write().unwrap_or_else(|e| e.into_inner()) = None;
+    *state.anomalies.write().unwrap_or_else(|e| e.into_inner()) = None;
+    match state.conn().and_then(|conn| canaries::all(&conn).map_err(db_error)) {
+        Ok(ids) => *state.canaries.write().unwrap_or_else(|e| e.into_inner()) = ids,
+        Err(e) => error!("Could not reload the canary IDs: {}", e.error.message),
+    }
+    match state.conn().and_then(|mut conn| reload_settings(state, &mut conn).map_err(db_error)) {
+        Ok(()) => info!("Settings and admin secrets reloaded from the new database"),
+        Err(e) => error!("ALERT: keeping the previous settings: could not load the new database's: {}", e.error.details.unwrap_or(e.error.message)),
+    }
+}
+
+// The settings as a start on the database in `conn` would load them: in the container
+// profile with IDREG_* applied, in a transaction that is dropped so nothing is stored.
+// Their generation config is recorded as at startup, unless this node only shadows or is passive
+fn reload_settings(state: &AppState, conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    if state.profile == Profile::Container {
+        stage_env_settings(&tx)?;
+    }
+    let settings = load_settings(&tx)?;
+    drop(tx);
+    state.admin.reload(conn, &settings)?;
+    if !settings.shadow_mode && state.leader.load(Ordering::SeqCst) {
+        genconfig::record(conn, &settings)?;
+    }
+    *state.settings.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(settings);
+    Ok(())
+}
+
+// POST /admin/switch_db?secret=yourpassword with {"path": "..."} – moves the server onto another database
+// file while suspended and stores it as DBPath; X-Dry-Run only validates the file
+#[post("/admin/switch_db?<secret>", format = "json", data = "<request>")]
+async fn switch_db(
+    secret: Option<String>,
+    request: Valid<SwitchDbRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<SwitchDbResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    ensure_suspended_active(state)?;
+
+    let path = request.into_inner().path.trim().to_string();
+    let previous = state.db.path();
+    if path == previous {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Already using that database").details(path));
+    }
+
+    let candidate = path.clone();
+    let check = tokio::task::spawn_blocking(move || dbswitch::validate(&candidate))
+        .await
+        .map_err(|_| JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Verification failed"))?
+        .map_err(restore_error)?;
+
+    let mut response = SwitchDbResponse { previous, path, check, persisted: false };
+    if dry_run.0 {
+        return Ok(DryRunMarked(Json(response), dry_run));
+    }
+
+    let (target, actor) = (response.path.clone(), admin_actor(client));
+    state.run_write(OpKind::Admin, move |state, _| {
+        dbswitch::switch(&state.db, &target, &actor, true).map_err(db_error)?;
+        forget_database(state);
+        Ok(())
+    }).await?;
+
+    // In a container the switch lasts until restart; IDREG_DB_PATH decides the file
+    if state.profile == Profile::Container {
+        info!("Database switched from {} to {}", response.previous, response.path);
+        return Ok(DryRunMarked(Json(response), dry_run));
+    }
+    response.persisted = match set_db_path(&response.path) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("ALERT: switched to {} but could not store it as DBPath: {:#}", response.path, e);
+            false
+        }
+    };
+    info!("Database switched from {} to {}", response.previous, response.path);
+    Ok(DryRunMarked(Json(response), dry_run))
+}
+
+// Switches to a DBPath changed in the registry, if it is valid; otherwise keeps the current database.
+// Like POST /admin/switch_db it needs the active node suspended; until then the path is kept
+// and followed at the next suspend
+#[cfg(windows)]
+fn follow_db_path(state: &AppState, path: &str) {
+    let previous = state.db.path();
+    let mut pending = state.pending_db_path.lock().unwrap_or_else(|e| e.into_inner());
+    // POST /admin/switch_db stores the path it has already switched to
+    if path == previous {
+        *pending = None;
+        return;
+    }
+    info!("DBPath changed in the registry from {} to {}", previous, path);
+    if let Err(e) = ensure_suspended_active(state) {
+        info!("Staying on {} until the server is suspended: {}", previous, e.error.message);
+        *pending = Some(path.to_string());
+        return;
+    }
+    *pending = None;
+    drop(pending);
+    if let Err(e) = dbswitch::validate(path) {
+        error!("ALERT: staying on {}: the new DBPath is not usable: {:#}", previous, e);
+        return;
+    }
+
+    let (db, target) = (state.db.clone(), path.to_string());
+    let active = !state.settings().shadow_mode;
+    let switched = state.writer.submit(move |_| dbswitch::switch(&db, &target, "registry", active)).blocking_recv();
+    match switched {
+        Ok(Ok(())) => {
+            forget_database(state);
+            info!("Database switched from {} to {}", previous, path);
+        }
+        Ok(Err(e)) => error!("ALERT: staying on {}: switching to {} failed: {:#}", previous, path, e),
+        Err(_) => error!("ALERT: staying on {}: the writer thread is gone", previous),
+    }
+}
+
+fn restore_error(e: anyhow::Error) -> JsonError {
+    match e.downcast_ref::<InvalidSnapshot>() {
+        Some(invalid) => {
+            JsonError::new(Status::UnprocessableEntity, ErrorCode::InvalidSnapshot, "Snapshot failed verification").details(invalid)
+        }
+        None => db_error(e),
+    }
+}
+
+// POST /admin/repair_ids?secret=yourpassword with {"action": "flag|reissue|alias|unconfirm_deleted", "ids": [...], ...}
+// All IDs are handled in one transaction: one failure leaves everything unchanged
+#[post("/admin/repair_ids?<secret>", format = "json", data = "<request>")]
+async fn repair_ids(
+    secret: Option<String>,
+    request: Valid<RepairRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<Vec<RepairResult>>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let request = request.into_inner();
+    let target = match (request.action, request.target.clone()) {
+        (RepairAction::Alias, None) => {
+            return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Alias needs a target ID"));
+        }
+        (RepairAction::Alias, Some(target)) if request.ids.contains(&target) => {
+            return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Cannot alias an ID to itself")
+                .details(target));
+        }
+        (_, target) => target,
+    };
+    let actor = admin_actor(client);
+
+    let results = state.run_write(OpKind::Admin, move |state, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let mut results = Vec::new();
+        for id in &request.ids {
+            let (action, new_id) = match request.action {
+                RepairAction::Flag => {
+                    let reason = request.reason.as_deref().unwrap_or("needs_review");
+                    repair::flag(&tx, id, reason).map_err(update_error)?;
+                    ("flag_id", None)
+                }
+                RepairAction::Reissue => {
+                    let new_id = repair::reissue(&tx, &state.settings(), id).map_err(update_error)?;
+                    ("reissue_id", Some(new_id))
+                }
+                RepairAction::Alias => {
+                    let target = target.clone().unwrap_or_default();
+                    repair::alias(&tx, id, &target).map_err(update_error)?;
+                    ("alias_id", Some(target))
+                }
+                RepairAction::UnconfirmDeleted => {
+                    repair::unconfirm_deleted(&tx, id).map_err(update_error)?;
+                    ("unconfirm_deleted_id", None)
+                }
+            };
+            audit::record(&tx, &actor, action, Some(id), new_id.as_deref()).map_err(db_error)?;
+            results.push(RepairResult { id: id.clone(), new_id });
+        }
+
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+            for result in &results {
+                state.id_cache.invalidate(&result.id);
+                state.forget_missing(&result.id);
+                if let Some(new_id) = &result.new_id {
+                    state.forget_missing(new_id);
+                }
+            }
+        }
+        Ok(results)
+    }).await?;
+
+    Ok(DryRunMarked(Json(results), dry_run))
+}
+
+// POST /admin/erase_owner?secret=yourpassword with {"owner": "...", "mode": "anonymize|delete"}
+// For data-deletion requests; send X-Dry-Run: true first to see what would go
+#[post("/admin/erase_owner?<secret>", format = "json", data = "<request>")]
+async fn erase_owner(
+    secret: Option<String>,
+    request: Valid<EraseRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<ErasureSummary>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let owner = clean_owner(&request.owner)?;
+    let mode = request.mode;
+    let actor = admin_actor(client);
+
+    let summary = state.run_write(OpKind::Admin, move |state, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let summary = erasure::erase_owner(&tx, &owner, mode).map_err(hold_error)?;
+        // The entry itself must not name the owner
+        let details = format!("{}: {} ID(s)", mode.as_str(), summary.ids);
+        audit::record(&tx, &actor, "erase_owner", None, Some(&details)).map_err(db_error)?;
+
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+            state.id_cache.clear();
+        }
+        Ok(summary)
+    }).await?;
+
+    if !dry_run.0 {
+        info!("Erased an owner's data ({}, {} ID(s))", summary.mode.as_str(), summary.ids);
+    }
+    Ok(DryRunMarked(Json(summary), dry_run))
+}
+
+// POST /admin/owners/merge?secret=yourpassword with {"from": "old_team", "to": "new_team"}
+// Moves all of an owner's IDs (and a legal hold on it) to another owner in one transaction
+#[post("/admin/owners/merge?<secret>", format = "json", data = "<request>")]
+async fn merge_owners(
+    secret: Option<String>,
+    request: Valid<MergeRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<MergeSummary>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let from = clean_owner(&request.from)?;
+    let to = clean_owner(&request.to)?;
+    if from == to {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Cannot merge an owner into itself").details(&from));
+    }
+    let actor = admin_actor(client);
+
+    let summary = state.run_write(OpKind::Admin, move |state, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let summary = owners::merge(&tx, &from, &to).map_err(|e| match e.downcast_ref::<ExternalRefClash>() {
+            Some(clash) => JsonError::new(Status::Conflict, ErrorCode::IdConflict, "Owners share an external_ref").details(clash.to_string()),
+            None => db_error(e),
+        })?;
+        let details = format!("from {}: {} ID(s)", from, summary.ids);
+        audit::record(&tx, &actor, "merge_owners", Some(&to), Some(&details)).map_err(db_error)?;
+
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+            state.id_cache.clear();
+        }
+        Ok(summary)
+    }).await?;
+
+    if !dry_run.0 {
+        info!("Merged owner {} into {} ({} ID(s))", summary.from, summary.to, summary.ids);
+    }
+    Ok(DryRunMarked(Json(summary), dry_run))
+}
+
+#[derive(serde::Serialize)]
+struct OwnerKeyResponse {
+    owner: String,
+    /// Shown once; only its hash is stored
+    key: String,
+}
+
+// POST /admin/owners/<owner>/key?secret=yourpassword – gives an owner a new key, replacing
+// any earlier one. From then on confirming its IDs needs the key in X-Owner-Key
+#[post("/admin/owners/<owner>/key?<secret>")]
+async fn set_owner_key(
+    owner: &str,
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<OwnerKeyResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let owner = clean_owner(owner)?;
+    let actor = admin_actor(client);
+
+    let key = state.run_write(OpKind::Admin, {
+        let owner = owner.clone();
+        move |state, conn| {
+            write_tx(state, conn, dry_run, |tx| {
+                let key = owners::set_key(tx, &owner).map_err(db_error)?;
+                audit::record(tx, &actor, "set_owner_key", Some(&owner), None).map_err(db_error)?;
+                Ok(key)
+            })
+        }
+    }).await?;
+
+    if !dry_run.0 {
+        info!("Owner {} got a new confirm key", owner);
+    }
+    Ok(DryRunMarked(Json(OwnerKeyResponse { owner, key }), dry_run))
+}
+
+// DELETE /admin/owners/<owner>/key?secret=yourpassword – removes an owner's key
+#[delete("/admin/owners/<owner>/key?<secret>")]
+async fn remove_owner_key(
+    owner: &str,
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<DeleteResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let owner = clean_owner(owner)?;
+    let actor = admin_actor(client);
+
+    let response = state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            if !owners::remove_key(tx, &owner).map_err(db_error)? {
+                return Err(JsonError::new(Status::NotFound, ErrorCode::NotFound, "Owner has no key").details(&owner));
+            }
+            audit::record(tx, &actor, "remove_owner_key", Some(&owner), None).map_err(db_error)?;
+            Ok(DeleteResponse { success: true, message: format!("Key of owner {} removed", owner) })
+        })
+    }).await?;
+    Ok(DryRunMarked(Json(response), dry_run))
+}
+
+// Failures from deleting or erasing something under a legal hold
+fn hold_error(e: anyhow::Error) -> JsonError {
+    match e.downcast_ref::<OnHold>() {
+        Some(held) => JsonError::new(Status::Conflict, ErrorCode::OnHold, "Under a legal hold").details(held.to_string()),
+        None => db_error(e),
+    }
+}
+
+// GET /admin/holds?secret=yourpassword – the legal holds in force
+#[get("/admin/holds?<secret>")]
+async fn list_holds(secret: Option<String>, state: &State<AppState>) -> Result<Json<Vec<Hold>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    state.run_db(OpKind::Admin, |state| {
+        let conn = state.conn()?;
+        holds::list(&conn).map_err(db_error)
+    }).await.map(Json)
+}
+
+// POST /admin/holds?secret=yourpassword with {"kind": "id|owner", "target": "...", "reason": "..."}
+// Deleting, purging and erasing what the hold covers is refused until it is released
+#[post("/admin/holds?<secret>", format = "json", data = "<request>")]
+async fn place_hold(
+    secret: Option<String>,
+    request: Valid<HoldRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<(Status, Json<Hold>)>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let HoldRequest { kind, target, reason } = request.into_inner();
+    let target = match kind {
+        HoldKind::Id => target.trim().to_string(),
+        HoldKind::Owner => clean_owner(&target)?,
+    };
+    let actor = admin_actor(client);
+    state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            if kind == HoldKind::Id && deleted_flag(tx, &target)?.is_none() {
+                return Err(id_not_found(&target));
+            }
+            if !holds::place(tx, kind, &target, reason.as_deref()).map_err(db_error)? {
+                return Err(JsonError::new(Status::Conflict, ErrorCode::OnHold, "Already under a legal hold").details(&target));
+            }
+            let details = format!("{}{}", kind.as_str(), reason.as_deref().map(|r| format!(": {}", r)).unwrap_or_default());
+            audit::record(tx, &actor, "place_hold", Some(&target), Some(&details)).map_err(db_error)?;
+            holds::get(tx, kind, &target).map_err(db_error)?.ok_or_else(|| hold_not_found(&target))
+        })
+    }).await.map(|hold| DryRunMarked((Status::Created, Json(hold)), dry_run))
+}
+
+// DELETE /admin/holds/<kind>/<target>?secret=yourpassword – releases a hold
+#[delete("/admin/holds/<kind>/<target>?<secret>")]
+async fn release_hold(
+    kind: &str,
+    target: String,
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<DeleteResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let kind = HoldKind::parse(kind).ok_or_else(|| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Unknown hold kind").details("expected id or owner")
+    })?;
+    let actor = admin_actor(client);
+    state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            if !holds::release(tx, kind, &target).map_err(db_error)? {
+                return Err(hold_not_found(&target));
+            }
+            audit::record(tx, &actor, "release_hold", Some(&target), Some(kind.as_str())).map_err(db_error)?;
+            Ok(DeleteResponse { success: true, message: format!("Hold on {} {} released", kind.as_str(), target) })
+        })
+    }).await.map(|response| DryRunMarked(Json(response), dry_run))
+}
+
+fn hold_not_found(target: &str) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Hold not found").details(target)
+}
+
+// GET /admin/canaries?secret=yourpassword – the registered canary IDs
+#[get("/admin/canaries?<secret>")]
+async fn list_canaries(secret: Option<String>, state: &State<AppState>) -> Result<Json<Vec<Canary>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    state.run_db(OpKind::Admin, |state| {
+        let conn = state.conn()?;
+        canaries::list(&conn).map_err(db_error)
+    }).await.map(Json)
+}
+
+// POST /admin/canaries?secret=yourpassword with {"id": "...", "note": "..."}
+// The ID is never issued, and looking it up raises an alert and an audit entry
+#[post("/admin/canaries?<secret>", format = "json", data = "<request>")]
+async fn add_canary(
+    secret: Option<String>,
+    request: Valid<CanaryRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<(Status, Json<Canary>)>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let CanaryRequest { id, note } = request.into_inner();
+    let id = id.trim().to_string();
+    if id.is_empty() {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Missing canary ID"));
+    }
+    let actor = admin_actor(client);
+    state.run_write(OpKind::Admin, move |state, conn| {
+        let canary = write_tx(state, conn, dry_run, |tx| {
+            let added = canaries::add(tx, &id, note.as_deref()).map_err(|e| match e.downcast_ref::<IssuedId>() {
+                Some(issued) => JsonError::new(Status::Conflict, ErrorCode::IdConflict, "A canary cannot be an issued ID")
+                    .details(issued.to_string()),
+                None => db_error(e),
+            })?;
+            if !added {
+                return Err(JsonError::new(Status::Conflict, ErrorCode::IdConflict, "Already a canary").details(&id));
+            }
+            audit::record(tx, &actor, "add_canary", Some(&id), note.as_deref()).map_err(db_error)?;
+            canaries::list(tx).map_err(db_error)?.into_iter().find(|c| c.id == id).ok_or_else(|| id_not_found(&id))
+        })?;
+        reload_canaries(state, conn)?;
+        Ok(canary)
+    }).await.map(|canary| DryRunMarked((Status::Created, Json(canary)), dry_run))
+}
+
+// DELETE /admin/canaries/<id>?secret=yourpassword – unregisters a canary; it may then be issued
+#[delete("/admin/canaries/<id>?<secret>")]
+async fn remove_canary(
+    id: String,
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<DeleteResponse>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let actor = admin_actor(client);
+    state.run_write(OpKind::Admin, move |state, conn| {
+        let response = write_tx(state, conn, dry_run, |tx| {
+            if !canaries::remove(tx, &id).map_err(db_error)? {
+                return Err(JsonError::new(Status::NotFound, ErrorCode::NotFound, "Canary not found").details(&id));
+            }
+            audit::record(tx, &actor, "remove_canary", Some(&id), None).map_err(db_error)?;
+            Ok(DeleteResponse { success: true, message: format!("Canary {} removed", id) })
+        })?;
+        reload_canaries(state, conn)?;
+        Ok(response)
+    }).await.map(|response| DryRunMarked(Json(response), dry_run))
+}
+
+fn reload_canaries(state: &AppState, conn: &Connection) -> Result<(), JsonError> {
+    let ids = canaries::all(conn).map_err(db_error)?;
+    *state.canaries.write().unwrap_or_else(|e| e.into_inner()) = ids;
+    Ok(())
+}
+
+// GET /admin – dashboard page; it asks for the secret and calls the routes below
+#[get("/admin")]
+fn admin_page() -> (ContentType, &'static str) {
+    (ContentType::HTML, include_str!("../static/admin.html"))
+}
+
+// GET /admin/schedules?secret=yourpassword – the maintenance tasks with their last and next runs
+#[get("/admin/schedules?<secret>")]
+async fn admin_schedules(secret: Option<String>, state: &State<AppState>) -> Result<Json<Vec<ScheduleStatus>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    Ok(Json(state.schedules.report()))
+}
+
+// GET /admin/generation_configs?secret=yourpassword – every id_length / charset used, with its ID count
+#[get("/admin/generation_configs?<secret>")]
+async fn admin_generation_configs(secret: Option<String>, state: &State<AppState>) -> Result<Json<Vec<genconfig::ConfigUsage>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+    let usage = state.run_db(OpKind::Admin, |state| {
+        let conn = state.conn()?;
+        genconfig::usage(&conn).map_err(db_error)
+    }).await?;
+    Ok(Json(usage))
+}
+
+// GET /admin/backups?secret=yourpassword – local and uploaded backups, with the last backup run
+#[get("/admin/backups?<secret>")]
+async fn admin_backups(secret: Option<String>, state: &State<AppState>) -> Result<Json<BackupsResponse>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let settings = state.settings().clone();
+    let (local, uploaded) = tokio::task::spawn_blocking(move || (maintenance::local_backups(&settings), list_uploaded(&settings)))
+        .await
+        .map_err(|_| JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Backup listing failed"))?;
+    let local = local.map_err(|e| {
+        JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Could not list backups").details(e)
+    })?;
+    // An unreachable store is reported rather than hiding the local backups
+    let (uploaded, upload_error) = match uploaded {
+        Some(Ok(keys)) => (Some(keys), None),
+        Some(Err(e)) => (None, Some(format!("{:#}", e))),
+        None => (None, None),
+    };
+
+    Ok(Json(BackupsResponse {
+        schedule: state.schedules.report().into_iter().find(|s| s.name == "backup"),
+        local,
+        upload: state.settings().backup_upload.clone(),
+        uploaded,
+        upload_error,
+    }))
+}
+
+#[cfg(feature = "object-storage")]
+fn list_uploaded(settings: &Settings) -> Option<Result<Vec<String>>> {
+    if settings.backup_upload.is_empty() {
+        return None;
+    }
+    Some(id_registry_server::offsite::list(settings).map(|mut keys| {
+        keys.sort_by(|a, b| b.cmp(a));
+        keys
+    }))
+}
+
+#[cfg(not(feature = "object-storage"))]
+fn list_uploaded(_settings: &Settings) -> Option<Result<Vec<String>>> {
+    None
+}
+
+// GET /admin/status?secret=yourpassword
+#[get("/admin/status?<secret>")]
+async fn admin_status(secret: Option<String>, state: &State<AppState>) -> Result<Json<AdminStatus>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let (stats, recent) = state.run_db(OpKind::Admin, |state| {
+        let conn = state.conn()?;
+
+        let stats = id_stats(&conn)?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM ids WHERE deleted = 0 ORDER BY created_at DESC, rowid DESC LIMIT 20",
+            DETAIL_COLUMNS
+        )).map_err(db_error)?;
+        let recent = stmt.query_map([], details_from_row).and_then(|rows| rows.collect::<Result<Vec<_>, _>>()).map_err(db_error)?;
+
+        Ok((stats, recent))
+    }).await?;
+
+    Ok(Json(AdminStatus {
+        status: if state.suspension.writes_blocked() { "Suspended".to_string() } else { "ok".to_string() },
+        suspension: state.suspension.status(),
+        role: if state.leader.load(Ordering::SeqCst) { "active".to_string() } else { "passive".to_string() },
+        stats,
+        recent,
+        settings: state.settings().as_ref().clone(),
+    }))
+}
+
+// PUT /admin/settings?secret=yourpassword&force=true with {"key": "value", ...}
+// Values are checked by loading the full settings inside the transaction; they take effect on restart.
+// Shrinking the keyspace or changing the charset needs force=true and records a new generation config
+#[put("/admin/settings?<secret>&<force>", format = "json", data = "<changes>")]
+async fn update_settings(
+    secret: Option<String>,
+    force: Option<bool>,
+    changes: Valid<HashMap<String, String>>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<String>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    // Stored hashed, like a rotated secret
+    let changes: Vec<(String, String)> = changes
+        .into_inner()
+        .into_iter()
+        .map(|(key, value)| match key.as_str() {
+            "admin_secret" => (key, adminauth::hash_secret(&value)),
+            _ => (key, value),
+        })
+        .collect();
+    let count = changes.len();
+    let version = state.run_write(OpKind::Admin, move |_, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let current = load_settings(&tx).map_err(db_error)?;
+        let proposed = stage_settings(&tx, &changes)?;
+        check_keyspace(&proposed).map_err(|e| {
+            JsonError::new(Status::BadRequest, ErrorCode::ConfigError, "Invalid setting value").details(format!("{:#}", e))
+        })?;
+        genconfig::guard(&current, &proposed, force.unwrap_or(false)).map_err(force_error)?;
+        if proposed.case_insensitive_ids && !current.case_insensitive_ids {
+            let clashing = casefold::duplicates(&tx).map_err(db_error)?;
+            if clashing > 0 {
+                return Err(JsonError::new(Status::Conflict, ErrorCode::IdConflict, "IDs differ only by letter case")
+                    .details(format!("{} IDs clash; repair them (POST /admin/find_duplicates) first", clashing)));
+            }
+        }
+
+        if dry_run.0 {
+            return Ok(None);
+        }
+        let changed = genconfig::differs(&current, &proposed);
+        let version = genconfig::record(&tx, &proposed).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(changed.then_some(version))
+    }).await?;
+
+    let message = match version {
+        Some(version) => format!("{} setting(s) saved as generation config version {}; restart the server to apply them", count, version),
+        None => format!("{} setting(s) saved; restart the server to apply them", count),
+    };
+    Ok(DryRunMarked(message, dry_run))
+}
+
+fn force_error(e: anyhow::Error) -> JsonError {
+    match e.downcast_ref::<ForceRequired>() {
+        Some(reason) => JsonError::new(Status::Conflict, ErrorCode::ForceRequired, "Change needs force=true").details(&reason.0),
+        None => db_error(e),
+    }
+}
+
+// POST /admin/settings/validate?secret=yourpassword with the same body as PUT /admin/settings
+// Reports what the change would mean for the ID space; nothing is saved
+#[post("/admin/settings/validate?<secret>", format = "json", data = "<changes>")]
+async fn validate_settings(
+    secret: Option<String>,
+    changes: Valid<HashMap<String, String>>,
+    state: &State<AppState>,
+) -> Result<Json<SettingsReport>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let changes: Vec<(String, String)> = changes.into_inner().into_iter().collect();
+    let report = state.run_db(OpKind::Admin, move |state| {
+        let mut conn = state.conn()?;
+        // Rolled back when dropped: the proposal is only loaded, never committed
+        let tx = conn.transaction().map_err(db_error)?;
+
+        let current = load_settings(&tx).map_err(db_error)?;
+        let proposed = stage_settings(&tx, &changes)?;
+
+        let mut stmt = tx.prepare("SELECT id FROM ids WHERE deleted = 0").map_err(db_error)?;
+        let mut rows = stmt.query([]).map_err(db_error)?;
+        let (mut existing_ids, mut invalid_ids, mut invalid_examples) = (0u64, 0u64, Vec::new());
+        while let Some(row) = rows.next().map_err(db_error)? {
+            let id: String = row.get(0).map_err(db_error)?;
+            existing_ids += 1;
+            if !proposed.id_matches_format(&id) {
+                invalid_ids += 1;
+                if invalid_examples.len() < 10 {
+                    invalid_examples.push(id);
+                }
+            }
+        }
+
+        Ok(SettingsReport::new(&current, &proposed, existing_ids, invalid_ids, invalid_examples))
+    }).await?;
+
+    Ok(Json(report))
+}
+
+// Stages the changes inside `tx`; see id_registry_server::stage_settings
+fn stage_settings(tx: &rusqlite::Transaction, changes: &[(String, String)]) -> Result<Settings, JsonError> {
+    id_registry_server::stage_settings(tx, changes).map_err(|e| match e.downcast_ref::<UnknownSetting>() {
+        Some(unknown) => JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Unknown setting").details(&unknown.0),
+        None => JsonError::new(Status::BadRequest, ErrorCode::ConfigError, "Invalid setting value").details(format!("{:#}", e)),
+    })
+}
+
+impl SettingsReport {
+    fn new(current: &Settings, proposed: &Settings, existing_ids: u64, invalid_ids: u64, invalid_examples: Vec<String>) -> Self {
+        let current_bits = current.keyspace_bits();
+        let proposed_bits = proposed.keyspace_bits();
+        // Chance that one fresh candidate hits an existing ID, with the registry at its current size
+        let collision_probability = (existing_ids as f64) / proposed_bits.exp2();
+
+        let mut warnings = Vec::new();
+        if proposed_bits < current_bits {
+            warnings.push(format!("Keyspace shrinks by {:.1} bits", current_bits - proposed_bits));
+        }
+        if let Some(shortfall) = proposed.keyspace_shortfall() {
+            warnings.push(shortfall);
+        }
+        if proposed.max_id_bytes() > current.max_id_bytes() {
+            warnings.push(format!(
+                "IDs can take up to {} bytes as UTF-8, up from {}",
+                proposed.max_id_bytes(),
+                current.max_id_bytes()
+            ));
+        }
+        if invalid_ids > 0 {
+            warnings.push(format!("{} existing ID(s) would not match the new format", invalid_ids));
+        }
+        if collision_probability > 0.01 {
+            warnings.push("More than 1% of new candidates would collide; generation will slow down".to_string());
+        }
+        if proposed.id_length != current.id_length || proposed.charset != current.charset {
+            warnings.push("Pre-generated pool IDs are discarded at the next start".to_string());
+        }
+
+        SettingsReport {
+            current_keyspace_bits: current_bits,
+            proposed_keyspace_bits: proposed_bits,
+            keyspace_delta_bits: proposed_bits - current_bits,
+            current_max_id_bytes: current.max_id_bytes(),
+            proposed_max_id_bytes: proposed.max_id_bytes(),
+            existing_ids,
+            invalid_ids,
+            invalid_examples,
+            collision_probability,
+            force_required: genconfig::force_reason(current, proposed),
+            warnings,
+        }
+    }
+}
+
+#[get("/health")]
+fn health(state: &State<AppState>,) -> Result<Json<HealthResponse>, JsonError> {
+    let db_path = state.db.path();
+
+    Ok(Json(HealthResponse {
+        status: if state.suspension.writes_blocked() { "Suspended".to_string() } else { "ok".to_string() },
+        suspension: state.suspension.status(),
+        role: if state.leader.load(Ordering::SeqCst) { "active".to_string() } else { "passive".to_string() },
+        db_path,
+        replication: state.replication.as_ref().map(Replication::status),
+        settings: state.settings().as_ref().clone(),
+    }))
+}
+
+#[get("/preview")]
+async fn preview(_limit: RateLimited, state: &State<AppState>,) -> Result<Json<PreviewResponse>, JsonError> {
+    state.run_db(OpKind::Read, |state| {
+        let conn = state.conn()?;
+
+        let id = generate_id(&conn, state.settings().as_ref(), None).map_err(generation_error)?;
+        Ok(Json(PreviewResponse { preview_id: id }))
+    }).await
+}
+
+// GET /stats – ID counts plus the latest consistency check
+#[get("/stats")]
+async fn stats(state: &State<AppState>) -> Result<Json<StatsResponse>, JsonError> {
+    let (ids, generation) = state.run_db(OpKind::Read, |state| {
+        let conn = state.conn()?;
+        Ok((id_stats(&conn)?, genstats::recent(&conn, 30).map_err(db_error)?))
+    }).await?;
+    let consistency = state.consistency.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let anomalies = state.anomalies.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+    Ok(Json(StatsResponse {
+        ids,
+        generation,
+        consistency,
+        anomalies,
+        latency: state.latency.report(),
+        pool_rejections: state.pool_rejections.load(Ordering::Relaxed),
+        circuit: state.breaker.state_name(),
+    }))
+}
+
+// GET /stats/tables – the /stats counts per table_name, with recent growth
+#[get("/stats/tables")]
+async fn table_stats(_limit: RateLimited, state: &State<AppState>) -> Result<Json<Vec<TableStats>>, JsonError> {
+    state.run_db(OpKind::Read, |state| {
+        let conn = state.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT table_name, COUNT(*),
+                    COALESCE(SUM(confirmed = 1 AND deleted = 0), 0),
+                    COALESCE(SUM(confirmed = 0 AND deleted = 0), 0),
+                    COALESCE(SUM(deleted = 1), 0),
+                    COALESCE(SUM(created_at > datetime('now', '-1 day')), 0),
+                    COALESCE(SUM(created_at > datetime('now', '-7 days')), 0),
+                    MAX(created_at)
+             FROM ids GROUP BY table_name ORDER BY table_name",
+        ).map_err(db_error)?;
+        stmt.query_map([], |row| {
+            let ids = IdStats { total: row.get(1)?, confirmed: row.get(2)?, unconfirmed: row.get(3)?, deleted: row.get(4)? };
+            let live = ids.confirmed + ids.unconfirmed;
+            Ok(TableStats {
+                table: row.get(0)?,
+                confirmation_ratio: (live > 0).then(|| ids.confirmed as f64 / live as f64),
+                ids,
+                issued_last_24h: row.get(5)?,
+                issued_last_7d: row.get(6)?,
+                last_issued_at: row.get(7)?,
+            })
+        }).and_then(|rows| rows.collect::<Result<Vec<_>, _>>()).map_err(db_error)
+    }).await.map(Json)
+}
+
+// GET /stats/timeseries?owner=&granularity=hour|day&days= – IDs issued per hour or day,
+// from counters kept on generation rather than a scan of ids
+#[get("/stats/timeseries?<owner>&<granularity>&<days>")]
+async fn timeseries(
+    owner: Option<&str>,
+    granularity: Option<&str>,
+    days: Option<u32>,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Json<Timeseries>, JsonError> {
+    let owner = owner.map(clean_owner).transpose()?;
+    let granularity = match granularity {
+        None => Granularity::Day,
+        Some(value) => Granularity::parse(value).ok_or_else(|| {
+            JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid granularity").details("granularity must be one of: hour, day")
+        })?,
+    };
+    let days = days.unwrap_or(30).clamp(1, 366);
+
+    let filter = owner.clone();
+    let points = state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        genstats::volume(&conn, filter.as_deref(), granularity, days).map_err(db_error)
+    }).await?;
+    Ok(Json(Timeseries { owner, granularity, days, points }))
+}
+
+// GET /metrics – latency histograms and counters in Prometheus text format
+#[get("/metrics")]
+fn metrics(state: &State<AppState>) -> (ContentType, String) {
+    let mut body = state.latency.prometheus();
+    body.push_str("# HELP id_registry_pool_rejections_total Requests answered 503 because no database connection was free\n");
+    body.push_str("# TYPE id_registry_pool_rejections_total counter\n");
+    body.push_str(&format!("id_registry_pool_rejections_total {}\n", state.pool_rejections.load(Ordering::Relaxed)));
+
+    (ContentType::new("text", "plain").with_params(("version", "0.0.4")), body)
+}
+
+// GET /tables – the table_name catalog
+#[get("/tables")]
+async fn list_tables(state: &State<AppState>) -> Result<Json<Vec<TableEntry>>, JsonError> {
+    state.run_db(OpKind::Read, |state| tables::list(&*state.conn()?).map_err(db_error))
+        .await
+        .map(Json)
+}
+
+#[get("/tables/<name>")]
+async fn get_table(name: String, state: &State<AppState>) -> Result<Json<TableEntry>, JsonError> {
+    let lookup = name.clone();
+    state.run_db(OpKind::Read, move |state| tables::get(&*state.conn()?, &lookup).map_err(db_error))
+        .await?
+        .map(Json)
+        .ok_or_else(|| table_not_found(&name))
+}
+
+// POST /tables?secret=yourpassword with {"name": ..., "description": ..., "active": true}
+#[post("/tables?<secret>", format = "json", data = "<entry>")]
+async fn create_table(
+    secret: Option<String>,
+    entry: Valid<TableEntry>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<(Status, Json<TableEntry>)>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let entry = entry.into_inner();
+    check_table_name(&entry.name)?;
+
+    let actor = admin_actor(client);
+    let created = entry.clone();
+    state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            tables::create(tx, &created).map_err(|e| {
+                if e.downcast_ref::<rusqlite::Error>().is_some_and(is_unique_violation) {
+                    JsonError::new(Status::Conflict, ErrorCode::TableExists, "Table is already registered").details(&created.name)
+                } else {
+                    db_error(e)
+                }
+            })?;
+            audit::record(tx, &actor, "create_table", Some(&created.name), created.description.as_deref()).map_err(db_error)
+        })
+    }).await?;
+
+    Ok(DryRunMarked((Status::Created, Json(entry)), dry_run))
+}
+
+fn check_table_name(name: &str) -> Result<(), JsonError> {
+    validation::table(name).map_err(|reason| JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid table name").details(reason))
+}
+
+// POST /admin/tables/rename?secret=yourpassword with {"from": "orders", "to": "sales_orders"}
+// Follows a downstream schema rename: every ID's table_name and the catalog entry, in one transaction
+#[post("/admin/tables/rename?<secret>", format = "json", data = "<request>")]
+async fn rename_table(
+    secret: Option<String>,
+    request: Valid<RenameRequest>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<RenameSummary>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let RenameRequest { from, to } = request.into_inner();
+    check_table_name(&to)?;
+    if from == to {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Table names are the same").details(&from));
+    }
+    let actor = admin_actor(client);
+
+    let summary = state.run_write(OpKind::Admin, move |state, conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+
+        let summary = tables::rename(&tx, &from, &to).map_err(|e| {
+            if e.downcast_ref::<rusqlite::Error>().is_some_and(is_unique_violation) {
+                JsonError::new(Status::Conflict, ErrorCode::TableExists, "Both names are registered").details(&to)
+            } else {
+                db_error(e)
+            }
+        })?;
+        let details = format!("from {}: {} ID(s)", from, summary.ids);
+        audit::record(&tx, &actor, "rename_table", Some(&to), Some(&details)).map_err(db_error)?;
+
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+            state.id_cache.clear();
+        }
+        Ok(summary)
+    }).await?;
+
+    if !dry_run.0 {
+        info!("Renamed table {} to {} ({} ID(s))", summary.from, summary.to, summary.ids);
+    }
+    Ok(DryRunMarked(Json(summary), dry_run))
+}
+
+// PUT /tables/<name>?secret=yourpassword with {"description": ..., "active": false, "deleted_retention_days": 90}
+#[put("/tables/<name>?<secret>", format = "json", data = "<update>")]
+async fn update_table(
+    name: String,
+    secret: Option<String>,
+    update: Valid<TableUpdate>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Json<TableEntry>>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let actor = admin_actor(client);
+    let update = update.into_inner();
+    state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let updated = tables::update(tx, &name, update.description.as_deref(), update.active, update.deleted_retention_days)
+                .map_err(db_error)?;
+            if !updated {
+                return Err(table_not_found(&name));
+            }
+            let mut details = Vec::new();
+            if let Some(active) = update.active {
+                details.push(format!("active={}", active));
+            }
+            if let Some(days) = update.deleted_retention_days {
+                details.push(format!("deleted_retention_days={}", days.map_or("default".to_string(), |d| d.to_string())));
+            }
+            let details = (!details.is_empty()).then(|| details.join(", "));
+            audit::record(tx, &actor, "update_table", Some(&name), details.as_deref()).map_err(db_error)?;
+            tables::get(tx, &name).map_err(db_error)?.ok_or_else(|| table_not_found(&name))
+        })
+    }).await.map(|entry| DryRunMarked(Json(entry), dry_run))
+}
+
+// DELETE /tables/<name>?secret=yourpassword – IDs already issued keep their table_name
+#[delete("/tables/<name>?<secret>")]
+async fn delete_table(
+    name: String,
+    secret: Option<String>,
+    client: Option<IpAddr>,
+    dry_run: DryRun,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<String>, JsonError> {
+    check_secret(secret.as_deref(), state)?;
+
+    let actor = admin_actor(client);
+    let deleted = name.clone();
+    state.run_write(OpKind::Admin, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            if !tables::delete(tx, &deleted).map_err(db_error)? {
+                return Err(table_not_found(&deleted));
+            }
+            audit::record(tx, &actor, "delete_table", Some(&deleted), None).map_err(db_error)
+        })
+    }).await?;
+
+    Ok(DryRunMarked(format!("Table {} removed from the catalog", name), dry_run))
+}
+
+fn table_not_found(name: &str) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Table not found").details(name)
+}
+
+// POST /ids – allocates a new ID
+#[post("/ids", format = "json", data = "<request>")]
+async fn generate(
+    request: Valid<GenerateRequest>,
+    links: WantLinks,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<IdDetails>>, JsonError> {
+    let request = request.into_inner();
+    state.run_write_metered(latency::Path::Generate, move |state, conn| create_id(&request.owner, request.table.as_deref(), request.suffix.as_deref(), request.external_ref.as_deref(), dry_run, state, conn))
+        .await
+        .map(|details| DryRunMarked(Negotiated(details.with_links(&links).with_derived(&state.settings(), dry_run)), dry_run))
+}
+
+// POST /ids with a protobuf GenerateRequest body
+#[post("/ids", format = "application/x-protobuf", data = "<body>")]
+async fn generate_proto(
+    body: Vec<u8>,
+    links: WantLinks,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<IdDetails>>, JsonError> {
+    let request = proto::GenerateRequest::decode(body.as_slice()).map_err(|e| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid protobuf body").details(e)
+    })?;
+    state.run_write_metered(latency::Path::Generate, move |state, conn| create_id(&request.owner, request.table.as_deref(), request.suffix.as_deref(), request.external_ref.as_deref(), dry_run, state, conn))
+        .await
+        .map(|details| DryRunMarked(Negotiated(details.with_links(&links).with_derived(&state.settings(), dry_run)), dry_run))
+}
+
+// POST /ids with an application/x-www-form-urlencoded body (owner=...&table=...)
+#[post("/ids", format = "form", data = "<form>")]
+async fn generate_form(
+    form: Form<GenerateForm>,
+    links: WantLinks,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<IdDetails>>, JsonError> {
+    let form = form.into_inner();
+    state.run_write_metered(latency::Path::Generate, move |state, conn| create_id(&form.owner, form.table.as_deref(), form.suffix.as_deref(), form.external_ref.as_deref(), dry_run, state, conn))
+        .await
+        .map(|details| DryRunMarked(Negotiated(details.with_links(&links).with_derived(&state.settings(), dry_run)), dry_run))
+}
+
+// POST /ids?owner=...&table=...&suffix=...&external_ref=... without any body
+#[post("/ids?<query..>", rank = 5)]
+async fn generate_query(
+    query: GenerateForm,
+    links: WantLinks,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<IdDetails>>, JsonError> {
+    state.run_write_metered(latency::Path::Generate, move |state, conn| create_id(&query.owner, query.table.as_deref(), query.suffix.as_deref(), query.external_ref.as_deref(), dry_run, state, conn))
+        .await
+        .map(|details| DryRunMarked(Negotiated(details.with_links(&links).with_derived(&state.settings(), dry_run)), dry_run))
+}
+
+// Owners are trimmed and must pass validation::owner
+fn clean_owner(owner: &str) -> Result<String, JsonError> {
+    validation::owner(owner).map_err(|reason| JsonError::new(Status::BadRequest, ErrorCode::InvalidOwner, "Invalid owner").details(reason))?;
+    Ok(owner.trim().to_string())
+}
+
+// Shared by all variants of POST /ids. With an external_ref the owner already used,
+// the ID issued for it then is returned instead of a new one
+fn create_id(
+    owner: &str,
+    table: Option<&str>,
+    suffix: Option<&str>,
+    external_ref: Option<&str>,
+    dry_run: DryRun,
+    state: &AppState,
+    conn: &mut Connection,
+) -> Result<IdDetails, JsonError> {
+    state.ensure_writable()?;
+
+    info!("Generate request: owner={}, table={:?}, suffix={:?}, external_ref={:?}", owner, table, suffix, external_ref);
+
+    let owner_clean = clean_owner(owner)?;
+    state.ensure_owner_writable(&owner_clean)?;
+    let external_ref = external_ref.map(clean_external_ref).transpose()?;
+
+    // Claim, insert and read back in one write transaction: concurrent readers never
+    // see a half-finished row and the response always carries the stored created_at.
+    // Being immediate, it also keeps two retries with one external_ref from both inserting
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(db_error)?;
+
+    if let Some(external_ref) = &external_ref
+        && let Some(id) = find_by_external_ref(&tx, &owner_clean, external_ref).map_err(db_error)?
+    {
+        return tx.query_row(&format!("SELECT {} FROM ids WHERE id = ?1", DETAIL_COLUMNS), [&id], details_from_row)
+            .map_err(db_error)
+            .and_then(|details| if details.deleted { Err(id_deleted(&id)) } else { Ok(details) });
+    }
+
+    tables::check(&tx, &state.settings(), table).map_err(table_error)?;
+    let (id, created_at, config_version) = issue_id(&tx, state.settings().as_ref(), &owner_clean, table, suffix, external_ref.as_deref())
+        .map_err(generation_error)?;
+
+    // A dry run rolls back here: the ID goes back to being unused (or back into the pool)
+    if !dry_run.0 {
+        tx.commit().map_err(db_error)?;
+        state.forget_missing(&id);
+    }
+
+    let mut details = IdDetails::issued(id, &owner_clean, table, created_at, config_version);
+    details.external_ref = external_ref;
+    Ok(details)
+}
+
+fn clean_external_ref(external_ref: &str) -> Result<String, JsonError> {
+    validation::external_ref(external_ref).map_err(|reason| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid external_ref").details(format!("external_ref {}", reason))
+    })?;
+    Ok(external_ref.trim().to_string())
+}
+
+// POST /ids/<id>/confirm?by=billing&owner=team – `by` names the confirming system, stored
+// as confirmed_by; with `owner`, an ID of another owner is refused. An owner with a key
+// needs it in X-Owner-Key
+#[post("/ids/<id>/confirm?<by>&<owner>")]
+async fn confirm(
+    id: String,
+    by: Option<String>,
+    owner: Option<String>,
+    key: OwnerKey,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<ConfirmResponse>>, JsonError> {
+    let by = by.as_deref().map(|by| clean_principal("by", by)).transpose()?;
+    let owner = owner.as_deref().map(clean_owner).transpose()?;
+    state.run_write_metered(latency::Path::Confirm, move |state, conn| {
+        confirm_id(&id, by.as_deref(), owner.as_deref(), key.0.as_deref(), dry_run, state, conn)
+    })
+        .await
+        .map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// A short name for a person or system (`param` names the field for the error); see
+// validation::principal
+fn clean_principal(param: &str, value: &str) -> Result<String, JsonError> {
+    validation::principal(value).map_err(|reason| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, &format!("Invalid {}", param)).details(format!("{} {}", param, reason))
+    })?;
+    Ok(value.trim().to_string())
+}
+
+// The owner key a confirm presented, checked against each ID's owner
+fn key_check<'a>(key: Option<&'a str>, state: &AppState) -> owners::KeyCheck<'a> {
+    owners::KeyCheck { key, required: state.settings().confirm_requires_owner }
+}
+
+fn confirm_id(
+    id: &str,
+    by: Option<&str>,
+    owner: Option<&str>,
+    key: Option<&str>,
+    dry_run: DryRun,
+    state: &AppState,
+    conn: &mut Connection,
+) -> Result<ConfirmResponse, JsonError> {
+    state.ensure_writable()?;
+
+    let tx = conn.transaction().map_err(db_error)?;
+    let id = &casefold::stored_id(&tx, &state.settings(), id).map_err(db_error)?;
+    state.ensure_ids_writable(&tx, std::slice::from_ref(id))?;
+
+    // Refused before anything else about the ID is told, like another owner's ID
+    if let Some(found) = batch::id_owner(&tx, id).map_err(db_error)?
+        && !key_check(key, state).allows(&tx, &found).map_err(db_error)?
+    {
+        return Err(owner_key_refused(id));
+    }
+
+    let rows_affected = tx.execute(
+        "UPDATE ids SET confirmed = 1, confirmed_at = CURRENT_TIMESTAMP, confirmed_by = ?2,
+                        version = version + 1, updated_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND confirmed = 0 AND deleted = 0 AND (?3 IS NULL OR owner = ?3)",
+        rusqlite::params![id, by, owner],
+    ).map_err(db_error)?;
+
+    if rows_affected == 0 {
+        // Nothing changed: no such ID, another owner's, a deleted one, or one confirmed before.
+        // Another owner's ID is refused before its state is told
+        if owner.is_some() && batch::id_owner(&tx, id).map_err(db_error)?.is_some_and(|found| Some(found.as_str()) != owner) {
+            return Err(JsonError::new(Status::Forbidden, ErrorCode::WrongOwner, "ID belongs to another owner").details(id));
+        }
+        let state = id_state(&tx, id).map_err(db_error)?.ok_or_else(|| id_not_found(id))?;
+        return match state.apply(Event::Confirm) {
+            Err(Refused::AlreadyConfirmed) => Ok(ConfirmResponse {
+                success: true,
+                message: format!("ID {} was already confirmed", id),
+                outcome: ConfirmOutcome::AlreadyConfirmed,
+            }),
+            Err(Refused::Deleted) | Ok(_) => Err(id_deleted(id)),
+        };
+    }
+
+    if !dry_run.0 {
+        tx.commit().map_err(db_error)?;
+        state.id_cache.invalidate(id);
+    }
+
+    Ok(ConfirmResponse {
+        success: true,
+        message: format!("ID {} confirmed", id),
+        outcome: ConfirmOutcome::Confirmed,
+    })
+}
+
+fn owner_key_refused(target: &str) -> JsonError {
+    JsonError::new(Status::Forbidden, ErrorCode::WrongOwner, "Missing or wrong owner key")
+        .details(format!("{}: pass the owner's key in X-Owner-Key", target))
+}
+
+// GET /changes?since=0&limit=100&id= – every change to an ID since the cursor, oldest first
+#[get("/changes?<query..>")]
+async fn list_changes(
+    query: ChangesQuery,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Negotiated<ChangeFeed>, JsonError> {
+    const DEFAULT_PAGE: usize = 100;
+    const MAX_PAGE: usize = 1_000;
+
+    let since = query.since.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let id = query.id.map(|id| casefold::stored_id(&conn, &state.settings(), &id)).transpose().map_err(db_error)?;
+
+        let mut page = changes::after(&conn, since, limit + 1, id.as_deref()).map_err(|e| match e.downcast_ref::<CursorExpired>() {
+            Some(expired) => JsonError::new(Status::Gone, ErrorCode::CursorExpired, "Cursor expired").details(expired.to_string()),
+            None => db_error(e),
+        })?;
+        let more = page.len() > limit;
+        page.truncate(limit);
+
+        // Deleted rows too: the feed reports them like any other change
+        let ids: Vec<&str> = page.iter().filter(|c| c.op != ChangeOp::Removed).map(|c| c.id.as_str()).collect();
+        let mut current: HashMap<String, IdDetails> = HashMap::new();
+        if !ids.is_empty() {
+            let placeholders = vec!["?"; ids.len()].join(",");
+            let mut stmt = conn.prepare(&format!("SELECT {} FROM ids WHERE id IN ({})", DETAIL_COLUMNS, placeholders))
+                .map_err(db_error)?;
+            current = stmt.query_map(rusqlite::params_from_iter(&ids), details_from_row)
+                .and_then(|rows| rows.map(|r| r.map(|d| (d.id.clone(), d))).collect())
+                .map_err(db_error)?;
+        }
+
+        let next = page.last().map_or(since, |c| c.seq);
+        let changes = page.into_iter()
+            .map(|change| {
+                let details = current.get(&change.id).cloned();
+                ChangeEntry { change, details }
+            })
+            .collect();
+        Ok(ChangeFeed { changes, next, more })
+    }).await.map(Negotiated)
+}
+
+// GET /ids?prefix=AB3&limit=100&after=...&sort=id&order=asc – live IDs, one page at a time
+#[get("/ids?<query..>")]
+async fn list_ids(
+    query: ListQuery<'_>,
+    scope: DeletedScope,
+    fields: Fields,
+    links: WantLinks,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Negotiated<IdList>, JsonError> {
+    let sort = param_or(query.sort, SortKey::Id, "sort", "id, created_at or owner")?;
+    let order = param_or(query.order, SortOrder::Asc, "order", "asc or desc")?;
+
+    // The prefix becomes a range on the unique id index: prefix <= id < upper bound
+    let mut filter = SqlFilter::new(scope.condition());
+    if let Some(prefix) = query.prefix.filter(|p| !p.is_empty()) {
+        if let Some(upper) = prefix_upper_bound(&prefix) {
+            filter.add("id < ?", upper);
+        }
+        filter.add("id >= ?", prefix);
+    }
+    if let Some(label) = query.label {
+        filter.add(LABEL_FILTER, label);
+    }
+
+    let page = Page { filter, sort, order, after: query.after, limit: query.limit };
+    let list = state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        page.fetch(&conn)
+    }).await?;
+    Ok(Negotiated(list.shaped(fields, &links)))
+}
+
+// GET /owners/<owner>/ids?table=&confirmed=&limit=&after=&sort=&order= – one owner's IDs,
+// paged like GET /ids
+#[get("/owners/<owner>/ids?<query..>")]
+async fn list_owner_ids(
+    owner: &str,
+    query: OwnerListQuery<'_>,
+    scope: DeletedScope,
+    fields: Fields,
+    links: WantLinks,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Negotiated<IdList>, JsonError> {
+    let owner = clean_owner(owner)?;
+    let sort = param_or(query.sort, SortKey::Id, "sort", "id, created_at or owner")?;
+    let order = param_or(query.order, SortOrder::Asc, "order", "asc or desc")?;
+
+    // Served by idx_ids_owner_id
+    let mut filter = SqlFilter::new(scope.condition());
+    filter.add("owner = ?", owner);
+    if let Some(table) = query.table {
+        filter.add("table_name = ?", table);
+    }
+    if let Some(confirmed) = query.confirmed {
+        filter.add("confirmed = ?", i64::from(confirmed));
+    }
+
+    let page = Page { filter, sort, order, after: query.after, limit: query.limit };
+    let list = state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        page.fetch(&conn)
+    }).await?;
+    Ok(Negotiated(list.shaped(fields, &links)))
+}
+
+// One page of a keyset-paged listing of ids rows
+struct Page {
+    filter: SqlFilter,
+    sort: SortKey,
+    order: SortOrder,
+    // Cursor: the `next` value of the previous page
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+impl Page {
+    const DEFAULT_SIZE: usize = 100;
+    const MAX_SIZE: usize = 1_000;
+
+    fn fetch(self, conn: &Connection) -> Result<IdList, JsonError> {
+        let Page { mut filter, sort, order, after, limit } = self;
+        let limit = limit.unwrap_or(Self::DEFAULT_SIZE).clamp(1, Self::MAX_SIZE);
+
+        let (cmp, direction) = match order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        // Keyset paging: the cursor is the last ID of the previous page; for other sort
+        // keys its key is looked up so the page continues after (key, id)
+        if let Some(after) = after {
+            match sort {
+                SortKey::Id => filter.add(&format!("id {} ?", cmp), after),
+                key => filter.add(
+                    &format!("({col}, id) {cmp} ((SELECT {col} FROM ids WHERE id = ?), ?)", col = key.column(), cmp = cmp),
+                    after,
+                ),
+            }
+        }
+
+        let order_by = match sort {
+            SortKey::Id => format!("id {}", direction),
+            key => format!("{} {d}, id {d}", key.column(), d = direction),
+        };
+        let sql = format!(
+            "SELECT {} FROM ids WHERE {} ORDER BY {} LIMIT {}",
+            DETAIL_COLUMNS,
+            filter.where_clause(),
+            order_by,
+            limit + 1
+        );
+        let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+        let mut ids = stmt.query_map(rusqlite::params_from_iter(&filter.params), details_from_row)
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>()).map_err(db_error)?;
+
+        // One row more than asked for tells us whether there is another page
+        let next = if ids.len() > limit {
+            ids.truncate(limit);
+            ids.last().map(|d| d.id.clone())
+        } else {
+            None
+        };
+        Ok(IdList { ids, next })
+    }
+}
+
+impl IdList {
+    fn shaped(mut self, fields: Fields, links: &WantLinks) -> Self {
+        self.ids = self.ids.into_iter().map(|d| d.with_fields(fields).with_links(links)).collect();
+        self
+    }
+}
+
+// GET /ids/count?owner=&table=&confirmed=&created_after= – just the number of matching live IDs
+#[get("/ids/count?<query..>")]
+async fn count_ids(query: CountQuery, _limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<CountResponse>, JsonError> {
+    let mut filter = SqlFilter::new("deleted = 0");
+    if let Some(owner) = query.owner {
+        filter.add("owner = ?", owner);
+    }
+    if let Some(table) = query.table {
+        filter.add("table_name = ?", table);
+    }
+    if let Some(confirmed) = query.confirmed {
+        filter.add("confirmed = ?", i64::from(confirmed));
+    }
+    if let Some(created_after) = query.created_after {
+        filter.add("created_at > ?", parse_timestamp(&created_after)?);
+    }
+    if let Some(label) = query.label {
+        filter.add(LABEL_FILTER, label);
+    }
+
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let sql = format!("SELECT COUNT(*) FROM ids WHERE {}", filter.where_clause());
+        conn.query_row(&sql, rusqlite::params_from_iter(&filter.params), |row| row.get(0))
+            .map(|count| Negotiated(CountResponse { count }))
+            .map_err(db_error)
+    }).await
+}
+
+// An optional query parameter: `default` when absent, 400 naming what it accepts when malformed
+fn param_or<T>(value: rocket::form::Result<'_, T>, default: T, name: &str, accepted: &str) -> Result<T, JsonError> {
+    match value {
+        Ok(value) => Ok(value),
+        Err(errors) if errors.iter().all(|e| matches!(e.kind, rocket::form::error::ErrorKind::Missing)) => Ok(default),
+        Err(_) => Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, &format!("Invalid {}", name))
+            .details(format!("{} must be one of: {}", name, accepted))),
+    }
+}
+
+// Turns a date or date-time from a query into the stored created_at format
+fn parse_timestamp(value: &str) -> Result<String, JsonError> {
+    const STORED: &str = "%Y-%m-%d %H:%M:%S";
+
+    let parsed = chrono::NaiveDateTime::parse_from_str(value, STORED)
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(value).map(|t| t.naive_utc()))
+        .or_else(|_| chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_time(chrono::NaiveTime::MIN)));
+
+    parsed.map(|t| t.format(STORED).to_string()).map_err(|_| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid timestamp")
+            .details(format!("'{}': use YYYY-MM-DD or YYYY-MM-DD HH:MM:SS (UTC)", value))
+    })
+}
+
+// Smallest string greater than every string starting with `prefix`, if there is one
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// POST /ids/batch {"owner": "billing", "table": "invoices", "count": 500} – issues many IDs
+// in one transaction (up to lookup_max_ids); if any of them fails, none are issued.
+// With Accept: application/x-ndjson the IDs are streamed instead; see stream_batch
+#[post("/ids/batch", format = "json", data = "<request>")]
+async fn generate_batch(
+    request: Valid<BatchGenerateRequest>,
+    ndjson: WantNdjson,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Either<Negotiated<Vec<IdDetails>>, (ContentType, NdjsonStream)>>, JsonError> {
+    let BatchGenerateRequest { owner, table, count } = request.into_inner();
+    if count == 0 {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Count must be at least 1"));
+    }
+    if ndjson.0 {
+        check_stream_size(count, state)?;
+    } else {
+        check_batch_size(count, state)?;
+    }
+    let owner = clean_owner(&owner)?;
+
+    if ndjson.0 {
+        let stream = stream_batch(state.inner().clone(), owner, table, count, dry_run);
+        return Ok(DryRunMarked(Either::Right((ContentType::new("application", "x-ndjson"), stream)), dry_run));
+    }
+
+    issue_batch(state, owner, table, count, dry_run)
+        .await
+        .map(|details| DryRunMarked(Either::Left(Negotiated(details)), dry_run))
+}
+
+// One transaction issuing `count` IDs; the new IDs are no longer "missing" once committed
+async fn issue_batch(
+    state: &AppState,
+    owner: String,
+    table: Option<String>,
+    count: usize,
+    dry_run: DryRun,
+) -> Result<Vec<IdDetails>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| {
+        state.ensure_owner_writable(&owner)?;
+        let issued = write_tx(state, conn, dry_run, |tx| {
+            tables::check(tx, &state.settings(), table.as_deref()).map_err(table_error)?;
+            batch::issue_many(tx, &state.settings(), &owner, table.as_deref(), count).map_err(generation_error)
+        })?;
+        info!("Batch generate: {} ID(s) for owner={}, table={:?}", issued.len(), owner, table);
+
+        Ok(issued.into_iter().map(|(id, created_at, config_version)| {
+            if !dry_run.0 {
+                state.forget_missing(&id);
+            }
+            IdDetails::issued(id, &owner, table.as_deref(), created_at, config_version).with_derived(&state.settings(), dry_run)
+        }).collect())
+    }).await
+}
+
+// Streams a large batch as NDJSON: IDs are issued and committed STREAM_CHUNK at a time and
+// each chunk is written out as soon as it commits, so neither side holds the whole batch.
+// A failure ends the stream with an error line; the IDs before it stay issued
+fn stream_batch(
+    state: AppState,
+    owner: String,
+    table: Option<String>,
+    count: usize,
+    dry_run: DryRun,
+) -> NdjsonStream {
+    const STREAM_CHUNK: usize = 500;
+
+    let (lines, stream) = ndjson_channel(2 * STREAM_CHUNK);
+    tokio::spawn(async move {
+        let mut remaining = count;
+        while remaining > 0 {
+            let chunk = remaining.min(STREAM_CHUNK);
+            remaining -= chunk;
+
+            let details = match issue_batch(&state, owner.clone(), table.clone(), chunk, dry_run).await {
+                Ok(details) => details,
+                Err(e) => {
+                    let line = serde_json::to_string(&e.error).unwrap_or_default();
+                    let _ = lines.send(line + "\n").await;
+                    return;
+                }
+            };
+            for details in details {
+                let line = serde_json::to_string(&details).unwrap_or_default();
+                if lines.send(line + "\n").await.is_err() {
+                    info!("Batch stream client went away with {} ID(s) left to issue", remaining);
+                    return;
+                }
+            }
+        }
+    });
+
+    stream
+}
+
+// A streamed response body and the sender feeding it. The small buffer means a slow
+// client holds back the producer rather than filling memory; a send fails once it's gone
+fn ndjson_channel(capacity: usize) -> (tokio::sync::mpsc::Sender<String>, NdjsonStream) {
+    let (lines, pending) = tokio::sync::mpsc::channel::<String>(capacity);
+    let stream = TextStream(stream::unfold(pending, |mut pending| async move {
+        pending.recv().await.map(|line| (line, pending))
+    }).boxed());
+    (lines, stream)
+}
+
+// One JSON document per line, each line ending in a newline
+fn ndjson_lines<T: serde::Serialize>(items: &[T]) -> String {
+    items.iter().map(|item| serde_json::to_string(item).unwrap_or_default() + "\n").collect()
+}
+
+// POST /jobs/generate {"owner": ..., "table": ..., "count": 100000} – POST /ids/batch as a
+// background job: answers 202 right away; poll GET /jobs/<id> for progress
+#[post("/jobs/generate", format = "json", data = "<request>")]
+async fn start_generate_job(
+    request: Valid<BatchGenerateRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<(Status, Json<JobStatus>), JsonError> {
+    let BatchGenerateRequest { owner, table, count } = request.into_inner();
+    if count == 0 {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Count must be at least 1"));
+    }
+    check_stream_size(count, state)?;
+    let owner = clean_owner(&owner)?;
+    state.ensure_owner_writable(&owner)?;
+
+    let job = start_job(state, "generate", Some(count), dry_run).await?;
+    tokio::spawn(run_generate_job(state.inner().clone(), job.job_id.clone(), owner, table, count));
+    Ok((Status::Accepted, Json(JobStatus::from(job))))
+}
+
+// POST /jobs/export {"owner": ..., "table": ...} – writes the matching live IDs to the
+// job's result, in ID order
+#[post("/jobs/export", format = "json", data = "<request>")]
+async fn start_export_job(
+    request: Valid<ExportRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<(Status, Json<JobStatus>), JsonError> {
+    let ExportRequest { owner, table } = request.into_inner();
+
+    let job = start_job(state, "export", None, dry_run).await?;
+    tokio::spawn(run_export_job(state.inner().clone(), job.job_id.clone(), owner, table));
+    Ok((Status::Accepted, Json(JobStatus::from(job))))
+}
+
+// GET /jobs/<id> – status and progress; `links.result` appears once the job has finished
+#[get("/jobs/<job_id>")]
+async fn get_job(job_id: String, _limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<JobStatus>, JsonError> {
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        jobs::get(&conn, &job_id).map_err(db_error)?.ok_or_else(|| job_not_found(&job_id))
+    }).await.map(|job| Negotiated(JobStatus::from(job)))
+}
+
+// GET /jobs/<id>/result – the job's output as NDJSON, streamed chunk by chunk. A failed
+// job's result holds whatever it produced before failing
+#[get("/jobs/<job_id>/result")]
+async fn job_result(job_id: String, _limit: RateLimited, state: &State<AppState>) -> Result<(ContentType, NdjsonStream), JsonError> {
+    let id = job_id.clone();
+    let job = state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        jobs::get(&conn, &id).map_err(db_error)?.ok_or_else(|| job_not_found(&id))
+    }).await?;
+    if job.status == "running" {
+        return Err(JsonError::new(Status::Conflict, ErrorCode::JobNotFinished, "Job is still running")
+            .details(format!("{} of {} done", job.done, job.total.map_or("?".to_string(), |t| t.to_string())))
+            .header("Retry-After", 5));
+    }
+
+    let (lines, stream) = ndjson_channel(2);
+    let state = state.inner().clone();
+    tokio::spawn(async move {
+        let mut seq = 0;
+        loop {
+            let id = job_id.clone();
+            let chunk = state.run_db(OpKind::Read, move |state| {
+                let conn = state.conn()?;
+                jobs::chunk_after(&conn, &id, seq).map_err(db_error)
+            }).await;
+            match chunk {
+                Ok(Some((next, body))) => {
+                    seq = next;
+                    if lines.send(body).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    let _ = lines.send(ndjson_lines(&[e.error])).await;
+                    return;
+                }
+            }
+        }
+    });
+    Ok((ContentType::new("application", "x-ndjson"), stream))
+}
+
+// Registers a job. Jobs write as they go, so they can't be dry runs
+async fn start_job(state: &AppState, kind: &'static str, total: Option<usize>, dry_run: DryRun) -> Result<Job, JsonError> {
+    if dry_run.0 {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Jobs cannot run as a dry run"));
+    }
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let job_id = jobs::create(tx, kind, total).map_err(db_error)?;
+            jobs::get(tx, &job_id).map_err(db_error)?.ok_or_else(|| job_not_found(&job_id))
+        })
+    }).await.inspect(|job| info!("Job {} ({}) started", job.job_id, kind))
+}
+
+// Chunk size of background jobs: each chunk is one transaction and one stored result chunk
+const JOB_CHUNK: usize = 500;
+
+async fn run_generate_job(state: AppState, job_id: String, owner: String, table: Option<String>, count: usize) {
+    let outcome = async {
+        let mut remaining = count;
+        while remaining > 0 {
+            let chunk = remaining.min(JOB_CHUNK);
+            let (job_id, owner, table) = (job_id.clone(), owner.clone(), table.clone());
+            state.run_write(OpKind::Write, move |state, conn| {
+                let issued = write_tx(state, conn, DryRun(false), |tx| {
+                    tables::check(tx, &state.settings(), table.as_deref()).map_err(table_error)?;
+                    let issued: Vec<IdDetails> = batch::issue_many(tx, &state.settings(), &owner, table.as_deref(), chunk)
+                        .map_err(generation_error)?
+                        .into_iter()
+                        .map(|(id, created_at, config_version)| {
+                            IdDetails::issued(id, &owner, table.as_deref(), created_at, config_version)
+                                .with_derived(&state.settings(), DryRun(false))
+                        })
+                        .collect();
+                    jobs::append(tx, &job_id, &ndjson_lines(&issued), issued.len()).map_err(db_error)?;
+                    Ok(issued)
+                })?;
+                issued.iter().for_each(|details| state.forget_missing(&details.id));
+                Ok(())
+            }).await?;
+            remaining -= chunk;
+        }
+        Ok(())
+    }.await;
+    close_job(&state, job_id, outcome).await;
+}
+
+async fn run_export_job(state: AppState, job_id: String, owner: Option<String>, table: Option<String>) {
+    let outcome = async {
+        let mut after: Option<String> = None;
+        loop {
+            let mut filter = SqlFilter::new("deleted = 0");
+            if let Some(owner) = &owner {
+                filter.add("owner = ?", owner.clone());
+            }
+            if let Some(table) = &table {
+                filter.add("table_name = ?", table.clone());
+            }
+            if let Some(after) = after.take() {
+                filter.add("id > ?", after);
+            }
+            let page = state.run_db(OpKind::Read, move |state| {
+                let conn = state.conn()?;
+                let sql = format!(
+                    "SELECT {} FROM ids WHERE {} ORDER BY id LIMIT {}",
+                    DETAIL_COLUMNS,
+                    filter.where_clause(),
+                    JOB_CHUNK
+                );
+                let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+                stmt.query_map(rusqlite::params_from_iter(&filter.params), details_from_row)
+                    .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+                    .map_err(db_error)
+            }).await?;
+            let Some(last) = page.last() else {
+                return Ok(());
+            };
+            after = Some(last.id.clone());
+
+            let (job_id, body) = (job_id.clone(), ndjson_lines(&page));
+            state.run_write(OpKind::Write, move |state, conn| {
+                write_tx(state, conn, DryRun(false), |tx| jobs::append(tx, &job_id, &body, page.len()).map_err(db_error))
+            }).await?;
+        }
+    }.await;
+    close_job(&state, job_id, outcome).await;
+}
+
+// Records how a job ended; the error goes into the job for GET /jobs/<id> to show
+async fn close_job(state: &AppState, job_id: String, outcome: Result<(), JsonError>) {
+    let error = outcome.err().map(|e| match &e.error.details {
+        Some(details) => format!("{}: {}", e.error.message, details),
+        None => e.error.message.clone(),
+    });
+    let id = job_id.clone();
+    let closed = state.run_write(OpKind::Write, move |_, conn| {
+        match &error {
+            None => jobs::finish(conn, &id),
+            Some(error) => jobs::fail(conn, &id, error),
+        }.map_err(db_error)
+    }).await;
+    match closed {
+        Ok(()) => info!("Job {} finished", job_id),
+        Err(e) => error!("Could not record the end of job {}: {}", job_id, e.error.message),
+    }
+}
+
+fn job_not_found(job_id: &str) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Job not found").details(job_id)
+}
+
+// POST /owners/<owner>/confirm_all?table=orders&by=billing – confirms all of an owner's
+// unconfirmed IDs (of one table, if given) in one transaction, for a batch going live at once.
+// Needs the owner's key in X-Owner-Key, if it has one
+#[post("/owners/<owner>/confirm_all?<table>&<by>")]
+async fn confirm_owner(
+    owner: &str,
+    table: Option<String>,
+    by: Option<String>,
+    key: OwnerKey,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<ConfirmAllResponse>>, JsonError> {
+    let owner = clean_owner(owner)?;
+    let by = by.as_deref().map(|by| clean_principal("by", by)).transpose()?;
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        state.ensure_owner_writable(&owner)?;
+        let ids = write_tx(state, conn, dry_run, |tx| {
+            if !key_check(key.0.as_deref(), state).allows(tx, &owner).map_err(db_error)? {
+                return Err(owner_key_refused(&owner));
+            }
+            batch::confirm_owner(tx, &owner, table.as_deref(), by.as_deref()).map_err(db_error)
+        })?;
+        if !dry_run.0 {
+            ids.iter().for_each(|id| state.id_cache.invalidate(id));
+        }
+        Ok(ConfirmAllResponse { owner: owner.clone(), table: table.clone(), confirmed: ids.len() })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// POST /ids/confirm?by=billing&owner=team {"ids": [...]} – confirms many IDs in one
+// transaction. Each ID gets its own outcome; unknown, deleted or other owners' ones (and
+// those of owners X-Owner-Key doesn't open) don't fail the rest
+#[post("/ids/confirm?<by>&<owner>", format = "json", data = "<request>")]
+async fn confirm_batch(
+    by: Option<String>,
+    owner: Option<String>,
+    key: OwnerKey,
+    request: Valid<LookupRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<Vec<BatchResult>>>, JsonError> {
+    let ids = request.into_inner().ids;
+    check_batch_size(ids.len(), state)?;
+    let by = by.as_deref().map(|by| clean_principal("by", by)).transpose()?;
+    let owner = owner.as_deref().map(clean_owner).transpose()?;
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        let ids = ids.iter().map(|id| casefold::stored_id(conn, &state.settings(), id)).collect::<Result<Vec<_>>>().map_err(db_error)?;
+        state.ensure_ids_writable(conn, &ids)?;
+        let results = write_tx(state, conn, dry_run, |tx| {
+            batch::confirm_many(tx, &ids, by.as_deref(), owner.as_deref(), key_check(key.0.as_deref(), state)).map_err(db_error)
+        })?;
+        if !dry_run.0 {
+            ids.iter().for_each(|id| state.id_cache.invalidate(id));
+        }
+        Ok(results)
+    }).await.map(|results| DryRunMarked(Negotiated(results), dry_run))
+}
+
+// POST /ids/delete {"ids": [...]} – soft-deletes many IDs in one transaction, reporting
+// each ID's outcome like POST /ids/confirm
+#[post("/ids/delete", format = "json", data = "<request>")]
+async fn delete_batch(
+    request: Valid<LookupRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<Vec<BatchResult>>>, JsonError> {
+    let ids = request.into_inner().ids;
+    check_batch_size(ids.len(), state)?;
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        let ids = ids.iter().map(|id| casefold::stored_id(conn, &state.settings(), id)).collect::<Result<Vec<_>>>().map_err(db_error)?;
+        state.ensure_ids_writable(conn, &ids)?;
+        let results = write_tx(state, conn, dry_run, |tx| batch::delete_many(tx, &ids).map_err(db_error))?;
+        if !dry_run.0 {
+            ids.iter().for_each(|id| state.id_cache.invalidate(id));
+        }
+        Ok(results)
+    }).await.map(|results| DryRunMarked(Negotiated(results), dry_run))
+}
+
+// Streamed and background batches go up to batch_stream_max_ids
+fn check_stream_size(count: usize, state: &AppState) -> Result<(), JsonError> {
+    let max = state.settings().batch_stream_max_ids;
+    if count > max {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Too many IDs in one request")
+            .details(format!("at most {} per streamed or background batch", max)));
+    }
+    Ok(())
+}
+
+// Routes taking many IDs at once accept up to lookup_max_ids of them
+fn check_batch_size(count: usize, state: &AppState) -> Result<(), JsonError> {
+    let max = state.settings().lookup_max_ids;
+    if count > max {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Too many IDs in one request")
+            .details(format!("at most {} per request", max)));
+    }
+    Ok(())
+}
+
+// POST /ids/lookup with {"ids": [...]} – details for many IDs in one query
+#[post("/ids/lookup", format = "json", data = "<request>")]
+async fn lookup_ids(
+    request: Valid<LookupRequest>,
+    limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Negotiated<Vec<LookupResult>>, JsonError> {
+    let ids = request.into_inner().ids;
+    let max = state.settings().lookup_max_ids;
+    if ids.len() > max {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Too many IDs in one lookup")
+            .details(format!("at most {} per request", max)));
+    }
+    check_canaries(&ids, "POST /ids/lookup", limit.0, state);
+
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+
+        let placeholders = vec!["?"; ids.len()].join(",");
+        let settings = &state.settings();
+        let collate = if settings.case_insensitive_ids { " COLLATE NOCASE" } else { "" };
+
+        // Repaired IDs resolve to the ID that replaced them, as on GET /ids/<id>
+        let mut stmt = conn
+            .prepare(&format!("SELECT alias, id FROM id_aliases WHERE alias{} IN ({})", collate, placeholders))
+            .map_err(db_error)?;
+        let aliases: HashMap<String, String> = stmt
+            .query_map(rusqlite::params_from_iter(&ids), |row| Ok((casefold::key(settings, &row.get::<_, String>(0)?), row.get(1)?)))
+            .and_then(|rows| rows.collect())
+            .map_err(db_error)?;
+
+        let wanted: Vec<&String> = ids.iter().chain(aliases.values()).collect();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM ids WHERE deleted = 0 AND id{} IN ({})",
+            DETAIL_COLUMNS, collate, vec!["?"; wanted.len()].join(",")
+        )).map_err(db_error)?;
+
+        let found: HashMap<String, IdDetails> = stmt.query_map(rusqlite::params_from_iter(&wanted), details_from_row).and_then(|rows| rows.map(|r| r.map(|d| (casefold::key(settings, &d.id), d))).collect()).map_err(db_error)?;
+
+        Ok(ids.into_iter().map(|id| {
+            let key = casefold::key(settings, &id);
+            let target = aliases.get(&key).map_or(key, |target| casefold::key(settings, target));
+            let details = found.get(&target).cloned();
+            LookupResult { found: details.is_some(), id, details }
+        }).collect())
+    }).await.map(Negotiated)
+}
+
+// POST /verify_scan with {"scan": "ab3k-9xq2-..."} – normalizes a scanned code and says
+// in one call whether it is an issued ID and whether that is confirmed
+#[post("/verify_scan", format = "json", data = "<request>")]
+async fn verify_scan(
+    request: Valid<ScanRequest>,
+    limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Negotiated<ScanResult>, JsonError> {
+    let scan = request.into_inner().scan;
+    let id = normalize_scan(&state.settings(), &scan);
+    if id.is_empty() {
+        return Err(JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Nothing to verify").details("the scan is empty"));
+    }
+    check_canaries(std::slice::from_ref(&id), "POST /verify_scan", limit.0, state);
+
+    state.run_db(OpKind::Read, move |state| {
+        let (status, details) = match load_id(&id, DeletedScope::Live, state)? {
+            Some(d) if d.confirmed != 0 => (ScanStatus::Confirmed, Some(d)),
+            Some(d) => (ScanStatus::Unconfirmed, Some(d)),
+            None if deleted_flag(&*state.conn()?, &id)?.is_some() => (ScanStatus::Deleted, None),
+            None => (ScanStatus::Unknown, None),
+        };
+        let problem = match status {
+            ScanStatus::Unknown => format_problem(&state.settings(), &id),
+            _ => None,
+        };
+        Ok(ScanResult { scan: scan.clone(), id: id.clone(), status, problem, details })
+    }).await.map(Negotiated)
+}
+
+// GET /validate/<id> – whether `id` fits the current length, charset and content policy,
+// without looking it up; the same rules generation follows
+#[get("/validate/<id>")]
+fn validate_id(id: &str, limit: RateLimited, state: &State<AppState>) -> Result<Json<IdValidation>, JsonError> {
+    state.ensure_readable()?;
+    let id = normalize_id(&state.settings(), id);
+    check_canaries(std::slice::from_ref(&id), "GET /validate/<id>", limit.0, state);
+    let problem = format_problem(&state.settings(), &id);
+    Ok(Json(IdValidation { id, valid: problem.is_none(), problem }))
+}
+
+// POST /tokens/verify with {"token": "..."} – checks a token's signature; the ID itself
+// isn't looked up, matching what a service holding token_key can do offline
+#[post("/tokens/verify", format = "json", data = "<request>")]
+fn verify_token(request: Valid<TokenRequest>, _limit: RateLimited, state: &State<AppState>) -> Result<Json<TokenCheck>, JsonError> {
+    let key = &state.settings().token_key;
+    if key.is_empty() {
+        return Err(JsonError::new(Status::NotImplemented, ErrorCode::NotImplemented, "Signed tokens are not enabled")
+            .details("set token_key"));
+    }
+    let id = tokens::verify(key, &request.token).map(str::to_string);
+    Ok(Json(TokenCheck { valid: id.is_some(), id }))
+}
+
+// GET /ids/<id>/qr.png?size=256 – the ID, or qr_url_template filled in with it, as a QR
+// code about `size` pixels wide
+#[get("/ids/<id>/qr.png?<size>")]
+async fn id_qr_png(id: String, size: Option<u32>, limit: RateLimited, state: &State<AppState>) -> Result<(ContentType, Vec<u8>), JsonError> {
+    let (matrix, scale) = id_qr(id, size, "GET /ids/<id>/qr.png", limit, state).await?;
+    let png = codes::png(&matrix, scale).map_err(|e| JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Cannot render the QR code").details(e))?;
+    Ok((ContentType::PNG, png))
+}
+
+// GET /ids/<id>/qr.svg?size=256 – as qr.png, as an SVG
+#[get("/ids/<id>/qr.svg?<size>")]
+async fn id_qr_svg(id: String, size: Option<u32>, limit: RateLimited, state: &State<AppState>) -> Result<(ContentType, String), JsonError> {
+    let (matrix, scale) = id_qr(id, size, "GET /ids/<id>/qr.svg", limit, state).await?;
+    Ok((ContentType::SVG, codes::svg(&matrix, scale)))
+}
+
+// GET /ids/<id>/barcode.png?size=256 – the ID as a Code 128 barcode about `size` pixels
+// wide, for label printers that cannot read QR codes
+#[get("/ids/<id>/barcode.png?<size>")]
+async fn id_barcode_png(id: String, size: Option<u32>, limit: RateLimited, state: &State<AppState>) -> Result<(ContentType, Vec<u8>), JsonError> {
+    let id = check_live_for_code(&id, "GET /ids/<id>/barcode.png", limit, state).await?;
+    let matrix = codes::code128(&id).map_err(|e| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Cannot encode as a Code 128 barcode").details(e)
+    })?;
+    let scale = matrix.scale_for(size.unwrap_or(256).clamp(64, 2048));
+    let png = codes::png(&matrix, scale).map_err(|e| JsonError::new(Status::InternalServerError, ErrorCode::InternalError, "Cannot render the barcode").details(e))?;
+    Ok((ContentType::PNG, png))
+}
+
+// Only live IDs are rendered, so this can't be used to print codes for made-up ones.
+// Returns the ID as issued, which is what the code carries
+async fn check_live_for_code(id: &str, route: &'static str, limit: RateLimited, state: &AppState) -> Result<String, JsonError> {
+    check_canaries(&[id.to_string()], route, limit.0, state);
+
+    let lookup = id.to_string();
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let id = casefold::stored_id(&conn, &state.settings(), &lookup).map_err(db_error)?;
+        match deleted_flag(&conn, &id)? {
+            None => Err(id_not_found(&id)),
+            Some(true) => Err(id_deleted(&id)),
+            Some(false) => Ok(id),
+        }
+    }).await
+}
+
+async fn id_qr(id: String, size: Option<u32>, route: &'static str, limit: RateLimited, state: &AppState) -> Result<(Matrix, u32), JsonError> {
+    let id = check_live_for_code(&id, route, limit, state).await?;
+
+    let template = &state.settings().qr_url_template;
+    let data = if template.is_empty() {
+        id
+    } else {
+        template.replace("{id}", RawStr::new(&id).percent_encode().as_str())
+    };
+    let matrix = codes::qr(&data).map_err(|e| {
+        JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Cannot encode as a QR code").details(e)
+    })?;
+    let scale = matrix.scale_for(size.unwrap_or(256).clamp(64, 2048));
+    Ok((matrix, scale))
+}
+
+// Served from the cache when possible: clients poll this while waiting for confirmation.
+// ?notes=true adds the ID's support notes, which are never cached.
+#[get("/ids/<id>?<notes>")]
+async fn get_id(
+    id: String,
+    notes: Option<bool>,
+    scope: DeletedScope,
+    fields: Fields,
+    links: WantLinks,
+    limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<Tagged<Negotiated<IdDetails>>, JsonError> {
+    // Before the caches, which would answer without reaching run_db's check
+    state.ensure_readable()?;
+    let id = normalize_id(&state.settings(), &id);
+    check_canaries(std::slice::from_ref(&id), "GET /ids/<id>", limit.0, state);
+
+    // The caches only ever hold live IDs
+    let cacheable = scope == DeletedScope::Live;
+    if cacheable {
+        if let Some(cached) = state.id_cache.get(&id) {
+            let version = cached.version;
+            let details = with_notes(cached, notes, state).await?;
+            return Ok(Tagged { body: Negotiated(details.with_fields(fields).with_links(&links).with_derived(&state.settings(), DryRun(false))), version });
+        }
+        if state.missing_cache.get(&casefold::key(&state.settings(), &id)).is_some() {
+            return Err(id_not_found(&id));
+        }
+    }
+
+    let lookup_id = id.clone();
+    let generations = (state.id_cache.generation(), state.missing_cache.generation());
+    let details = state.run_db_metered(OpKind::Read, latency::Path::Get, move |state| load_id(&lookup_id, scope, state)).await?;
+
+    match details {
+        Some(d) => {
+            // Keyed by the ID as stored, which is what writes invalidate; another spelling
+            // (an alias, a different case) always reads through
+            if cacheable {
+                state.id_cache.insert(&d.id, d.clone(), generations.0);
+            }
+            let version = d.version;
+            let d = with_notes(d, notes, state).await?;
+            Ok(Tagged { body: Negotiated(d.with_fields(fields).with_links(&links).with_derived(&state.settings(), DryRun(false))), version })
+        }
+        None => {
+            if cacheable {
+                state.missing_cache.insert(&casefold::key(&state.settings(), &id), (), generations.1);
+            }
+            Err(id_not_found(&id))
+        }
+    }
+}
+
+// Alerts and audits any canary among `ids`. The lookup itself goes on as for any unknown
+// ID, so whoever made it can't tell it was noticed; nor does it wait for the audit entry,
+// which is written in the background (and not at all in shadow mode, like any write)
+fn check_canaries(ids: &[String], route: &'static str, client: Option<IpAddr>, state: &AppState) {
+    let hits: Vec<String> = {
+        let canaries = state.canaries.read().unwrap_or_else(|e| e.into_inner());
+        if canaries.is_empty() {
+            return;
+        }
+        ids.iter().filter(|id| canaries.contains(*id)).cloned().collect()
+    };
+    if hits.is_empty() {
+        return;
+    }
+
+    let actor = format!("client@{}", client.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string()));
+    for id in &hits {
+        error!("ALERT: canary ID {} was looked up by {} ({})", id, actor, route);
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let dry_run = DryRun(state.settings().shadow_mode);
+        let recorded = state.run_write(OpKind::Write, move |state, conn| {
+            write_tx(state, conn, dry_run, |tx| {
+                hits.iter().try_for_each(|id| audit::record(tx, &actor, "canary_hit", Some(id), Some(route)).map_err(db_error))
+            })
+        }).await;
+        if let Err(e) = recorded {
+            error!("Could not audit a canary hit: {}", e.error.message);
+        }
+    });
+}
+
+// Attaches the notes when asked for
+async fn with_notes(mut details: IdDetails, wanted: Option<bool>, state: &AppState) -> Result<IdDetails, JsonError> {
+    if wanted == Some(true) {
+        let id = details.id.clone();
+        details.notes = Some(state.run_db(OpKind::Read, move |state| {
+            let conn = state.conn()?;
+            notes::list(&conn, &id).map_err(db_error)
+        }).await?);
+    }
+    Ok(details)
+}
+
+fn load_id(id: &str, scope: DeletedScope, state: &AppState) -> Result<Option<IdDetails>, JsonError> {
+    let conn = state.conn()?;
+    let id = &casefold::stored_id(&conn, &state.settings(), id).map_err(db_error)?;
+
+    // Repaired IDs (see /admin/repair_ids) resolve to the ID that replaced them, except
+    // when an admin is looking at deleted rows, who gets the retired row itself
+    let target = match scope {
+        DeletedScope::Live => "COALESCE((SELECT id FROM id_aliases WHERE alias = ?1), ?1)",
+        _ => "?1",
+    };
+    let sql = format!("SELECT {} FROM ids WHERE id = {} AND {}", DETAIL_COLUMNS, target, scope.condition());
+
+    conn.query_row(&sql, [id], details_from_row).optional().map_err(db_error)
+}
+
+// PUT /ids/<id> – changes owner and/or table; If-Match must carry the ETag from GET /ids/<id>,
+// or * for whatever version the live ID is at
+#[put("/ids/<id>", format = "json", data = "<request>")]
+async fn update_id(
+    id: String,
+    request: Valid<UpdateRequest>,
+    if_match: IfMatch,
+    links: WantLinks,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Tagged<Negotiated<IdDetails>>>, JsonError> {
+    let Some(tag) = if_match.0 else {
+        return Err(JsonError::new(Status::PreconditionRequired, ErrorCode::PreconditionRequired, "If-Match header is required")
+            .details("send the ETag from GET /ids/<id>"));
+    };
+    let expected = match tag.trim() {
+        "*" => None,
+        _ => Some(parse_etag(&tag).ok_or_else(|| {
+            JsonError::new(Status::PreconditionFailed, ErrorCode::VersionConflict, "If-Match does not name a version").details(&tag)
+        })?),
+    };
+
+    let request = request.into_inner();
+    let details = state.run_write(OpKind::Write, move |state, conn| update_record(&id, expected, &request, dry_run, state, conn)).await?;
+
+    let version = details.version;
+    Ok(DryRunMarked(Tagged { body: Negotiated(details.with_links(&links)), version }, dry_run))
+}
+
+// Only updates the row if nobody changed it since `expected` was read, or any live row without one
+fn update_record(id: &str, expected: Option<i64>, request: &UpdateRequest, dry_run: DryRun, state: &AppState, conn: &mut Connection) -> Result<IdDetails, JsonError> {
+    state.ensure_writable()?;
+
+    let changes = IdChanges {
+        owner: request.owner.as_deref().map(clean_owner).transpose()?,
+        table: request.table.clone(),
+    };
+    let tx = conn.transaction().map_err(db_error)?;
+    let id = &casefold::stored_id(&tx, &state.settings(), id).map_err(db_error)?;
+    state.ensure_ids_writable(&tx, &[id.to_string()])?;
+    if let Some(owner) = &changes.owner {
+        state.ensure_owner_writable(owner)?;
+        // A held ID, or an ID of a held owner, stays with its owner
+        holds::check_id(&tx, id).map_err(hold_error)?;
+    }
+
+    if let Some(table) = &changes.table {
+        tables::check(&tx, &state.settings(), table.as_deref()).map_err(table_error)?;
+    }
+    let record = update_id_record(&tx, id, expected, &changes).map_err(update_error)?;
+
+    if !dry_run.0 {
+        tx.commit().map_err(db_error)?;
+        state.id_cache.invalidate(id);
+    }
+
+    Ok(IdDetails::from(record))
+}
+
+// POST /ids/<id>/notes {"author": "jane", "text": "..."} – appends a support note
+#[post("/ids/<id>/notes", format = "json", data = "<request>")]
+async fn add_note(
+    id: String,
+    request: Valid<NoteRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<Note>>, JsonError> {
+    let NoteRequest { author, text } = request.into_inner();
+    let author = clean_principal("author", &author)?;
+    let text = text.trim().to_string();
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        state.ensure_writable()?;
+
+        let tx = conn.transaction().map_err(db_error)?;
+        let id = casefold::stored_id(&tx, &state.settings(), &id).map_err(db_error)?;
+        state.ensure_ids_writable(&tx, std::slice::from_ref(&id))?;
+        let note = notes::add(&tx, &id, &author, &text).map_err(update_error)?;
+        if !dry_run.0 {
+            tx.commit().map_err(db_error)?;
+        }
+        Ok(note)
+    }).await.map(|note| DryRunMarked(Negotiated(note), dry_run))
+}
+
+// GET /ids/<id>/notes – all notes on an ID (live or deleted), oldest first
+#[get("/ids/<id>/notes")]
+async fn list_notes(id: String, _limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<Vec<Note>>, JsonError> {
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let id = casefold::stored_id(&conn, &state.settings(), &id).map_err(db_error)?;
+        if deleted_flag(&conn, &id)?.is_none() {
+            return Err(id_not_found(&id));
+        }
+        notes::list(&conn, &id).map_err(db_error)
+    }).await.map(Negotiated)
+}
+
+// Checks the parts of an external reference that were sent
+fn check_ref(kind: Option<&str>, label: Option<&str>, url: Option<&str>) -> Result<Option<String>, JsonError> {
+    let invalid = |details: &str| JsonError::new(Status::BadRequest, ErrorCode::BadRequest, "Invalid reference").details(details);
+
+    if let Some(Err(reason)) = label.map(validation::label) {
+        return Err(invalid(&format!("label {}", reason)));
+    }
+    if let Some(Err(reason)) = url.map(validation::url) {
+        return Err(invalid(&format!("url {}", reason)));
+    }
+    kind.map(|kind| clean_principal("kind", kind)).transpose()
+}
+
+fn ref_not_found(ref_id: i64) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Reference not found").details(ref_id)
+}
+
+// Runs a small write in a transaction, committed unless it is a dry run
+fn write_tx<T>(
+    state: &AppState,
+    conn: &mut Connection,
+    dry_run: DryRun,
+    work: impl FnOnce(&rusqlite::Transaction) -> Result<T, JsonError>,
+) -> Result<T, JsonError> {
+    state.ensure_writable()?;
+
+    let tx = conn.transaction().map_err(db_error)?;
+    let result = work(&tx)?;
+    if !dry_run.0 {
+        tx.commit().map_err(db_error)?;
+    }
+    Ok(result)
+}
+
+// GET /ids/<id>/refs – external references on an ID, oldest first
+#[get("/ids/<id>/refs")]
+async fn list_refs(id: String, _limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<Vec<ExternalRef>>, JsonError> {
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let id = casefold::stored_id(&conn, &state.settings(), &id).map_err(db_error)?;
+        if deleted_flag(&conn, &id)?.is_none() {
+            return Err(id_not_found(&id));
+        }
+        refs::list(&conn, &id).map_err(db_error)
+    }).await.map(Negotiated)
+}
+
+// POST /ids/<id>/refs {"kind": "erp_order", "label": "Order 4711", "url": "https://..."}
+#[post("/ids/<id>/refs", format = "json", data = "<request>")]
+async fn add_ref(
+    id: String,
+    request: Valid<RefRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<ExternalRef>>, JsonError> {
+    let RefRequest { kind, label, url } = request.into_inner();
+    let kind = check_ref(Some(&kind), label.as_deref(), Some(&url))?.unwrap_or(kind);
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let id = casefold::stored_id(tx, &state.settings(), &id).map_err(db_error)?;
+            state.ensure_ids_writable(tx, std::slice::from_ref(&id))?;
+            refs::add(tx, &id, &kind, label.as_deref(), &url).map_err(update_error)
+        })
+    }).await.map(|added| DryRunMarked(Negotiated(added), dry_run))
+}
+
+// PUT /ids/<id>/refs/<ref_id> – changes kind, label and/or url
+#[put("/ids/<id>/refs/<ref_id>", format = "json", data = "<request>")]
+async fn update_ref(
+    id: String,
+    ref_id: i64,
+    request: Valid<RefUpdate>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<ExternalRef>>, JsonError> {
+    let RefUpdate { kind, label, url } = request.into_inner();
+    let kind = check_ref(kind.as_deref(), label.clone().flatten().as_deref(), url.as_deref())?;
+    let changes = RefChanges { kind, label, url };
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let id = casefold::stored_id(tx, &state.settings(), &id).map_err(db_error)?;
+            state.ensure_ids_writable(tx, std::slice::from_ref(&id))?;
+            refs::update(tx, &id, ref_id, &changes).map_err(db_error)?.ok_or_else(|| ref_not_found(ref_id))
+        })
+    }).await.map(|updated| DryRunMarked(Negotiated(updated), dry_run))
+}
+
+// DELETE /ids/<id>/refs/<ref_id>
+#[delete("/ids/<id>/refs/<ref_id>")]
+async fn delete_ref(
+    id: String,
+    ref_id: i64,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<DeleteResponse>>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let id = casefold::stored_id(tx, &state.settings(), &id).map_err(db_error)?;
+            state.ensure_ids_writable(tx, std::slice::from_ref(&id))?;
+            if !refs::delete(tx, &id, ref_id).map_err(db_error)? {
+                return Err(ref_not_found(ref_id));
+            }
+            Ok(DeleteResponse {
+                success: true,
+                message: format!("Reference {} removed from {}", ref_id, id),
+            })
+        })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// Failures from labels::tag: unknown label or ID
+fn label_error(e: anyhow::Error) -> JsonError {
+    match e.downcast_ref::<UnknownLabel>() {
+        Some(unknown) => label_not_found(&unknown.name),
+        None => update_error(e),
+    }
+}
+
+fn label_not_found(name: &str) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::NotFound, "Label not found").details(name)
+}
+
+// GET /labels – every label with the number of IDs carrying it
+#[get("/labels")]
+async fn list_labels(_limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<Vec<Label>>, JsonError> {
+    state.run_db(OpKind::Read, |state| {
+        let conn = state.conn()?;
+        labels::list(&conn).map_err(db_error)
+    }).await.map(Negotiated)
+}
+
+// POST /labels {"name": "recall-2024-07", "description": "..."}
+#[post("/labels", format = "json", data = "<request>")]
+async fn create_label(
+    request: Valid<LabelRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<(Status, Json<Label>)>, JsonError> {
+    let LabelRequest { name, description } = request.into_inner();
+    let name = clean_principal("name", &name)?;
+
+    let created = name.clone();
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            labels::create(tx, &created, description.as_deref()).map_err(|e| {
+                if e.downcast_ref::<rusqlite::Error>().is_some_and(is_unique_violation) {
+                    JsonError::new(Status::Conflict, ErrorCode::LabelExists, "Label already exists").details(&created)
+                } else {
+                    db_error(e)
+                }
+            })?;
+            Ok(Label { name: created.clone(), description: description.clone(), ids: 0 })
+        })
+    }).await.map(|label| DryRunMarked((Status::Created, Json(label)), dry_run))
+}
+
+// DELETE /labels/<name> – also takes the label off every ID
+#[delete("/labels/<name>")]
+async fn delete_label(
+    name: String,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<DeleteResponse>>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            if !labels::delete(tx, &name).map_err(db_error)? {
+                return Err(label_not_found(&name));
+            }
+            Ok(DeleteResponse { success: true, message: format!("Label {} deleted", name) })
+        })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// POST /labels/<name>/ids {"ids": [...]} – tags a batch of IDs at once (up to lookup_max_ids)
+#[post("/labels/<name>/ids", format = "json", data = "<request>")]
+async fn tag_ids(
+    name: String,
+    request: Valid<LookupRequest>,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<TagResponse>>, JsonError> {
+    let ids = request.into_inner().ids;
+    check_batch_size(ids.len(), state)?;
+
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let ids = ids.iter().map(|id| casefold::stored_id(tx, &state.settings(), id)).collect::<Result<Vec<_>>>().map_err(db_error)?;
+            state.ensure_ids_writable(tx, &ids)?;
+            let tagged = labels::tag(tx, &name, &ids).map_err(label_error)?;
+            Ok(TagResponse { label: name.clone(), tagged })
+        })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// GET /ids/<id>/labels
+#[get("/ids/<id>/labels")]
+async fn id_labels(id: String, _limit: RateLimited, state: &State<AppState>) -> Result<Negotiated<Vec<String>>, JsonError> {
+    state.run_db(OpKind::Read, move |state| {
+        let conn = state.conn()?;
+        let id = casefold::stored_id(&conn, &state.settings(), &id).map_err(db_error)?;
+        if deleted_flag(&conn, &id)?.is_none() {
+            return Err(id_not_found(&id));
+        }
+        labels::labels_of(&conn, &id).map_err(db_error)
+    }).await.map(Negotiated)
+}
+
+// PUT /ids/<id>/labels/<name> – tags one ID; a no-op if it already carries the label
+#[put("/ids/<id>/labels/<name>")]
+async fn tag_id(
+    id: String,
+    name: String,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<TagResponse>>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let id = casefold::stored_id(tx, &state.settings(), &id).map_err(db_error)?;
+            state.ensure_ids_writable(tx, std::slice::from_ref(&id))?;
+            let tagged = labels::tag(tx, &name, std::slice::from_ref(&id)).map_err(label_error)?;
+            Ok(TagResponse { label: name.clone(), tagged })
+        })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// DELETE /ids/<id>/labels/<name>
+#[delete("/ids/<id>/labels/<name>")]
+async fn untag_id(
+    id: String,
+    name: String,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<DeleteResponse>>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| {
+        write_tx(state, conn, dry_run, |tx| {
+            let id = casefold::stored_id(tx, &state.settings(), &id).map_err(db_error)?;
+            state.ensure_ids_writable(tx, std::slice::from_ref(&id))?;
+            if !labels::untag(tx, &name, &id).map_err(db_error)? {
+                return Err(JsonError::new(Status::NotFound, ErrorCode::NotFound, "ID does not carry this label")
+                    .details(format!("{} / {}", id, name)));
+            }
+            Ok(DeleteResponse { success: true, message: format!("Label {} removed from {}", name, id) })
+        })
+    }).await.map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+// DELETE /ids/<id> – soft delete: the row stays (so the ID is never reissued) but reads treat it as gone.
+// Deleted supersedes confirmed, so the confirmation is cleared with it
+#[delete("/ids/<id>")]
+async fn delete_id(
+    id: String,
+    dry_run: DryRun,
+    _limit: RateLimited,
+    state: &State<AppState>,
+) -> Result<DryRunMarked<Negotiated<DeleteResponse>>, JsonError> {
+    state.run_write(OpKind::Write, move |state, conn| soft_delete(&id, dry_run, state, conn))
+        .await
+        .map(|response| DryRunMarked(Negotiated(response), dry_run))
+}
+
+fn soft_delete(id: &str, dry_run: DryRun, state: &AppState, conn: &mut Connection) -> Result<DeleteResponse, JsonError> {
+    state.ensure_writable()?;
+
+    let tx = conn.transaction().map_err(db_error)?;
+    let id = &casefold::stored_id(&tx, &state.settings(), id).map_err(db_error)?;
+    state.ensure_ids_writable(&tx, &[id.to_string()])?;
+
+    holds::check_id(&tx, id).map_err(hold_error)?;
+    let rows_affected = tx.execute(
+        "UPDATE ids SET deleted = 1, confirmed = 0, version = version + 1, updated_at = CURRENT_TIMESTAMP,
+                        deleted_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND deleted = 0",
+        [id],
+    ).map_err(db_error)?;
+
+    if rows_affected == 0 {
+        return Err(match deleted_flag(&tx, id)? {
+            Some(_) => id_deleted(id),
+            None => id_not_found(id),
+        });
+    }
+
+    if !dry_run.0 {
+        tx.commit().map_err(db_error)?;
+        state.id_cache.invalidate(id);
+    }
+
+    Ok(DeleteResponse {
+        success: true,
+        message: format!("ID {} deleted", id),
+    })
+}
+
+// None if the ID was never issued, otherwise whether it has been deleted
+fn deleted_flag(conn: &rusqlite::Connection, id: &str) -> Result<Option<bool>, JsonError> {
+    conn.query_row("SELECT deleted != 0 FROM ids WHERE id = ?1", [id], |row| row.get(0))
+        .optional()
+        .map_err(db_error)
+}
+
+fn id_not_found(id: &str) -> JsonError {
+    JsonError::new(Status::NotFound, ErrorCode::IdNotFound, "ID not found").details(id)
+}
+
+fn id_deleted(id: &str) -> JsonError {
+    JsonError::new(Status::Gone, ErrorCode::IdDeleted, "ID has been deleted").details(id)
+}
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{audit, changes, genstats, holds, jobs, DbPool, Settings};
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Housekeeping of data that outlives its use: finished jobs past `job_retention_hours`,
+/// and audit entries, changes feed entries and generation statistics past their retention settings.
+pub fn janitor(pool: &DbPool, settings: &Settings) -> Result<String> {
+    let conn = pool.get()?;
+    let expired = jobs::expire(&conn, settings.job_retention_hours)?;
+    let audit = audit::prune(&conn, settings.audit_retention_days, settings.audit_max_rows)?;
+    let changes = changes::prune(&conn, settings.changes_retention_days)?;
+    let stats = genstats::prune(&conn, settings.generation_stats_retention_days)?;
+    Ok(format!(
+        "{} expired job(s), {} audit record(s), {} change(s) and {} day(s) of generation statistics removed",
+        expired, audit, changes, stats
+    ))
+}
+
+/// Removes soft-deleted IDs once they have been deleted longer than their table's
+/// `deleted_retention_days`, or the setting of that name for tables without one (0 keeps
+/// them forever). Their notes, refs and labels go with them, and so do aliases pointing
+/// to them, which would resolve to nothing. Retired IDs are kept, as their alias still
+/// resolves, and so are held ones; anything purged is forgotten and could be issued
+/// again. Meant for the writer's connection.
+pub fn purge(conn: &mut Connection, settings: &Settings) -> Result<String> {
+    let tx = conn.transaction()?;
+    let ids: Vec<String> = tx
+        .prepare(&format!(
+            "SELECT i.id FROM ids i LEFT JOIN tables t ON t.name = i.table_name
+             WHERE i.deleted != 0 AND i.deleted_at IS NOT NULL
+               AND COALESCE(t.deleted_retention_days, ?1) > 0
+               AND i.deleted_at < datetime('now', '-' || COALESCE(t.deleted_retention_days, ?1) || ' days')
+               AND i.id NOT IN (SELECT alias FROM id_aliases)
+               AND {}",
+            holds::NOT_HELD
+        ))?
+        .query_map([settings.deleted_retention_days], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for id in &ids {
+        tx.execute("DELETE FROM id_notes WHERE target = ?1", [id])?;
+        tx.execute("DELETE FROM id_refs WHERE target = ?1", [id])?;
+        tx.execute("DELETE FROM id_labels WHERE id = ?1", [id])?;
+        tx.execute("DELETE FROM id_aliases WHERE id = ?1", [id])?;
+        tx.execute("DELETE FROM ids WHERE id = ?1", [id])?;
+    }
+    tx.commit()?;
+    Ok(format!("{} soft-deleted ID(s) purged", ids.len()))
+}
+
+/// Copies the WAL back into the database file and truncates it, so the WAL doesn't
+/// keep growing when readers are rarely all idle at once.
+pub fn checkpoint(pool: &DbPool) -> Result<String> {
+    let conn = pool.get()?;
+    let (busy, log, checkpointed): (i64, i64, i64) =
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    if busy != 0 {
+        anyhow::bail!("checkpoint blocked by an active reader or writer ({} of {} frames copied)", checkpointed, log);
+    }
+    Ok(format!("{} WAL frame(s) checkpointed", checkpointed))
+}
+
+const BACKUP_PREFIX: &str = "id-registry-";
+
+/// Writes a consistent copy of the database to `backup_dir` with `VACUUM INTO`, then
+/// deletes all but the newest `backup_keep` copies. With `backup_upload` set the copy
+/// is also uploaded to object storage, keeping the newest `backup_upload_keep` there.
+pub fn backup(pool: &DbPool, settings: &Settings) -> Result<String> {
+    let dir = Path::new(&settings.backup_dir);
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create backup directory {}", dir.display()))?;
+
+    let name = format!("{}{}.db", BACKUP_PREFIX, chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let path = dir.join(name);
+    pool.get()?.execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+
+    // The timestamped names sort by age
+    let mut backups: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(BACKUP_PREFIX) && n.ends_with(".db")))
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(settings.backup_keep.max(1) as usize);
+    for old in &backups[..excess] {
+        fs::remove_file(old).with_context(|| format!("Cannot remove old backup {}", old.display()))?;
+    }
+
+    let summary = format!("{} written, {} old backup(s) removed", path.display(), excess);
+    upload(settings, &path, summary)
+}
+
+#[cfg(feature = "object-storage")]
+fn upload(settings: &Settings, path: &Path, summary: String) -> Result<String> {
+    if settings.backup_upload.is_empty() {
+        return Ok(summary);
+    }
+    // The local copy is kept even when the upload fails, so say it was written
+    let uploaded = crate::offsite::upload(settings, path, BACKUP_PREFIX).with_context(|| format!("{}, but", summary))?;
+    Ok(format!("{}; {}", summary, uploaded))
+}
+
+#[cfg(not(feature = "object-storage"))]
+fn upload(_settings: &Settings, _path: &Path, summary: String) -> Result<String> {
+    Ok(summary)
+}
+
+/// A backup file in `backup_dir`, for `GET /admin/backups`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+/// The backups in `backup_dir`, newest first.
+pub fn local_backups(settings: &Settings) -> Result<Vec<BackupFile>> {
+    let dir = Path::new(&settings.backup_dir);
+    if settings.backup_dir.is_empty() || !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(BACKUP_PREFIX) && name.ends_with(".db") {
+            backups.push(BackupFile { name, size_bytes: entry.metadata()?.len() });
+        }
+    }
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
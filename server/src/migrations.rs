@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+
+use crate::{adminauth, info};
+
+// The ids and settings tables are created by the setup GUI (or by hand), so
+// everything here builds on top of that base schema. Each entry is applied
+// once, in order, and tracked through PRAGMA user_version.
+const MIGRATIONS: &[&str] = &[
+    // 1: leader lease for active/passive deployments
+    "CREATE TABLE IF NOT EXISTS leader_lease (
+         id          INTEGER PRIMARY KEY CHECK (id = 1),
+         holder      TEXT NOT NULL,
+         expires_at  INTEGER NOT NULL
+     );",
+    // 2: pre-generated, not yet assigned IDs
+    "CREATE TABLE IF NOT EXISTS pool (
+         id          TEXT PRIMARY KEY,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 3: hand-made databases may lack the primary key; uniqueness must never depend on it
+    "CREATE UNIQUE INDEX IF NOT EXISTS idx_ids_id ON ids (id);",
+    // 4: row version for optimistic locking (ETag / If-Match on PUT /ids/<id>)
+    "ALTER TABLE ids ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE ids ADD COLUMN updated_at DATETIME;",
+    // 5: who did what through the admin and API routes
+    "CREATE TABLE IF NOT EXISTS audit_log (
+         id       INTEGER PRIMARY KEY AUTOINCREMENT,
+         at       DATETIME DEFAULT CURRENT_TIMESTAMP,
+         actor    TEXT NOT NULL,
+         action   TEXT NOT NULL,
+         target   TEXT,
+         details  TEXT
+     );",
+    // 6: repair tooling: a review flag on IDs, and old IDs that now resolve to another one
+    "ALTER TABLE ids ADD COLUMN flag TEXT;
+     CREATE TABLE IF NOT EXISTS id_aliases (
+         alias       TEXT PRIMARY KEY,
+         id          TEXT NOT NULL,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 7: catalog of known table_name values
+    "CREATE TABLE IF NOT EXISTS tables (
+         name         TEXT PRIMARY KEY,
+         description  TEXT,
+         active       INTEGER NOT NULL DEFAULT 1,
+         created_at   DATETIME DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 8: when and by whom each ID was confirmed
+    "ALTER TABLE ids ADD COLUMN confirmed_at DATETIME;
+     ALTER TABLE ids ADD COLUMN confirmed_by TEXT;",
+    // 9: append-only support notes
+    "CREATE TABLE IF NOT EXISTS id_notes (
+         seq     INTEGER PRIMARY KEY AUTOINCREMENT,
+         target  TEXT NOT NULL,
+         at      DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+         author  TEXT NOT NULL,
+         text    TEXT NOT NULL
+     );
+     CREATE INDEX IF NOT EXISTS idx_id_notes_target ON id_notes (target, seq);",
+    // 10: links from IDs to records in other systems
+    "CREATE TABLE IF NOT EXISTS id_refs (
+         ref_id      INTEGER PRIMARY KEY AUTOINCREMENT,
+         target      TEXT NOT NULL,
+         kind        TEXT NOT NULL,
+         label       TEXT,
+         url         TEXT NOT NULL,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+     );
+     CREATE INDEX IF NOT EXISTS idx_id_refs_target ON id_refs (target, ref_id);",
+    // 11: labels, many-to-many with ids
+    "CREATE TABLE IF NOT EXISTS labels (
+         name         TEXT PRIMARY KEY,
+         description  TEXT,
+         created_at   DATETIME DEFAULT CURRENT_TIMESTAMP
+     );
+     CREATE TABLE IF NOT EXISTS id_labels (
+         id          TEXT NOT NULL,
+         label       TEXT NOT NULL,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP,
+         PRIMARY KEY (id, label)
+     );
+     CREATE INDEX IF NOT EXISTS idx_id_labels_label ON id_labels (label, id);",
+    // 12: collision retries per day, to watch keyspace pressure
+    "CREATE TABLE IF NOT EXISTS generation_stats (
+         day          TEXT PRIMARY KEY,
+         generations  INTEGER NOT NULL,
+         retries      INTEGER NOT NULL,
+         max_retries  INTEGER NOT NULL
+     );",
+    // 13: background jobs; results are kept as NDJSON chunks in order
+    "CREATE TABLE IF NOT EXISTS jobs (
+         job_id       TEXT PRIMARY KEY,
+         kind         TEXT NOT NULL,
+         status       TEXT NOT NULL DEFAULT 'running',
+         total        INTEGER,
+         done         INTEGER NOT NULL DEFAULT 0,
+         error        TEXT,
+         created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+         finished_at  DATETIME
+     );
+     CREATE TABLE IF NOT EXISTS job_chunks (
+         job_id  TEXT NOT NULL,
+         seq     INTEGER NOT NULL,
+         body    TEXT NOT NULL,
+         PRIMARY KEY (job_id, seq)
+     );",
+    // 14: every id_length / charset combination the server has generated with
+    "CREATE TABLE IF NOT EXISTS generation_configs (
+         version     INTEGER PRIMARY KEY,
+         id_length   INTEGER NOT NULL,
+         charset     TEXT NOT NULL,
+         created_at  DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+     );
+     INSERT INTO generation_configs (version, id_length, charset)
+         SELECT 1, l.value, c.value FROM settings l, settings c
+         WHERE l.key = 'id_length' AND c.key = 'charset';",
+    // 15: the generation config each ID was issued under; NULL for IDs from before versioning
+    "ALTER TABLE ids ADD COLUMN config_version INTEGER;",
+    // 16: soft-delete retention: when each ID was deleted, and per-table overrides of the window
+    "ALTER TABLE ids ADD COLUMN deleted_at DATETIME;
+     UPDATE ids SET deleted_at = COALESCE(updated_at, created_at) WHERE deleted != 0;
+     ALTER TABLE tables ADD COLUMN deleted_retention_days INTEGER;",
+    // 17: legal holds on single IDs or whole owners
+    "CREATE TABLE IF NOT EXISTS holds (
+         kind        TEXT NOT NULL,
+         target      TEXT NOT NULL,
+         reason      TEXT,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP,
+         PRIMARY KEY (kind, target)
+     );",
+    // 18: the changes feed, written by triggers so every path that touches ids is covered
+    "CREATE TABLE IF NOT EXISTS id_changes (
+         seq      INTEGER PRIMARY KEY AUTOINCREMENT,
+         id       TEXT NOT NULL,
+         op       TEXT NOT NULL,
+         version  INTEGER NOT NULL,
+         at       DATETIME DEFAULT CURRENT_TIMESTAMP
+     );
+     CREATE TRIGGER IF NOT EXISTS ids_created AFTER INSERT ON ids BEGIN
+         INSERT INTO id_changes (id, op, version) VALUES (NEW.id, 'created', NEW.version);
+     END;
+     CREATE TRIGGER IF NOT EXISTS ids_updated AFTER UPDATE ON ids BEGIN
+         INSERT INTO id_changes (id, op, version) VALUES (
+             NEW.id,
+             CASE WHEN NEW.deleted != 0 AND OLD.deleted = 0 THEN 'deleted'
+                  WHEN NEW.confirmed != 0 AND OLD.confirmed = 0 THEN 'confirmed'
+                  ELSE 'updated' END,
+             NEW.version);
+     END;
+     CREATE TRIGGER IF NOT EXISTS ids_removed AFTER DELETE ON ids BEGIN
+         INSERT INTO id_changes (id, op, version) VALUES (OLD.id, 'removed', OLD.version);
+     END;",
+    // 19: the caller's natural key, making POST /ids idempotent per (owner, external_ref)
+    "ALTER TABLE ids ADD COLUMN external_ref TEXT;
+     CREATE UNIQUE INDEX IF NOT EXISTS idx_ids_owner_external_ref ON ids (owner, external_ref)
+         WHERE external_ref IS NOT NULL;",
+    // 20: hourly generation counts per owner for GET /stats/timeseries, kept by a trigger
+    "CREATE TABLE IF NOT EXISTS generation_volume (
+         hour   TEXT NOT NULL,
+         owner  TEXT NOT NULL,
+         ids    INTEGER NOT NULL,
+         PRIMARY KEY (hour, owner)
+     );
+     INSERT INTO generation_volume (hour, owner, ids)
+         SELECT strftime('%Y-%m-%d %H:00', created_at), owner, COUNT(*) FROM ids
+         WHERE created_at IS NOT NULL GROUP BY 1, 2;
+     CREATE TRIGGER IF NOT EXISTS ids_counted AFTER INSERT ON ids BEGIN
+         INSERT INTO generation_volume (hour, owner, ids)
+             VALUES (strftime('%Y-%m-%d %H:00', COALESCE(NEW.created_at, CURRENT_TIMESTAMP)), NEW.owner, 1)
+             ON CONFLICT (hour, owner) DO UPDATE SET ids = ids + 1;
+     END;",
+    // 21: canary IDs, which are never issued and alert when looked up
+    "CREATE TABLE IF NOT EXISTS canaries (
+         id          TEXT PRIMARY KEY,
+         note        TEXT,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 22: the counter behind generation_mode = sequence
+    "CREATE TABLE IF NOT EXISTS id_sequence (
+         id    INTEGER PRIMARY KEY CHECK (id = 1),
+         next  INTEGER NOT NULL
+     );
+     INSERT OR IGNORE INTO id_sequence (id, next) VALUES (1, 0);",
+    // 23: per-owner keys, which confirming an owner's IDs requires once set
+    "CREATE TABLE IF NOT EXISTS owner_keys (
+         owner       TEXT PRIMARY KEY,
+         key_hash    TEXT NOT NULL,
+         created_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+     );",
+    // 24: one sequence counter per random-part length; id_sequence seeds the lengths not used since
+    "CREATE TABLE IF NOT EXISTS id_sequences (
+         random_length  INTEGER PRIMARY KEY,
+         next           INTEGER NOT NULL
+     );",
+    // 25: generation configs for IDs generation lengthened; 0 for the configs as set
+    "ALTER TABLE generation_configs ADD COLUMN extra_length INTEGER NOT NULL DEFAULT 0;",
+    // 26: the id_content_policy of each generation config; the configs so far get the current one
+    "ALTER TABLE generation_configs ADD COLUMN content_policy TEXT NOT NULL DEFAULT '';
+     UPDATE generation_configs SET content_policy =
+         COALESCE((SELECT value FROM settings WHERE key = 'id_content_policy'), 'no_all_digits');",
+    // 27: consistency_interval_secs gave way to consistency_schedule; a disabled check stays disabled
+    "INSERT OR IGNORE INTO settings (key, value)
+         SELECT 'consistency_schedule', '' FROM settings WHERE key = 'consistency_interval_secs' AND TRIM(value) = '0';
+     DELETE FROM settings WHERE key = 'consistency_interval_secs';",
+    // 28: the generation_mode of each generation config; the configs so far get the current one
+    "ALTER TABLE generation_configs ADD COLUMN generation_mode TEXT NOT NULL DEFAULT 'random';
+     UPDATE generation_configs SET generation_mode =
+         COALESCE((SELECT TRIM(value) FROM settings WHERE key = 'generation_mode'), 'random');",
+];
+
+// Indexes the query paths rely on. Unlike migrations these are re-checked on every
+// start, so one dropped by hand (or lost in a restore) comes back.
+const INDEXES: &[&str] = &[
+    "CREATE UNIQUE INDEX IF NOT EXISTS idx_ids_id ON ids (id);",
+    "CREATE INDEX IF NOT EXISTS idx_ids_owner_created ON ids (owner, created_at);",
+    "CREATE INDEX IF NOT EXISTS idx_ids_table_name ON ids (table_name);",
+    // Sorted listings page on (key, id)
+    "CREATE INDEX IF NOT EXISTS idx_ids_created_id ON ids (created_at, id);",
+    "CREATE INDEX IF NOT EXISTS idx_ids_owner_id ON ids (owner, id);",
+    "CREATE INDEX IF NOT EXISTS idx_ids_unconfirmed ON ids (created_at) WHERE confirmed = 0 AND deleted = 0;",
+    // The purge task looks for soft-deleted rows by age
+    "CREATE INDEX IF NOT EXISTS idx_ids_deleted_at ON ids (deleted_at) WHERE deleted != 0;",
+    // One ID's history, /changes?id=
+    "CREATE INDEX IF NOT EXISTS idx_id_changes_id ON id_changes (id, seq);",
+];
+
+/// The schema version [`run`] brings a database to.
+pub fn latest() -> usize {
+    MIGRATIONS.len()
+}
+
+// The unique index on ids.id (migration 3, and INDEXES on every start) can't be created
+// while an ID has more than one row; says which, and how to get out of it, instead of
+// failing on the bare constraint error
+fn check_unique_ids(conn: &Connection) -> Result<()> {
+    let (has_ids, indexed): (bool, bool) = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'ids'),
+                EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'idx_ids_id')",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if !has_ids || indexed {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare("SELECT id FROM ids GROUP BY id HAVING COUNT(*) > 1 ORDER BY id")?;
+    let duplicated = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    if duplicated.is_empty() {
+        return Ok(());
+    }
+    let examples: Vec<&str> = duplicated.iter().take(5).map(String::as_str).collect();
+    anyhow::bail!(
+        "{} ID(s) have more than one row in ids (e.g. {}), so the unique index on ids.id cannot be created. \
+         Rename all but one row of each with sqlite3 to something outside the charset, e.g. \
+         UPDATE ids SET id = id || '~2' WHERE rowid = ..., then start the server, list them with \
+         POST /admin/find_duplicates and give them new IDs with POST /admin/repair_ids (reissue)",
+        duplicated.len(),
+        examples.join(", ")
+    )
+}
+
+/// Creates the base schema the setup GUI would, for a database that has none (the
+/// `--dev` fallback). Returns the generated admin secret if it had to add one.
+pub fn bootstrap(conn: &Connection) -> Result<Option<String>> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ids (
+             id          TEXT PRIMARY KEY,
+             owner       TEXT NOT NULL,
+             table_name  TEXT,
+             user_id     TEXT,
+             confirmed   INTEGER DEFAULT 0,
+             created_at  DATETIME DEFAULT CURRENT_TIMESTAMP,
+             deleted     INTEGER DEFAULT 0
+         );
+         CREATE TABLE IF NOT EXISTS settings (
+             key    TEXT PRIMARY KEY,
+             value  TEXT
+         );
+         -- Only a new database refuses a small keyspace; older ones default to warn
+         INSERT INTO settings (key, value) SELECT 'min_keyspace_mode', 'refuse' WHERE NOT EXISTS (SELECT 1 FROM settings);
+         INSERT OR IGNORE INTO settings (key, value) VALUES ('id_length', '12');
+         INSERT OR IGNORE INTO settings (key, value)
+             VALUES ('charset', 'abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789');",
+    )?;
+
+    let secret = adminauth::generate_secret();
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO settings (key, value) VALUES ('admin_secret', ?1)",
+        [adminauth::hash_secret(&secret)],
+    )?;
+    Ok((added > 0).then_some(secret))
+}
+
+/// Brings the database schema up to date with this server version.
+pub fn run(conn: &mut Connection) -> Result<()> {
+    let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    check_unique_ids(conn)?;
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)
+            .with_context(|| format!("Migration {} failed", version))?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        info!("Applied schema migration {}", version);
+    }
+
+    for sql in INDEXES {
+        conn.execute_batch(sql)
+            .with_context(|| format!("Failed to create index: {}", sql))?;
+    }
+    // Refreshes planner statistics where they are stale; cheap when nothing changed
+    conn.execute_batch("PRAGMA optimize;")?;
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::response::{self, Responder};
+use rocket::Request;
+use serde::Serialize;
+use id_registry_server::error;
+
+/// Response body encoded according to the request's Accept header:
+/// MessagePack, CBOR or Protobuf when asked for, JSON otherwise.
+pub struct Negotiated<T>(pub T);
+
+/// Protobuf encoding for a response type. Types without a message in
+/// proto/id_registry.proto keep the default and answer 406 to Protobuf requests.
+pub trait ProtoEncode {
+    fn encode_proto(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+enum Format {
+    Json,
+    MsgPack,
+    Cbor,
+    Protobuf,
+}
+
+fn format_of(media: &MediaType) -> Option<Format> {
+    if media.top() != "application" {
+        return None;
+    }
+    match media.sub().as_str() {
+        "msgpack" | "x-msgpack" => Some(Format::MsgPack),
+        "cbor" => Some(Format::Cbor),
+        "x-protobuf" | "protobuf" => Some(Format::Protobuf),
+        "json" => Some(Format::Json),
+        _ => None,
+    }
+}
+
+// Highest-weighted format we can produce; JSON when nothing matches
+fn preferred_format(req: &Request<'_>) -> Format {
+    let Some(accept) = req.accept() else {
+        return Format::Json;
+    };
+
+    let mut best: Option<(f32, Format)> = None;
+    for media in accept.iter() {
+        let weight = media.weight_or(1.0);
+        if let Some(format) = format_of(media.media_type())
+            && best.as_ref().is_none_or(|(w, _)| weight > *w)
+        {
+            best = Some((weight, format));
+        }
+    }
+
+    best.map(|(_, format)| format).unwrap_or(Format::Json)
+}
+
+impl<'r, T: Serialize + ProtoEncode> Responder<'r, 'static> for Negotiated<T> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let (content_type, body) = match preferred_format(req) {
+            Format::MsgPack => (
+                ContentType(MediaType::MsgPack),
+                rmp_serde::to_vec_named(&self.0).map_err(|e| {
+                    error!("MessagePack encoding failed: {}", e);
+                    Status::InternalServerError
+                })?,
+            ),
+            Format::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(&self.0, &mut buf).map_err(|e| {
+                    error!("CBOR encoding failed: {}", e);
+                    Status::InternalServerError
+                })?;
+                (ContentType::new("application", "cbor"), buf)
+            }
+            Format::Protobuf => (
+                ContentType::new("application", "x-protobuf"),
+                self.0.encode_proto().ok_or(Status::NotAcceptable)?,
+            ),
+            Format::Json => (
+                ContentType::JSON,
+                serde_json::to_vec(&self.0).map_err(|e| {
+                    error!("JSON encoding failed: {}", e);
+                    Status::InternalServerError
+                })?,
+            ),
+        };
+
+        // The body depends on Accept, so shared caches must not hand one format to another client
+        response::Response::build()
+            .header(content_type)
+            .raw_header_adjoin("Vary", "Accept")
+            .sized_body(body.len(), std::io::Cursor::new(body))
+            .ok()
+    }
+}
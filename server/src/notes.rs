@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::{Connection, Row};
+use serde::Serialize;
+
+use crate::IdNotFound;
+
+/// Longest note text accepted, in characters.
+pub const MAX_NOTE_CHARS: usize = 2000;
+
+/// A support note on an ID. Notes are append-only: there is no way to edit or remove one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Note {
+    pub at: String,
+    pub author: String,
+    pub text: String,
+}
+
+fn note_from_row(row: &Row<'_>) -> rusqlite::Result<Note> {
+    Ok(Note {
+        at: row.get(0)?,
+        author: row.get(1)?,
+        text: row.get(2)?,
+    })
+}
+
+/// Adds a note to `id`, which may be live or deleted but must have been issued.
+pub fn add(conn: &Connection, id: &str, author: &str, text: &str) -> Result<Note> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM ids WHERE id = ?1)", [id], |row| row.get(0))?;
+    if !exists {
+        return Err(IdNotFound { id: id.to_string() }.into());
+    }
+
+    let note = conn.query_row(
+        "INSERT INTO id_notes (target, author, text) VALUES (?1, ?2, ?3) RETURNING at, author, text",
+        [id, author, text],
+        note_from_row,
+    )?;
+    Ok(note)
+}
+
+/// All notes on `id`, oldest first.
+pub fn list(conn: &Connection, id: &str) -> Result<Vec<Note>> {
+    let mut stmt = conn.prepare("SELECT at, author, text FROM id_notes WHERE target = ?1 ORDER BY seq")?;
+    let notes = stmt.query_map([id], note_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(notes)
+}
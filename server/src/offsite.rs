@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Copies backups to object storage (S3-compatible or Azure Blob) so they don't live
+//! on the same disk as the database. Only built with the `object-storage` feature.
+use crate::Settings;
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// Uploads the backup at `path`, then deletes all but the newest `backup_upload_keep`
+/// uploaded backups. Returns a one-line summary.
+pub fn upload(settings: &Settings, path: &Path, backup_prefix: &str) -> Result<String> {
+    let file_name = path.file_name().and_then(|n| n.to_str()).context("Backup has no file name")?;
+    let key = format!("{}{}", settings.backup_upload_prefix, file_name);
+    let body = fs::read(path).with_context(|| format!("Cannot read backup {}", path.display()))?;
+
+    let store = Store::from_settings(settings)?;
+    store.put(&key, &body).with_context(|| format!("Upload of {} failed", key))?;
+
+    // The timestamped names sort by age
+    let mut uploaded: Vec<String> = list(settings)?
+        .into_iter()
+        .filter(|k| k.strip_prefix(&settings.backup_upload_prefix).is_some_and(|n| n.starts_with(backup_prefix) && n.ends_with(".db")))
+        .collect();
+    uploaded.sort();
+    let excess = uploaded.len().saturating_sub(settings.backup_upload_keep.max(1) as usize);
+    for old in &uploaded[..excess] {
+        store.delete(old).with_context(|| format!("Cannot delete old upload {}", old))?;
+    }
+
+    Ok(format!("uploaded to {} as {}, {} old upload(s) removed", store.name(), key, excess))
+}
+
+/// Keys of the objects under `backup_upload_prefix`.
+pub fn list(settings: &Settings) -> Result<Vec<String>> {
+    Store::from_settings(settings)?.list(&settings.backup_upload_prefix)
+}
+
+enum Store<'a> {
+    S3 { url: &'a str, region: &'a str, access_key: &'a str, secret_key: &'a str },
+    Azure { url: &'a str, sas: &'a str },
+}
+
+impl<'a> Store<'a> {
+    fn from_settings(settings: &'a Settings) -> Result<Self> {
+        let url = settings.backup_upload_url.trim_end_matches('/');
+        match settings.backup_upload.as_str() {
+            "s3" => Ok(Store::S3 {
+                url,
+                region: &settings.backup_s3_region,
+                access_key: &settings.backup_s3_access_key,
+                secret_key: &settings.backup_s3_secret_key,
+            }),
+            "azure" => Ok(Store::Azure { url, sas: settings.backup_azure_sas.trim_start_matches('?') }),
+            other => bail!("Unknown backup_upload target '{}'", other),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Store::S3 { .. } => "S3",
+            Store::Azure { .. } => "Azure Blob",
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8]) -> Result<()> {
+        match self {
+            Store::S3 { .. } => self.s3_request("PUT", key, &[], body)?,
+            Store::Azure { url, sas } => {
+                ureq::put(&format!("{}/{}?{}", url, encode_path(key), sas))
+                    .set("x-ms-blob-type", "BlockBlob")
+                    .send_bytes(body)?
+            }
+        };
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match self {
+            Store::S3 { .. } => self.s3_request("DELETE", key, &[], &[])?,
+            Store::Azure { url, sas } => ureq::delete(&format!("{}/{}?{}", url, encode_path(key), sas)).call()?,
+        };
+        Ok(())
+    }
+
+    // One page of results is enough: only the last few backups are ever kept
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let (xml, tag) = match self {
+            Store::S3 { .. } => (
+                self.s3_request("GET", "", &[("list-type", "2"), ("prefix", prefix)], &[])?.into_string()?,
+                "Key",
+            ),
+            Store::Azure { url, sas } => (
+                ureq::get(&format!("{}?restype=container&comp=list&prefix={}&{}", url, encode(prefix), sas))
+                    .call()?
+                    .into_string()?,
+                "Name",
+            ),
+        };
+        Ok(xml_values(&xml, tag))
+    }
+
+    // A path-style S3 request signed with AWS Signature Version 4
+    fn s3_request(&self, method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response> {
+        let Store::S3 { url, region, access_key, secret_key } = self else {
+            unreachable!("s3_request on a non-S3 store");
+        };
+        let Some((scheme, (host, bucket))) = url.split_once("://").and_then(|(s, rest)| Some((s, rest.split_once('/')?))) else {
+            bail!("backup_upload_url must look like https://host/bucket");
+        };
+        let bucket_path = format!("/{}", bucket);
+
+        let path = if key.is_empty() { bucket_path } else { format!("{}/{}", bucket_path, encode_path(key)) };
+        let mut params: Vec<(String, String)> = query.iter().map(|(k, v)| (encode(k), encode(v))).collect();
+        params.sort();
+        let query_string = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(body));
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, query_string, host, payload_hash, amz_date, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut signing_key = hmac(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+        for part in [region.as_bytes(), b"s3", b"aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+        let mut target = format!("{}://{}{}", scheme, host, path);
+        if !query_string.is_empty() {
+            target = format!("{}?{}", target, query_string);
+        }
+        let request = ureq::request(method, &target)
+            .set("x-amz-date", &amz_date)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("Authorization", &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                access_key, scope, signature
+            ));
+        Ok(if body.is_empty() { request.call()? } else { request.send_bytes(body)? })
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// RFC 3986 percent-encoding of everything but unreserved characters, as SigV4 expects
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Like encode, keeping the slashes of an object key
+fn encode_path(key: &str) -> String {
+    key.split('/').map(encode).collect::<Vec<_>>().join("/")
+}
+
+// The text of every <tag>...</tag> element; the listings are simple enough not to need a parser
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close).map(|(value, _)| value.replace("&amp;", "&")))
+        .collect()
+}
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Operations on owners as a whole, e.g. after a team is renamed or reorganized.
+use anyhow::Result;
+
+use crate::{adminauth, genstats};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+
+/// What merging one owner into another moved.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeSummary {
+    pub from: String,
+    pub to: String,
+    pub ids: usize,
+    /// Whether `from` was under a legal hold, which now covers `to`
+    pub hold_moved: bool,
+}
+
+/// Returned (inside anyhow::Error) when both owners used the same external_ref, which
+/// must stay unique per owner.
+#[derive(Debug)]
+pub struct ExternalRefClash {
+    pub external_refs: Vec<String>,
+}
+
+impl std::fmt::Display for ExternalRefClash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Both owners have IDs for external_ref {}", self.external_refs.join(", "))
+    }
+}
+
+impl std::error::Error for ExternalRefClash {}
+
+/// Reassigns every ID of `from`, deleted ones included, to `to`. A hold on `from` is
+/// carried over to `to`, so nothing it covered becomes deletable, as are its generation
+/// volumes. Fails with ExternalRefClash, changing nothing, if an external_ref is in use
+/// by both. Run it in a transaction.
+pub fn merge(conn: &Connection, from: &str, to: &str) -> Result<MergeSummary> {
+    if from == to {
+        anyhow::bail!("Cannot merge owner '{}' into itself", from);
+    }
+
+    let external_refs: Vec<String> = conn
+        .prepare(
+            "SELECT f.external_ref FROM ids f JOIN ids t ON t.owner = ?2 AND t.external_ref = f.external_ref
+             WHERE f.owner = ?1 ORDER BY f.external_ref LIMIT 10",
+        )?
+        .query_map([from, to], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    if !external_refs.is_empty() {
+        return Err(ExternalRefClash { external_refs }.into());
+    }
+
+    let ids = conn.execute(
+        "UPDATE ids SET owner = ?2, version = version + 1, updated_at = CURRENT_TIMESTAMP WHERE owner = ?1",
+        [from, to],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO holds (kind, target, reason)
+         SELECT kind, ?2, reason FROM holds WHERE kind = 'owner' AND target = ?1",
+        [from, to],
+    )?;
+    let hold_moved = conn.execute("DELETE FROM holds WHERE kind = 'owner' AND target = ?1", [from])? > 0;
+    genstats::move_owner(conn, from, to)?;
+
+    Ok(MergeSummary { from: from.to_string(), to: to.to_string(), ids, hold_moved })
+}
+
+/// Gives `owner` a new random key, replacing any earlier one, and returns it. Only its
+/// hash is stored, so this is the one time it can be read.
+pub fn set_key(conn: &Connection, owner: &str) -> Result<String> {
+    let key = adminauth::generate_secret();
+    conn.execute(
+        "INSERT INTO owner_keys (owner, key_hash) VALUES (?1, ?2)
+         ON CONFLICT (owner) DO UPDATE SET key_hash = excluded.key_hash, created_at = CURRENT_TIMESTAMP",
+        [owner, &adminauth::hash_secret(&key)],
+    )?;
+    Ok(key)
+}
+
+/// Removes `owner`'s key; false if it had none.
+pub fn remove_key(conn: &Connection, owner: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM owner_keys WHERE owner = ?1", [owner])? > 0)
+}
+
+/// The owner key a confirm request presented, and whether owners without a key are
+/// closed (confirm_requires_owner).
+#[derive(Debug, Clone, Copy)]
+pub struct KeyCheck<'a> {
+    pub key: Option<&'a str>,
+    pub required: bool,
+}
+
+impl KeyCheck<'_> {
+    /// Whether the caller may confirm `owner`'s IDs: an owner with a key needs it, one
+    /// without is open unless `required`.
+    pub fn allows(&self, conn: &Connection, owner: &str) -> Result<bool> {
+        may_confirm(conn, owner, self.key, self.required)
+    }
+}
+
+fn may_confirm(conn: &Connection, owner: &str, key: Option<&str>, required: bool) -> Result<bool> {
+    let stored: Option<String> = conn
+        .query_row("SELECT key_hash FROM owner_keys WHERE owner = ?1", [owner], |row| row.get(0))
+        .optional()?;
+    Ok(match stored {
+        Some(stored) => key.is_some_and(|key| adminauth::verify_secret(&stored, key)),
+        None => !required,
+    })
+}
@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! `--check`: everything the server needs to start, checked without starting it or
+//! changing the database, so a deployment pipeline can stop before swapping the binary.
+//! The report is printed as JSON and summed up in the exit code.
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{check_keyspace, db_path, load_settings, migrations, stage_env_settings, Profile, DEV_DB_PATH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    /// Not checked, because of an earlier check
+    Skipped,
+    /// The server would start, but something needs a look
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// No check failed
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// 0 when every check passed, 1 with warnings only, 2 when a check failed.
+    pub fn exit_code(&self) -> i32 {
+        match self.checks.iter().map(|check| check.outcome).max() {
+            Some(Outcome::Fail) => 2,
+            Some(Outcome::Warn) => 1,
+            _ => 0,
+        }
+    }
+}
+
+// Every check in order, so those not reached can be reported as skipped
+const CHECKS: [&str; 7] = ["profile", "db_path", "database", "schema", "settings", "write_access", "wal"];
+
+struct Checks(Vec<Check>);
+
+impl Checks {
+    fn push(&mut self, name: &'static str, outcome: Outcome, detail: impl Into<String>) {
+        self.0.push(Check { name, outcome, detail: detail.into() });
+    }
+
+    fn fail(mut self, name: &'static str, detail: impl Into<String>) -> Report {
+        self.push(name, Outcome::Fail, detail);
+        self.finish("an earlier check failed")
+    }
+
+    // Marks the checks not run yet as skipped, for `reason`
+    fn finish(mut self, reason: &str) -> Report {
+        for name in CHECKS.iter().skip(self.0.len()) {
+            self.push(name, Outcome::Skipped, reason);
+        }
+        let ready = self.0.iter().all(|check| check.outcome != Outcome::Fail);
+        Report { ready, checks: self.0 }
+    }
+}
+
+/// Runs the checks in order: profile, database path, opening the database, its schema
+/// version, the settings (with `IDREG_*` applied in the container profile), write
+/// access to the file and its directory, and WAL mode, tried on a scratch file when the
+/// database is not in it yet.
+pub fn run() -> Report {
+    let mut checks = Checks(Vec::new());
+
+    let profile = match Profile::detect() {
+        Ok(profile) => profile,
+        Err(e) => return checks.fail("profile", format!("{:#}", e)),
+    };
+    checks.push("profile", Outcome::Ok, format!("{:?}", profile).to_lowercase());
+
+    let path = match db_path(profile) {
+        Ok(path) => path,
+        Err(e) => return checks.fail("db_path", format!("{:#}", e)),
+    };
+    checks.push("db_path", Outcome::Ok, path.clone());
+
+    if !Path::new(&path).is_file() {
+        // Where the server creates the database itself (see create_db_pool)
+        if profile == Profile::Container || path == DEV_DB_PATH {
+            checks.push("database", Outcome::Warn, format!("{} does not exist yet; it is created on first start", path));
+            return checks.finish("no database yet");
+        }
+        return checks.fail("database", format!("no database file at {}", path));
+    }
+    // Without CREATE, so a mistyped path doesn't leave an empty file behind
+    let mut conn = match Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_WRITE) {
+        Ok(conn) => conn,
+        Err(e) => return checks.fail("database", format!("cannot open {}: {}", path, e)),
+    };
+    let _ = conn.busy_timeout(Duration::from_secs(2));
+    if let Err(e) = conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)) {
+        return checks.fail("database", format!("not a readable SQLite database: {}", e));
+    }
+    checks.push("database", Outcome::Ok, "opened");
+
+    let version: usize = match conn.query_row("PRAGMA user_version", [], |row| row.get(0)) {
+        Ok(version) => version,
+        Err(e) => return checks.fail("schema", e.to_string()),
+    };
+    let latest = migrations::latest();
+    if version > latest {
+        return checks.fail("schema", format!("schema version {} is newer than this server's {}", version, latest));
+    }
+    let pending = match latest - version {
+        0 => String::new(),
+        pending => format!("; {} migration(s) are applied at start", pending),
+    };
+    checks.push("schema", Outcome::Ok, format!("version {}{}", version, pending));
+
+    // Staged in a transaction that is dropped, so nothing is stored
+    let settings = match conn.transaction() {
+        Ok(tx) => {
+            let applied = if profile == Profile::Container { stage_env_settings(&tx) } else { Ok(Vec::new()) };
+            applied.and_then(|_| load_settings(&tx))
+        }
+        Err(e) => Err(e.into()),
+    };
+    match settings {
+        Ok(settings) => match settings.keyspace_shortfall() {
+            Some(_) if let Err(e) = check_keyspace(&settings) => return checks.fail("settings", format!("{:#}", e)),
+            Some(shortfall) => checks.push("settings", Outcome::Warn, shortfall),
+            None => checks.push(
+                "settings",
+                Outcome::Ok,
+                format!("id_length {}, {} charset characters", settings.id_length, settings.charset.chars().count()),
+            ),
+        },
+        Err(e) => return checks.fail("settings", format!("{:#}", e)),
+    }
+
+    match write_access(&conn, &path) {
+        Ok(None) => checks.push("write_access", Outcome::Ok, "the database and its directory are writable"),
+        Ok(Some(busy)) => checks.push("write_access", Outcome::Warn, busy),
+        Err(e) => return checks.fail("write_access", e),
+    }
+
+    // The server switches every connection to WAL; a file system that can't do it
+    // (some network shares) leaves the old mode in place. Switching is a change to the
+    // file, so a database not in WAL yet is only read, and WAL is tried on a scratch file
+    match conn.query_row("PRAGMA journal_mode", [], |row| row.get::<_, String>(0)) {
+        Ok(mode) if mode.eq_ignore_ascii_case("wal") => checks.push("wal", Outcome::Ok, "journal_mode is wal"),
+        Ok(mode) => match wal_supported(&path) {
+            Ok(true) => checks.push("wal", Outcome::Ok, format!("journal_mode is {}; the server switches it to wal", mode)),
+            Ok(false) => return checks.fail("wal", format!("journal_mode is {} and WAL is not supported here", mode)),
+            Err(e) => return checks.fail("wal", format!("cannot try WAL next to the database: {}", e)),
+        },
+        Err(e) => return checks.fail("wal", e.to_string()),
+    }
+
+    checks.finish("")
+}
+
+// Whether a database next to `path` can switch to WAL, tried on a scratch file that is
+// removed again, with its -wal and -shm files
+fn wal_supported(path: &str) -> rusqlite::Result<bool> {
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let scratch = dir.join(format!(".idreg-check-{}.db", std::process::id()));
+    let result = Connection::open(&scratch).and_then(|conn| {
+        let mode = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.execute_batch("CREATE TABLE probe (x); INSERT INTO probe VALUES (1);")?;
+        Ok(mode.eq_ignore_ascii_case("wal"))
+    });
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = scratch.clone().into_os_string();
+        file.push(suffix);
+        let _ = std::fs::remove_file(file);
+    }
+    result
+}
+
+// Takes and releases the write lock, and creates a file next to the database as SQLite
+// does for the WAL. A lock held by a running server is only worth a warning
+fn write_access(conn: &Connection, path: &str) -> Result<Option<String>, String> {
+    if conn.is_readonly(rusqlite::DatabaseName::Main).unwrap_or(false) {
+        return Err("the database file is read-only".to_string());
+    }
+    match conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;") {
+        Ok(()) => {}
+        Err(rusqlite::Error::SqliteFailure(e, _)) if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+            return Ok(Some("the database is locked by another writer, likely the running server".to_string()));
+        }
+        Err(e) => return Err(format!("cannot write to the database: {}", e)),
+    }
+
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let probe = dir.join(format!(".idreg-check-{}", std::process::id()));
+    std::fs::write(&probe, b"").map_err(|e| format!("cannot create files in {}: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(None)
+}
@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Protobuf messages from `proto/id_registry.proto` (package idregistry.v1).
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenerateRequest {
+    #[prost(string, tag = "1")]
+    pub owner: String,
+    #[prost(string, optional, tag = "2")]
+    pub table: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub suffix: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub external_ref: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IdDetails {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub owner: String,
+    #[prost(string, optional, tag = "3")]
+    pub table: Option<String>,
+    #[prost(int32, tag = "4")]
+    pub confirmed: i32,
+    #[prost(string, tag = "5")]
+    pub created_at: String,
+    #[prost(int64, tag = "6")]
+    pub version: i64,
+    #[prost(string, optional, tag = "7")]
+    pub confirmed_at: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub confirmed_by: Option<String>,
+    #[prost(int64, optional, tag = "9")]
+    pub config_version: Option<i64>,
+    #[prost(string, optional, tag = "10")]
+    pub external_ref: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    pub token: Option<String>,
+    #[prost(string, optional, tag = "12")]
+    pub display_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConfirmResponse {
+    #[prost(bool, tag = "1")]
+    pub success: bool,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(string, tag = "3")]
+    pub outcome: String,
+}
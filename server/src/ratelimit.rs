@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where a client stands in the current window; sent back as X-RateLimit-* headers.
+#[derive(Debug, Clone, Copy)]
+pub struct RateInfo {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_secs: u64,
+}
+
+/// Fixed-window request counter per client IP. A limit of 0 disables it.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Counts one request from `ip`. Ok while under the limit, Err once it is used up.
+    pub fn check(&self, ip: IpAddr) -> Result<RateInfo, RateInfo> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Keep the map from growing without bound
+        if windows.len() > 10_000 {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let entry = windows.entry(ip).or_insert((now, 0));
+        if now.duration_since(entry.0) >= self.window {
+            *entry = (now, 0);
+        }
+
+        let reset_secs = self.window.saturating_sub(now.duration_since(entry.0)).as_secs().max(1);
+
+        if entry.1 >= self.limit {
+            return Err(RateInfo {
+                limit: self.limit,
+                remaining: 0,
+                reset_secs,
+            });
+        }
+
+        entry.1 += 1;
+        Ok(RateInfo {
+            limit: self.limit,
+            remaining: self.limit - entry.1,
+            reset_secs,
+        })
+    }
+}
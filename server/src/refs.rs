@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::Serialize;
+
+use crate::IdNotFound;
+
+/// A link from an ID to a record in another system, e.g. kind "erp_order" pointing
+/// at the order page in the ERP.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternalRef {
+    /// Numeric handle for updating or removing the reference
+    pub ref_id: i64,
+    pub kind: String,
+    pub label: Option<String>,
+    pub url: String,
+    pub created_at: String,
+}
+
+/// Changes for [`update`]; `None` leaves a field as it is and `label: Some(None)` clears the label.
+#[derive(Debug, Clone, Default)]
+pub struct RefChanges {
+    pub kind: Option<String>,
+    pub label: Option<Option<String>>,
+    pub url: Option<String>,
+}
+
+const COLUMNS: &str = "ref_id, kind, label, url, created_at";
+
+fn ref_from_row(row: &Row<'_>) -> rusqlite::Result<ExternalRef> {
+    Ok(ExternalRef {
+        ref_id: row.get(0)?,
+        kind: row.get(1)?,
+        label: row.get(2)?,
+        url: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+/// References on `id`, oldest first.
+pub fn list(conn: &Connection, id: &str) -> Result<Vec<ExternalRef>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM id_refs WHERE target = ?1 ORDER BY ref_id", COLUMNS))?;
+    let refs = stmt.query_map([id], ref_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(refs)
+}
+
+/// Attaches a reference to `id`, which must be a live ID; fails with IdNotFound otherwise.
+pub fn add(conn: &Connection, id: &str, kind: &str, label: Option<&str>, url: &str) -> Result<ExternalRef> {
+    let live: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM ids WHERE id = ?1 AND deleted = 0)",
+        [id],
+        |row| row.get(0),
+    )?;
+    if !live {
+        return Err(IdNotFound { id: id.to_string() }.into());
+    }
+
+    let added = conn.query_row(
+        &format!("INSERT INTO id_refs (target, kind, label, url) VALUES (?1, ?2, ?3, ?4) RETURNING {}", COLUMNS),
+        rusqlite::params![id, kind, label, url],
+        ref_from_row,
+    )?;
+    Ok(added)
+}
+
+/// Changes a reference on `id`. Returns None if `id` has no reference `ref_id`.
+pub fn update(conn: &Connection, id: &str, ref_id: i64, changes: &RefChanges) -> Result<Option<ExternalRef>> {
+    let updated = conn
+        .query_row(
+            &format!(
+                "UPDATE id_refs SET kind = COALESCE(?3, kind),
+                                    label = CASE WHEN ?4 THEN ?5 ELSE label END,
+                                    url = COALESCE(?6, url)
+                 WHERE target = ?1 AND ref_id = ?2
+                 RETURNING {}",
+                COLUMNS
+            ),
+            rusqlite::params![
+                id,
+                ref_id,
+                changes.kind,
+                changes.label.is_some(),
+                changes.label.clone().flatten(),
+                changes.url,
+            ],
+            ref_from_row,
+        )
+        .optional()?;
+    Ok(updated)
+}
+
+/// Removes a reference from `id`. Returns false if there is no such reference.
+pub fn delete(conn: &Connection, id: &str, ref_id: i64) -> Result<bool> {
+    let removed = conn.execute("DELETE FROM id_refs WHERE target = ?1 AND ref_id = ?2", rusqlite::params![id, ref_id])?;
+    Ok(removed > 0)
+}
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Registry change notifications; only built on Windows, where the DBPath lives.
+use crate::{error, get_db_path};
+use anyhow::{Context, Result};
+use std::thread;
+use std::time::Duration;
+use winreg::enums::*;
+use winreg::RegKey;
+
+const SETTINGS_KEY: &str = "Software\\IdRegistry\\Settings";
+
+/// Starts a thread that watches the IdRegistry registry key and calls `on_change` with
+/// the new DBPath whenever it changes from the last value seen (starting at `current`).
+pub fn spawn<F>(current: String, on_change: F)
+where
+    F: Fn(&str) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut last = current;
+        loop {
+            if let Err(e) = wait_for_change() {
+                error!("Registry watch failed, retrying in 30 s: {:#}", e);
+                thread::sleep(Duration::from_secs(30));
+                continue;
+            }
+            match get_db_path() {
+                Ok(path) if path != last => {
+                    on_change(&path);
+                    last = path;
+                }
+                Ok(_) => {}
+                Err(e) => error!("Registry changed but DBPath can't be read: {:#}", e),
+            }
+        }
+    });
+}
+
+// Blocks until a value under the key is set or deleted
+fn wait_for_change() -> Result<()> {
+    use windows_sys::Win32::System::Registry::{RegNotifyChangeKeyValue, REG_NOTIFY_CHANGE_LAST_SET};
+
+    let key = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey_with_flags(SETTINGS_KEY, KEY_NOTIFY)
+        .context("Failed to open IdRegistry registry key")?;
+    // Synchronous: returns once the key changes
+    let status = unsafe { RegNotifyChangeKeyValue(key.raw_handle(), 0, REG_NOTIFY_CHANGE_LAST_SET, 0, 0) };
+    if status != 0 {
+        return Err(std::io::Error::from_raw_os_error(status as i32)).context("RegNotifyChangeKeyValue failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{holds, issue_id, IdDeleted, IdNotFound, Settings};
+
+/// Suspicious live IDs, as found by [`find_duplicates`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateReport {
+    /// Groups of IDs that are the same apart from letter case
+    pub case_groups: Vec<Vec<String>>,
+    /// IDs using characters outside the charset they were issued under (the current one
+    /// for IDs from before generation configs were versioned)
+    pub outside_charset: Vec<String>,
+}
+
+/// Scans the live IDs for case-only duplicates and characters outside the charset.
+pub fn find_duplicates(conn: &Connection, settings: &Settings) -> Result<DuplicateReport> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM ids
+         WHERE deleted = 0 AND lower(id) IN (
+             SELECT lower(id) FROM ids WHERE deleted = 0 GROUP BY lower(id) HAVING COUNT(*) > 1
+         )",
+    )?;
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for id in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        let id = id?;
+        groups.entry(id.to_lowercase()).or_default().push(id);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT i.id, g.charset FROM ids i LEFT JOIN generation_configs g ON g.version = i.config_version
+         WHERE i.deleted = 0",
+    )?;
+    let mut outside_charset = Vec::new();
+    for row in stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))? {
+        let (id, charset) = row?;
+        let charset = charset.as_deref().unwrap_or(&settings.charset);
+        if !id.chars().all(|c| charset.contains(c)) {
+            outside_charset.push(id);
+        }
+    }
+
+    Ok(DuplicateReport {
+        case_groups: groups.into_values().collect(),
+        outside_charset,
+    })
+}
+
+// Fails with IdNotFound / IdDeleted unless `id` is a live row
+fn ensure_live(conn: &Connection, id: &str) -> Result<()> {
+    let deleted: Option<bool> = conn
+        .query_row("SELECT deleted != 0 FROM ids WHERE id = ?1", [id], |row| row.get(0))
+        .optional()?;
+    match deleted {
+        None => Err(IdNotFound { id: id.to_string() }.into()),
+        Some(true) => Err(IdDeleted { id: id.to_string() }.into()),
+        Some(false) => Ok(()),
+    }
+}
+
+// Retires `id` in favour of `target`: soft-deleted, flagged, and resolvable through id_aliases.
+// Aliases that pointed to `id` move on to `target`, so they never end at a retired ID.
+// Fails with OnHold if `id` or its owner is held, since retiring deletes it
+fn retire(conn: &Connection, id: &str, target: &str, reason: &str) -> Result<()> {
+    holds::check_id(conn, id)?;
+    conn.execute(
+        "UPDATE ids SET deleted = 1, confirmed = 0, flag = ?2, version = version + 1, updated_at = CURRENT_TIMESTAMP,
+                        deleted_at = CURRENT_TIMESTAMP
+         WHERE id = ?1",
+        [id, reason],
+    )?;
+    conn.execute("UPDATE id_aliases SET id = ?2 WHERE id = ?1", [id, target])?;
+    conn.execute(
+        "INSERT INTO id_aliases (alias, id) VALUES (?1, ?2)
+         ON CONFLICT (alias) DO UPDATE SET id = excluded.id",
+        [id, target],
+    )?;
+    Ok(())
+}
+
+/// Marks `id` for review without changing anything else about it.
+pub fn flag(conn: &Connection, id: &str, reason: &str) -> Result<()> {
+    ensure_live(conn, id)?;
+    conn.execute(
+        "UPDATE ids SET flag = ?2, version = version + 1, updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+        [id, reason],
+    )?;
+    Ok(())
+}
+
+/// Replaces `id` with a freshly issued one for the same owner, table and confirmation
+/// state. The old ID keeps resolving to the new one. Returns the new ID.
+pub fn reissue(conn: &Connection, settings: &Settings, id: &str) -> Result<String> {
+    ensure_live(conn, id)?;
+
+    let (owner, table): (String, Option<String>) = conn.query_row(
+        "SELECT owner, table_name FROM ids WHERE id = ?1",
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let (new_id, _, _) = issue_id(conn, settings, &owner, table.as_deref(), None, None)?;
+    conn.execute(
+        "UPDATE ids SET (confirmed, confirmed_at, confirmed_by) =
+             (SELECT confirmed, confirmed_at, confirmed_by FROM ids WHERE id = ?2)
+         WHERE id = ?1",
+        [&new_id, id],
+    )?;
+
+    retire(conn, id, &new_id, "reissued")?;
+    Ok(new_id)
+}
+
+/// Clears the confirmation of `id` if it is deleted but still confirmed, as rows deleted
+/// before deleting cleared it are. `confirmed_at` and `confirmed_by` stay as history;
+/// live and unconfirmed IDs are left as they are. Fails with IdNotFound.
+pub fn unconfirm_deleted(conn: &Connection, id: &str) -> Result<()> {
+    let changed = conn.execute(
+        "UPDATE ids SET confirmed = 0, version = version + 1, updated_at = CURRENT_TIMESTAMP
+         WHERE id = ?1 AND deleted != 0 AND confirmed != 0",
+        [id],
+    )?;
+    if changed == 0 {
+        let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM ids WHERE id = ?1)", [id], |row| row.get(0))?;
+        if !exists {
+            return Err(IdNotFound { id: id.to_string() }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Folds `id` into `target`, an existing live ID: `id` is retired and resolves to `target`.
+pub fn alias(conn: &Connection, id: &str, target: &str) -> Result<()> {
+    if id == target {
+        anyhow::bail!("Cannot alias {} to itself", id);
+    }
+    ensure_live(conn, id)?;
+    ensure_live(conn, target)?;
+
+    retire(conn, id, target, "aliased")
+}
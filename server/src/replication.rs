@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{error, DbManager, Settings};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use rusqlite::{Connection, DatabaseName};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far the standby copy is behind, as shown by `/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    pub replica_path: String,
+    pub last_sync: Option<String>,
+    /// Age of the oldest change not yet in the replica; 0 when it is up to date
+    pub lag_ms: u64,
+    pub last_error: Option<String>,
+}
+
+/// Shared view of the replicator's status.
+#[derive(Clone)]
+pub struct Replication(Arc<Mutex<ReplicationStatus>>);
+
+impl Replication {
+    pub fn status(&self) -> ReplicationStatus {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Starts a thread that keeps a standby copy of the database at `replica_path`,
+/// refreshed within `replica_interval_ms` of every committed change. Only runs while
+/// `active` holds, so passive nodes don't fight over the replica. Returns None when
+/// `replica_path` is empty. Follows the pool's database file when it is switched.
+pub fn spawn<F>(settings: &Settings, manager: DbManager, active: F) -> Result<Option<Replication>>
+where
+    F: Fn() -> bool + Send + 'static,
+{
+    if settings.replica_path.trim().is_empty() {
+        return Ok(None);
+    }
+    let replica = PathBuf::from(&settings.replica_path);
+    // A connection of its own: PRAGMA data_version only moves for other connections' commits
+    let mut conn = Connection::open(manager.path()).context("Cannot open the database for replication")?;
+    let interval = Duration::from_millis(settings.replica_interval_ms.max(100));
+
+    let replication = Replication(Arc::new(Mutex::new(ReplicationStatus {
+        replica_path: settings.replica_path.clone(),
+        last_sync: None,
+        lag_ms: 0,
+        last_error: None,
+    })));
+    let shared = replication.clone();
+
+    thread::spawn(move || {
+        let mut synced_version = None;
+        // When the first change the replica doesn't have yet was noticed
+        let mut dirty_since: Option<Instant> = None;
+
+        loop {
+            if active() {
+                if manager.is_stale(&conn) {
+                    match Connection::open(manager.path()) {
+                        Ok(fresh) => {
+                            conn = fresh;
+                            synced_version = None;
+                        }
+                        Err(e) => error!("Replication could not reopen the database: {}", e),
+                    }
+                }
+                let result = conn
+                    .query_row("PRAGMA data_version", [], |row| row.get::<_, i64>(0))
+                    .map_err(anyhow::Error::from)
+                    .and_then(|version| {
+                        if synced_version == Some(version) {
+                            return Ok(None);
+                        }
+                        dirty_since.get_or_insert_with(Instant::now);
+                        copy(&conn, &replica)?;
+                        Ok(Some(version))
+                    });
+
+                let mut status = shared.0.lock().unwrap_or_else(|e| e.into_inner());
+                match result {
+                    Ok(Some(version)) => {
+                        synced_version = Some(version);
+                        dirty_since = None;
+                        status.last_sync = Some(Utc::now().to_rfc3339());
+                        status.last_error = None;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        if status.last_error.is_none() {
+                            error!("ALERT: replication to {} failed: {:#}", replica.display(), e);
+                        }
+                        status.last_error = Some(format!("{:#}", e));
+                    }
+                }
+                status.lag_ms = dirty_since.map_or(0, |since| since.elapsed().as_millis() as u64);
+            }
+            thread::sleep(interval);
+        }
+    });
+
+    Ok(Some(replication))
+}
+
+// Copies the database with the backup API into a temporary file next to the replica,
+// then renames it over the replica so the standby is never half-written
+fn copy(conn: &Connection, replica: &Path) -> Result<()> {
+    if let Some(dir) = replica.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    let partial = replica.with_extension("partial");
+    conn.backup(DatabaseName::Main, &partial, None)
+        .with_context(|| format!("Cannot write {}", partial.display()))?;
+    std::fs::rename(&partial, replica).with_context(|| format!("Cannot replace {}", replica.display()))?;
+    Ok(())
+}
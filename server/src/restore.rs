@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::migrations;
+use anyhow::{Context, Result};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Returned (inside anyhow::Error) when a snapshot fails verification.
+#[derive(Debug)]
+pub struct InvalidSnapshot(pub String);
+
+impl std::fmt::Display for InvalidSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSnapshot {}
+
+/// What verification found in a snapshot.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotCheck {
+    pub schema_version: usize,
+    /// Migrations that will be applied after restoring it
+    pub pending_migrations: usize,
+    pub ids: i64,
+}
+
+/// Opens `path` read-only and checks it can replace the live database: it passes
+/// `PRAGMA integrity_check`, has an `ids` table, and its schema is not newer than
+/// this server knows.
+pub fn verify(path: &Path) -> Result<SnapshotCheck> {
+    let invalid = |message: String| anyhow::Error::new(InvalidSnapshot(message));
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| invalid(format!("cannot open {}: {}", path.display(), e)))?;
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check(1)", [], |row| row.get(0))
+        .map_err(|e| invalid(format!("not a readable SQLite database: {}", e)))?;
+    if integrity != "ok" {
+        return Err(invalid(format!("integrity check failed: {}", integrity)));
+    }
+
+    let schema_version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if schema_version > migrations::latest() {
+        return Err(invalid(format!(
+            "schema version {} is newer than this server's {}",
+            schema_version,
+            migrations::latest()
+        )));
+    }
+    let ids: i64 = conn
+        .query_row("SELECT COUNT(*) FROM ids", [], |row| row.get(0))
+        .map_err(|_| invalid("no ids table; not an ID registry database".to_string()))?;
+
+    Ok(SnapshotCheck { schema_version, pending_migrations: migrations::latest() - schema_version, ids })
+}
+
+/// Replaces the contents of the database behind `conn` with the snapshot at `path`,
+/// after copying the current contents to `keep_dir`. The copy goes through SQLite's
+/// backup API, so other connections see either the old database or the new one.
+/// Older snapshots are migrated afterwards. Returns the path of the preserved copy.
+pub fn swap_in(conn: &mut Connection, path: &Path, keep_dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(keep_dir).with_context(|| format!("Cannot create {}", keep_dir.display()))?;
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    // Restoring twice within a second (e.g. undoing a restore) mustn't reuse the name
+    let previous = (1..)
+        .map(|n| keep_dir.join(if n == 1 { format!("pre-restore-{}.db", stamp) } else { format!("pre-restore-{}-{}.db", stamp, n) }))
+        .find(|path| !path.exists())
+        .expect("an unused name");
+    conn.execute("VACUUM INTO ?1", [previous.to_string_lossy()])
+        .with_context(|| format!("Cannot preserve the current database as {}", previous.display()))?;
+
+    conn.restore(DatabaseName::Main, path, None::<fn(rusqlite::backup::Progress)>)
+        .with_context(|| format!("Restore from {} failed", path.display()))?;
+    migrations::run(conn)?;
+    Ok(previous)
+}
@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{error, info};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Timelike, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A five-field cron expression (minute, hour, day of month, month, day of week),
+/// evaluated in UTC. Fields take `*`, numbers, ranges `a-b`, lists `a,b` and steps
+/// `*/n` or `a-b/n`; day of week is 0-6 from Sunday (7 is Sunday too). As in cron, a
+/// day matches if either day field does when both are restricted.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// Bitmask of the values `field` selects within min..=max
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).context("invalid step")?),
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (a.parse()?, b.parse()?),
+                None => {
+                    let value: u32 = range.parse()?;
+                    // A bare number with a step runs from there to the end, like cron
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if from < min || to > max || from > to {
+            bail!("{} is outside {}-{}", part, min, max);
+        }
+        for value in (from..=to).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("'{}' must have five fields: minute hour day month weekday", expr);
+        };
+        let field = |value: &str, name: &str, min, max| {
+            parse_field(value, min, max).with_context(|| format!("invalid {} field '{}' in '{}'", name, value, expr))
+        };
+
+        let mut weekdays = field(weekday, "weekday", 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            expr: expr.to_string(),
+            minutes: field(minute, "minute", 0, 59)?,
+            hours: field(hour, "hour", 0, 23)?,
+            days: field(day, "day", 1, 31)?,
+            months: field(month, "month", 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first matching minute strictly after `after`, or None if there is none
+    /// within four years (e.g. February 30th).
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = t + ChronoDuration::days(4 * 366);
+
+        while t < limit {
+            let date = t.date_naive();
+            if self.months & (1 << t.month()) == 0 {
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?.and_utc();
+            } else if !self.day_matches(date) {
+                t = date.succ_opt()?.and_hms_opt(0, 0, 0)?.and_utc();
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + ChronoDuration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += ChronoDuration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// What `GET /admin/schedules` shows for one task.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    pub name: &'static str,
+    pub cron: String,
+    pub next_run: Option<String>,
+    pub last_run: Option<String>,
+    pub last_ok: Option<bool>,
+    /// The task's summary on success, the error otherwise
+    pub last_outcome: Option<String>,
+    pub last_duration_ms: Option<u64>,
+}
+
+type Task = Box<dyn Fn() -> Result<String> + Send>;
+
+struct Entry {
+    cron: Cron,
+    task: Task,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Runs maintenance tasks on cron schedules from one background thread, one task at a
+/// time. Build it with [`Scheduler::add`], then [`Scheduler::spawn`] it.
+#[derive(Default)]
+pub struct Scheduler {
+    entries: Vec<Entry>,
+    statuses: Vec<ScheduleStatus>,
+}
+
+/// Shared view of the scheduler's task statuses.
+#[derive(Clone)]
+pub struct Schedules(Arc<Mutex<Vec<ScheduleStatus>>>);
+
+impl Schedules {
+    pub fn report(&self) -> Vec<ScheduleStatus> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules `task` under `name`; an empty `expr` leaves it disabled. With
+    /// `run_at_start` its first run is right away rather than at the first match.
+    /// The task returns a one-line summary of what it did.
+    pub fn add<F>(&mut self, name: &'static str, expr: &str, run_at_start: bool, task: F) -> Result<()>
+    where
+        F: Fn() -> Result<String> + Send + 'static,
+    {
+        if expr.trim().is_empty() {
+            info!("Scheduled task {} is disabled", name);
+            return Ok(());
+        }
+        let cron = Cron::parse(expr).with_context(|| format!("Invalid schedule for {}", name))?;
+        let next = if run_at_start { Some(Utc::now()) } else { cron.next_after(Utc::now()) };
+
+        self.statuses.push(ScheduleStatus {
+            name,
+            cron: cron.expr.clone(),
+            next_run: next.map(|t| t.to_rfc3339()),
+            last_run: None,
+            last_ok: None,
+            last_outcome: None,
+            last_duration_ms: None,
+        });
+        self.entries.push(Entry { cron, task: Box::new(task), next });
+        Ok(())
+    }
+
+    /// Starts the scheduler thread and returns the statuses it keeps up to date.
+    pub fn spawn(self) -> Schedules {
+        let Scheduler { mut entries, statuses } = self;
+        let schedules = Schedules(Arc::new(Mutex::new(statuses)));
+        let shared = schedules.clone();
+
+        thread::spawn(move || loop {
+            for (index, entry) in entries.iter_mut().enumerate() {
+                if entry.next.is_none_or(|next| next > Utc::now()) {
+                    continue;
+                }
+
+                let started_at = Utc::now();
+                let started = Instant::now();
+                let result = (entry.task)();
+                let elapsed = started.elapsed();
+                entry.next = entry.cron.next_after(Utc::now());
+
+                let mut statuses = shared.0.lock().unwrap_or_else(|e| e.into_inner());
+                let status = &mut statuses[index];
+                match &result {
+                    Ok(summary) => info!("Scheduled task {} done in {} ms: {}", status.name, elapsed.as_millis(), summary),
+                    Err(e) => error!("Scheduled task {} failed: {:#}", status.name, e),
+                }
+                status.last_run = Some(started_at.to_rfc3339());
+                status.last_ok = Some(result.is_ok());
+                status.last_outcome = Some(result.unwrap_or_else(|e| format!("{:#}", e)));
+                status.last_duration_ms = Some(elapsed.as_millis() as u64);
+                status.next_run = entry.next.map(|t| t.to_rfc3339());
+            }
+
+            // Wake at the next due task, but at least every minute
+            let now = Utc::now();
+            let wait = entries
+                .iter()
+                .filter_map(|entry| entry.next)
+                .map(|next| (next - now).to_std().unwrap_or_default())
+                .min()
+                .unwrap_or(Duration::from_secs(60))
+                .min(Duration::from_secs(60));
+            thread::sleep(wait.max(Duration::from_millis(100)));
+        });
+
+        schedules
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Built-in load test: issues and looks up IDs for a throwaway owner on a copy of the
+//! database, each in a transaction that is rolled back, so a new host can be measured
+//! with real data without touching the database it serves.
+use anyhow::{Context, Result};
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{Connection, DatabaseName, TransactionBehavior};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{issue_id, Settings};
+
+/// Owner the test IDs are issued to; none of them is ever committed.
+pub const OWNER: &str = "__selftest__";
+
+/// Time taken by one round.
+pub struct Sample {
+    pub generate: Duration,
+    pub lookup: Duration,
+}
+
+/// Latency of one operation over all rounds, in ms.
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestReport {
+    pub ops: usize,
+    pub elapsed_ms: f64,
+    /// Rounds (one generation and one lookup each) per second
+    pub ops_per_sec: f64,
+    pub generate: Percentiles,
+    pub lookup: Percentiles,
+}
+
+/// A copy of the database for the test to run on. It sits next to the original, so the
+/// test measures the same disk, and is deleted with its WAL files when dropped.
+pub struct Scratch {
+    conn: Option<Connection>,
+    path: PathBuf,
+}
+
+impl Scratch {
+    /// Copies the database `conn` has open, which lives at `db_path`.
+    pub fn create(conn: &Connection, db_path: &str) -> Result<Scratch> {
+        let dir = Path::new(db_path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+        let path = dir.join(format!(".idreg-selftest-{}.db", token));
+        // From here on dropping the scratch deletes the file, even if the copy fails
+        let mut scratch = Scratch { conn: None, path };
+        conn.backup(DatabaseName::Main, &scratch.path, None)
+            .with_context(|| format!("Cannot copy the database to {}", scratch.path.display()))?;
+        let copy = Connection::open(&scratch.path)?;
+        copy.execute_batch("PRAGMA journal_mode=WAL;")?;
+        scratch.conn = Some(copy);
+        Ok(scratch)
+    }
+
+    /// [`run`] on the copy.
+    pub fn run(&mut self, settings: &Settings, rounds: usize) -> Result<Vec<Sample>> {
+        let conn = self.conn.as_mut().context("The self-test copy is not open")?;
+        run(conn, settings, rounds)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        // Closed first, as Windows doesn't delete open files
+        self.conn.take();
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Runs `rounds` rounds of issuing an ID the way POST /ids does and reading it back the
+/// way GET /ids/<id> does, each in its own immediate transaction. Nothing is committed,
+/// so the cost of a commit (an fsync) is not part of the numbers.
+pub fn run(conn: &mut Connection, settings: &Settings, rounds: usize) -> Result<Vec<Sample>> {
+    let mut samples = Vec::with_capacity(rounds);
+    for _ in 0..rounds {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let started = Instant::now();
+        let (id, _, _) = issue_id(&tx, settings, OWNER, None, None, None)?;
+        let generate = started.elapsed();
+
+        let started = Instant::now();
+        tx.prepare_cached("SELECT id, owner, table_name, confirmed, created_at, version FROM ids WHERE id = ?1")?
+            .query_row([&id], |row| row.get::<_, String>(0))?;
+        let lookup = started.elapsed();
+
+        tx.rollback()?;
+        samples.push(Sample { generate, lookup });
+    }
+    Ok(samples)
+}
+
+/// Sums up the samples of a test that took `elapsed` in all.
+pub fn report(samples: &[Sample], elapsed: Duration) -> SelftestReport {
+    let percentiles = |mut times: Vec<Duration>| {
+        times.sort();
+        let at = |q: f64| {
+            let index = ((q * times.len() as f64).ceil() as usize).clamp(1, times.len().max(1)) - 1;
+            times.get(index).map_or(0.0, |t| t.as_secs_f64() * 1000.0)
+        };
+        Percentiles { p50_ms: at(0.50), p95_ms: at(0.95), p99_ms: at(0.99), max_ms: at(1.0) }
+    };
+    let secs = elapsed.as_secs_f64();
+    SelftestReport {
+        ops: samples.len(),
+        elapsed_ms: secs * 1000.0,
+        ops_per_sec: if secs > 0.0 { samples.len() as f64 / secs } else { 0.0 },
+        generate: percentiles(samples.iter().map(|s| s.generate).collect()),
+        lookup: percentiles(samples.iter().map(|s| s.lookup).collect()),
+    }
+}
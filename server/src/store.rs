@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! The SQLite side of generation: [`IdStore`] over the `ids`, `pool` and `canaries`
+//! tables and the `id_sequences` counters.
+use anyhow::Result;
+use id_registry_core::{casefold, IdStore, Settings};
+use rusqlite::Connection;
+use std::collections::HashSet;
+
+/// A connection (or transaction) as the store generation checks candidates against.
+pub struct SqliteStore<'c>(pub &'c Connection);
+
+impl IdStore for SqliteStore<'_> {
+    // Candidates already issued, waiting in the pool or kept as canaries, in one query
+    fn existing_ids(&self, settings: &Settings, candidates: &[String]) -> Result<HashSet<String>> {
+        if candidates.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let placeholders = vec!["?"; candidates.len()].join(", ");
+        let id = if settings.case_insensitive_ids { "id COLLATE NOCASE" } else { "id" };
+        let sql = format!(
+            "SELECT id FROM ids WHERE {1} IN ({0}) UNION SELECT id FROM pool WHERE {1} IN ({0})
+             UNION SELECT id FROM canaries WHERE {1} IN ({0})",
+            placeholders, id
+        );
+
+        let mut stmt = self.0.prepare_cached(&sql)?;
+        let params = candidates.iter().chain(candidates.iter()).chain(candidates.iter());
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| row.get::<_, String>(0))?;
+
+        let mut found = HashSet::new();
+        for row in rows {
+            found.insert(casefold::key(settings, &row?));
+        }
+        Ok(found)
+    }
+
+    // A length without a counter yet starts where the single counter of older versions
+    // stopped, which was shared by every length
+    fn sequence_next(&self, random_length: u32) -> Result<i64> {
+        let mut stmt = self.0.prepare_cached(
+            "SELECT COALESCE((SELECT next FROM id_sequences WHERE random_length = ?1),
+                             (SELECT next FROM id_sequence WHERE id = 1), 0)",
+        )?;
+        Ok(stmt.query_row([random_length], |row| row.get(0))?)
+    }
+
+    fn set_sequence_next(&self, random_length: u32, next: i64) -> Result<()> {
+        self.0
+            .prepare_cached(
+                "INSERT INTO id_sequences (random_length, next) VALUES (?1, ?2)
+                 ON CONFLICT (random_length) DO UPDATE SET next = excluded.next",
+            )?
+            .execute((random_length, next))?;
+        Ok(())
+    }
+}
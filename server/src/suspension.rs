@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Maintenance suspensions (`POST /suspend?scope=...`). A suspension covers writes (the
+//! default), everything but admin routes, or one owner's writes; owner suspensions add
+//! up, so several owners can be held at once while everyone else carries on.
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::RwLock;
+
+/// What `POST /suspend` and `POST /resume` act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// Every write; reads keep serving
+    Writes,
+    /// Reads too; only admin routes and /health still answer
+    All,
+    /// Writes to one owner's IDs, including generating new ones
+    Owner(String),
+}
+
+impl Scope {
+    /// `writes`, `all` or `owner:<name>`.
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "writes" => Some(Scope::Writes),
+            "all" => Some(Scope::All),
+            _ => scope.strip_prefix("owner:").filter(|owner| !owner.is_empty()).map(|owner| Scope::Owner(owner.to_string())),
+        }
+    }
+}
+
+/// What is suspended right now, as reported by /health and /admin/status.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SuspensionStatus {
+    pub writes: bool,
+    pub all: bool,
+    pub owners: BTreeSet<String>,
+}
+
+#[derive(Default)]
+pub struct Suspension {
+    state: RwLock<SuspensionStatus>,
+}
+
+impl Suspension {
+    pub fn suspend(&self, scope: Scope) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        match scope {
+            Scope::Writes => state.writes = true,
+            Scope::All => state.all = true,
+            Scope::Owner(owner) => {
+                state.owners.insert(owner);
+            }
+        }
+    }
+
+    /// Lifts one scope, or with None every suspension.
+    pub fn resume(&self, scope: Option<Scope>) {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        match scope {
+            None => *state = SuspensionStatus::default(),
+            Some(Scope::Writes) => state.writes = false,
+            Some(Scope::All) => state.all = false,
+            Some(Scope::Owner(owner)) => {
+                state.owners.remove(&owner);
+            }
+        }
+    }
+
+    /// Whether all writes are refused (by `writes` or `all`).
+    pub fn writes_blocked(&self) -> bool {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        state.writes || state.all
+    }
+
+    pub fn reads_blocked(&self) -> bool {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).all
+    }
+
+    /// Whether writes to `owner`'s IDs are refused by an owner suspension.
+    pub fn owner_blocked(&self, owner: &str) -> bool {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).owners.contains(owner)
+    }
+
+    /// Whether any owner is suspended, so callers can skip looking owners up.
+    pub fn any_owner(&self) -> bool {
+        !self.state.read().unwrap_or_else(|e| e.into_inner()).owners.is_empty()
+    }
+
+    pub fn status(&self) -> SuspensionStatus {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
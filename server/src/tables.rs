@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::Settings;
+
+/// A registered table_name that IDs may be issued for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableEntry {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+    /// Days soft-deleted IDs of this table are kept before the purge task removes them;
+    /// 0 keeps them forever, None falls back to the `deleted_retention_days` setting
+    #[serde(default)]
+    pub deleted_retention_days: Option<u32>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Returned (inside anyhow::Error) when a table is not an active catalog entry
+/// while `enforce_table_catalog` is on.
+#[derive(Debug)]
+pub struct UnknownTable {
+    pub name: String,
+    /// A registered name differing only by case, e.g. "orders" for "Orders"
+    pub suggestion: Option<String>,
+}
+
+impl std::fmt::Display for UnknownTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.suggestion {
+            Some(suggestion) => write!(f, "Table '{}' is not registered; did you mean '{}'?", self.name, suggestion),
+            None => write!(f, "Table '{}' is not registered or not active", self.name),
+        }
+    }
+}
+
+impl std::error::Error for UnknownTable {}
+
+fn entry_from_row(row: &Row<'_>) -> rusqlite::Result<TableEntry> {
+    Ok(TableEntry {
+        name: row.get(0)?,
+        description: row.get(1)?,
+        active: row.get(2)?,
+        deleted_retention_days: row.get(3)?,
+    })
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<TableEntry>> {
+    let mut stmt = conn.prepare("SELECT name, description, active, deleted_retention_days FROM tables ORDER BY name")?;
+    let entries = stmt.query_map([], entry_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(entries)
+}
+
+pub fn get(conn: &Connection, name: &str) -> Result<Option<TableEntry>> {
+    let entry = conn
+        .query_row("SELECT name, description, active, deleted_retention_days FROM tables WHERE name = ?1", [name], entry_from_row)
+        .optional()?;
+    Ok(entry)
+}
+
+/// Registers a new table; fails with a UNIQUE violation if the name is taken.
+pub fn create(conn: &Connection, entry: &TableEntry) -> Result<()> {
+    conn.execute(
+        "INSERT INTO tables (name, description, active, deleted_retention_days) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![entry.name, entry.description, entry.active, entry.deleted_retention_days],
+    )?;
+    Ok(())
+}
+
+/// Changes description, active flag and/or retention (`Some(None)` clears the override).
+/// Returns false if there is no such table.
+pub fn update(
+    conn: &Connection,
+    name: &str,
+    description: Option<&str>,
+    active: Option<bool>,
+    deleted_retention_days: Option<Option<u32>>,
+) -> Result<bool> {
+    let changed = conn.execute(
+        "UPDATE tables SET description = COALESCE(?2, description), active = COALESCE(?3, active),
+                           deleted_retention_days = CASE WHEN ?4 THEN ?5 ELSE deleted_retention_days END
+         WHERE name = ?1",
+        rusqlite::params![name, description, active, deleted_retention_days.is_some(), deleted_retention_days.flatten()],
+    )?;
+    Ok(changed > 0)
+}
+
+/// What renaming a table changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenameSummary {
+    pub from: String,
+    pub to: String,
+    /// IDs whose table_name changed, deleted ones included
+    pub ids: usize,
+    /// Whether the catalog entry was renamed too
+    pub catalog: bool,
+}
+
+/// Moves every ID of table `from` to `to` and renames its catalog entry, if any. When
+/// both names are registered it fails with a UNIQUE violation, changing nothing
+/// provided the caller rolls back.
+pub fn rename(conn: &Connection, from: &str, to: &str) -> Result<RenameSummary> {
+    let catalog = conn.execute("UPDATE tables SET name = ?2 WHERE name = ?1", [from, to])? > 0;
+    let ids = conn.execute(
+        "UPDATE ids SET table_name = ?2, version = version + 1, updated_at = CURRENT_TIMESTAMP WHERE table_name = ?1",
+        [from, to],
+    )?;
+    Ok(RenameSummary { from: from.to_string(), to: to.to_string(), ids, catalog })
+}
+
+/// Removes a table from the catalog; IDs already issued for it keep their table_name.
+/// Returns false if there is no such table.
+pub fn delete(conn: &Connection, name: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM tables WHERE name = ?1", [name])? > 0)
+}
+
+/// Checks `table` against the catalog when `enforce_table_catalog` is on; anything goes otherwise.
+pub fn check(conn: &Connection, settings: &Settings, table: Option<&str>) -> Result<()> {
+    let Some(name) = table else {
+        return Ok(());
+    };
+    if !settings.enforce_table_catalog {
+        return Ok(());
+    }
+
+    let active: Option<bool> = conn
+        .query_row("SELECT active FROM tables WHERE name = ?1", [name], |row| row.get(0))
+        .optional()?;
+    if active == Some(true) {
+        return Ok(());
+    }
+
+    let suggestion = conn
+        .query_row(
+            "SELECT name FROM tables WHERE lower(name) = lower(?1) AND active = 1 LIMIT 1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Err(UnknownTable { name: name.to_string(), suggestion }.into())
+}
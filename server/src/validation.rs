@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//! Request body validation. [`Valid`] takes the place of `Json` as a data guard: it
+//! parses the body, then checks every field against the rules below, and rejects the
+//! request with 422 `validation_failed` listing each field that failed, parse errors
+//! included (see the 422 catcher). The handlers' own checks use the same rules, for
+//! bodies sent as forms, query strings or protobuf.
+use id_registry_server::errors::FieldError;
+use id_registry_server::{check_suffix, Settings};
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::Request;
+use serde::de::DeserializeOwned;
+
+use crate::AppState;
+
+pub const MAX_OWNER_CHARS: usize = 100;
+pub const MAX_TABLE_CHARS: usize = 100;
+pub const MAX_ID_CHARS: usize = 200;
+/// Descriptions, reasons and other short free text
+pub const MAX_TEXT_CHARS: usize = 500;
+
+/// A parsed JSON body whose fields passed [`Validate`].
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Valid<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for Valid<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// The field errors of a rejected body, kept for the 422 catcher.
+pub struct Invalid(pub Vec<FieldError>);
+
+/// Field rules of a request body; bodies with nothing to check beyond their types keep
+/// the default.
+pub trait Validate {
+    fn validate(&self, _checks: &mut Checks<'_>) {}
+}
+
+/// Collects the errors of one body.
+pub struct Checks<'a> {
+    pub settings: &'a Settings,
+    errors: Vec<FieldError>,
+}
+
+impl Checks<'_> {
+    pub fn check(&mut self, field: impl Into<String>, result: Result<(), String>) {
+        if let Err(message) = result {
+            self.errors.push(FieldError { field: field.into(), message });
+        }
+    }
+
+    /// Checks each ID of a list, reporting them as `field[index]`, and the list's length.
+    pub fn ids(&mut self, field: &str, ids: &[String], max: usize) {
+        if ids.len() > max {
+            self.check(field, Err(format!("at most {} IDs per request", max)));
+        }
+        for (i, value) in ids.iter().enumerate() {
+            self.check(format!("{}[{}]", field, i), id(value));
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned + Validate> FromData<'r> for Valid<T> {
+    type Error = ();
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return Outcome::Error((Status::PayloadTooLarge, ())),
+            Err(_) => return Outcome::Error((Status::BadRequest, ())),
+        };
+
+        let value: T = match serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&body)) {
+            Ok(value) => value,
+            Err(e) => {
+                let field = match e.path().to_string() {
+                    path if path == "." => "body".to_string(),
+                    path => path,
+                };
+                return reject(req, vec![FieldError { field, message: e.inner().to_string() }]);
+            }
+        };
+
+        let Some(state) = req.rocket().state::<AppState>() else {
+            return Outcome::Success(Valid(value));
+        };
+        let settings = state.settings();
+        let mut checks = Checks { settings: &settings, errors: Vec::new() };
+        value.validate(&mut checks);
+        if !checks.errors.is_empty() {
+            return reject(req, checks.errors);
+        }
+        Outcome::Success(Valid(value))
+    }
+}
+
+fn reject<'r, T: DeserializeOwned + Validate>(req: &'r Request<'_>, errors: Vec<FieldError>) -> data::Outcome<'r, Valid<T>> {
+    req.local_cache(|| Some(Invalid(errors)));
+    Outcome::Error((Status::UnprocessableEntity, ()))
+}
+
+/// Owners: letters, digits and underscores.
+pub fn owner(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > MAX_OWNER_CHARS || !value.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("must be 1-{} letters, digits or underscores", MAX_OWNER_CHARS));
+    }
+    Ok(())
+}
+
+/// Table names, as in the catalog: letters, digits and underscores.
+pub fn table(value: &str) -> Result<(), String> {
+    if value.is_empty() || value.chars().count() > MAX_TABLE_CHARS || !value.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("must be 1-{} letters, digits or underscores", MAX_TABLE_CHARS));
+    }
+    Ok(())
+}
+
+/// IDs sent to look up or act on. Only loosely checked, since IDs issued under earlier
+/// settings may not match the current length and charset.
+pub fn id(value: &str) -> Result<(), String> {
+    if value.is_empty() || value.chars().count() > MAX_ID_CHARS || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("must be 1-{} characters without spaces", MAX_ID_CHARS));
+    }
+    Ok(())
+}
+
+/// External refs are the caller's own keys, so anything printable goes.
+pub fn external_ref(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() || value.len() > 200 || value.chars().any(char::is_control) {
+        return Err("must be 1-200 printable characters".to_string());
+    }
+    Ok(())
+}
+
+/// A short name for a person or system: letters, digits and _ - . : @
+pub fn principal(value: &str) -> Result<(), String> {
+    let value = value.trim();
+    let valid = !value.is_empty() && value.len() <= 100 && value.chars().all(|c| c.is_alphanumeric() || "_-.:@".contains(c));
+    if !valid {
+        return Err("must be 1-100 letters, digits or _ - . : @".to_string());
+    }
+    Ok(())
+}
+
+/// Free text of at most `max` characters, not blank.
+pub fn text(value: &str, max: usize) -> Result<(), String> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > max {
+        return Err(format!("must be 1-{} characters", max));
+    }
+    Ok(())
+}
+
+/// Labels of external references; may be blank.
+pub fn label(value: &str) -> Result<(), String> {
+    if value.chars().count() > 200 {
+        return Err("must be at most 200 characters".to_string());
+    }
+    Ok(())
+}
+
+/// Links of external references.
+pub fn url(value: &str) -> Result<(), String> {
+    let valid = (value.starts_with("http://") || value.starts_with("https://"))
+        && value.len() <= 2000
+        && !value.chars().any(char::is_whitespace);
+    if !valid {
+        return Err("must be an http(s) URL of at most 2000 characters".to_string());
+    }
+    Ok(())
+}
+
+pub fn suffix(settings: &Settings, value: &str) -> Result<(), String> {
+    check_suffix(settings, value).map_err(|e| e.to_string())
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+use crate::{error, info, DbManager, DbPool};
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// Settles the race between a job starting and its submitter giving up on it: whichever
+/// comes first wins, so a cancelled job never runs and a started one is never cancelled.
+#[derive(Clone, Default)]
+pub struct Claim(Arc<AtomicBool>);
+
+impl Claim {
+    /// Taken by the job before it runs; false if it was cancelled.
+    pub fn start(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+
+    /// Taken by a submitter that gives up; false if the job already started.
+    pub fn cancel(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Serializes all writes onto one thread that owns one connection: jobs run one at a
+/// time in the order they were submitted, so request handlers never contend for
+/// SQLite's write lock among themselves.
+pub struct Writer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Writer {
+    /// Takes a connection out of `pool` for good and starts the writer thread on it.
+    /// After the database file is switched it trades it in for one to the new file.
+    pub fn start(pool: &DbPool, manager: DbManager) -> Result<Self> {
+        let pool = pool.clone();
+        let mut conn = pool.get().context("No connection for the writer thread")?;
+        let (jobs, queue) = mpsc::channel::<Job>();
+
+        thread::Builder::new()
+            .name("sqlite-writer".into())
+            .spawn(move || {
+                for job in queue {
+                    if manager.is_stale(&conn) {
+                        match pool.get() {
+                            Ok(fresh) => conn = fresh,
+                            Err(e) => error!("Writer could not reconnect after a database switch: {}", e),
+                        }
+                    }
+                    // A panicking job only fails its own request; its result sender is
+                    // dropped, which the submitter sees as a failed task
+                    if panic::catch_unwind(AssertUnwindSafe(|| job(&mut conn))).is_err() {
+                        error!("Writer job panicked");
+                    }
+                }
+            })
+            .context("Failed to start the writer thread")?;
+
+        info!("Writer thread started");
+        Ok(Writer { jobs })
+    }
+
+    /// Queues `job` behind the writes already submitted; the receiver yields its result.
+    pub fn submit<T, F>(&self, job: F) -> oneshot::Receiver<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> T + Send + 'static,
+    {
+        self.submit_claimed(Claim::default(), job)
+    }
+
+    /// [`submit`](Self::submit) for a job that is skipped if `claim` is cancelled before
+    /// its turn comes.
+    pub fn submit_claimed<T, F>(&self, claim: Claim, job: F) -> oneshot::Receiver<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> T + Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        // If the thread is gone the job (and `done`) are dropped and the receiver errors
+        let _ = self.jobs.send(Box::new(move |conn| {
+            if claim.start() {
+                let _ = done.send(job(conn));
+            }
+        }));
+        result
+    }
+}